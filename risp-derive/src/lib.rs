@@ -0,0 +1,478 @@
+//! Derive macros converting Rust types to and from `risp::Form`
+//!
+//! Structs with named fields become maps with keyword keys, tuple structs become vectors and
+//! unit structs become nil. Enums are tagged: unit variants are written as a keyword, every other
+//! variant as a map with a single entry from the variant keyword to its contents.
+//!
+//! Field and variant names are converted to kebab-case, so `max_depth` is read from
+//! `:max-depth` and `ReadOnly` from `:read-only`.
+//!
+//! Attributes:
+//! - `#[form(vector)]` on a struct with named fields converts it to a vector in field order
+//! - `#[form(rename = "name")]` on a field or variant overrides its keyword
+//! - `#[form(optional)]` on an `Option<T>` field accepts a missing key or nil as `None`, and
+//!   leaves the key out when writing `None`
+//! - `#[form(default)]` or `#[form(default = "path::to::fn")]` on a field fills in a missing key
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, Attribute, Data, DeriveInput, Fields, GenericArgument, Ident, LitStr,
+    Member, PathArguments, Type,
+};
+
+#[proc_macro_derive(IntoForm, attributes(form))]
+pub fn derive_into_form(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    into_form(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(FromForm, attributes(form))]
+pub fn derive_from_form(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    from_form(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// How a struct or variant body is laid out
+#[derive(Clone, Copy, PartialEq)]
+enum Shape {
+    Map,
+    Vector,
+    Unit,
+}
+
+struct FieldInfo {
+    member: Member,
+    binding: Ident,
+    key: String,
+    ty: Type,
+    /// The `T` of an `Option<T>` field marked `optional`
+    optional: Option<Type>,
+    default: Option<TokenStream2>,
+}
+
+struct Body {
+    shape: Shape,
+    fields: Vec<FieldInfo>,
+}
+
+#[derive(Default)]
+struct Attrs {
+    rename: Option<String>,
+    vector: bool,
+    optional: bool,
+    default: Option<TokenStream2>,
+}
+
+fn parse_attrs(attrs: &[Attribute]) -> syn::Result<Attrs> {
+    let mut parsed = Attrs::default();
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("form")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                parsed.rename = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("vector") {
+                parsed.vector = true;
+            } else if meta.path.is_ident("optional") {
+                parsed.optional = true;
+            } else if meta.path.is_ident("default") {
+                parsed.default = Some(if meta.input.peek(syn::Token![=]) {
+                    let path: syn::ExprPath = meta.value()?.parse::<LitStr>()?.parse()?;
+                    quote!(#path())
+                } else {
+                    quote!(::core::default::Default::default())
+                });
+            } else {
+                return Err(meta.error("unknown form attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(parsed)
+}
+
+/// Convert a Rust identifier to the kebab-case keyword used for it
+fn kebab_case(ident: &Ident) -> String {
+    let name = ident.to_string();
+    let name = name.strip_prefix("r#").unwrap_or(&name);
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c == '_' {
+            out.push('-');
+        } else if c.is_uppercase() {
+            if i > 0 && !out.ends_with('-') {
+                out.push('-');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else { return None };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(ref args) = segment.arguments else {
+        return None;
+    };
+    match args.args.first()? {
+        GenericArgument::Type(inner) => Some(inner),
+        _ => None,
+    }
+}
+
+fn parse_body(fields: &Fields, attrs: &Attrs) -> syn::Result<Body> {
+    let shape = match fields {
+        Fields::Named(_) if attrs.vector => Shape::Vector,
+        Fields::Named(_) => Shape::Map,
+        Fields::Unnamed(_) => Shape::Vector,
+        Fields::Unit => Shape::Unit,
+    };
+    let fields = fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let attrs = parse_attrs(&field.attrs)?;
+            let (member, binding, key) = match field.ident {
+                Some(ref ident) => (
+                    Member::Named(ident.clone()),
+                    format_ident!("__{}", ident.to_string().trim_start_matches("r#")),
+                    kebab_case(ident),
+                ),
+                None => (
+                    Member::Unnamed(i.into()),
+                    format_ident!("__{i}"),
+                    i.to_string(),
+                ),
+            };
+            let optional = if attrs.optional {
+                let inner = option_inner(&field.ty).ok_or_else(|| {
+                    syn::Error::new_spanned(&field.ty, "optional fields must have type Option<T>")
+                })?;
+                Some(inner.clone())
+            } else {
+                None
+            };
+            Ok(FieldInfo {
+                member,
+                binding,
+                key: attrs.rename.unwrap_or(key),
+                ty: field.ty.clone(),
+                optional,
+                default: attrs.default,
+            })
+        })
+        .collect::<syn::Result<_>>()?;
+    Ok(Body { shape, fields })
+}
+
+fn reject_generics(input: &DeriveInput) -> syn::Result<()> {
+    if input.generics.params.is_empty() {
+        Ok(())
+    } else {
+        Err(syn::Error::new_spanned(
+            &input.generics,
+            "generic types are not supported",
+        ))
+    }
+}
+
+/// `Path { member: binding, .. }`, usable both as a pattern and as an expression
+fn destructure(path: &TokenStream2, body: &Body) -> TokenStream2 {
+    let members = body.fields.iter().map(|field| &field.member);
+    let bindings = body.fields.iter().map(|field| &field.binding);
+    match body.shape {
+        Shape::Unit => quote!(#path),
+        _ => quote!(#path { #(#members: #bindings),* }),
+    }
+}
+
+/// Expression building a `Form` from the bindings of a body
+fn write_body(body: &Body) -> TokenStream2 {
+    match body.shape {
+        Shape::Unit => quote!(::risp::Form::nil()),
+        Shape::Vector => {
+            let bindings = body.fields.iter().map(|field| &field.binding);
+            quote!(::risp::Form::vector([#(::risp::Form::from(#bindings)),*]))
+        }
+        Shape::Map => {
+            let inserts = body.fields.iter().map(|field| {
+                let binding = &field.binding;
+                let key = &field.key;
+                if field.optional.is_some() {
+                    quote! {
+                        if let ::core::option::Option::Some(value) = #binding {
+                            map.insert(::risp::Form::keyword(#key), ::risp::Form::from(value));
+                        }
+                    }
+                } else {
+                    quote!(map.insert(::risp::Form::keyword(#key), ::risp::Form::from(#binding));)
+                }
+            });
+            quote! {{
+                let mut map = ::std::collections::HashMap::new();
+                #(#inserts)*
+                ::risp::Form::hash_map(map)
+            }}
+        }
+    }
+}
+
+fn into_form(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    reject_generics(input)?;
+    let name = &input.ident;
+    let conversion = match input.data {
+        Data::Struct(ref data) => {
+            let body = parse_body(&data.fields, &parse_attrs(&input.attrs)?)?;
+            let pattern = destructure(&quote!(#name), &body);
+            let write = write_body(&body);
+            quote! {
+                let #pattern = value;
+                #write
+            }
+        }
+        Data::Enum(ref data) => {
+            let arms = data
+                .variants
+                .iter()
+                .map(|variant| {
+                    let attrs = parse_attrs(&variant.attrs)?;
+                    let ident = &variant.ident;
+                    let tag = attrs.rename.clone().unwrap_or_else(|| kebab_case(ident));
+                    let body = parse_body(&variant.fields, &attrs)?;
+                    let pattern = destructure(&quote!(#name::#ident), &body);
+                    let write = match (body.shape, body.fields.len()) {
+                        (Shape::Unit, _) => quote!(::risp::Form::keyword(#tag)),
+                        // Newtype variants hold their value directly
+                        (Shape::Vector, 1) if !attrs.vector => {
+                            let binding = &body.fields[0].binding;
+                            quote!(::risp::Form::hash_map(
+                                [(::risp::Form::keyword(#tag), ::risp::Form::from(#binding))].into()
+                            ))
+                        }
+                        _ => {
+                            let write = write_body(&body);
+                            quote!(::risp::Form::hash_map(
+                                [(::risp::Form::keyword(#tag), #write)].into()
+                            ))
+                        }
+                    };
+                    Ok(quote!(#pattern => #write,))
+                })
+                .collect::<syn::Result<Vec<_>>>()?;
+            quote! {
+                match value {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "unions cannot be converted to forms",
+            ))
+        }
+    };
+    Ok(quote! {
+        // Forms are map keys, see the note on `impl Hash for Form` in risp
+        #[allow(clippy::mutable_key_type)]
+        impl ::core::convert::From<#name> for ::risp::Form {
+            fn from(value: #name) -> ::risp::Form {
+                #conversion
+            }
+        }
+    })
+}
+
+/// Expression converting `value` to a field's type, naming the field on failure
+fn convert_field(ty: &Type, key: &str) -> TokenStream2 {
+    quote! {
+        <::risp::Form as ::core::convert::TryInto<#ty>>::try_into(value).map_err(|error| {
+            ::risp::Error::InvalidField {
+                field: #key.into(),
+                source: ::std::boxed::Box::new(::risp::Error::from(error)),
+            }
+        })?
+    }
+}
+
+/// Expression for a field whose value is `found: Option<Form>`
+fn read_field(field: &FieldInfo) -> TokenStream2 {
+    let key = &field.key;
+    let missing = match field.default {
+        Some(ref default) => quote!(#default),
+        None if field.optional.is_some() => quote!(::core::option::Option::None),
+        None => quote! {
+            return ::core::result::Result::Err(::risp::Error::MissingField(#key.into()))
+        },
+    };
+    match field.optional {
+        Some(ref inner) => {
+            let convert = convert_field(inner, key);
+            quote! {
+                match found {
+                    ::core::option::Option::Some(value) if !value.is_nil() => {
+                        ::core::option::Option::Some(#convert)
+                    }
+                    ::core::option::Option::Some(_) => ::core::option::Option::None,
+                    ::core::option::Option::None => #missing,
+                }
+            }
+        }
+        None => {
+            let convert = convert_field(&field.ty, key);
+            quote! {
+                match found {
+                    ::core::option::Option::Some(value) => #convert,
+                    ::core::option::Option::None => #missing,
+                }
+            }
+        }
+    }
+}
+
+/// Statements reading the bindings of a body out of `form`
+fn read_body(body: &Body) -> TokenStream2 {
+    let invalid = quote!(return ::core::result::Result::Err(::risp::Error::InvalidArgument));
+    let bindings = body.fields.iter().map(|field| &field.binding);
+    let reads = body.fields.iter().map(read_field);
+    match body.shape {
+        Shape::Unit => quote! {
+            if !form.is_nil() {
+                #invalid;
+            }
+        },
+        Shape::Vector => quote! {
+            let mut items = match form.kind {
+                ::risp::FormKind::List(items) | ::risp::FormKind::Vector(items) => items.into_iter(),
+                _ => #invalid,
+            };
+            #(
+                let #bindings = {
+                    let found = items.next();
+                    #reads
+                };
+            )*
+            if items.next().is_some() {
+                #invalid;
+            }
+        },
+        Shape::Map => {
+            let keys = body.fields.iter().map(|field| &field.key);
+            quote! {
+                #[allow(unused_mut)]
+                let mut map = match form.kind {
+                    ::risp::FormKind::HashMap(map) => map,
+                    _ => #invalid,
+                };
+                #(
+                    let #bindings = {
+                        let found = map.remove(&::risp::Form::keyword(#keys));
+                        #reads
+                    };
+                )*
+            }
+        }
+    }
+}
+
+fn from_form(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    reject_generics(input)?;
+    let name = &input.ident;
+    let conversion = match input.data {
+        Data::Struct(ref data) => {
+            let body = parse_body(&data.fields, &parse_attrs(&input.attrs)?)?;
+            let read = read_body(&body);
+            let construct = destructure(&quote!(#name), &body);
+            quote! {
+                let form = self;
+                #read
+                ::core::result::Result::Ok(#construct)
+            }
+        }
+        Data::Enum(ref data) => {
+            let arms = data
+                .variants
+                .iter()
+                .map(|variant| {
+                    let attrs = parse_attrs(&variant.attrs)?;
+                    let ident = &variant.ident;
+                    let tag = attrs.rename.clone().unwrap_or_else(|| kebab_case(ident));
+                    let body = parse_body(&variant.fields, &attrs)?;
+                    let construct = destructure(&quote!(#name::#ident), &body);
+                    Ok(match (body.shape, body.fields.len()) {
+                        (Shape::Vector, 1) if !attrs.vector => {
+                            let field = &body.fields[0];
+                            let binding = &field.binding;
+                            let convert = convert_field(&field.ty, &tag);
+                            quote! {
+                                #tag => {
+                                    let value = form;
+                                    let #binding = #convert;
+                                    ::core::result::Result::Ok(#construct)
+                                }
+                            }
+                        }
+                        _ => {
+                            let read = read_body(&body);
+                            // Name the variant when its contents fail to convert
+                            quote! {
+                                #tag => (|| -> ::risp::Result<#name> {
+                                    #read
+                                    ::core::result::Result::Ok(#construct)
+                                })()
+                                .map_err(|error| ::risp::Error::InvalidField {
+                                    field: #tag.into(),
+                                    source: ::std::boxed::Box::new(error),
+                                }),
+                            }
+                        }
+                    })
+                })
+                .collect::<syn::Result<Vec<_>>>()?;
+            quote! {
+                let (tag, form) = match self.kind {
+                    ::risp::FormKind::Keyword(tag) => (tag, ::risp::Form::nil()),
+                    ::risp::FormKind::HashMap(map) if map.len() == 1 => {
+                        let (tag, form) = map.into_iter().next().expect("map has one entry");
+                        match tag.kind {
+                            ::risp::FormKind::Keyword(tag) => (tag, form),
+                            _ => return ::core::result::Result::Err(::risp::Error::InvalidArgument),
+                        }
+                    }
+                    _ => return ::core::result::Result::Err(::risp::Error::InvalidArgument),
+                };
+                match tag.name() {
+                    #(#arms)*
+                    other => ::core::result::Result::Err(::risp::Error::UnknownVariant(other.into())),
+                }
+            }
+        }
+        Data::Union(_) => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "unions cannot be converted from forms",
+            ))
+        }
+    };
+    Ok(quote! {
+        // Forms are map keys, see the note on `impl Hash for Form` in risp
+        #[allow(clippy::mutable_key_type)]
+        impl ::core::convert::TryInto<#name> for ::risp::Form {
+            type Error = ::risp::Error;
+
+            fn try_into(self) -> ::risp::Result<#name> {
+                #conversion
+            }
+        }
+    })
+}
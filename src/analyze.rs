@@ -0,0 +1,968 @@
+//! Analysis of forms before evaluation
+//!
+//! Rather than walking a form every time it runs, `eval` first analyzes it into a tree of Rust
+//! closures. Analysis expands macros once, resolves special forms, and gives every local
+//! variable a numbered slot in the frame of the function it belongs to, so running the result
+//! only does the work left at runtime: looking up globals, calling functions and binding values.
+//!
+//! A function is analyzed on its first call, and closures created by the same `fn*` form share
+//! the result. Closures capture the values of the locals in scope when they are created.
+//!
+//! The tree-walking evaluator in [`eval`](crate::eval) defines the semantics, and still runs
+//! whatever analysis declines:
+//!
+//! - `def!`, `defmacro!` and `ns` inside a local scope, which define names in that scope
+//! - closures in `let*` or `loop` bindings that may refer to names bound after them
+//! - forms that fail to analyze, e.g. a malformed special form or a macro call that fails to
+//!   expand, so the error is raised when the form is reached
+//!
+//! Because macros are expanded during analysis, redefining a macro doesn't change functions that
+//! have already been called. The forms in a top-level `do` are analyzed one at a time, so a
+//! macro defined by one of them can be used by the ones after it, as in a file loaded with
+//! `load-file`.
+
+use std::rc::Rc;
+
+use crate::{
+    destructure::{self, bind},
+    dynamic,
+    env::LookupCache,
+    eval::{self, Catch, CatchFilter, Expansion},
+    exec,
+    form::{Ident, UserFn},
+    intern::sym,
+    interrupt, limits, namespace, pattern, special,
+    trace::{self, Site},
+    Env, Error, Form, FormKind, Result,
+};
+
+/// Analyzed code, run against the frame of the function it belongs to
+type Code = Box<dyn Fn(&mut Activation) -> Result<Ret>>;
+
+/// Extra capacity given to argument lists, so that most functions can extend them into their
+/// frame without reallocating
+const FRAME_SPARE: usize = 4;
+
+/// The locals of a running function, and the environment it looks up other names in
+struct Activation {
+    slots: Vec<Form>,
+    env: Env,
+}
+
+/// What running analyzed code produced
+enum Ret {
+    Value(Form),
+    /// A call in tail position, left for the caller to make so that tail calls don't grow the
+    /// stack
+    Call(Form, Vec<Form>, Option<Rc<Site>>),
+    /// The arguments of `recur`, for the enclosing `loop` or function to rebind
+    Recur(Vec<Form>),
+}
+
+/// Run `code` in a position that needs its value
+fn value(code: &Code, act: &mut Activation) -> Result<Form> {
+    match code(act)? {
+        Ret::Value(value) => Ok(value),
+        Ret::Call(f, args, site) => invoke(f, args, site),
+        Ret::Recur(_) => Err(Error::InvalidRecur("can only be used in tail position")),
+    }
+}
+
+fn constant(form: Form) -> Code {
+    Box::new(move |_| Ok(Ret::Value(form.clone())))
+}
+
+/// Analyze and evaluate `form` in `env`
+pub(crate) fn eval(form: Form, env: &mut Env) -> Result<Form> {
+    let _depth = limits::enter()?;
+    let result = match form.kind {
+        FormKind::List(ref items) if form.as_fn_name() == Some(sym::DO) => items[1..]
+            .iter()
+            .try_fold(Form::nil(), |_, form| eval(form.clone(), env)),
+        _ => {
+            let mut scope = Scope::new(env, false);
+            match scope.analyze(&form, true) {
+                Some(code) => {
+                    let mut act = Activation {
+                        slots: vec![Form::nil(); scope.slots],
+                        env: env.clone(),
+                    };
+                    let result = match code(&mut act) {
+                        Ok(Ret::Value(value)) => Ok(value),
+                        Ok(Ret::Call(f, args, site)) => invoke(f, args, site),
+                        Ok(Ret::Recur(_)) => {
+                            Err(Error::InvalidRecur("used outside of loop or fn*"))
+                        }
+                        Err(err) => Err(err),
+                    };
+                    // `ns` switches the namespace of the caller
+                    *env = act.env;
+                    result
+                }
+                None => eval::interpret_top_level(form, env),
+            }
+        }
+    };
+    if result.is_ok() {
+        trace::clear();
+    }
+    result
+}
+
+/// Call `f` with `args`. `site` is the call form, if the call should appear in stack traces.
+pub(crate) fn invoke(mut f: Form, mut args: Vec<Form>, mut site: Option<Rc<Site>>) -> Result<Form> {
+    let _depth = limits::enter()?;
+    // The function that made the current tail call, which keeps its frame if a native function
+    // fails
+    let mut caller = None;
+    loop {
+        let user_fn = match f.kind {
+            FormKind::NativeFn(_) | FormKind::MultiFn(_) => {
+                let result = call_to_completion(&f, args);
+                if result.is_err() {
+                    Site::unwind(&site, &f);
+                    if let Some((ref caller, ref caller_site)) = caller {
+                        Site::unwind(caller_site, caller);
+                    }
+                }
+                return result;
+            }
+            FormKind::UserFn(ref user_fn) => Rc::clone(user_fn),
+            _ => return Err(Error::NotCallable),
+        };
+        let result = match lambda(&user_fn) {
+            Some(lambda) => lambda.call(&user_fn, args),
+            None => eval::interpret_call(f.clone(), Form::list(args)).map(Ret::Value),
+        };
+        match result {
+            Ok(Ret::Value(value)) => return Ok(value),
+            Ok(Ret::Call(next, next_args, next_site)) => {
+                caller = Some((std::mem::replace(&mut f, next), site));
+                args = next_args;
+                site = next_site;
+            }
+            Ok(Ret::Recur(_)) => unreachable!("recur is handled by the function it targets"),
+            Err(err) => {
+                Site::unwind(&site, &f);
+                return Err(err);
+            }
+        }
+    }
+}
+
+/// Call a native function or multimethod, which runs without a frame of its own
+fn call_to_completion(f: &Form, args: Vec<Form>) -> Result<Form> {
+    match f.kind {
+        FormKind::NativeFn(ref native) => native
+            .call(Form::list(args))
+            .and_then(|result| limits::allocate(&result).map(|()| result)),
+        FormKind::MultiFn(ref multi) => multi.call(Form::list(args)),
+        _ => Err(Error::NotCallable),
+    }
+}
+
+/// The analysis of `user_fn`, analyzing it on the first call
+fn lambda(user_fn: &UserFn) -> Option<&Lambda> {
+    let analyzed = &user_fn.compiled.analyzed;
+    if analyzed.get().is_none() {
+        // Analysis can call macros, which may call this function, so the cell is only filled
+        // once analysis is done
+        let _ = analyzed.set(Lambda::compile(user_fn));
+    }
+    analyzed.get()?.as_ref()
+}
+
+/// Whether calls of `f` run analyzed code
+pub(crate) fn is_compiled(f: &Form) -> bool {
+    matches!(f.kind, FormKind::UserFn(ref user_fn) if lambda(user_fn).is_some())
+}
+
+pub(crate) struct Lambda {
+    arities: Vec<CompiledArity>,
+}
+
+struct CompiledArity {
+    params: Vec<Binder>,
+    rest: Option<Binder>,
+    /// Whether the parameters are plain symbols, which take the first slots in order, so the
+    /// arguments can become the frame as they are
+    direct: bool,
+    slots: usize,
+    body: Code,
+}
+
+impl Lambda {
+    fn compile(user_fn: &UserFn) -> Option<Lambda> {
+        let arities = user_fn
+            .arities
+            .iter()
+            .map(|arity| {
+                let mut scope = Scope::new(&user_fn.env, true);
+                scope.recur = true;
+                let params = arity
+                    .binds
+                    .iter()
+                    .map(|pattern| scope.binder(pattern))
+                    .collect::<Option<Vec<_>>>()?;
+                let rest = match arity.bind_rest {
+                    Some(ref pattern) => Some(scope.binder(pattern)?),
+                    None => None,
+                };
+                let body = scope.analyze(&arity.body, true)?;
+                let direct = params
+                    .iter()
+                    .chain(&rest)
+                    .all(|binder| matches!(binder, Binder::Slot(_)));
+                Some(CompiledArity {
+                    params,
+                    rest,
+                    direct,
+                    slots: scope.slots,
+                    body,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Lambda { arities })
+    }
+
+    /// Run the body of the arity of `user_fn` matching `args`, returning a tail call for the
+    /// caller to make
+    fn call(&self, user_fn: &UserFn, mut args: Vec<Form>) -> Result<Ret> {
+        let arity = &self.arities[eval::select_arity(user_fn, args.len())?];
+        if arity.rest.is_some() {
+            let rest = args.split_off(arity.params.len());
+            args.push(Form::list(rest));
+        }
+        let binders = arity.params.iter().chain(&arity.rest);
+        let mut act = Activation {
+            slots: Vec::new(),
+            env: user_fn.env.clone(),
+        };
+        if arity.direct {
+            args.resize(arity.slots, Form::nil());
+            act.slots = args;
+        } else {
+            act.slots = vec![Form::nil(); arity.slots];
+            for (binder, value) in binders.clone().zip(args) {
+                binder.bind(value, &mut act)?;
+            }
+        }
+        loop {
+            match (arity.body)(&mut act)? {
+                Ret::Recur(recur_args) => {
+                    // The rest parameter is passed as a single sequence, not spread
+                    let expected = arity.params.len() + usize::from(arity.rest.is_some());
+                    if recur_args.len() != expected {
+                        return Err(Error::ArityMismatch {
+                            found: recur_args.len(),
+                            expected: expected.to_string(),
+                        });
+                    }
+                    for (binder, value) in binders.clone().zip(recur_args) {
+                        binder.bind(value, &mut act)?;
+                    }
+                }
+                ret => return Ok(ret),
+            }
+        }
+    }
+}
+
+/// Where a binding form stores its value
+enum Binder {
+    Slot(usize),
+    Pattern(Box<Pattern>),
+}
+
+/// A destructuring pattern, with the slots of the names it binds and its analyzed `:or`
+/// defaults
+struct Pattern {
+    form: Form,
+    slots: Vec<(Ident, usize)>,
+    defaults: Vec<(Ident, Code)>,
+}
+
+impl Binder {
+    fn bind(&self, value: Form, act: &mut Activation) -> Result<()> {
+        match *self {
+            Binder::Slot(slot) => {
+                act.slots[slot] = value;
+                Ok(())
+            }
+            Binder::Pattern(ref pattern) => {
+                bind(&pattern.form, value, &mut SlotBinder { pattern, act })
+            }
+        }
+    }
+}
+
+struct SlotBinder<'a> {
+    pattern: &'a Pattern,
+    act: &'a mut Activation,
+}
+
+impl destructure::Binder for SlotBinder<'_> {
+    fn set(&mut self, name: Ident, value: Form) {
+        if let Some(&(_, slot)) = self.pattern.slots.iter().find(|(n, _)| *n == name) {
+            self.act.slots[slot] = value;
+        }
+    }
+
+    fn default(&mut self, name: Ident, _: &Form) -> Result<Form> {
+        match self.pattern.defaults.iter().find(|(n, _)| *n == name) {
+            Some((_, code)) => value(code, self.act),
+            None => Ok(Form::nil()),
+        }
+    }
+}
+
+/// Collect the `:or` defaults of the map patterns in `pattern`
+pub(crate) fn defaults(pattern: &Form, found: &mut Vec<(Ident, Form)>) {
+    match pattern.kind {
+        FormKind::Vector(ref items) => items.iter().for_each(|item| defaults(item, found)),
+        FormKind::HashMap(ref entries) => {
+            for (key, target) in entries {
+                match (&key.kind, &target.kind) {
+                    (FormKind::Keyword(sym::OR), FormKind::HashMap(listed)) => {
+                        for (name, default) in listed {
+                            if let Some(name) = name.as_symbol() {
+                                if found.iter().all(|(n, _)| *n != name) {
+                                    found.push((name, default.clone()));
+                                }
+                            }
+                        }
+                    }
+                    (FormKind::Keyword(_), _) => {}
+                    _ => defaults(key, found),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The elements of a special form after its name, padded with nil to `N` like the tree-walker
+/// does, or `None` if there are too many
+pub(crate) fn parts<const N: usize>(items: &[Form]) -> Option<[Form; N]> {
+    if items.len() > N + 1 {
+        return None;
+    }
+    Some(std::array::from_fn(|i| {
+        items.get(i + 1).cloned().unwrap_or_else(Form::nil)
+    }))
+}
+
+/// A body of several forms as a single form
+pub(crate) fn body(forms: &[Form]) -> Form {
+    match forms {
+        [] => Form::nil(),
+        [form] => form.clone(),
+        _ => Form::list(std::iter::once(Form::symbol(sym::DO)).chain(forms.iter().cloned())),
+    }
+}
+
+/// The state of analysis within one function, or within a top-level form
+struct Scope<'a> {
+    /// The environment macros are looked up in
+    env: &'a Env,
+    /// Locals in scope with their slots, innermost last
+    locals: Vec<(Ident, usize)>,
+    /// The number of slots the frame needs
+    slots: usize,
+    /// Whether `recur` has a target
+    recur: bool,
+    /// Whether a local environment surrounds the form, in which `def!` would define
+    nested: bool,
+    /// Names that the `let*` or `loop` bindings being analyzed are yet to bind
+    pending: Vec<Ident>,
+}
+
+impl<'a> Scope<'a> {
+    fn new(env: &'a Env, nested: bool) -> Scope<'a> {
+        Scope {
+            env,
+            locals: Vec::new(),
+            slots: 0,
+            recur: false,
+            nested,
+            pending: Vec::new(),
+        }
+    }
+
+    fn local(&self, name: Ident) -> Option<usize> {
+        self.locals
+            .iter()
+            .rev()
+            .find(|(local, _)| *local == name)
+            .map(|&(_, slot)| slot)
+    }
+
+    /// Allocate slots for the names bound by `pattern` and bring them into scope
+    fn binder(&mut self, pattern: &Form) -> Option<Binder> {
+        if let Some(name) = pattern.as_symbol() {
+            let slot = self.slots;
+            self.slots += 1;
+            self.locals.push((name, slot));
+            return Some(Binder::Slot(slot));
+        }
+        let mut names = Vec::new();
+        destructure::names(pattern, &mut names);
+        let slots = names
+            .into_iter()
+            .map(|name| {
+                let slot = self.slots;
+                self.slots += 1;
+                self.locals.push((name, slot));
+                (name, slot)
+            })
+            .collect();
+        let mut found = Vec::new();
+        defaults(pattern, &mut found);
+        let defaults = found
+            .into_iter()
+            .map(|(name, default)| Some((name, self.analyze(&default, false)?)))
+            .collect::<Option<_>>()?;
+        Some(Binder::Pattern(Box::new(Pattern {
+            form: pattern.clone(),
+            slots,
+            defaults,
+        })))
+    }
+
+    fn analyze(&mut self, form: &Form, tail: bool) -> Option<Code> {
+        match form.kind {
+            FormKind::Symbol(name) => Some(self.symbol(name)),
+            FormKind::List(ref items) if !items.is_empty() => self.list(form, items, tail),
+            FormKind::Vector(ref items) => {
+                let items = self.analyze_all(items)?;
+                let meta = form.meta.clone();
+                Some(Box::new(move |act| {
+                    let items = items
+                        .iter()
+                        .map(|code| value(code, act))
+                        .collect::<Result<Vec<_>>>()?;
+                    let evaluated = Form {
+                        kind: FormKind::Vector(items),
+                        meta: meta.clone(),
+                    };
+                    limits::allocate(&evaluated)?;
+                    Ok(Ret::Value(evaluated))
+                }))
+            }
+            FormKind::HashMap(ref entries) => {
+                let entries = entries
+                    .iter()
+                    .map(|(k, v)| Some((self.analyze(k, false)?, self.analyze(v, false)?)))
+                    .collect::<Option<Vec<_>>>()?;
+                let meta = form.meta.clone();
+                Some(Box::new(move |act| {
+                    // Forms are map keys, see the note on `impl Hash for Form`
+                    #[allow(clippy::mutable_key_type)]
+                    let entries = entries
+                        .iter()
+                        .map(|(k, v)| Ok((value(k, act)?, value(v, act)?)))
+                        .collect::<Result<_>>()?;
+                    let evaluated = Form {
+                        kind: FormKind::HashMap(entries),
+                        meta: meta.clone(),
+                    };
+                    limits::allocate(&evaluated)?;
+                    Ok(Ret::Value(evaluated))
+                }))
+            }
+            _ => Some(constant(form.clone())),
+        }
+    }
+
+    fn analyze_all(&mut self, forms: &[Form]) -> Option<Vec<Code>> {
+        forms.iter().map(|form| self.analyze(form, false)).collect()
+    }
+
+    fn symbol(&self, name: Ident) -> Code {
+        // Locals shadow dynamic vars, so only names that aren't local can take a binding
+        match self.local(name) {
+            Some(slot) => Box::new(move |act| Ok(Ret::Value(act.slots[slot].clone()))),
+            None => {
+                let cache = LookupCache::default();
+                Box::new(move |act| Ok(Ret::Value(act.env.get_cached(name, &cache)?)))
+            }
+        }
+    }
+
+    fn list(&mut self, form: &Form, items: &[Form], tail: bool) -> Option<Code> {
+        let head = items[0].as_symbol();
+        if head.is_some_and(|head| self.local(head).is_none()) {
+            if let Some(macro_) = eval::as_macro_call(form, self.env) {
+                let expanded = eval::expand_call(macro_, form.clone()).ok()?;
+                return self.analyze(&expanded, tail);
+            }
+        }
+        // Special forms registered from Rust are left to the tree-walker
+        if head.is_some_and(special::is_registered) {
+            return None;
+        }
+        match head {
+            Some(sym::DEF) => self.def(items),
+            Some(sym::DEFMACRO) => self.defmacro(items),
+            Some(sym::LET) => self.let_(items, tail),
+            Some(sym::DO) => self.do_(&items[1..], tail),
+            Some(sym::MATCH) => self.match_(items, tail),
+            Some(sym::IF) => self.if_(items, tail),
+            Some(sym::FN) => self.fn_(form),
+            Some(sym::EVAL) => {
+                let [arg] = parts(items)?;
+                let arg = self.analyze(&arg, false)?;
+                Some(Box::new(move |act| {
+                    let evaluated = value(&arg, act)?;
+                    crate::eval(evaluated, &mut act.env.root()).map(Ret::Value)
+                }))
+            }
+            Some(sym::NS) if !self.nested => {
+                let form = form.clone();
+                Some(Box::new(move |act| {
+                    namespace::ns(form.clone(), &mut act.env).map(Ret::Value)
+                }))
+            }
+            Some(sym::NS) => None,
+            Some(sym::REQUIRE) => {
+                let form = form.clone();
+                Some(Box::new(move |act| {
+                    namespace::require(form.clone(), &mut act.env).map(Ret::Value)
+                }))
+            }
+            Some(sym::BINDING) => self.binding(items),
+            Some(sym::SET) => {
+                let [name, value_form] = parts(items)?;
+                let name = name.as_symbol()?;
+                let code = self.analyze(&value_form, false)?;
+                Some(Box::new(move |act| {
+                    let var = dynamic::resolve(name, &act.env)?;
+                    let evaluated = value(&code, act)?;
+                    dynamic::replace(var, evaluated, &act.env).map(Ret::Value)
+                }))
+            }
+            Some(sym::QUOTE) => {
+                let [quoted] = parts(items)?;
+                Some(constant(quoted))
+            }
+            Some(sym::QUASIQUOTE) => {
+                let [arg] = parts(items)?;
+                self.analyze(&eval::quasiquote_(arg).ok()?, tail)
+            }
+            Some(sym::QUASIQUOTEEXPAND) => {
+                let [arg] = parts(items)?;
+                Some(constant(eval::quasiquote_(arg).ok()?))
+            }
+            Some(name @ (sym::MACROEXPAND | sym::MACROEXPAND_1 | sym::MACROEXPAND_ALL)) => {
+                let [arg] = parts(items)?;
+                let expansion = Expansion::of(name)?;
+                Some(Box::new(move |act| {
+                    expansion.expand(arg.clone(), &act.env).map(Ret::Value)
+                }))
+            }
+            Some(sym::TRY) => self.try_(form),
+            Some(sym::LOOP) => self.loop_(items),
+            Some(sym::RECUR) => self.recur(items, tail),
+            _ => self.call(form, items),
+        }
+    }
+
+    fn call(&mut self, form: &Form, items: &[Form]) -> Option<Code> {
+        let f = self.analyze(&items[0], false)?;
+        let args = self.analyze_all(&items[1..])?;
+        let site = Site::new(items[0].as_symbol(), form.meta.clone());
+        Some(Box::new(move |act| {
+            limits::step()?;
+            interrupt::check()?;
+            let f = value(&f, act)?;
+            // Room for the locals of the function called, which uses the arguments as its frame
+            let mut evaluated = Vec::with_capacity(args.len() + FRAME_SPARE);
+            for code in &args {
+                evaluated.push(value(code, act)?);
+            }
+            Ok(Ret::Call(f, evaluated, Some(Rc::clone(&site))))
+        }))
+    }
+
+    fn def(&mut self, items: &[Form]) -> Option<Code> {
+        if self.nested {
+            return None;
+        }
+        let [symbol, value_form] = parts(items)?;
+        let code = self.analyze(&value_form, false)?;
+        Some(Box::new(move |act| {
+            let evaluated = value(&code, act)?;
+            eval::define(symbol.clone(), evaluated, &mut act.env).map(Ret::Value)
+        }))
+    }
+
+    fn defmacro(&mut self, items: &[Form]) -> Option<Code> {
+        if self.nested {
+            return None;
+        }
+        let [symbol, value_form] = parts(items)?;
+        let symbol = symbol.as_symbol()?;
+        let code = self.analyze(&value_form, false)?;
+        Some(Box::new(move |act| {
+            let evaluated = value(&code, act)?;
+            eval::define_macro(symbol, evaluated, &mut act.env).map(Ret::Value)
+        }))
+    }
+
+    /// Analyze `let*` or `loop` bindings, bringing each name into scope after its value
+    fn bindings(&mut self, bindings: &Form) -> Option<Vec<(Code, Binder)>> {
+        let bindings = bindings.as_slice()?;
+        if bindings.len() % 2 != 0 {
+            return None;
+        }
+        let pending = self.pending.len();
+        let mut analyzed = Vec::with_capacity(bindings.len() / 2);
+        for (i, pair) in bindings.chunks(2).enumerate() {
+            // Names bound by this pattern or the ones after it, which a closure created by the
+            // value would see in the tree-walker
+            for pattern in bindings[i * 2..].iter().step_by(2) {
+                destructure::names(pattern, &mut self.pending);
+            }
+            let code = self.analyze(&pair[1], false);
+            self.pending.truncate(pending);
+            analyzed.push((code?, self.binder(&pair[0])?));
+        }
+        self.pending.truncate(pending);
+        Some(analyzed)
+    }
+
+    /// Run `f` in a new local scope, dropping the names it brings into scope afterwards
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Option<T>) -> Option<T> {
+        let (locals, nested, recur) = (self.locals.len(), self.nested, self.recur);
+        self.nested = true;
+        let result = f(self);
+        self.locals.truncate(locals);
+        (self.nested, self.recur) = (nested, recur);
+        result
+    }
+
+    fn let_(&mut self, items: &[Form], tail: bool) -> Option<Code> {
+        let [bindings, body] = parts(items)?;
+        self.nested(|scope| {
+            let bindings = scope.bindings(&bindings)?;
+            let body = scope.analyze(&body, tail)?;
+            Some(Box::new(move |act: &mut Activation| {
+                for (code, binder) in &bindings {
+                    let evaluated = value(code, act)?;
+                    binder.bind(evaluated, act)?;
+                }
+                body(act)
+            }) as Code)
+        })
+    }
+
+    fn loop_(&mut self, items: &[Form]) -> Option<Code> {
+        let bindings = items.get(1)?;
+        let body_form = body(&items[2..]);
+        let locals = self
+            .locals
+            .iter()
+            .map(|&(name, _)| name)
+            .collect::<Vec<_>>();
+        eval::check_tail(&Form::list(items.to_vec()), false, self.env, &locals).ok()?;
+        self.nested(|scope| {
+            let bindings = scope.bindings(bindings)?;
+            scope.recur = true;
+            let body = scope.analyze(&body_form, true)?;
+            Some(Box::new(move |act: &mut Activation| {
+                for (code, binder) in &bindings {
+                    let evaluated = value(code, act)?;
+                    binder.bind(evaluated, act)?;
+                }
+                loop {
+                    match body(act)? {
+                        Ret::Recur(args) => {
+                            if args.len() != bindings.len() {
+                                return Err(Error::ArityMismatch {
+                                    found: args.len(),
+                                    expected: bindings.len().to_string(),
+                                });
+                            }
+                            for ((_, binder), arg) in bindings.iter().zip(args) {
+                                binder.bind(arg, act)?;
+                            }
+                        }
+                        ret => return Ok(ret),
+                    }
+                }
+            }) as Code)
+        })
+    }
+
+    fn recur(&mut self, items: &[Form], tail: bool) -> Option<Code> {
+        let args = self.analyze_all(&items[1..])?;
+        let error = match (self.recur, tail) {
+            (false, _) => "used outside of loop or fn*",
+            (true, false) => "can only be used in tail position",
+            (true, true) => {
+                return Some(Box::new(move |act| {
+                    limits::step()?;
+                    interrupt::check()?;
+                    let args = args
+                        .iter()
+                        .map(|code| value(code, act))
+                        .collect::<Result<Vec<_>>>()?;
+                    Ok(Ret::Recur(args))
+                }))
+            }
+        };
+        Some(Box::new(move |_| Err(Error::InvalidRecur(error))))
+    }
+
+    fn do_(&mut self, forms: &[Form], tail: bool) -> Option<Code> {
+        let Some((last, init)) = forms.split_last() else {
+            return Some(constant(Form::nil()));
+        };
+        let init = self.analyze_all(init)?;
+        let last = self.analyze(last, tail)?;
+        Some(Box::new(move |act| {
+            for code in &init {
+                value(code, act)?;
+            }
+            last(act)
+        }))
+    }
+
+    fn if_(&mut self, items: &[Form], tail: bool) -> Option<Code> {
+        let [predicate, on_true, on_false] = parts(items)?;
+        let predicate = self.analyze(&predicate, false)?;
+        let on_true = self.analyze(&on_true, tail)?;
+        let on_false = self.analyze(&on_false, tail)?;
+        Some(Box::new(move |act| {
+            if value(&predicate, act)?.is_truthy() {
+                on_true(act)
+            } else {
+                on_false(act)
+            }
+        }))
+    }
+
+    fn match_(&mut self, items: &[Form], tail: bool) -> Option<Code> {
+        let value_form = items.get(1)?;
+        let clauses = pattern::clauses(items.get(2..).unwrap_or_default()).ok()?;
+        let value_code = self.analyze(value_form, false)?;
+        let clauses = clauses
+            .into_iter()
+            .map(|clause| {
+                // The predicates are evaluated outside the scope of the names the pattern binds
+                let predicates = self.analyze_all(clause.pattern.predicates())?;
+                self.nested(|scope| {
+                    let slots = clause
+                        .pattern
+                        .names()
+                        .iter()
+                        .map(|&name| {
+                            let slot = scope.slots;
+                            scope.slots += 1;
+                            scope.locals.push((name, slot));
+                            slot
+                        })
+                        .collect();
+                    let guard = match clause.guard {
+                        Some(ref guard) => Some(scope.analyze(guard, false)?),
+                        None => None,
+                    };
+                    Some(CompiledClause {
+                        predicates,
+                        slots,
+                        guard,
+                        body: scope.analyze(&clause.body, tail)?,
+                        pattern: clause.pattern,
+                    })
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Box::new(move |act| {
+            let matched = value(&value_code, act)?;
+            for clause in &clauses {
+                let predicates = clause
+                    .predicates
+                    .iter()
+                    .map(|code| value(code, act))
+                    .collect::<Result<Vec<_>>>()?;
+                let Some(bindings) = clause.pattern.matches_with(&matched, &predicates)? else {
+                    continue;
+                };
+                for (bound, &slot) in bindings.into_values().zip(&clause.slots) {
+                    act.slots[slot] = bound;
+                }
+                if let Some(ref guard) = clause.guard {
+                    if !value(guard, act)?.is_truthy() {
+                        continue;
+                    }
+                }
+                return (clause.body)(act);
+            }
+            Err(Error::NoMatch(matched))
+        }))
+    }
+
+    fn fn_(&mut self, form: &Form) -> Option<Code> {
+        // The closure captures the values of the locals now, so it can't see names that the
+        // bindings being analyzed are yet to bind
+        if !self.pending.is_empty() && mentions(form, &self.pending) {
+            return None;
+        }
+        let mut captured: Vec<(Ident, usize)> = Vec::new();
+        for &(name, slot) in self.locals.iter().rev() {
+            if captured.iter().all(|&(n, _)| n != name) {
+                captured.push((name, slot));
+            }
+        }
+        let form = form.clone();
+        let cache = exec::Cache::default();
+        Some(Box::new(move |act| {
+            let env = if captured.is_empty() {
+                act.env.clone()
+            } else {
+                let mut env = Env::new_with(&act.env);
+                env.extend(
+                    captured
+                        .iter()
+                        .map(|&(name, slot)| (name, act.slots[slot].clone())),
+                );
+                env
+            };
+            eval::fn_(form.clone(), &env, Rc::clone(&cache)).map(Ret::Value)
+        }))
+    }
+
+    fn binding(&mut self, items: &[Form]) -> Option<Code> {
+        let bindings = items.get(1)?.as_slice()?;
+        if bindings.len() % 2 != 0 {
+            return None;
+        }
+        let bindings = bindings
+            .chunks(2)
+            .map(|pair| Some((pair[0].as_symbol()?, self.analyze(&pair[1], false)?)))
+            .collect::<Option<Vec<_>>>()?;
+        let body = self.analyze_all(&items[2..])?;
+        Some(Box::new(move |act| {
+            // All values are evaluated before any of the bindings take effect
+            let mut pushed = Vec::with_capacity(bindings.len());
+            for (name, code) in &bindings {
+                let var = dynamic::resolve(*name, &act.env)?;
+                pushed.push((var, value(code, act)?));
+            }
+            let _restore = dynamic::push(pushed, &act.env);
+            let mut result = Form::nil();
+            for code in &body {
+                result = value(code, act)?;
+            }
+            Ok(Ret::Value(result))
+        }))
+    }
+
+    fn try_(&mut self, form: &Form) -> Option<Code> {
+        let (body, catches, finally) = eval::parse_try(form.clone()).ok()?;
+        let body = self.analyze_all(&body)?;
+        let catches = catches
+            .into_iter()
+            .map(|catch| self.catch(catch))
+            .collect::<Option<Vec<_>>>()?;
+        let finally = match finally {
+            Some(finally) => Some(self.analyze(&finally, false)?),
+            None => None,
+        };
+        Some(Box::new(move |act| {
+            let result = body
+                .iter()
+                .try_fold(Form::nil(), |_, code| value(code, act))
+                .or_else(|err| {
+                    if matches!(err, Error::Interrupted) {
+                        return Err(err);
+                    }
+                    let thrown = err.to_form();
+                    let frames = trace::save();
+                    for catch in &catches {
+                        if catch.matches(&thrown, act)? {
+                            catch.binder.bind(thrown, act)?;
+                            let result = value(&catch.body, act)?;
+                            trace::clear();
+                            return Ok(result);
+                        }
+                    }
+                    trace::restore(frames);
+                    Err(err)
+                });
+            if let Some(ref finally) = finally {
+                let frames = trace::save();
+                value(finally, act)?;
+                trace::restore(frames);
+            }
+            result.map(Ret::Value)
+        }))
+    }
+
+    fn catch(&mut self, catch: Catch) -> Option<CompiledCatch> {
+        let filter = match catch.filter {
+            CatchFilter::All => CompiledFilter::All,
+            CatchFilter::Type(kind) => CompiledFilter::Type(kind),
+            CatchFilter::Predicate(ref predicate) => {
+                CompiledFilter::Predicate(self.analyze(predicate, false)?)
+            }
+        };
+        self.nested(|scope| {
+            let binder = scope.binder(&catch.pattern)?;
+            let body = scope.analyze(&catch.body, false)?;
+            Some(CompiledCatch {
+                filter,
+                binder,
+                body,
+            })
+        })
+    }
+}
+
+/// A `match` clause, with the slots of the names its pattern binds
+struct CompiledClause {
+    pattern: pattern::Pattern,
+    predicates: Vec<Code>,
+    slots: Vec<usize>,
+    guard: Option<Code>,
+    body: Code,
+}
+
+enum CompiledFilter {
+    All,
+    Type(Ident),
+    Predicate(Code),
+}
+
+struct CompiledCatch {
+    filter: CompiledFilter,
+    binder: Binder,
+    body: Code,
+}
+
+impl CompiledCatch {
+    fn matches(&self, thrown: &Form, act: &mut Activation) -> Result<bool> {
+        match self.filter {
+            CompiledFilter::All => Ok(true),
+            CompiledFilter::Type(kind) => Ok(eval::has_type(thrown, kind)),
+            CompiledFilter::Predicate(ref predicate) => {
+                let predicate = value(predicate, act)?;
+                Ok(predicate.call(Form::list([thrown.clone()]))?.is_truthy())
+            }
+        }
+    }
+}
+
+/// Whether any of `names` appears as a symbol anywhere in `form`
+pub(crate) fn mentions(form: &Form, names: &[Ident]) -> bool {
+    match form.kind {
+        FormKind::Symbol(name) => names.contains(&name),
+        FormKind::List(ref items) | FormKind::Vector(ref items) => {
+            items.iter().any(|item| mentions(item, names))
+        }
+        FormKind::HashMap(ref entries) => entries
+            .iter()
+            .any(|(k, v)| mentions(k, names) || mentions(v, names)),
+        _ => false,
+    }
+}
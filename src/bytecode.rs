@@ -0,0 +1,904 @@
+//! Compilation of forms to bytecode for the [`vm`](crate::vm)
+//!
+//! A form compiles to a [`Chunk`]: a flat list of [`Op`]s and the tables they index into. Every
+//! op is eight bytes; constants, global names, call sites and everything else too big for an
+//! operand live in the tables. Like the [`analyze`](crate::analyze) backend, compilation expands
+//! macros once, resolves special forms, and gives every local a numbered slot in the frame of
+//! its function, and a function is compiled on its first call. Closures capture the values of
+//! the locals in scope when they are created.
+//!
+//! Compilation declines the same forms as analysis does, leaving them to the tree-walker:
+//! `def!`, `defmacro!` and `ns` inside a local scope, closures in `let*` or `loop` bindings that
+//! may refer to names bound after them, and forms that fail to compile.
+
+use std::{fmt, rc::Rc};
+
+use crate::{
+    analyze::{body, defaults, mentions, parts},
+    destructure,
+    env::LookupCache,
+    eval::{self, Catch, CatchFilter, Expansion},
+    exec,
+    form::{Arity, Ident, UserFn},
+    intern::sym,
+    pattern, special,
+    trace::Site,
+    Env, Error, Form, FormKind,
+};
+
+/// One instruction. Operands index into the tables of the chunk unless noted otherwise.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Op {
+    /// Push a constant
+    Const(u32),
+    Nil,
+    /// Push the value of a named local
+    Local(u32),
+    /// Push the value of a slot
+    Load(u32),
+    /// Pop into a slot
+    Store(u32),
+    /// Pop and destructure into the slots of a pattern
+    Bind(u32),
+    /// Push the value of a name that isn't local
+    Global(u32),
+    Pop,
+    /// Continue at an instruction
+    Jump(u32),
+    /// Pop, and continue at an instruction if the value is falsy
+    JumpIfFalse(u32),
+    /// Call the function below the arguments of a call site, pushing its value
+    Call(u32),
+    /// Call the function below the arguments of a call site in place of the current one
+    TailCall(u32),
+    Return,
+    /// Pop the arguments of `recur` into the slots of its target and jump there
+    Recur(u32),
+    /// Build a vector from the values on top of the stack
+    Vector(u32),
+    /// Build a map from the keys and values on top of the stack
+    HashMap(u32),
+    /// Create a closure from a `fn*` form
+    Closure(u32),
+    /// Pop and define the symbol or pattern in a constant
+    Def(u32),
+    /// Pop and define a macro with the name in the operand
+    DefMacro(Ident),
+    /// Pop and evaluate in the root environment
+    Eval,
+    /// Run the `ns` form in a constant
+    Ns(u32),
+    /// Run the `require` form in a constant
+    Require(u32),
+    /// Expand the form in a constant as far as the operand says
+    MacroExpand(u32, Expansion),
+    /// Fail unless the operand is a dynamic var
+    CheckDynamic(Ident),
+    /// Pop the value for the innermost binding of the dynamic var in the operand
+    SetDynamic(Ident),
+    /// Pop values for the dynamic vars of a `binding` form and bind them
+    PushBindings(u32),
+    /// Restore the dynamic vars bound by the last `PushBindings`
+    PopBindings,
+    /// Handle errors from here on by pushing the thrown value and continuing at an instruction
+    Try(u32),
+    /// Like `Try`, for a `finally` body, which also runs when evaluation is interrupted
+    TryFinally(u32),
+    /// Stop handling errors with the innermost handler
+    EndTry,
+    /// Push whether the thrown value on top of the stack has the `:type` in the operand
+    CatchType(Ident),
+    /// Pop a predicate and push whether it accepts the thrown value below it
+    CatchTest,
+    /// Forget the error being handled
+    Caught,
+    /// Raise the error being handled again
+    Rethrow,
+    /// Raise an error
+    Fail(u32),
+    /// Pop the values of the predicates of a `match` pattern and the value below them, and
+    /// push whether the pattern matches, storing what it binds in its slots if it does
+    Match(u32),
+    /// Pop the value that no clause of a `match` matched and raise an error
+    NoMatch,
+}
+
+// Operands are kept small so that code stays compact
+const _: () = assert!(std::mem::size_of::<Op>() == 8);
+
+/// A call and where it was made, for the stack trace
+pub(crate) struct CallSite {
+    pub(crate) argc: usize,
+    pub(crate) site: Rc<Site>,
+}
+
+/// A vector or map literal, with the number of elements or entries it is built from
+pub(crate) struct Literal {
+    pub(crate) len: usize,
+    pub(crate) meta: Option<Box<Form>>,
+}
+
+/// A `fn*` form, with the slots of the locals its closures capture
+pub(crate) struct ClosureSite {
+    pub(crate) form: Form,
+    pub(crate) captured: Vec<(Ident, usize)>,
+    pub(crate) cache: exec::Cache,
+}
+
+/// A destructuring pattern, with the slots of the names it binds and the compiled code of its
+/// `:or` defaults, which run in the frame of the pattern
+pub(crate) struct Pattern {
+    pub(crate) form: Form,
+    pub(crate) slots: Vec<(Ident, usize)>,
+    pub(crate) defaults: Vec<(Ident, Rc<Chunk>)>,
+}
+
+/// The pattern of a `match` clause, with the slots of the names it binds
+pub(crate) struct MatchSite {
+    pub(crate) pattern: pattern::Pattern,
+    pub(crate) slots: Vec<usize>,
+}
+
+/// Where `recur` jumps to: the slots it rebinds, in order, and the instruction that binds them
+pub(crate) struct RecurTarget {
+    pub(crate) slots: Vec<usize>,
+    pub(crate) target: usize,
+}
+
+/// An error raised by `Op::Fail`
+pub(crate) enum Failure {
+    Recur(&'static str),
+    RecurArity { found: usize, expected: usize },
+}
+
+impl Failure {
+    pub(crate) fn to_error(&self) -> Error {
+        match *self {
+            Failure::Recur(message) => Error::InvalidRecur(message),
+            Failure::RecurArity { found, expected } => Error::ArityMismatch {
+                found,
+                expected: expected.to_string(),
+            },
+        }
+    }
+}
+
+/// Compiled code and the tables it refers to
+#[derive(Default)]
+pub(crate) struct Chunk {
+    pub(crate) code: Vec<Op>,
+    pub(crate) constants: Vec<Form>,
+    pub(crate) globals: Vec<(Ident, LookupCache)>,
+    pub(crate) sites: Vec<CallSite>,
+    pub(crate) literals: Vec<Literal>,
+    pub(crate) closures: Vec<ClosureSite>,
+    pub(crate) patterns: Vec<Pattern>,
+    pub(crate) matches: Vec<MatchSite>,
+    pub(crate) recurs: Vec<RecurTarget>,
+    pub(crate) bindings: Vec<Vec<Ident>>,
+    pub(crate) failures: Vec<Failure>,
+    /// The name of each slot of the frame, `None` for slots that hold unnamed values
+    pub(crate) names: Vec<Option<Ident>>,
+}
+
+impl Chunk {
+    /// The number of slots the frame needs
+    pub(crate) fn slots(&self) -> usize {
+        self.names.len()
+    }
+}
+
+impl fmt::Display for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (at, op) in self.code.iter().enumerate() {
+            write!(f, "{at:04} {op:?}")?;
+            match *op {
+                Op::Const(i) | Op::Def(i) | Op::Ns(i) | Op::Require(i) | Op::MacroExpand(i, _) => {
+                    write!(f, " ; {:?}", self.constants[i as usize])?
+                }
+                Op::Global(i) => write!(f, " ; {}", self.globals[i as usize].0)?,
+                Op::Local(i) => {
+                    if let Some(name) = self.names[i as usize] {
+                        write!(f, " ; {name}")?
+                    }
+                }
+                Op::Call(i) | Op::TailCall(i) => {
+                    write!(f, " ; {} args", self.sites[i as usize].argc)?
+                }
+                Op::Closure(i) => write!(f, " ; {:?}", self.closures[i as usize].form)?,
+                _ => {}
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// One compiled arity of a function. Its arguments take the first slots of the frame, with the
+/// rest arguments as a list after the positional ones.
+pub(crate) struct CompiledArity {
+    pub(crate) params: usize,
+    pub(crate) rest: bool,
+    pub(crate) chunk: Rc<Chunk>,
+}
+
+/// The compiled arities of a function, shared by the closures created by one `fn*` form
+pub(crate) struct Function {
+    pub(crate) arities: Vec<CompiledArity>,
+}
+
+impl Function {
+    pub(crate) fn compile(user_fn: &UserFn) -> Option<Function> {
+        let arities = user_fn
+            .arities
+            .iter()
+            .map(|arity| Compiler::new(&user_fn.env, true).arity(arity))
+            .collect::<Option<_>>()?;
+        Some(Function { arities })
+    }
+}
+
+/// Compile a top-level form, or return `None` to leave it to the tree-walker
+pub(crate) fn compile(form: &Form, env: &Env) -> Option<Chunk> {
+    let mut compiler = Compiler::new(env, false);
+    compiler.compile(form, Position::Tail)?;
+    compiler.emit(Op::Return);
+    Some(compiler.finish())
+}
+
+/// A slot holding a destructured `loop` value, and the pattern binding it
+type Rebind = (usize, u32);
+
+/// How the value of a form is used
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Position {
+    /// As an intermediate value
+    Inner,
+    /// As the value of a `loop` that isn't in tail position, so `recur` can jump but calls
+    /// return to the function
+    Loop,
+    /// As the value of the function, so calls can replace its frame
+    Tail,
+}
+
+/// Where a binding form stores its value
+enum Binder {
+    Slot(usize),
+    Pattern(u32),
+}
+
+/// The state of compiling one function arity, or a top-level form
+struct Compiler<'a> {
+    /// The environment macros are looked up in
+    env: &'a Env,
+    chunk: Chunk,
+    /// The name of every slot allocated so far, shared by the chunks of `:or` defaults
+    names: Vec<Option<Ident>>,
+    /// Locals in scope with their slots, innermost last
+    locals: Vec<(Ident, usize)>,
+    /// The innermost `loop` or function that `recur` jumps to, in the recur table
+    recur: Option<u32>,
+    /// Whether a local environment surrounds the form, in which `def!` would define
+    nested: bool,
+    /// Names that the `let*` or `loop` bindings being compiled are yet to bind
+    pending: Vec<Ident>,
+}
+
+fn index(len: usize) -> u32 {
+    u32::try_from(len).expect("chunk table too large")
+}
+
+impl<'a> Compiler<'a> {
+    fn new(env: &'a Env, nested: bool) -> Compiler<'a> {
+        Compiler {
+            env,
+            chunk: Chunk::default(),
+            names: Vec::new(),
+            locals: Vec::new(),
+            recur: None,
+            nested,
+            pending: Vec::new(),
+        }
+    }
+
+    fn finish(mut self) -> Chunk {
+        self.chunk.names = self.names;
+        self.chunk
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        self.chunk.code.push(op);
+        self.chunk.code.len() - 1
+    }
+
+    fn here(&self) -> u32 {
+        index(self.chunk.code.len())
+    }
+
+    /// Point the jump or handler at `at` to the next instruction
+    fn patch(&mut self, at: usize) {
+        let here = self.here();
+        match self.chunk.code[at] {
+            Op::Jump(ref mut target)
+            | Op::JumpIfFalse(ref mut target)
+            | Op::Try(ref mut target)
+            | Op::TryFinally(ref mut target) => *target = here,
+            op => unreachable!("patching {op:?}"),
+        }
+    }
+
+    fn constant(&mut self, form: Form) -> u32 {
+        self.chunk.constants.push(form);
+        index(self.chunk.constants.len() - 1)
+    }
+
+    fn fail(&mut self, failure: Failure) {
+        self.chunk.failures.push(failure);
+        self.emit(Op::Fail(index(self.chunk.failures.len() - 1)));
+    }
+
+    fn slot(&mut self, name: Option<Ident>) -> usize {
+        self.names.push(name);
+        self.names.len() - 1
+    }
+
+    fn local(&self, name: Ident) -> Option<usize> {
+        self.locals
+            .iter()
+            .rev()
+            .find(|(local, _)| *local == name)
+            .map(|&(_, slot)| slot)
+    }
+
+    fn arity(mut self, arity: &Arity) -> Option<CompiledArity> {
+        let params = arity
+            .binds
+            .iter()
+            .chain(&arity.bind_rest)
+            .collect::<Vec<_>>();
+        for param in &params {
+            self.slot(param.as_symbol());
+        }
+        // Destructuring parameters are bound from their argument by the first instructions, which
+        // `recur` jumps back to
+        for (slot, param) in params.iter().enumerate() {
+            match param.as_symbol() {
+                Some(name) => self.locals.push((name, slot)),
+                None => {
+                    let binder = self.binder(param)?;
+                    self.emit(Op::Load(index(slot)));
+                    self.store(&binder);
+                }
+            }
+        }
+        self.chunk.recurs.push(RecurTarget {
+            slots: (0..params.len()).collect(),
+            target: 0,
+        });
+        self.recur = Some(0);
+        self.compile(&arity.body, Position::Tail)?;
+        self.emit(Op::Return);
+        Some(CompiledArity {
+            params: arity.binds.len(),
+            rest: arity.bind_rest.is_some(),
+            chunk: Rc::new(self.finish()),
+        })
+    }
+
+    /// Allocate slots for the names bound by `pattern` and bring them into scope
+    fn binder(&mut self, pattern: &Form) -> Option<Binder> {
+        if let Some(name) = pattern.as_symbol() {
+            let slot = self.slot(Some(name));
+            self.locals.push((name, slot));
+            return Some(Binder::Slot(slot));
+        }
+        let mut names = Vec::new();
+        destructure::names(pattern, &mut names);
+        let slots = names
+            .into_iter()
+            .map(|name| {
+                let slot = self.slot(Some(name));
+                self.locals.push((name, slot));
+                (name, slot)
+            })
+            .collect();
+        let mut found = Vec::new();
+        defaults(pattern, &mut found);
+        let defaults = found
+            .into_iter()
+            .map(|(name, default)| Some((name, self.default(&default)?)))
+            .collect::<Option<_>>()?;
+        self.chunk.patterns.push(Pattern {
+            form: pattern.clone(),
+            slots,
+            defaults,
+        });
+        Some(Binder::Pattern(index(self.chunk.patterns.len() - 1)))
+    }
+
+    /// Compile an `:or` default into a chunk of its own, which runs in the current frame
+    fn default(&mut self, form: &Form) -> Option<Rc<Chunk>> {
+        let outer = std::mem::take(&mut self.chunk);
+        let compiled = self.compile(form, Position::Inner);
+        self.emit(Op::Return);
+        let mut chunk = std::mem::replace(&mut self.chunk, outer);
+        compiled?;
+        chunk.names = self.names.clone();
+        Some(Rc::new(chunk))
+    }
+
+    /// Pop the value on top of the stack into `binder`
+    fn store(&mut self, binder: &Binder) {
+        match *binder {
+            Binder::Slot(slot) => self.emit(Op::Store(index(slot))),
+            Binder::Pattern(pattern) => self.emit(Op::Bind(pattern)),
+        };
+    }
+
+    fn compile(&mut self, form: &Form, position: Position) -> Option<()> {
+        match form.kind {
+            FormKind::Symbol(name) => {
+                self.symbol(name);
+                Some(())
+            }
+            FormKind::List(ref items) if !items.is_empty() => self.list(form, items, position),
+            FormKind::Vector(ref items) => {
+                self.compile_all(items)?;
+                let literal = self.literal(items.len(), form);
+                self.emit(Op::Vector(literal));
+                Some(())
+            }
+            FormKind::HashMap(ref entries) => {
+                for (key, value) in entries {
+                    self.compile(key, Position::Inner)?;
+                    self.compile(value, Position::Inner)?;
+                }
+                let literal = self.literal(entries.len(), form);
+                self.emit(Op::HashMap(literal));
+                Some(())
+            }
+            _ => {
+                self.quote(form.clone());
+                Some(())
+            }
+        }
+    }
+
+    fn compile_all(&mut self, forms: &[Form]) -> Option<()> {
+        forms
+            .iter()
+            .try_for_each(|form| self.compile(form, Position::Inner))
+    }
+
+    fn literal(&mut self, len: usize, form: &Form) -> u32 {
+        self.chunk.literals.push(Literal {
+            len,
+            meta: form.meta.clone(),
+        });
+        index(self.chunk.literals.len() - 1)
+    }
+
+    fn quote(&mut self, form: Form) {
+        if form.is_nil() {
+            self.emit(Op::Nil);
+        } else {
+            let constant = self.constant(form);
+            self.emit(Op::Const(constant));
+        }
+    }
+
+    fn symbol(&mut self, name: Ident) {
+        match self.local(name) {
+            Some(slot) => self.emit(Op::Local(index(slot))),
+            None => {
+                self.chunk.globals.push((name, LookupCache::default()));
+                self.emit(Op::Global(index(self.chunk.globals.len() - 1)))
+            }
+        };
+    }
+
+    fn list(&mut self, form: &Form, items: &[Form], position: Position) -> Option<()> {
+        let head = items[0].as_symbol();
+        if head.is_some_and(|head| self.local(head).is_none()) {
+            if let Some(macro_) = eval::as_macro_call(form, self.env) {
+                let expanded = eval::expand_call(macro_, form.clone()).ok()?;
+                return self.compile(&expanded, position);
+            }
+        }
+        // Special forms registered from Rust are left to the tree-walker
+        if head.is_some_and(special::is_registered) {
+            return None;
+        }
+        match head {
+            Some(sym::DEF) => self.def(items),
+            Some(sym::DEFMACRO) => self.defmacro(items),
+            Some(sym::LET) => self.let_(items, position),
+            Some(sym::DO) => self.do_(&items[1..], position),
+            Some(sym::MATCH) => self.match_(items, position),
+            Some(sym::IF) => self.if_(items, position),
+            Some(sym::FN) => self.fn_(form),
+            Some(sym::EVAL) => {
+                let [arg] = parts(items)?;
+                self.compile(&arg, Position::Inner)?;
+                self.emit(Op::Eval);
+                Some(())
+            }
+            Some(sym::NS) if !self.nested => {
+                let constant = self.constant(form.clone());
+                self.emit(Op::Ns(constant));
+                Some(())
+            }
+            Some(sym::NS) => None,
+            Some(sym::REQUIRE) => {
+                let constant = self.constant(form.clone());
+                self.emit(Op::Require(constant));
+                Some(())
+            }
+            Some(sym::BINDING) => self.binding(items),
+            Some(sym::SET) => {
+                let [name, value] = parts(items)?;
+                let name = name.as_symbol()?;
+                self.emit(Op::CheckDynamic(name));
+                self.compile(&value, Position::Inner)?;
+                self.emit(Op::SetDynamic(name));
+                Some(())
+            }
+            Some(sym::QUOTE) => {
+                let [quoted] = parts(items)?;
+                self.quote(quoted);
+                Some(())
+            }
+            Some(sym::QUASIQUOTE) => {
+                let [arg] = parts(items)?;
+                self.compile(&eval::quasiquote_(arg).ok()?, position)
+            }
+            Some(sym::QUASIQUOTEEXPAND) => {
+                let [arg] = parts(items)?;
+                self.quote(eval::quasiquote_(arg).ok()?);
+                Some(())
+            }
+            Some(name @ (sym::MACROEXPAND | sym::MACROEXPAND_1 | sym::MACROEXPAND_ALL)) => {
+                let [arg] = parts(items)?;
+                let expansion = Expansion::of(name)?;
+                let constant = self.constant(arg);
+                self.emit(Op::MacroExpand(constant, expansion));
+                Some(())
+            }
+            Some(sym::TRY) => self.try_(form),
+            Some(sym::LOOP) => self.loop_(items, position),
+            Some(sym::RECUR) => self.recur(items, position),
+            _ => self.call(form, items, position),
+        }
+    }
+
+    fn call(&mut self, form: &Form, items: &[Form], position: Position) -> Option<()> {
+        self.compile_all(items)?;
+        self.chunk.sites.push(CallSite {
+            argc: items.len() - 1,
+            site: Site::new(items[0].as_symbol(), form.meta.clone()),
+        });
+        let site = index(self.chunk.sites.len() - 1);
+        self.emit(match position {
+            Position::Tail => Op::TailCall(site),
+            _ => Op::Call(site),
+        });
+        Some(())
+    }
+
+    fn def(&mut self, items: &[Form]) -> Option<()> {
+        if self.nested {
+            return None;
+        }
+        let [symbol, value] = parts(items)?;
+        self.compile(&value, Position::Inner)?;
+        let constant = self.constant(symbol);
+        self.emit(Op::Def(constant));
+        Some(())
+    }
+
+    fn defmacro(&mut self, items: &[Form]) -> Option<()> {
+        if self.nested {
+            return None;
+        }
+        let [symbol, value] = parts(items)?;
+        let symbol = symbol.as_symbol()?;
+        self.compile(&value, Position::Inner)?;
+        self.emit(Op::DefMacro(symbol));
+        Some(())
+    }
+
+    /// Compile `let*` or `loop` bindings, bringing each name into scope after its value.
+    /// Destructured values are kept in a slot of their own, so `recur` can bind them again;
+    /// returns the slot of each binding, and those slots with their patterns.
+    fn bindings(&mut self, bindings: &Form) -> Option<(Vec<usize>, Vec<Rebind>)> {
+        let bindings = bindings.as_slice()?;
+        if bindings.len() % 2 != 0 {
+            return None;
+        }
+        let pending = self.pending.len();
+        let mut slots = Vec::with_capacity(bindings.len() / 2);
+        let mut patterns = Vec::new();
+        for (i, pair) in bindings.chunks(2).enumerate() {
+            // Names bound by this pattern or the ones after it, which a closure created by the
+            // value would see in the tree-walker
+            for pattern in bindings[i * 2..].iter().step_by(2) {
+                destructure::names(pattern, &mut self.pending);
+            }
+            let compiled = self.compile(&pair[1], Position::Inner);
+            self.pending.truncate(pending);
+            compiled?;
+            if pair[0].is_symbol() {
+                let Binder::Slot(slot) = self.binder(&pair[0])? else {
+                    unreachable!("symbols bind to a slot")
+                };
+                self.emit(Op::Store(index(slot)));
+                slots.push(slot);
+            } else {
+                let slot = self.slot(None);
+                self.emit(Op::Store(index(slot)));
+                let Binder::Pattern(pattern) = self.binder(&pair[0])? else {
+                    unreachable!("patterns bind through the pattern table")
+                };
+                self.emit(Op::Load(index(slot)));
+                self.emit(Op::Bind(pattern));
+                slots.push(slot);
+                patterns.push((slot, pattern));
+            }
+        }
+        Some((slots, patterns))
+    }
+
+    /// Run `f` in a new local scope, dropping the names it brings into scope afterwards
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Option<T>) -> Option<T> {
+        let (locals, nested, recur) = (self.locals.len(), self.nested, self.recur);
+        self.nested = true;
+        let result = f(self);
+        self.locals.truncate(locals);
+        (self.nested, self.recur) = (nested, recur);
+        result
+    }
+
+    fn let_(&mut self, items: &[Form], position: Position) -> Option<()> {
+        let [bindings, body] = parts(items)?;
+        self.nested(|compiler| {
+            compiler.bindings(&bindings)?;
+            compiler.compile(&body, position)
+        })
+    }
+
+    fn loop_(&mut self, items: &[Form], position: Position) -> Option<()> {
+        let bindings = items.get(1)?;
+        let body_form = body(&items[2..]);
+        let locals = self
+            .locals
+            .iter()
+            .map(|&(name, _)| name)
+            .collect::<Vec<_>>();
+        eval::check_tail(&Form::list(items.to_vec()), false, self.env, &locals).ok()?;
+        self.nested(|compiler| {
+            let (slots, patterns) = compiler.bindings(bindings)?;
+            // `recur` binds the destructured values again before continuing with the body
+            let skip = (!patterns.is_empty()).then(|| compiler.emit(Op::Jump(0)));
+            let target = compiler.here() as usize;
+            for (slot, pattern) in patterns {
+                compiler.emit(Op::Load(index(slot)));
+                compiler.emit(Op::Bind(pattern));
+            }
+            if let Some(skip) = skip {
+                compiler.patch(skip);
+            }
+            compiler.chunk.recurs.push(RecurTarget { slots, target });
+            compiler.recur = Some(index(compiler.chunk.recurs.len() - 1));
+            let position = match position {
+                Position::Tail => Position::Tail,
+                _ => Position::Loop,
+            };
+            compiler.compile(&body_form, position)
+        })
+    }
+
+    fn recur(&mut self, items: &[Form], position: Position) -> Option<()> {
+        let recur = match (self.recur, position) {
+            (None, _) => Err("used outside of loop or fn*"),
+            (Some(_), Position::Inner) => Err("can only be used in tail position"),
+            (Some(recur), _) => Ok(recur),
+        };
+        match recur {
+            Ok(recur) => {
+                self.compile_all(&items[1..])?;
+                let found = items.len() - 1;
+                let expected = self.chunk.recurs[recur as usize].slots.len();
+                if found == expected {
+                    self.emit(Op::Recur(recur));
+                } else {
+                    self.fail(Failure::RecurArity { found, expected });
+                }
+            }
+            Err(message) => {
+                // The arguments are never evaluated, but must still compile
+                self.fail(Failure::Recur(message));
+                self.compile_all(&items[1..])?;
+            }
+        }
+        Some(())
+    }
+
+    /// Compile a body, leaving the value of its last form
+    fn body(&mut self, forms: &[Form], position: Position) -> Option<()> {
+        let Some((last, init)) = forms.split_last() else {
+            self.emit(Op::Nil);
+            return Some(());
+        };
+        for form in init {
+            self.compile(form, Position::Inner)?;
+            self.emit(Op::Pop);
+        }
+        self.compile(last, position)
+    }
+
+    fn do_(&mut self, forms: &[Form], position: Position) -> Option<()> {
+        self.body(forms, position)
+    }
+
+    fn if_(&mut self, items: &[Form], position: Position) -> Option<()> {
+        let [predicate, on_true, on_false] = parts(items)?;
+        self.compile(&predicate, Position::Inner)?;
+        let to_false = self.emit(Op::JumpIfFalse(0));
+        self.compile(&on_true, position)?;
+        let to_end = self.emit(Op::Jump(0));
+        self.patch(to_false);
+        self.compile(&on_false, position)?;
+        self.patch(to_end);
+        Some(())
+    }
+
+    fn match_(&mut self, items: &[Form], position: Position) -> Option<()> {
+        let value = items.get(1)?;
+        let clauses = pattern::clauses(items.get(2..).unwrap_or_default()).ok()?;
+        self.compile(value, Position::Inner)?;
+        let matched = self.slot(None);
+        self.emit(Op::Store(index(matched)));
+        let mut to_end = Vec::new();
+        for clause in clauses {
+            self.emit(Op::Load(index(matched)));
+            // The predicates are evaluated outside the scope of the names the pattern binds
+            self.compile_all(clause.pattern.predicates())?;
+            let to_next = self.nested(|compiler| {
+                let slots = clause
+                    .pattern
+                    .names()
+                    .iter()
+                    .map(|&name| {
+                        let slot = compiler.slot(Some(name));
+                        compiler.locals.push((name, slot));
+                        slot
+                    })
+                    .collect();
+                compiler.chunk.matches.push(MatchSite {
+                    pattern: clause.pattern,
+                    slots,
+                });
+                compiler.emit(Op::Match(index(compiler.chunk.matches.len() - 1)));
+                let mut to_next = vec![compiler.emit(Op::JumpIfFalse(0))];
+                if let Some(ref guard) = clause.guard {
+                    compiler.compile(guard, Position::Inner)?;
+                    to_next.push(compiler.emit(Op::JumpIfFalse(0)));
+                }
+                compiler.compile(&clause.body, position)?;
+                Some(to_next)
+            })?;
+            to_end.push(self.emit(Op::Jump(0)));
+            for jump in to_next {
+                self.patch(jump);
+            }
+        }
+        self.emit(Op::Load(index(matched)));
+        self.emit(Op::NoMatch);
+        for jump in to_end {
+            self.patch(jump);
+        }
+        Some(())
+    }
+
+    fn fn_(&mut self, form: &Form) -> Option<()> {
+        // The closure captures the values of the locals now, so it can't see names that the
+        // bindings being compiled are yet to bind
+        if !self.pending.is_empty() && mentions(form, &self.pending) {
+            return None;
+        }
+        let mut captured: Vec<(Ident, usize)> = Vec::new();
+        for &(name, slot) in self.locals.iter().rev() {
+            if captured.iter().all(|&(n, _)| n != name) {
+                captured.push((name, slot));
+            }
+        }
+        self.chunk.closures.push(ClosureSite {
+            form: form.clone(),
+            captured,
+            cache: exec::Cache::default(),
+        });
+        self.emit(Op::Closure(index(self.chunk.closures.len() - 1)));
+        Some(())
+    }
+
+    fn binding(&mut self, items: &[Form]) -> Option<()> {
+        let bindings = items.get(1)?.as_slice()?;
+        if bindings.len() % 2 != 0 {
+            return None;
+        }
+        let mut names = Vec::with_capacity(bindings.len() / 2);
+        for pair in bindings.chunks(2) {
+            let name = pair[0].as_symbol()?;
+            self.emit(Op::CheckDynamic(name));
+            self.compile(&pair[1], Position::Inner)?;
+            names.push(name);
+        }
+        self.chunk.bindings.push(names);
+        self.emit(Op::PushBindings(index(self.chunk.bindings.len() - 1)));
+        self.body(&items[2..], Position::Inner)?;
+        self.emit(Op::PopBindings);
+        Some(())
+    }
+
+    fn try_(&mut self, form: &Form) -> Option<()> {
+        let (body, catches, finally) = eval::parse_try(form.clone()).ok()?;
+        let finally_handler = finally.is_some().then(|| self.emit(Op::TryFinally(0)));
+        let catch_handler = (!catches.is_empty()).then(|| self.emit(Op::Try(0)));
+        self.body(&body, Position::Inner)?;
+        if let Some(catch_handler) = catch_handler {
+            self.emit(Op::EndTry);
+            let to_end = self.emit(Op::Jump(0));
+            // The thrown value is on top of the stack
+            self.patch(catch_handler);
+            let mut to_ends = vec![to_end];
+            for catch in catches {
+                to_ends.push(self.catch(catch)?);
+            }
+            self.emit(Op::Rethrow);
+            for to_end in to_ends {
+                self.patch(to_end);
+            }
+        }
+        if let (Some(finally_handler), Some(finally)) = (finally_handler, finally) {
+            self.emit(Op::EndTry);
+            self.compile(&finally, Position::Inner)?;
+            self.emit(Op::Pop);
+            let to_end = self.emit(Op::Jump(0));
+            self.patch(finally_handler);
+            self.emit(Op::Pop);
+            self.compile(&finally, Position::Inner)?;
+            self.emit(Op::Pop);
+            self.emit(Op::Rethrow);
+            self.patch(to_end);
+        }
+        Some(())
+    }
+
+    /// Compile a `catch*` clause, which runs with the thrown value on top of the stack, and
+    /// return the jump to the end of the `try*` form that its body finishes with
+    fn catch(&mut self, catch: Catch) -> Option<usize> {
+        let to_next = match catch.filter {
+            CatchFilter::All => None,
+            CatchFilter::Type(kind) => {
+                self.emit(Op::CatchType(kind));
+                Some(self.emit(Op::JumpIfFalse(0)))
+            }
+            CatchFilter::Predicate(ref predicate) => {
+                self.compile(predicate, Position::Inner)?;
+                self.emit(Op::CatchTest);
+                Some(self.emit(Op::JumpIfFalse(0)))
+            }
+        };
+        self.nested(|compiler| {
+            let binder = compiler.binder(&catch.pattern)?;
+            compiler.store(&binder);
+            compiler.compile(&catch.body, Position::Inner)
+        })?;
+        self.emit(Op::Caught);
+        let to_end = self.emit(Op::Jump(0));
+        if let Some(to_next) = to_next {
+            self.patch(to_next);
+        }
+        Some(to_end)
+    }
+}
@@ -1,8 +1,14 @@
-use std::collections::HashMap;
+use std::{
+    any::Any,
+    collections::{BTreeMap, HashMap, HashSet},
+    hash::Hash,
+    ops::Deref,
+    rc::Rc,
+};
 
 use crate::{
     form::{Atom, Ident},
-    Form, FormKind, Result,
+    Error, Form, FormKind, Result,
 };
 
 #[derive(Debug)]
@@ -24,6 +30,51 @@ impl From<Rest> for Form {
     }
 }
 
+fn wrong_type(expected: &'static str, found: Form) -> Error {
+    Error::WrongType { expected, found }
+}
+
+/// Convert the element at `position` of a sequence, recording the position on failure
+fn convert_at<T, E>(position: usize, form: Form) -> Result<T>
+where
+    Form: TryInto<T, Error = E>,
+    Error: From<E>,
+{
+    TryInto::<T>::try_into(form).map_err(|error| Error::AtPosition {
+        position,
+        source: Box::new(error.into()),
+    })
+}
+
+/// Convert a map value, naming its key on failure
+fn convert_value<T, E>(key: &Form, form: Form) -> Result<T>
+where
+    Form: TryInto<T, Error = E>,
+    Error: From<E>,
+{
+    TryInto::<T>::try_into(form).map_err(|error| Error::InvalidField {
+        field: format!("{key:?}"),
+        source: Box::new(error.into()),
+    })
+}
+
+fn into_items(form: Form) -> Result<Vec<Form>> {
+    match form.kind {
+        FormKind::Nil => Ok(Vec::new()),
+        FormKind::List(inner) | FormKind::Vector(inner) => Ok(inner),
+        FormKind::LazySeq(seq) => seq.iter().collect(),
+        _ => Err(wrong_type("list or vector", form)),
+    }
+}
+
+fn into_entries(form: Form) -> Result<HashMap<Form, Form>> {
+    match form.kind {
+        FormKind::Nil => Ok(HashMap::new()),
+        FormKind::HashMap(map) => Ok(map),
+        _ => Err(wrong_type("map", form)),
+    }
+}
+
 impl<T, E> TryInto<Vec<T>> for Form
 where
     Form: TryInto<T, Error = E>,
@@ -32,35 +83,68 @@ where
     type Error = crate::Error;
 
     fn try_into(self) -> Result<Vec<T>> {
-        match self.kind {
-            FormKind::Nil => Ok(Vec::new()),
-            FormKind::List(inner) | FormKind::Vector(inner) => {
-                inner.into_iter().map(|x| Ok(x.try_into()?)).collect()
-            }
-            _ => Err(crate::Error::InvalidArgument),
-        }
+        into_items(self)?
+            .into_iter()
+            .enumerate()
+            .map(|(i, x)| convert_at::<T, E>(i, x))
+            .collect()
     }
 }
 
-impl<T, U, E> TryInto<HashMap<T, U>> for Form
+impl<T, E> TryInto<HashSet<T>> for Form
 where
     Form: TryInto<T, Error = E>,
-    Form: TryInto<U, Error = E>,
     crate::Error: From<E>,
+    T: Hash + Eq,
+{
+    type Error = crate::Error;
+
+    fn try_into(self) -> Result<HashSet<T>> {
+        into_items(self)?
+            .into_iter()
+            .enumerate()
+            .map(|(i, x)| convert_at::<T, E>(i, x))
+            .collect()
+    }
+}
+
+impl<T, U, E, F> TryInto<HashMap<T, U>> for Form
+where
+    Form: TryInto<T, Error = E>,
+    Form: TryInto<U, Error = F>,
+    crate::Error: From<E> + From<F>,
     T: std::hash::Hash + std::cmp::Eq,
-    U: std::hash::Hash + std::cmp::Eq,
 {
     type Error = crate::Error;
 
     fn try_into(self) -> Result<HashMap<T, U>> {
-        match self.kind {
-            FormKind::Nil => Ok(HashMap::new()),
-            FormKind::HashMap(map) => map
-                .into_iter()
-                .map(|(k, v)| Ok((k.try_into()?, v.try_into()?)))
-                .collect(),
-            _ => Err(crate::Error::InvalidArgument),
-        }
+        into_entries(self)?
+            .into_iter()
+            .map(|(k, v)| {
+                let value = convert_value::<U, F>(&k, v)?;
+                Ok((TryInto::<T>::try_into(k)?, value))
+            })
+            .collect()
+    }
+}
+
+impl<T, U, E, F> TryInto<BTreeMap<T, U>> for Form
+where
+    Form: TryInto<T, Error = E>,
+    Form: TryInto<U, Error = F>,
+    crate::Error: From<E> + From<F>,
+    T: Ord,
+{
+    type Error = crate::Error;
+
+    fn try_into(self) -> Result<BTreeMap<T, U>> {
+        into_entries(self)?
+            .into_iter()
+            .map(|(k, v)| {
+                let value = convert_value::<U, F>(&k, v)?;
+                Ok((TryInto::<T>::try_into(k)?, value))
+            })
+            .collect()
     }
 }
 
@@ -68,17 +152,71 @@ impl From<Form> for () {
     fn from(_val: Form) -> Self {}
 }
 
-impl From<Form> for bool {
-    fn from(val: Form) -> Self {
-        !matches!(val.kind, FormKind::Nil | FormKind::Boolean(false))
+impl<T: Into<Form>> From<Option<T>> for Form {
+    fn from(val: Option<T>) -> Self {
+        match val {
+            Some(x) => x.into(),
+            None => Form::nil(),
+        }
     }
 }
 
-impl From<Option<Form>> for Form {
-    fn from(val: Option<Form>) -> Self {
-        match val {
-            Some(x) => x,
-            None => Form::nil(),
+impl From<()> for Form {
+    fn from(_val: ()) -> Self {
+        Form::nil()
+    }
+}
+
+impl From<bool> for Form {
+    fn from(val: bool) -> Self {
+        Form::boolean(val)
+    }
+}
+
+impl From<i64> for Form {
+    fn from(val: i64) -> Self {
+        Form::int(val)
+    }
+}
+
+impl From<f64> for Form {
+    fn from(val: f64) -> Self {
+        Form::float(val)
+    }
+}
+
+impl From<String> for Form {
+    fn from(val: String) -> Self {
+        Form::string(val)
+    }
+}
+
+impl From<&str> for Form {
+    fn from(val: &str) -> Self {
+        Form::string(val)
+    }
+}
+
+impl<T: Into<Form>> From<Vec<T>> for Form {
+    fn from(val: Vec<T>) -> Self {
+        Form::vector(val.into_iter().map(Into::into))
+    }
+}
+
+impl<K: Into<Form>, V: Into<Form>> From<HashMap<K, V>> for Form {
+    fn from(val: HashMap<K, V>) -> Self {
+        Form::hash_map(val.into_iter().map(|(k, v)| (k.into(), v.into())).collect())
+    }
+}
+
+impl TryInto<bool> for Form {
+    type Error = crate::Error;
+
+    /// Only `true` and `false` convert; use `Form::is_truthy` for Lisp truthiness
+    fn try_into(self) -> Result<bool> {
+        match self.kind {
+            FormKind::Boolean(b) => Ok(b),
+            _ => Err(wrong_type("boolean", self)),
         }
     }
 }
@@ -90,18 +228,67 @@ impl TryInto<i64> for Form {
         match self.kind {
             FormKind::Integer(i) => Ok(i),
             FormKind::Float(f) => Ok(f as i64),
-            _ => Err(crate::Error::InvalidArgument),
+            _ => Err(wrong_type("integer", self)),
         }
     }
 }
 
+impl TryInto<f64> for Form {
+    type Error = crate::Error;
+
+    fn try_into(self) -> Result<f64> {
+        match self.kind {
+            FormKind::Integer(i) => Ok(i as f64),
+            FormKind::Float(f) => Ok(f),
+            _ => Err(wrong_type("number", self)),
+        }
+    }
+}
+
+macro_rules! unsigned_impls {
+    ($($ty:ty => $expected:literal)+) => {
+        $(
+            impl TryInto<$ty> for Form {
+                type Error = crate::Error;
+
+                fn try_into(self) -> Result<$ty> {
+                    match self.kind {
+                        FormKind::Integer(i) => i.try_into().map_err(|_| wrong_type($expected, self)),
+                        _ => Err(wrong_type($expected, self)),
+                    }
+                }
+            }
+        )+
+    };
+}
+
+unsigned_impls! {
+    usize => "non-negative integer"
+    u32 => "integer between 0 and 4294967295"
+}
+
+impl TryInto<char> for Form {
+    type Error = crate::Error;
+
+    /// Characters are represented as strings of length one
+    fn try_into(self) -> Result<char> {
+        if let FormKind::String(ref s) = self.kind {
+            let mut chars = s.chars();
+            if let (Some(c), None) = (chars.next(), chars.next()) {
+                return Ok(c);
+            }
+        }
+        Err(wrong_type("single character string", self))
+    }
+}
+
 impl TryInto<Ident> for Form {
     type Error = crate::Error;
 
     fn try_into(self) -> std::result::Result<Ident, Self::Error> {
         match self.kind {
             FormKind::Symbol(ident) => Ok(ident),
-            _ => Err(crate::Error::InvalidArgument),
+            _ => Err(wrong_type("symbol", self)),
         }
     }
 }
@@ -112,7 +299,7 @@ impl TryInto<String> for Form {
     fn try_into(self) -> std::result::Result<String, Self::Error> {
         match self.kind {
             FormKind::String(s) => Ok(s),
-            _ => Err(crate::Error::InvalidArgument),
+            _ => Err(wrong_type("string", self)),
         }
     }
 }
@@ -123,13 +310,72 @@ impl TryInto<Atom> for Form {
     fn try_into(self) -> std::result::Result<Atom, Self::Error> {
         match self.kind {
             FormKind::Atom(atom) => Ok(atom),
-            _ => Err(crate::Error::InvalidArgument),
+            _ => Err(wrong_type("atom", self)),
         }
     }
 }
 
+/// A host value downcast to a concrete Rust type
+///
+/// Use as a native function parameter to accept values created with `Form::host`.
+#[derive(Debug)]
+pub struct Host<T> {
+    pub(crate) value: Rc<T>,
+}
+
+impl<T> Host<T> {
+    pub fn into_rc(self) -> Rc<T> {
+        self.value
+    }
+}
+
+impl<T> Deref for Host<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Any> TryInto<Host<T>> for Form {
+    type Error = crate::Error;
+
+    fn try_into(self) -> std::result::Result<Host<T>, Self::Error> {
+        match self.kind {
+            FormKind::Host(ref host) => match host.downcast() {
+                Some(value) => Ok(Host { value }),
+                None => Err(wrong_type(std::any::type_name::<T>(), self)),
+            },
+            _ => Err(wrong_type(std::any::type_name::<T>(), self)),
+        }
+    }
+}
+
+/// `Option<T>` converts nil to `None`. There is no generic impl because it would overlap with the
+/// standard library's conversion into `Option<Form>`, which never produces `None`. Collections
+/// are left out as well since they already convert nil to an empty collection.
+macro_rules! option_impls {
+    ($($ty:ty)+) => {
+        $(
+            impl TryInto<Option<$ty>> for Form {
+                type Error = crate::Error;
+
+                fn try_into(self) -> Result<Option<$ty>> {
+                    if self.is_nil() {
+                        Ok(None)
+                    } else {
+                        TryInto::<$ty>::try_into(self).map(Some)
+                    }
+                }
+            }
+        )+
+    };
+}
+
+option_impls! { bool i64 f64 usize u32 char String Ident Atom }
+
 macro_rules! tuple_impls {
-    ($($len:tt => ($($name:ident $error:ident)+))+) => {
+    ($($len:tt => ($($position:tt $name:ident $error:ident)+))+) => {
         $(
             impl<$($name, $error),+,> TryInto<($($name,)+)> for Form
             where
@@ -145,17 +391,21 @@ macro_rules! tuple_impls {
                 fn try_into(self) -> std::result::Result<($($name,)+), crate::Error> {
                     match self.kind {
                         crate::form::FormKind::List(mut inner) | crate::form::FormKind::Vector(mut inner) => {
+                            let found = inner.len();
                             let mut iter = inner.drain(..).fuse();
                             $(
-                                let $name = Into::<Form>::into(iter.next()).try_into()?;
+                                let $name = convert_at::<$name, $error>($position, iter.next().into())?;
                             )+
                             if iter.next().is_none() {
                                 Ok(($($name,)+))
                             } else {
-                                Err(crate::Error::InvalidArgument)
+                                Err(crate::Error::TooManyElements { expected: $len, found })
                             }
                         }
-                        _ => Err(crate::Error::InvalidArgument),
+                        crate::form::FormKind::LazySeq(seq) => {
+                            TryInto::<($($name,)+)>::try_into(Form::list(seq.iter().collect::<crate::Result<Vec<_>>>()?))
+                        }
+                        _ => Err(wrong_type("list or vector", self)),
                     }
                 }
             }
@@ -176,11 +426,14 @@ macro_rules! tuple_impls {
                         crate::form::FormKind::List(mut inner) | crate::form::FormKind::Vector(mut inner) => {
                             let mut iter = inner.drain(..).fuse();
                             $(
-                                let $name = Into::<Form>::into(iter.next()).try_into()?;
+                                let $name = convert_at::<$name, $error>($position, iter.next().into())?;
                             )+
                             Ok(($($name,)+ Rest::new(iter)))
                         }
-                        _ => Err(crate::Error::InvalidArgument),
+                        crate::form::FormKind::LazySeq(seq) => {
+                            TryInto::<($($name,)+ Rest)>::try_into(Form::list(seq.iter().collect::<crate::Result<Vec<_>>>()?))
+                        }
+                        _ => Err(wrong_type("list or vector", self)),
                     }
                 }
             }
@@ -189,9 +442,12 @@ macro_rules! tuple_impls {
 }
 
 tuple_impls! {
-    1 => (T0 E0)
-    2 => (T0 E0 T1 E1)
-    3 => (T0 E0 T1 E1 T2 E2)
-    4 => (T0 E0 T1 E1 T2 E2 T3 E3)
-    5 => (T0 E0 T1 E1 T2 E2 T3 E3 T4 E4)
+    1 => (0 T0 E0)
+    2 => (0 T0 E0 1 T1 E1)
+    3 => (0 T0 E0 1 T1 E1 2 T2 E2)
+    4 => (0 T0 E0 1 T1 E1 2 T2 E2 3 T3 E3)
+    5 => (0 T0 E0 1 T1 E1 2 T2 E2 3 T3 E3 4 T4 E4)
+    6 => (0 T0 E0 1 T1 E1 2 T2 E2 3 T3 E3 4 T4 E4 5 T5 E5)
+    7 => (0 T0 E0 1 T1 E1 2 T2 E2 3 T3 E3 4 T4 E4 5 T5 E5 6 T6 E6)
+    8 => (0 T0 E0 1 T1 E1 2 T2 E2 3 T3 E3 4 T4 E4 5 T5 E5 6 T6 E6 7 T7 E7)
 }
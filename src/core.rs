@@ -1,77 +1,124 @@
 use itertools::Itertools;
 
-use crate::{convert::Rest, form::Atom, Env, Form, FormKind, Result};
-use std::{collections::HashMap, fmt::Write};
+use crate::{
+    convert::Rest,
+    delay::{Delay, Promise},
+    form::Atom,
+    intern::sym,
+    interrupt,
+    lazy::{self, LazySeq},
+    multi::{self, Hierarchy, MultiFn},
+    Env, Form, FormKind, Ident, Result,
+};
+use std::{collections::HashMap, fmt::Write, rc::Rc};
 
 pub fn populate(env: &mut Env) {
-    env.extend(
-        [
-            ("+", Form::native_fn(&add)),
-            ("-", Form::native_fn(&sub)),
-            ("*", Form::native_fn(&mul)),
-            ("/", Form::native_fn(&div)),
-            ("list", Form::native_fn(&list)),
-            ("list?", Form::native_fn(&is_list)),
-            ("empty?", Form::native_fn(&is_empty)),
-            ("count", Form::native_fn(&count)),
-            ("=", Form::native_fn(&eq)),
-            ("<", Form::native_fn(&lt)),
-            ("<=", Form::native_fn(&lte)),
-            (">", Form::native_fn(&gt)),
-            (">=", Form::native_fn(&gte)),
-            ("pr-str", Form::native_fn(&pr_str)),
-            ("str", Form::native_fn(&str_)),
-            ("prn", Form::native_fn(&prn)),
-            ("println", Form::native_fn(&println_)),
-            ("read-string", Form::native_fn(&read_string)),
-            ("slurp", Form::native_fn(&slurp)),
-            ("atom", Form::native_fn(&atom)),
-            ("atom?", Form::native_fn(&is_atom)),
-            ("deref", Form::native_fn(&deref)),
-            ("reset!", Form::native_fn(&reset)),
-            ("swap!", Form::native_fn(&swap)),
-            ("cons", Form::native_fn(&cons)),
-            ("concat", Form::native_fn(&concat)),
-            ("vec", Form::native_fn(&vec_)),
-            ("nth", Form::native_fn(&nth)),
-            ("first", Form::native_fn(&first)),
-            ("rest", Form::native_fn(&rest)),
-            ("apply", Form::native_fn(&apply)),
-            ("map", Form::native_fn(&map)),
-            ("nil?", Form::native_fn(&is_nil)),
-            ("true?", Form::native_fn(&is_true)),
-            ("false?", Form::native_fn(&is_false)),
-            ("symbol", Form::native_fn(&symbol)),
-            ("symbol?", Form::native_fn(&is_symbol)),
-            ("keyword", Form::native_fn(&keyword)),
-            ("keyword?", Form::native_fn(&is_keyword)),
-            ("vector", Form::native_fn(&vector)),
-            ("vector?", Form::native_fn(&is_vector)),
-            ("sequential?", Form::native_fn(&is_sequential)),
-            ("throw", Form::native_fn(&throw)),
-            ("hash-map", Form::native_fn(&hash_map)),
-            ("map?", Form::native_fn(&is_map)),
-            ("assoc", Form::native_fn(&assoc)),
-            ("dissoc", Form::native_fn(&dissoc)),
-            ("get", Form::native_fn(&get)),
-            ("contains?", Form::native_fn(&contains)),
-            ("keys", Form::native_fn(&keys)),
-            ("vals", Form::native_fn(&vals)),
-            ("readline", Form::native_fn(&readline)),
-            ("time-ms", Form::native_fn(&time_ms)),
-            ("meta", Form::native_fn(&meta)),
-            ("with-meta", Form::native_fn(&with_meta)),
-            ("fn?", Form::native_fn(&is_fn)),
-            ("string?", Form::native_fn(&is_string)),
-            ("number?", Form::native_fn(&is_number)),
-            ("macro?", Form::native_fn(&is_macro)),
-            ("seq", Form::native_fn(&seq)),
-            ("conj", Form::native_fn(&conj)),
-            ("*host-language*", Form::string("rust.2")),
-        ]
-        .into_iter()
-        .map(|(symbol, func)| (symbol.to_string(), func)),
-    );
+    env.extend([
+        ("+", Form::native_fn(&add)),
+        ("-", Form::native_fn(&sub)),
+        ("*", Form::native_fn(&mul)),
+        ("/", Form::native_fn(&div)),
+        ("list", Form::native_fn(&list)),
+        ("list?", Form::native_fn(&is_list)),
+        ("empty?", Form::native_fn(&is_empty)),
+        ("count", Form::native_fn(&count)),
+        ("=", Form::native_fn(&eq)),
+        ("<", Form::native_fn(&lt)),
+        ("<=", Form::native_fn(&lte)),
+        (">", Form::native_fn(&gt)),
+        (">=", Form::native_fn(&gte)),
+        ("pr-str", Form::native_fn(&pr_str)),
+        ("str", Form::native_fn(&str_)),
+        ("prn", Form::native_fn(&prn)),
+        ("println", Form::native_fn(&println_)),
+        ("read-string", Form::native_fn(&read_string)),
+        ("slurp", Form::native_fn(&slurp)),
+        ("atom", Form::native_fn(&atom)),
+        ("atom?", Form::native_fn(&is_atom)),
+        ("deref", Form::native_fn(&deref)),
+        ("delay*", Form::native_fn(&delay)),
+        ("delay?", Form::native_fn(&is_delay)),
+        ("force", Form::native_fn(&force)),
+        ("realized?", Form::native_fn(&is_realized)),
+        ("promise", Form::native_fn(&promise)),
+        ("deliver", Form::native_fn(&deliver)),
+        ("memoize", Form::native_fn(&memoize)),
+        ("multi-fn?", Form::native_fn(&is_multi_fn)),
+        ("add-method*", Form::native_fn(&add_method)),
+        ("remove-method", Form::native_fn(&remove_method)),
+        ("get-method", Form::native_fn(&get_method)),
+        ("reset!", Form::native_fn(&reset)),
+        ("swap!", Form::native_fn(&swap)),
+        ("cons", Form::native_fn(&cons)),
+        ("concat", Form::native_fn(&concat)),
+        ("vec", Form::native_fn(&vec_)),
+        ("nth", Form::native_fn(&nth)),
+        ("first", Form::native_fn(&first)),
+        ("rest", Form::native_fn(&rest)),
+        ("apply", Form::native_fn(&apply)),
+        ("map", Form::native_fn(&map)),
+        ("lazy-seq*", Form::native_fn(&lazy_seq)),
+        ("seq?", Form::native_fn(&is_seq)),
+        ("iterate", Form::native_fn(&iterate)),
+        ("repeat", Form::native_fn(&repeat)),
+        ("cycle", Form::native_fn(&cycle)),
+        ("range", Form::native_fn(&range)),
+        ("take", Form::native_fn(&take)),
+        ("drop", Form::native_fn(&drop)),
+        ("take-while", Form::native_fn(&take_while)),
+        ("doall", Form::native_fn(&doall)),
+        ("dorun", Form::native_fn(&dorun)),
+        ("nil?", Form::native_fn(&is_nil)),
+        ("true?", Form::native_fn(&is_true)),
+        ("false?", Form::native_fn(&is_false)),
+        ("symbol", Form::native_fn(&symbol)),
+        ("symbol?", Form::native_fn(&is_symbol)),
+        ("keyword", Form::native_fn(&keyword)),
+        ("keyword?", Form::native_fn(&is_keyword)),
+        ("vector", Form::native_fn(&vector)),
+        ("vector?", Form::native_fn(&is_vector)),
+        ("sequential?", Form::native_fn(&is_sequential)),
+        ("throw", Form::native_fn(&throw)),
+        ("ex-info", Form::native_fn(&ex_info)),
+        ("ex-message", Form::native_fn(&ex_message)),
+        ("ex-data", Form::native_fn(&ex_data)),
+        ("ex-cause", Form::native_fn(&ex_cause)),
+        ("print-stack-trace", Form::native_fn(&print_stack_trace)),
+        ("hash-map", Form::native_fn(&hash_map)),
+        ("map?", Form::native_fn(&is_map)),
+        ("assoc", Form::native_fn(&assoc)),
+        ("dissoc", Form::native_fn(&dissoc)),
+        ("get", Form::native_fn(&get)),
+        ("contains?", Form::native_fn(&contains)),
+        ("keys", Form::native_fn(&keys)),
+        ("vals", Form::native_fn(&vals)),
+        ("readline", Form::native_fn(&readline)),
+        ("time-ms", Form::native_fn(&time_ms)),
+        ("meta", Form::native_fn(&meta)),
+        ("with-meta", Form::native_fn(&with_meta)),
+        ("vary-meta", Form::native_fn(&vary_meta)),
+        ("alter-meta!", Form::native_fn(&alter_meta)),
+        ("fn?", Form::native_fn(&is_fn)),
+        ("string?", Form::native_fn(&is_string)),
+        ("number?", Form::native_fn(&is_number)),
+        ("macro?", Form::native_fn(&is_macro)),
+        ("seq", Form::native_fn(&seq)),
+        ("conj", Form::native_fn(&conj)),
+        ("gc", Form::native_fn(&gc)),
+        ("gc-stats", Form::native_fn(&gc_stats)),
+        ("*host-language*", Form::string("rust.2")),
+        ("*load-path*", Form::vector([Form::string(".")])),
+    ]);
+    let hierarchy = multi::hierarchy(env);
+    env.extend([
+        ("multi-fn*", with_hierarchy(&hierarchy, multi_fn)),
+        ("derive", with_hierarchy(&hierarchy, derive)),
+        ("isa?", with_hierarchy(&hierarchy, isa)),
+    ]);
+    crate::eval_str(r#"(def! ^:dynamic *print-length* nil)"#, env);
+    crate::eval_str(r#"(def! ^:dynamic *print-readably* true)"#, env);
+    crate::eval_str(r#"(def! ^:dynamic *out* :stdout)"#, env);
+    crate::eval_str(r#"(def! ^:dynamic *e nil)"#, env);
     crate::eval_str(r#"(def! not (fn* (a) (if a false true)))"#, env);
     crate::eval_str(
         r#"(def! load-file (fn* (f) (eval (read-string (str "(do " (slurp f) "\nnil)")))))"#,
@@ -81,6 +128,26 @@ pub fn populate(env: &mut Env) {
         r#"(defmacro! cond (fn* (& xs) (if (> (count xs) 0) (list 'if (first xs) (if (> (count xs) 1) (nth xs 1) (throw "odd number of forms to cond")) (cons 'cond (rest (rest xs)))))))"#,
         env,
     );
+    crate::eval_str(
+        r#"(defmacro! lazy-seq (fn* (& body) `(lazy-seq* (fn* [] (do ~@body)))))"#,
+        env,
+    );
+    crate::eval_str(
+        r#"(defmacro! delay (fn* (& body) `(delay* (fn* [] (do ~@body)))))"#,
+        env,
+    );
+    crate::eval_str(
+        r#"(defmacro! defmulti (fn* (name dispatch) `(if (multi-fn? (try* ~name (catch* :unknown-symbol _ nil))) ~name (def! ~name (multi-fn* '~name ~dispatch)))))"#,
+        env,
+    );
+    crate::eval_str(
+        r#"(defmacro! defmethod (fn* (name value & fn-tail) `(add-method* ~name ~value (fn* ~@fn-tail))))"#,
+        env,
+    );
+    crate::eval_str(
+        r#"(defmacro! with-out-str (fn* (& body) `(binding [*out* (atom "")] ~@body @*out*)))"#,
+        env,
+    );
 }
 
 fn add(params: Form) -> Result<Form> {
@@ -115,13 +182,26 @@ fn is_list(params: Form) -> Result<Form> {
 }
 
 fn is_empty(params: Form) -> Result<Form> {
-    let (parsed,): (Vec<Form>,) = params.try_into()?;
+    let (coll,): (Form,) = params.try_into()?;
+    if let FormKind::LazySeq(seq) = coll.kind {
+        return Ok(Form::boolean(seq.step()?.is_none()));
+    }
+    let parsed: Vec<Form> = coll.try_into()?;
     Ok(Form::boolean(parsed.is_empty()))
 }
 
 fn count(params: Form) -> Result<Form> {
-    let (parsed,): (Vec<Form>,) = params.try_into()?;
-    Ok(Form::int(parsed.len().try_into()?))
+    let (coll,): (Form,) = params.try_into()?;
+    let count = match coll.kind {
+        FormKind::LazySeq(seq) => seq
+            .iter()
+            .try_fold(0, |count, item| item.map(|_| count + 1))?,
+        _ => {
+            let parsed: Vec<Form> = coll.try_into()?;
+            parsed.len()
+        }
+    };
+    Ok(Form::int(count.try_into()?))
 }
 
 fn eq(params: Form) -> Result<Form> {
@@ -149,14 +229,41 @@ fn gte(params: Form) -> Result<Form> {
     Ok(Form::boolean(a >= b))
 }
 
+/// Print `form` for `pr-str` and `prn`, readably unless `*print-readably*` is false
+fn write_readably(s: &mut String, form: &Form) {
+    if crate::dynamic::get(sym::PRINT_READABLY).is_none_or(|readably| readably.is_truthy()) {
+        let _ = write!(s, "{form:?}");
+    } else {
+        let _ = write!(s, "{form}");
+    }
+}
+
+/// Write `s` to `*out*`: `:stdout`, `:stderr`, or an atom holding a string to append to
+fn write_out(s: &str) -> Result<()> {
+    match crate::dynamic::get(sym::OUT).map(|out| out.kind) {
+        Some(FormKind::Keyword(sym::STDERR)) => eprint!("{s}"),
+        Some(FormKind::Atom(atom)) => {
+            let mut value = atom.value.borrow_mut();
+            let mut out: String = value.clone().try_into()?;
+            out.push_str(s);
+            *value = Form::string(out);
+        }
+        _ => print!("{s}"),
+    }
+    Ok(())
+}
+
 fn pr_str(params: Form) -> Result<Form> {
     let forms: Vec<Form> = params.try_into()?;
-    let mut sep = "";
     let mut s = String::new();
-    for form in forms {
-        let _ = write!(&mut s, "{sep}{form:?}");
-        sep = " ";
+    for (i, form) in forms.iter().enumerate() {
+        if i > 0 {
+            s.push(' ');
+        }
+        write_readably(&mut s, form);
     }
+    // Printing stops at an interrupt and leaves it pending, to be raised here
+    interrupt::check()?;
     Ok(Form::string(s))
 }
 
@@ -166,28 +273,29 @@ fn str_(params: Form) -> Result<Form> {
     for form in forms {
         let _ = write!(&mut s, "{form}");
     }
+    interrupt::check()?;
     Ok(Form::string(s))
 }
 
 fn prn(params: Form) -> Result<Form> {
-    let forms: Vec<Form> = params.try_into()?;
-    let mut sep = "";
-    for form in forms {
-        print!("{sep}{form:?}");
-        sep = " ";
-    }
-    print!("\n");
+    let Form {
+        kind: FormKind::String(mut s),
+        ..
+    } = pr_str(params)?
+    else {
+        unreachable!("pr_str returns a string")
+    };
+    s.push('\n');
+    write_out(&s)?;
     Ok(Form::nil())
 }
 
 fn println_(params: Form) -> Result<Form> {
     let forms: Vec<Form> = params.try_into()?;
-    let mut sep = "";
-    for form in forms {
-        print!("{sep}{form}");
-        sep = " ";
-    }
-    print!("\n");
+    let mut s = forms.iter().map(|form| form.to_string()).join(" ");
+    interrupt::check()?;
+    s.push('\n');
+    write_out(&s)?;
     Ok(Form::nil())
 }
 
@@ -202,8 +310,19 @@ fn slurp(params: Form) -> Result<Form> {
 }
 
 fn atom(params: Form) -> Result<Form> {
-    let (form,): (Form,) = params.try_into()?;
-    Ok(Form::atom(Atom::new(form)))
+    let (form, Rest { values: options }): (Form, Rest) = params.try_into()?;
+    if options.len() % 2 == 1 {
+        return Err(crate::Error::InvalidArgument);
+    }
+    let atom = Atom::new(form);
+    for (key, value) in options.into_iter().tuples() {
+        if key == Form::keyword("meta") {
+            *atom.meta.borrow_mut() = value;
+        } else {
+            return Err(crate::Error::InvalidArgument);
+        }
+    }
+    Ok(Form::atom(atom))
 }
 
 fn is_atom(params: Form) -> Result<Form> {
@@ -211,10 +330,169 @@ fn is_atom(params: Form) -> Result<Form> {
     Ok(Form::boolean(form.is_atom()))
 }
 
+/// `(deref ref)` is the value of an atom, delay or promise. `(deref promise timeout-ms
+/// timeout-val)` returns `timeout-val` if the promise hasn't been delivered; nothing else could
+/// deliver it while this thread waits, so it returns at once.
 fn deref(params: Form) -> Result<Form> {
-    let (atom,): (Atom,) = params.try_into()?;
-    let form = atom.value.borrow().clone();
-    Ok(form)
+    let (
+        reference,
+        Rest {
+            values: mut timeout,
+        },
+    ): (Form, Rest) = params.try_into()?;
+    let timeout_val = match timeout.len() {
+        0 => None,
+        2 => timeout.pop(),
+        _ => return Err(crate::Error::InvalidArgument),
+    };
+    match reference.kind {
+        FormKind::Atom(atom) => Ok(atom.value.borrow().clone()),
+        FormKind::Delay(delay) => delay.force(),
+        FormKind::Promise(promise) => promise
+            .value()
+            .or(timeout_val)
+            .ok_or(crate::Error::Undelivered),
+        kind => Err(crate::Error::WrongType {
+            expected: "atom, delay or promise",
+            found: Form {
+                kind,
+                meta: reference.meta,
+            },
+        }),
+    }
+}
+
+/// `(delay* f)` is a delay that calls `f` when first forced. The `delay` macro wraps its body
+/// in such a function.
+fn delay(params: Form) -> Result<Form> {
+    let (f,): (Form,) = params.try_into()?;
+    Ok(Delay::capturing([f], |[f]| f.clone().call(Form::empty_list())).into())
+}
+
+fn is_delay(params: Form) -> Result<Form> {
+    let (arg,): (Form,) = params.try_into()?;
+    Ok(Form::boolean(arg.is_delay()))
+}
+
+/// `(force x)` is the value of `x` if it is a delay, or else `x`
+fn force(params: Form) -> Result<Form> {
+    let (arg,): (Form,) = params.try_into()?;
+    match arg.kind {
+        FormKind::Delay(delay) => delay.force(),
+        _ => Ok(arg),
+    }
+}
+
+/// Whether a delay has been forced, a promise delivered, or the first element of a lazy
+/// sequence computed
+fn is_realized(params: Form) -> Result<Form> {
+    let (arg,): (Form,) = params.try_into()?;
+    match arg.kind {
+        FormKind::Delay(delay) => Ok(Form::boolean(delay.is_realized())),
+        FormKind::Promise(promise) => Ok(Form::boolean(promise.is_delivered())),
+        FormKind::LazySeq(seq) => Ok(Form::boolean(seq.is_realized())),
+        _ => Err(crate::Error::InvalidArgument),
+    }
+}
+
+fn promise(_params: Form) -> Result<Form> {
+    Ok(Promise::new().into())
+}
+
+/// `(deliver promise value)` gives the promise its value and returns it, or returns nil if it
+/// was delivered before
+fn deliver(params: Form) -> Result<Form> {
+    let (promise, value): (Form, Form) = params.try_into()?;
+    match promise.kind {
+        FormKind::Promise(ref inner) if inner.deliver(value) => Ok(promise),
+        FormKind::Promise(_) => Ok(Form::nil()),
+        _ => Err(crate::Error::InvalidArgument),
+    }
+}
+
+/// `(memoize f)` is a function that calls `f` once for each distinct list of arguments and
+/// then returns the cached result
+fn memoize(params: Form) -> Result<Form> {
+    let (f,): (Form,) = params.try_into()?;
+    if !f.is_user_fn() && !f.is_native_fn() && !f.is_multi_fn() {
+        return Err(crate::Error::NotCallable);
+    }
+    // The results are cached in a map in an atom, where the collector sees them
+    let cache = Form::atom(Atom::new(Form::hash_map(HashMap::new())));
+    Ok(Form::native_closure([f, cache], memo_call))
+}
+
+/// Look `args` up in a memoized function's cache, calling `f` on a miss
+fn memo_call([f, cache]: &[Form; 2], args: Form) -> Result<Form> {
+    let FormKind::Atom(ref cache) = cache.kind else {
+        unreachable!("the cache is an atom");
+    };
+    if let FormKind::HashMap(ref cached) = cache.value.borrow().kind {
+        if let Some(value) = cached.get(&args) {
+            return Ok(value.clone());
+        }
+    }
+    let value = f.clone().call(args.clone())?;
+    if let FormKind::HashMap(ref mut cached) = cache.value.borrow_mut().kind {
+        cached.insert(args, value.clone());
+    }
+    Ok(value)
+}
+
+/// A function that calls `f` with the hierarchy of the interpreter and a list of its arguments
+fn with_hierarchy(hierarchy: &Rc<Hierarchy>, f: fn(&Rc<Hierarchy>, Form) -> Result<Form>) -> Form {
+    let hierarchy = Rc::clone(hierarchy);
+    Form::native_closure([], move |[], args| f(&hierarchy, args))
+}
+
+/// `(multi-fn* name dispatch-fn)` creates the multimethod that `defmulti` defines
+fn multi_fn(hierarchy: &Rc<Hierarchy>, args: Form) -> Result<Form> {
+    let (name, dispatch): (Ident, Form) = args.try_into()?;
+    Ok(MultiFn::with_hierarchy(name, dispatch, Rc::clone(hierarchy)).into_form())
+}
+
+fn is_multi_fn(params: Form) -> Result<Form> {
+    let (arg,): (Form,) = params.try_into()?;
+    Ok(Form::boolean(arg.is_multi_fn()))
+}
+
+fn multimethod(f: &Form) -> Result<Rc<MultiFn>> {
+    MultiFn::of(f).ok_or_else(|| crate::Error::WrongType {
+        expected: "multimethod",
+        found: f.clone(),
+    })
+}
+
+/// `(add-method* multi dispatch-value f)`, which `defmethod` expands to
+fn add_method(params: Form) -> Result<Form> {
+    let (multi, value, method): (Form, Form, Form) = params.try_into()?;
+    multimethod(&multi)?.add_method(value, method);
+    Ok(multi)
+}
+
+fn remove_method(params: Form) -> Result<Form> {
+    let (multi, value): (Form, Form) = params.try_into()?;
+    multimethod(&multi)?.remove_method(&value);
+    Ok(multi)
+}
+
+/// The method that would handle a dispatch value, or nil
+fn get_method(params: Form) -> Result<Form> {
+    let (multi, value): (Form, Form) = params.try_into()?;
+    Ok(multimethod(&multi)?
+        .method(&value)?
+        .unwrap_or_else(Form::nil))
+}
+
+fn derive(hierarchy: &Rc<Hierarchy>, args: Form) -> Result<Form> {
+    let (child, parent): (Form, Form) = args.try_into()?;
+    hierarchy.derive(child, parent)?;
+    Ok(Form::nil())
+}
+
+fn isa(hierarchy: &Rc<Hierarchy>, args: Form) -> Result<Form> {
+    let (child, parent): (Form, Form) = args.try_into()?;
+    Ok(Form::boolean(hierarchy.isa(&child, &parent)))
 }
 
 fn reset(params: Form) -> Result<Form> {
@@ -234,24 +512,63 @@ fn swap(params: Form) -> Result<Form> {
 }
 
 fn cons(params: Form) -> Result<Form> {
-    let (x, mut seq): (Form, Vec<Form>) = params.try_into()?;
+    let (x, seq): (Form, Form) = params.try_into()?;
+    if seq.is_lazy_seq() {
+        return Ok(LazySeq::cons(x, seq).into());
+    }
+    let mut seq: Vec<Form> = seq.try_into()?;
     seq.insert(0, x);
     Ok(Form::list(seq))
 }
 
 fn concat(params: Form) -> Result<Form> {
-    let lists: Vec<Vec<Form>> = params.try_into()?;
-    Ok(Form::list(lists.into_iter().flatten()))
+    let colls: Vec<Form> = params.try_into()?;
+    let colls = colls
+        .into_iter()
+        .map(lazy::seq)
+        .collect::<Result<Vec<_>>>()?;
+    Ok(concat_from(Form::nil(), lazy::seq(Form::list(colls))?))
+}
+
+/// The elements of `current`, then those of each collection in the sequence `colls`
+fn concat_from(current: Form, colls: Form) -> Form {
+    LazySeq::capturing([current, colls], |[current, colls]| {
+        match lazy::uncons(current.clone())? {
+            Some((first, rest)) => {
+                Ok(LazySeq::cons(first, concat_from(rest, colls.clone())).into())
+            }
+            None => Ok(match lazy::uncons(colls.clone())? {
+                Some((coll, colls)) => concat_from(coll, colls),
+                None => Form::nil(),
+            }),
+        }
+    })
+    .into()
 }
 
 fn vec_(params: Form) -> Result<Form> {
-    let (arg,): (Vec<Form>,) = params.try_into()?;
+    let (arg,): (Form,) = params.try_into()?;
+    if arg.is_lazy_seq() {
+        return Ok(Form::vector(lazy::realize(arg)?));
+    }
+    let arg: Vec<Form> = arg.try_into()?;
     Ok(Form::vector(arg))
 }
 
 fn nth(params: Form) -> Result<Form> {
-    let (list, index): (Vec<Form>, i64) = params.try_into()?;
+    let (coll, index): (Form, i64) = params.try_into()?;
     let index: usize = index.try_into()?;
+    if let FormKind::LazySeq(seq) = coll.kind {
+        // Errors realizing the elements before `index` are reported rather than skipped
+        for (i, item) in seq.iter().enumerate() {
+            let item = item?;
+            if i == index {
+                return Ok(item);
+            }
+        }
+        return Err(crate::Error::IndexOutOfRange(index));
+    }
+    let list: Vec<Form> = coll.try_into()?;
     list.get(index)
         .cloned()
         .ok_or(crate::Error::IndexOutOfRange(index))
@@ -259,22 +576,21 @@ fn nth(params: Form) -> Result<Form> {
 
 fn first(params: Form) -> Result<Form> {
     let (list,): (Form,) = params.try_into()?;
-    if list.is_nil() || list.is_empty_sequential() {
-        Ok(Form::nil())
-    } else if list.is_sequential() {
-        let (first, _): (Form, Rest) = list.try_into()?;
-        Ok(first)
+    if list.is_nil() || list.is_sequential() {
+        Ok(lazy::uncons(list)?.map_or_else(Form::nil, |(first, _)| first))
     } else {
         Err(crate::Error::InvalidArgument)
     }
 }
 
+/// Everything after the first element. The rest of a lazy sequence is left unrealized.
 fn rest(params: Form) -> Result<Form> {
     let (list,): (Form,) = params.try_into()?;
-    if list.is_nil() || list.is_empty_sequential() {
-        Ok(Form::empty_list())
-    } else if let Ok((_, rest)) = <Form as TryInto<(Form, Rest)>>::try_into(list) {
-        Ok(rest.into())
+    if list.is_nil() || list.is_sequential() {
+        Ok(match lazy::uncons(list)? {
+            Some((_, rest)) if !rest.is_nil() => rest,
+            _ => Form::empty_list(),
+        })
     } else {
         Err(crate::Error::InvalidArgument)
     }
@@ -286,19 +602,205 @@ fn apply(params: Form) -> Result<Form> {
     let mut args = rest.values.drain(..rest.values.len()).collect::<Vec<_>>();
     if let Some(list_args) = last {
         args.extend(list_args.try_into_iter()?);
+        interrupt::check()?;
         f.call(Form::list(args))
     } else {
         Err(crate::Error::InvalidArgument)
     }
 }
 
+/// `(map f coll & colls)` calls `f` with an element of each collection at a time, stopping at
+/// the end of the shortest
 fn map(params: Form) -> Result<Form> {
-    let (f, list): (Form, Vec<Form>) = params.try_into()?;
-    let mapped = list
-        .into_iter()
-        .map(|arg| f.clone().call(Form::list([arg])))
-        .collect::<Result<Vec<Form>>>()?;
-    Ok(Form::list(mapped))
+    let (f, coll, Rest { values: more }): (Form, Form, Rest) = params.try_into()?;
+    let colls = std::iter::once(coll)
+        .chain(more)
+        .map(lazy::seq)
+        .collect::<Result<Vec<_>>>()?;
+    Ok(map_seqs(f, colls))
+}
+
+fn map_seqs(f: Form, colls: Vec<Form>) -> Form {
+    LazySeq::capturing([f, Form::list(colls)], |[f, colls]| {
+        let FormKind::List(ref colls) = colls.kind else {
+            unreachable!("the collections are captured as a list");
+        };
+        let mut args = Vec::with_capacity(colls.len());
+        let mut rests = Vec::with_capacity(colls.len());
+        for coll in colls {
+            match lazy::uncons(coll.clone())? {
+                Some((first, rest)) => {
+                    args.push(first);
+                    rests.push(rest);
+                }
+                None => return Ok(Form::nil()),
+            }
+        }
+        let value = f.clone().call(Form::list(args))?;
+        Ok(LazySeq::cons(value, map_seqs(f.clone(), rests)).into())
+    })
+    .into()
+}
+
+/// `(lazy-seq* f)` is a sequence produced by calling `f` when it is first needed. The
+/// `lazy-seq` macro wraps its body in such a function.
+fn lazy_seq(params: Form) -> Result<Form> {
+    let (f,): (Form,) = params.try_into()?;
+    Ok(LazySeq::capturing([f], |[f]| f.clone().call(Form::empty_list())).into())
+}
+
+fn is_seq(params: Form) -> Result<Form> {
+    let (arg,): (Form,) = params.try_into()?;
+    Ok(Form::boolean(arg.is_list() || arg.is_lazy_seq()))
+}
+
+/// `(iterate f x)` is x, `(f x)`, `(f (f x))`...
+fn iterate(params: Form) -> Result<Form> {
+    let (f, x): (Form, Form) = params.try_into()?;
+    Ok(iterate_from(f, x))
+}
+
+fn iterate_from(f: Form, x: Form) -> Form {
+    let next = LazySeq::capturing([f, x.clone()], |[f, x]| {
+        Ok(iterate_from(
+            f.clone(),
+            f.clone().call(Form::list([x.clone()]))?,
+        ))
+    });
+    LazySeq::cons(x, next.into()).into()
+}
+
+/// `(repeat x)` is x forever, `(repeat n x)` n times
+fn repeat(params: Form) -> Result<Form> {
+    let (first, Rest { values: mut rest }): (Form, Rest) = params.try_into()?;
+    match rest.pop() {
+        None => Ok(repeat_forever(first)),
+        Some(x) if rest.is_empty() => Ok(take_seq(first.try_into()?, repeat_forever(x))),
+        Some(_) => Err(crate::Error::InvalidArgument),
+    }
+}
+
+fn repeat_forever(x: Form) -> Form {
+    LazySeq::capturing([x], |[x]| {
+        Ok(LazySeq::cons(x.clone(), repeat_forever(x.clone())).into())
+    })
+    .into()
+}
+
+/// `(cycle coll)` repeats the elements of `coll` forever
+fn cycle(params: Form) -> Result<Form> {
+    let (coll,): (Form,) = params.try_into()?;
+    let coll = lazy::seq(coll)?;
+    Ok(cycle_from(coll.clone(), coll))
+}
+
+fn cycle_from(coll: Form, current: Form) -> Form {
+    LazySeq::capturing([coll, current], |[coll, current]| {
+        match lazy::uncons(current.clone())? {
+            Some((first, rest)) => Ok(LazySeq::cons(first, cycle_from(coll.clone(), rest)).into()),
+            None if lazy::uncons(coll.clone())?.is_none() => Ok(Form::nil()),
+            None => Ok(cycle_from(coll.clone(), coll.clone())),
+        }
+    })
+    .into()
+}
+
+/// `(range)` counts up from 0 forever, `(range end)` from 0 to before `end`, and
+/// `(range start end step)` from `start` by `step`
+fn range(params: Form) -> Result<Form> {
+    let (start, end, step) = match params.try_into()? {
+        (None, None, None) => (0, None, 1),
+        (Some(end), None, None) => (0, Some(end), 1),
+        (Some(start), end, step) => (start, end, step.unwrap_or(1)),
+        (None, ..) => unreachable!("arguments are filled in order"),
+    };
+    Ok(range_from(start, end, step))
+}
+
+fn range_from(start: i64, end: Option<i64>, step: i64) -> Form {
+    LazySeq::new(move || {
+        let done = match end {
+            Some(end) if step > 0 => start >= end,
+            Some(end) if step < 0 => start <= end,
+            _ => false,
+        };
+        Ok(if done {
+            Form::nil()
+        } else {
+            let rest = match start.checked_add(step) {
+                Some(next) => range_from(next, end, step),
+                None => Form::nil(),
+            };
+            LazySeq::cons(Form::int(start), rest).into()
+        })
+    })
+    .into()
+}
+
+/// `(take n coll)` is the first `n` elements of `coll`
+fn take(params: Form) -> Result<Form> {
+    let (n, coll): (i64, Form) = params.try_into()?;
+    Ok(take_seq(n, lazy::seq(coll)?))
+}
+
+fn take_seq(n: i64, coll: Form) -> Form {
+    LazySeq::capturing([coll], move |[coll]| {
+        if n <= 0 {
+            return Ok(Form::nil());
+        }
+        Ok(match lazy::uncons(coll.clone())? {
+            Some((first, rest)) => LazySeq::cons(first, take_seq(n - 1, rest)).into(),
+            None => Form::nil(),
+        })
+    })
+    .into()
+}
+
+/// `(drop n coll)` is `coll` without its first `n` elements
+fn drop(params: Form) -> Result<Form> {
+    let (n, coll): (i64, Form) = params.try_into()?;
+    let coll = lazy::seq(coll)?;
+    Ok(LazySeq::capturing([coll], move |[coll]| {
+        let mut coll = coll.clone();
+        for _ in 0..n {
+            match lazy::uncons(coll)? {
+                Some((_, rest)) => coll = rest,
+                None => return Ok(Form::nil()),
+            }
+        }
+        Ok(coll)
+    })
+    .into())
+}
+
+/// `(take-while pred coll)` is the elements of `coll` up to the first one `pred` is falsy for
+fn take_while(params: Form) -> Result<Form> {
+    let (pred, coll): (Form, Form) = params.try_into()?;
+    Ok(take_while_seq(pred, lazy::seq(coll)?))
+}
+
+fn take_while_seq(pred: Form, coll: Form) -> Form {
+    LazySeq::capturing([pred, coll], |[pred, coll]| {
+        Ok(match lazy::uncons(coll.clone())? {
+            Some((first, rest)) if pred.clone().call(Form::list([first.clone()]))?.is_truthy() => {
+                LazySeq::cons(first, take_while_seq(pred.clone(), rest)).into()
+            }
+            _ => Form::nil(),
+        })
+    })
+    .into()
+}
+
+/// `(doall coll)` realizes all of a lazy sequence, for its side effects, and returns it
+fn doall(params: Form) -> Result<Form> {
+    let (coll,): (Form,) = params.try_into()?;
+    lazy::iter(coll.clone())?.try_for_each(|item| item.map(|_| ()))?;
+    Ok(coll)
+}
+
+/// `(dorun coll)` realizes all of a lazy sequence, for its side effects, and returns nil
+fn dorun(params: Form) -> Result<Form> {
+    doall(params).map(|_| Form::nil())
 }
 
 fn is_nil(params: Form) -> Result<Form> {
@@ -360,6 +862,91 @@ fn throw(params: Form) -> Result<Form> {
     Err(crate::Error::UserError(arg))
 }
 
+// Forms are map keys, see the note on `impl Hash for Form`
+#[allow(clippy::mutable_key_type)]
+fn ex_info(params: Form) -> Result<Form> {
+    let (message, data, cause): (String, HashMap<Form, Form>, Option<Form>) = params.try_into()?;
+    let mut map = HashMap::from([
+        (Form::keyword(sym::TYPE), Form::keyword(sym::EX_INFO)),
+        (Form::keyword(sym::MESSAGE), Form::string(message)),
+        (Form::keyword(sym::DATA), Form::hash_map(data)),
+    ]);
+    if let Some(cause) = cause.filter(|cause| !cause.is_nil()) {
+        map.insert(Form::keyword(sym::CAUSE), cause);
+    }
+    Ok(Form::hash_map(map))
+}
+
+/// The entry `key` of a caught error map, or nil for other values
+fn ex_field(params: Form, key: Ident) -> Result<Form> {
+    let (ex,): (Form,) = params.try_into()?;
+    Ok(match ex.kind {
+        FormKind::HashMap(mut map) => map.remove(&Form::keyword(key)).unwrap_or_else(Form::nil),
+        _ => Form::nil(),
+    })
+}
+
+fn ex_message(params: Form) -> Result<Form> {
+    match params.as_slice() {
+        Some(
+            [message @ Form {
+                kind: FormKind::String(_),
+                ..
+            }],
+        ) => Ok(message.clone()),
+        _ => ex_field(params, sym::MESSAGE),
+    }
+}
+
+fn ex_data(params: Form) -> Result<Form> {
+    ex_field(params, sym::DATA)
+}
+
+fn ex_cause(params: Form) -> Result<Form> {
+    ex_field(params, sym::CAUSE)
+}
+
+/// `(print-stack-trace)` prints `*e`, or the error map given as argument, with its trace
+// Forms are map keys, see the note on `impl Hash for Form`
+#[allow(clippy::mutable_key_type)]
+fn print_stack_trace(params: Form) -> Result<Form> {
+    let (error,): (Option<Form>,) = params.try_into()?;
+    let error: HashMap<Form, Form> = match error
+        .filter(|error| !error.is_nil())
+        .or_else(|| crate::dynamic::get(sym::LAST_ERROR))
+    {
+        Some(error) if !error.is_nil() => error.try_into()?,
+        _ => return Ok(Form::nil()),
+    };
+    let field = |key| {
+        error
+            .get(&Form::keyword(key))
+            .cloned()
+            .unwrap_or_else(Form::nil)
+    };
+    let type_name = match field(sym::TYPE).kind {
+        FormKind::Keyword(name) => name.name(),
+        _ => "error",
+    };
+    let mut s = format!("{type_name}: {}\n", field(sym::MESSAGE));
+    let frames: Vec<HashMap<Form, Form>> = match field(sym::TRACE) {
+        trace if trace.is_nil() => Vec::new(),
+        trace => trace.try_into()?,
+    };
+    for frame in frames {
+        let entry = |key| frame.get(&Form::keyword(key)).cloned();
+        let int = |key| entry(key).and_then(|form| form.try_into().ok());
+        let frame = crate::trace::Frame {
+            name: entry(sym::NAME).map(|name| name.to_string()),
+            line: int(sym::LINE),
+            column: int(sym::COLUMN),
+        };
+        let _ = writeln!(s, "  at {frame}");
+    }
+    write_out(&s)?;
+    Ok(Form::nil())
+}
+
 fn hash_map(params: Form) -> Result<Form> {
     let args: Vec<Form> = params.try_into()?;
     if args.len() % 2 == 1 {
@@ -425,29 +1012,74 @@ fn time_ms(_params: Form) -> Result<Form> {
     Ok(Form::int(millis))
 }
 
+pub(crate) fn supports_meta(form: &Form) -> bool {
+    form.is_list()
+        || form.is_vector()
+        || form.is_lazy_seq()
+        || form.is_hash_map()
+        || form.is_user_fn()
+        || form.is_macro()
+        || form.is_native_fn()
+        || form.is_symbol()
+        || form.is_atom()
+}
+
+/// Metadata of `form`, or nil. Atoms carry their own shared metadata.
+pub(crate) fn form_meta(form: &Form) -> Form {
+    match form.kind {
+        FormKind::Atom(ref atom) => atom.meta.borrow().clone(),
+        _ => form.meta.as_deref().cloned().unwrap_or_else(Form::nil),
+    }
+}
+
+/// Return `target` with its metadata replaced by `meta`
+///
+/// For atoms this returns a new reference to the same value, whose metadata is separate from
+/// the original's: `alter-meta!` on one of them leaves the other alone.
+pub(crate) fn set_meta(mut target: Form, meta: Form) -> Result<Form> {
+    match target.kind {
+        FormKind::Atom(atom) => Ok(Form::atom(atom.with_meta(meta))),
+        _ if supports_meta(&target) => {
+            target.meta = Some(Box::new(meta));
+            Ok(target)
+        }
+        _ => Err(crate::Error::InvalidArgument),
+    }
+}
+
 fn meta(params: Form) -> Result<Form> {
     let (arg,): (Form,) = params.try_into()?;
-    Ok(arg.meta.map(|meta| *meta.clone()).unwrap_or(Form::nil()))
+    Ok(form_meta(&arg))
 }
 
 fn with_meta(params: Form) -> Result<Form> {
-    let (mut target, meta): (Form, Form) = params.try_into()?;
-    if target.is_list()
-        || target.is_vector()
-        || target.is_hash_map()
-        || target.is_user_fn()
-        || target.is_native_fn()
-    {
-        target.meta = Some(Box::new(meta));
-        Ok(target)
-    } else {
-        Err(crate::Error::InvalidArgument)
-    }
+    let (target, meta): (Form, Form) = params.try_into()?;
+    set_meta(target, meta)
+}
+
+fn vary_meta(params: Form) -> Result<Form> {
+    let (target, func, Rest { values: rest }): (Form, Form, Rest) = params.try_into()?;
+    let mut args = vec![form_meta(&target)];
+    args.extend(rest);
+    let meta = func.call(Form::list(args))?;
+    set_meta(target, meta)
+}
+
+fn alter_meta(params: Form) -> Result<Form> {
+    let (atom, func, Rest { values: rest }): (Atom, Form, Rest) = params.try_into()?;
+    let old_meta = atom.meta.borrow().clone();
+    let mut args = vec![old_meta];
+    args.extend(rest);
+    let new_meta = func.call(Form::list(args))?;
+    *atom.meta.borrow_mut() = new_meta.clone();
+    Ok(new_meta)
 }
 
 fn is_fn(params: Form) -> Result<Form> {
     let (arg,): (Form,) = params.try_into()?;
-    Ok(Form::boolean(arg.is_user_fn() || arg.is_native_fn()))
+    Ok(Form::boolean(
+        arg.is_user_fn() || arg.is_native_fn() || arg.is_multi_fn(),
+    ))
 }
 
 fn is_macro(params: Form) -> Result<Form> {
@@ -472,9 +1104,11 @@ fn seq(params: Form) -> Result<Form> {
         FormKind::List(ref list) if list.is_empty() => Ok(Form::nil()),
         FormKind::List(list) => Ok(Form::list(list)),
         FormKind::String(ref s) if s.is_empty() => Ok(Form::nil()),
-        FormKind::String(ref s) => Ok(Form::list(s.chars().map(|c| Form::string(c)))),
+        FormKind::String(ref s) => Ok(Form::list(s.chars().map(Form::string))),
         FormKind::Vector(ref vec) if vec.is_empty() => Ok(Form::nil()),
-        FormKind::Vector(vec) => Ok(Form::list(vec.into_iter())),
+        FormKind::Vector(vec) => Ok(Form::list(vec)),
+        FormKind::LazySeq(ref seq) if seq.step()?.is_none() => Ok(Form::nil()),
+        FormKind::LazySeq(_) => Ok(arg),
         _ => Err(crate::Error::InvalidArgument),
     }
 }
@@ -491,10 +1125,22 @@ fn conj(params: Form) -> Result<Form> {
             vec.extend(rest.values);
             Ok(Form::vector(vec))
         }
+        FormKind::LazySeq(_) => Ok(rest
+            .values
+            .into_iter()
+            .fold(collection, |seq, x| LazySeq::cons(x, seq).into())),
         _ => Err(crate::Error::InvalidArgument),
     }
 }
 
+fn gc(_params: Form) -> Result<Form> {
+    Ok(crate::gc::collect().to_form())
+}
+
+fn gc_stats(_params: Form) -> Result<Form> {
+    Ok(crate::gc::stats().to_form())
+}
+
 fn _template(_params: Form) -> Result<Form> {
     Err(crate::Error::InvalidArgument)
 }
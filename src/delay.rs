@@ -0,0 +1,221 @@
+//! Delays and promises
+//!
+//! A [`Delay`] computes its value the first time it is forced with `force` or `deref` and
+//! caches it, for values that are expensive and may not be needed. A [`Promise`] starts empty
+//! and is given its value once, with `deliver`. Both are reference types like atoms: copies
+//! share the value, and two are equal only if they are the same one.
+
+use std::{
+    cell::RefCell,
+    hash::{Hash, Hasher},
+    rc::{Rc, Weak},
+};
+
+use crate::{lazy::Thunk, Error, Form, Result};
+
+pub(crate) enum State {
+    Pending(Thunk),
+    Forcing,
+    Realized(Form),
+}
+
+/// A value computed when it is first needed
+#[derive(Clone)]
+pub struct Delay(Rc<RefCell<State>>);
+
+impl Delay {
+    fn from_thunk(thunk: Thunk) -> Delay {
+        let cell = Rc::new(RefCell::new(State::Pending(thunk)));
+        crate::gc::track_delay(&cell);
+        Delay(cell)
+    }
+
+    /// A delay whose value is computed by calling `f`
+    ///
+    /// The collector can't see the forms `f` holds, so they are never freed while the delay is
+    /// pending if they refer back to it. Use [`Delay::capturing`] for those.
+    pub fn new(f: impl Fn() -> Result<Form> + 'static) -> Delay {
+        Delay::from_thunk(Thunk::new(f))
+    }
+
+    /// A delay whose value is computed by calling `f` with `captured`
+    pub fn capturing<const N: usize>(
+        captured: [Form; N],
+        f: impl Fn(&[Form; N]) -> Result<Form> + 'static,
+    ) -> Delay {
+        Delay::from_thunk(Thunk::capturing(captured, f))
+    }
+
+    /// The value, computing it if this is the first time. If computing it fails, the delay
+    /// stays pending and the next force tries again.
+    pub fn force(&self) -> Result<Form> {
+        let state = std::mem::replace(&mut *self.0.borrow_mut(), State::Forcing);
+        let thunk = match state {
+            State::Pending(thunk) => thunk,
+            State::Forcing => return Err(Error::RecursiveDelay),
+            State::Realized(value) => {
+                *self.0.borrow_mut() = State::Realized(value.clone());
+                return Ok(value);
+            }
+        };
+        let result = crate::limits::step().and_then(|()| thunk.call());
+        *self.0.borrow_mut() = match result {
+            Ok(ref value) => State::Realized(value.clone()),
+            Err(_) => State::Pending(thunk),
+        };
+        result
+    }
+
+    /// Whether the value has been computed
+    pub fn is_realized(&self) -> bool {
+        matches!(*self.0.borrow(), State::Realized(_))
+    }
+
+    /// The value if it has been computed
+    pub fn value(&self) -> Option<Form> {
+        match *self.0.borrow() {
+            State::Realized(ref value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Access for the collector, which registers the cell of each delay
+impl Delay {
+    pub(crate) fn upgrade(cell: &Weak<RefCell<State>>) -> Option<Delay> {
+        cell.upgrade().map(Delay)
+    }
+
+    pub(crate) fn addr(&self) -> usize {
+        Rc::as_ptr(&self.0) as *const () as usize
+    }
+
+    pub(crate) fn strong_count(&self) -> usize {
+        Rc::strong_count(&self.0)
+    }
+
+    /// Call `f` with the forms a pending delay captured, or the value of a realized one.
+    /// Returns false if the delay is being forced.
+    pub(crate) fn for_each_form(&self, mut f: impl FnMut(&Form)) -> bool {
+        let Ok(state) = self.0.try_borrow() else {
+            return false;
+        };
+        match *state {
+            State::Pending(ref thunk) => thunk.captured().iter().for_each(f),
+            State::Forcing => {}
+            State::Realized(ref value) => f(value),
+        }
+        true
+    }
+
+    /// Empty the delay, returning what it held
+    pub(crate) fn clear(&self) -> State {
+        self.0.replace(State::Realized(Form::nil()))
+    }
+}
+
+impl PartialEq for Delay {
+    fn eq(&self, other: &Delay) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Delay {}
+
+impl Hash for Delay {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(Rc::as_ptr(&self.0) as *const () as usize);
+    }
+}
+
+impl From<Delay> for Form {
+    fn from(delay: Delay) -> Form {
+        Form::delay(delay)
+    }
+}
+
+/// A value delivered once, after the promise is created
+#[derive(Clone)]
+pub struct Promise(Rc<RefCell<Option<Form>>>);
+
+impl Promise {
+    pub fn new() -> Promise {
+        let cell = Rc::new(RefCell::new(None));
+        crate::gc::track_promise(&cell);
+        Promise(cell)
+    }
+
+    /// Give the promise its value, returning false if it already had one, which is kept
+    pub fn deliver(&self, value: Form) -> bool {
+        let mut slot = self.0.borrow_mut();
+        if slot.is_some() {
+            return false;
+        }
+        *slot = Some(value);
+        true
+    }
+
+    /// The delivered value
+    pub fn value(&self) -> Option<Form> {
+        self.0.borrow().clone()
+    }
+
+    pub fn is_delivered(&self) -> bool {
+        self.0.borrow().is_some()
+    }
+}
+
+/// Access for the collector, which registers the cell of each promise
+impl Promise {
+    pub(crate) fn upgrade(cell: &Weak<RefCell<Option<Form>>>) -> Option<Promise> {
+        cell.upgrade().map(Promise)
+    }
+
+    pub(crate) fn addr(&self) -> usize {
+        Rc::as_ptr(&self.0) as *const () as usize
+    }
+
+    pub(crate) fn strong_count(&self) -> usize {
+        Rc::strong_count(&self.0)
+    }
+
+    /// Call `f` with the delivered value. Returns false if it is being delivered.
+    pub(crate) fn for_each_form(&self, f: impl FnMut(&Form)) -> bool {
+        let Ok(slot) = self.0.try_borrow() else {
+            return false;
+        };
+        slot.iter().for_each(f);
+        true
+    }
+
+    /// Empty the promise, returning its value
+    pub(crate) fn clear(&self) -> Option<Form> {
+        self.0.take()
+    }
+}
+
+impl Default for Promise {
+    fn default() -> Promise {
+        Promise::new()
+    }
+}
+
+impl PartialEq for Promise {
+    fn eq(&self, other: &Promise) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Promise {}
+
+impl Hash for Promise {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(Rc::as_ptr(&self.0) as *const () as usize);
+    }
+}
+
+impl From<Promise> for Form {
+    fn from(promise: Promise) -> Form {
+        Form::promise(promise)
+    }
+}
@@ -0,0 +1,249 @@
+//! Destructuring of binding forms
+//!
+//! `let*`, `fn*` parameters and `def!` accept a pattern wherever they accept a symbol:
+//!
+//! - a symbol binds the whole value
+//! - a vector binds positional elements, with `& rest` binding the remaining elements as a list
+//!   and `:as name` binding the whole value. Missing elements bind nil.
+//! - a map binds values by key. `{a :x}` binds `a` to the value at `:x`, `:keys [a b]` and
+//!   `:strs [a b]` bind values at `:a`/`:b` and `"a"`/`"b"`, `:or {a default}` supplies
+//!   defaults for missing keys and `:as name` binds the whole map. Defaults are evaluated after
+//!   the keys that are present are bound, in the order of `:keys`, then `:strs`, so a default can
+//!   use the names listed before it. A list of alternating keys and values is read as a map,
+//!   which allows keyword arguments after `&`.
+//!
+//! Patterns nest, so `[a {:keys [b]} & [c]]` is valid.
+
+use std::collections::HashMap;
+
+use crate::{eval::interpret, form::Ident, intern::sym, lazy, Env, Error, Form, FormKind, Result};
+
+/// Where a pattern stores the values it binds
+pub(crate) trait Binder {
+    fn set(&mut self, name: Ident, value: Form);
+
+    /// Evaluate the `:or` default of `name`
+    fn default(&mut self, name: Ident, default: &Form) -> Result<Form>;
+}
+
+impl Binder for Env {
+    fn set(&mut self, name: Ident, value: Form) {
+        Env::set(self, name, value);
+    }
+
+    fn default(&mut self, _: Ident, default: &Form) -> Result<Form> {
+        interpret(default.clone(), self)
+    }
+}
+
+fn mismatch(pattern: &Form, found: Form) -> Error {
+    Error::BindingMismatch {
+        pattern: Box::new(pattern.clone()),
+        found,
+    }
+}
+
+fn invalid(pattern: &Form) -> Error {
+    Error::InvalidPattern(pattern.clone())
+}
+
+/// Bind `value` to `pattern` in `env`. `:or` defaults are evaluated in `env`.
+pub(crate) fn bind(pattern: &Form, value: Form, env: &mut impl Binder) -> Result<()> {
+    match pattern.kind {
+        FormKind::Symbol(ident) => {
+            env.set(ident, value);
+            Ok(())
+        }
+        FormKind::Vector(ref items) => bind_vector(pattern, items, value, env),
+        FormKind::HashMap(ref entries) => bind_map(pattern, entries, value, env),
+        _ => Err(invalid(pattern)),
+    }
+}
+
+/// Split a vector pattern into its positional patterns, the `&` rest pattern and the `:as` name
+pub(crate) fn split_vector<'a>(
+    pattern: &Form,
+    items: &'a [Form],
+) -> Result<(&'a [Form], Option<&'a Form>, Option<Ident>)> {
+    let (items, as_) = match items {
+        [init @ .., as_kw, name] if *as_kw == Form::keyword(sym::AS) => (
+            init,
+            Some(name.as_symbol().ok_or_else(|| invalid(pattern))?),
+        ),
+        _ => (items, None),
+    };
+    match items
+        .iter()
+        .position(|item| item.as_symbol() == Some(sym::AMPERSAND))
+    {
+        Some(i) if i + 2 == items.len() => Ok((&items[..i], Some(&items[i + 1]), as_)),
+        Some(_) => Err(invalid(pattern)),
+        None => Ok((items, None, as_)),
+    }
+}
+
+fn bind_vector(pattern: &Form, items: &[Form], value: Form, env: &mut impl Binder) -> Result<()> {
+    let (positional, rest, as_) = split_vector(pattern, items)?;
+    if let Some(name) = as_ {
+        env.set(name, value.clone());
+    }
+    let mut values = match value.kind {
+        FormKind::Nil => Vec::new().into_iter(),
+        FormKind::List(items) | FormKind::Vector(items) => items.into_iter(),
+        FormKind::LazySeq(_) => return bind_lazy(positional, rest, value, env),
+        _ => return Err(mismatch(pattern, value)),
+    };
+    for item in positional {
+        bind(item, values.next().unwrap_or_else(Form::nil), env)?;
+    }
+    if let Some(rest) = rest {
+        bind(rest, Form::list(values), env)?;
+    }
+    Ok(())
+}
+
+/// Bind the elements of a lazy sequence, realizing only as many as there are positional
+/// patterns. The rest pattern gets the unrealized remainder.
+fn bind_lazy(
+    positional: &[Form],
+    rest: Option<&Form>,
+    mut values: Form,
+    env: &mut impl Binder,
+) -> Result<()> {
+    for item in positional {
+        let (first, more) = lazy::uncons(values)?.unwrap_or_else(|| (Form::nil(), Form::nil()));
+        bind(item, first, env)?;
+        values = more;
+    }
+    if let Some(rest) = rest {
+        bind(rest, values, env)?;
+    }
+    Ok(())
+}
+
+/// The symbols listed after `:keys` or `:strs`
+fn key_names(pattern: &Form, names: &Form) -> Result<Vec<Ident>> {
+    match names.kind {
+        FormKind::Vector(ref names) => names
+            .iter()
+            .map(|name| name.as_symbol().ok_or_else(|| invalid(pattern)))
+            .collect(),
+        _ => Err(invalid(pattern)),
+    }
+}
+
+// Forms are map keys, see the note on `impl Hash for Form`
+#[allow(clippy::mutable_key_type)]
+fn bind_map(
+    pattern: &Form,
+    entries: &HashMap<Form, Form>,
+    value: Form,
+    env: &mut impl Binder,
+) -> Result<()> {
+    let map = match value.kind {
+        FormKind::Nil => HashMap::new(),
+        FormKind::HashMap(ref map) => map.clone(),
+        // Keyword arguments, as in `(fn* (& {:keys [k]}) k)`
+        FormKind::List(ref items) | FormKind::Vector(ref items) if items.len() % 2 == 0 => items
+            .chunks(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect(),
+        _ => return Err(mismatch(pattern, value)),
+    };
+    let defaults = match entries.get(&Form::keyword(sym::OR)) {
+        None => HashMap::new(),
+        Some(Form {
+            kind: FormKind::HashMap(defaults),
+            ..
+        }) => defaults
+            .iter()
+            .map(|(name, default)| {
+                let name = name.as_symbol().ok_or_else(|| invalid(pattern))?;
+                Ok((name, default))
+            })
+            .collect::<Result<HashMap<Ident, &Form>>>()?,
+        Some(_) => return Err(invalid(pattern)),
+    };
+    if let Some(name) = entries.get(&Form::keyword(sym::AS)) {
+        let name = name.as_symbol().ok_or_else(|| invalid(pattern))?;
+        env.set(name, value.clone());
+    }
+    // Each target with the key it is looked up by: the names after `:keys` and `:strs` in the
+    // order they are listed, then the `{a :x}` entries
+    let mut targets = Vec::new();
+    if let Some(names) = entries.get(&Form::keyword(sym::KEYS)) {
+        for name in key_names(pattern, names)? {
+            targets.push((Form::symbol(name), Form::keyword(name)));
+        }
+    }
+    if let Some(names) = entries.get(&Form::keyword(sym::STRS)) {
+        for name in key_names(pattern, names)? {
+            targets.push((Form::symbol(name), Form::string(name.name())));
+        }
+    }
+    for (key, target) in entries {
+        if !matches!(
+            key.kind,
+            FormKind::Keyword(sym::KEYS | sym::STRS | sym::AS | sym::OR)
+        ) {
+            targets.push((key.clone(), target.clone()));
+        }
+    }
+    // Everything found in the map is bound before any default is evaluated, and defaults are
+    // evaluated in the order above, so a default can refer to any name bound before it
+    let mut missing = Vec::new();
+    for (target, key) in targets {
+        match map.get(&key) {
+            Some(found) => bind(&target, found.clone(), env)?,
+            None => missing.push(target),
+        }
+    }
+    for target in missing {
+        let default = target
+            .as_symbol()
+            .and_then(|name| Some((name, *defaults.get(&name)?)));
+        let found = match default {
+            Some((name, default)) => Binder::default(env, name, default)?,
+            None => Form::nil(),
+        };
+        bind(&target, found, env)?;
+    }
+    Ok(())
+}
+
+/// Collect the names `pattern` binds, in the order they first appear. Invalid parts of the
+/// pattern are skipped, since binding reports them.
+pub(crate) fn names(pattern: &Form, names: &mut Vec<Ident>) {
+    let push = |name: Ident, names: &mut Vec<Ident>| {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    };
+    match pattern.kind {
+        FormKind::Symbol(sym::AMPERSAND) => {}
+        FormKind::Symbol(ident) => push(ident, names),
+        FormKind::Vector(ref items) => items.iter().for_each(|item| self::names(item, names)),
+        FormKind::HashMap(ref entries) => {
+            for (key, target) in entries {
+                match key.kind {
+                    FormKind::Keyword(sym::KEYS | sym::STRS) => {
+                        if let FormKind::Vector(ref listed) = target.kind {
+                            listed
+                                .iter()
+                                .filter_map(Form::as_symbol)
+                                .for_each(|name| push(name, names));
+                        }
+                    }
+                    FormKind::Keyword(sym::AS) => {
+                        if let Some(name) = target.as_symbol() {
+                            push(name, names);
+                        }
+                    }
+                    FormKind::Keyword(_) => {}
+                    _ => self::names(key, names),
+                }
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,214 @@
+//! Dynamic vars
+//!
+//! A var defined with `(def! ^:dynamic *name* value)` can be rebound for the extent of a
+//! `binding` form, and `set!` replaces the innermost binding. Bindings are restored when
+//! `binding` returns, whether or not its body succeeded.
+//!
+//! Dynamic vars are kept in the runtime of their interpreter by namespace-qualified name, which
+//! holds their root value and bindings, so native functions can read them as well. The
+//! definition in the environment only makes the name known. A symbol only takes the value of the
+//! var when it resolves to that definition, so locals of the same name shadow the var, bound or
+//! not.
+
+use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
+
+use itertools::Itertools;
+
+use crate::{
+    convert::Rest,
+    eval::interpret,
+    intern::sym,
+    runtime::{self, Runtime},
+    Env, Error, Form, FormKind, Ident, Result,
+};
+
+/// The name of a var: the namespace it is defined in, or `None` for the base environment, and
+/// its name there
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct VarName {
+    pub(crate) ns: Option<Ident>,
+    pub(crate) name: Ident,
+}
+
+impl VarName {
+    /// A var of the base environment, such as those `core::populate` defines
+    pub(crate) fn core(name: Ident) -> VarName {
+        VarName { ns: None, name }
+    }
+}
+
+impl fmt::Display for VarName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.ns {
+            Some(ns) => write!(f, "{ns}/{}", self.name),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+struct Var {
+    root: Form,
+    bindings: Vec<Form>,
+}
+
+impl Var {
+    /// The innermost binding, or the root value if there is none
+    fn current(&self) -> Form {
+        self.bindings
+            .last()
+            .cloned()
+            .unwrap_or_else(|| self.root.clone())
+    }
+}
+
+/// The dynamic vars of one interpreter
+#[derive(Default)]
+pub(crate) struct Vars(RefCell<HashMap<VarName, Var>>);
+
+impl Vars {
+    /// Call `f` with every root value and binding
+    pub(crate) fn for_each_value(&self, f: impl FnMut(&Form)) -> bool {
+        let Ok(vars) = self.0.try_borrow() else {
+            return false;
+        };
+        vars.values()
+            .flat_map(|var| std::iter::once(&var.root).chain(&var.bindings))
+            .for_each(f);
+        true
+    }
+}
+
+/// Whether the metadata of a `def!` target marks it as dynamic
+pub(crate) fn is_marked(symbol: &Form) -> bool {
+    match symbol.meta.as_deref() {
+        Some(Form {
+            kind: FormKind::HashMap(meta),
+            ..
+        }) => meta
+            .get(&Form::keyword(sym::DYNAMIC))
+            .is_some_and(Form::is_truthy),
+        _ => false,
+    }
+}
+
+pub(crate) fn is_dynamic(name: VarName, env: &Env) -> bool {
+    env.runtime().vars.0.borrow().contains_key(&name)
+}
+
+/// Declare `name` as dynamic in the interpreter of `env`, or update the root value of an
+/// existing dynamic var
+pub(crate) fn define(name: VarName, root: Form, env: &Env) {
+    env.runtime()
+        .vars
+        .0
+        .borrow_mut()
+        .entry(name)
+        .and_modify(|var| var.root = root.clone())
+        .or_insert_with(|| Var {
+            root,
+            bindings: Vec::new(),
+        });
+}
+
+/// The innermost binding of the dynamic var `name` in the interpreter of `env`, or its root
+/// value if it isn't bound
+pub(crate) fn value(name: VarName, env: &Env) -> Option<Form> {
+    let runtime = env.runtime();
+    let vars = runtime.vars.0.borrow();
+    vars.get(&name).map(Var::current)
+}
+
+/// The current value of the dynamic var `name` of the base environment, in the interpreter
+/// currently evaluating
+pub(crate) fn get(name: impl Into<Ident>) -> Option<Form> {
+    let runtime = runtime::current()?;
+    let vars = runtime.vars.0.borrow();
+    vars.get(&VarName::core(name.into())).map(Var::current)
+}
+
+/// Pops the bindings pushed by a `binding` form when dropped
+pub(crate) struct Restore {
+    runtime: Rc<Runtime>,
+    names: Vec<VarName>,
+}
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        let mut vars = self.runtime.vars.0.borrow_mut();
+        for name in &self.names {
+            if let Some(var) = vars.get_mut(name) {
+                var.bindings.pop();
+            }
+        }
+    }
+}
+
+/// The dynamic var `name` refers to in `env`, ignoring locals, or `Error::NotDynamic`
+pub(crate) fn resolve(name: Ident, env: &Env) -> Result<VarName> {
+    env.root()
+        .var(name)
+        .filter(|&var| is_dynamic(var, env))
+        .ok_or_else(|| Error::NotDynamic(name.name().into()))
+}
+
+/// Bind each of the dynamic vars in `pushed` in the interpreter of `env` until the returned
+/// guard is dropped
+pub(crate) fn push(pushed: Vec<(VarName, Form)>, env: &Env) -> Restore {
+    let runtime = env.runtime();
+    let names = pushed.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+    {
+        let mut vars = runtime.vars.0.borrow_mut();
+        for (name, value) in pushed {
+            vars.get_mut(&name)
+                .expect("checked that the var is dynamic")
+                .bindings
+                .push(value);
+        }
+    }
+    Restore { runtime, names }
+}
+
+/// Replace the innermost binding of dynamic var `name` in the interpreter of `env` with `value`
+pub(crate) fn replace(name: VarName, value: Form, env: &Env) -> Result<Form> {
+    let runtime = env.runtime();
+    let mut vars = runtime.vars.0.borrow_mut();
+    let var = vars
+        .get_mut(&name)
+        .expect("checked that the var is dynamic");
+    match var.bindings.last_mut() {
+        Some(current) => {
+            *current = value.clone();
+            Ok(value)
+        }
+        None => Err(Error::Unbound(name.to_string())),
+    }
+}
+
+/// `(binding [name value ...] body...)`
+pub(crate) fn binding(form: Form, env: &mut Env) -> Result<Form> {
+    let (_, bindings, Rest { values: body }): ((), Vec<Form>, Rest) = form.try_into()?;
+    if bindings.len() % 2 != 0 {
+        return Err(Error::InvalidArgument);
+    }
+    // All values are evaluated before any of the bindings take effect
+    let mut pushed = Vec::with_capacity(bindings.len() / 2);
+    for (name, value) in bindings.into_iter().tuples() {
+        let name = resolve(name.try_into()?, env)?;
+        pushed.push((name, interpret(value, env)?));
+    }
+    let restore = push(pushed, env);
+    let mut result = Form::nil();
+    for form in body {
+        result = interpret(form, env)?;
+    }
+    drop(restore);
+    Ok(result)
+}
+
+/// `(set! name value)`, which replaces the innermost binding of a dynamic var
+pub(crate) fn set(form: Form, env: &mut Env) -> Result<Form> {
+    let (_, name, value): ((), Ident, Form) = form.try_into()?;
+    let name = resolve(name, env)?;
+    let value = interpret(value, env)?;
+    replace(name, value, env)
+}
@@ -1,11 +1,32 @@
-use std::{collections::HashMap, rc::Rc, sync::Mutex};
+use std::{
+    cell::{Cell, RefCell},
+    rc::{Rc, Weak},
+    sync::Mutex,
+};
 
-use crate::{Error, Form, Result};
+use crate::{
+    dynamic::{self, VarName},
+    intern::{Ident, IdentMap},
+    namespace::Namespace,
+    runtime::Runtime,
+    Error, Form, Result,
+};
 
 #[derive(Clone, Debug)]
-struct EnvInner {
-    data: HashMap<String, Form>,
-    parent: Option<Env>,
+pub(crate) struct EnvInner {
+    pub(crate) data: IdentMap<Form>,
+    pub(crate) parent: Option<Env>,
+    /// Set on the root environment of a namespace
+    pub(crate) namespace: Option<Box<Namespace>>,
+    /// Set on the base environment
+    pub(crate) runtime: Option<Rc<Runtime>>,
+}
+
+impl EnvInner {
+    /// Whether these are definitions of a namespace or the base environment, rather than locals
+    fn is_global(&self) -> bool {
+        self.parent.is_none() || self.namespace.is_some()
+    }
 }
 
 /// Env
@@ -13,64 +34,236 @@ struct EnvInner {
 /// Prefer the Extend implementation - it avoids repeatedly taking/releasing the mutex lock
 #[derive(Clone, Debug)]
 pub struct Env {
-    inner: Rc<Mutex<EnvInner>>,
+    pub(crate) inner: Rc<Mutex<EnvInner>>,
 }
 
+thread_local! {
+    /// Incremented whenever a definition or namespace changes, so cached lookups look again
+    static GENERATION: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Forget all cached lookups
+pub(crate) fn invalidate() {
+    GENERATION.with(|generation| generation.set(generation.get() + 1));
+}
+
+/// Where a lookup found its value: the generation and environment it was made in, the
+/// environment whose definitions hold the value, the var it is and whether that var is dynamic.
+/// The value itself isn't kept, as it is often the closure doing the lookup, and holding it would
+/// keep the closure alive through its own code.
+type Lookup = (
+    u64,
+    Weak<Mutex<EnvInner>>,
+    Weak<Mutex<EnvInner>>,
+    Option<VarName>,
+    bool,
+);
+
+/// What a lookup found
+struct Found {
+    value: Form,
+    /// The environment whose definitions hold the value under the name looked up. Names
+    /// resolved through another namespace have none.
+    holder: Option<Rc<Mutex<EnvInner>>>,
+    /// The var the name refers to, if it is defined in a namespace or the base environment
+    /// rather than bound locally
+    var: Option<VarName>,
+    /// Whether the var is dynamic, in which case the interpreter holds its value
+    dynamic: bool,
+}
+
+impl Found {
+    /// The value, or the current value of the var if it is dynamic
+    fn current(self, env: &Env) -> Form {
+        match self.var {
+            Some(var) if self.dynamic => dynamic::value(var, env).unwrap_or(self.value),
+            _ => self.value,
+        }
+    }
+}
+
+/// Where the last lookup of one name found it, valid until the environment or any definition
+/// changes
+#[derive(Default)]
+pub(crate) struct LookupCache(RefCell<Option<Lookup>>);
+
 impl Env {
+    fn from_inner(inner: EnvInner) -> Env {
+        let inner = Rc::new(Mutex::new(inner));
+        crate::gc::track_env(&inner);
+        Env { inner }
+    }
+
     pub fn new() -> Env {
-        Env {
-            inner: Rc::new(Mutex::new(EnvInner {
-                data: HashMap::new(),
-                parent: None,
-            })),
-        }
+        Env::from_inner(EnvInner {
+            data: IdentMap::default(),
+            parent: None,
+            namespace: None,
+            runtime: Some(Rc::default()),
+        })
     }
 
     pub fn new_with(parent: &Env) -> Env {
-        Env {
-            inner: Rc::new(Mutex::new(EnvInner {
-                data: HashMap::new(),
-                parent: Some(parent.clone()),
-            })),
+        Env::from_inner(EnvInner {
+            data: IdentMap::default(),
+            parent: Some(parent.clone()),
+            namespace: None,
+            runtime: None,
+        })
+    }
+
+    pub(crate) fn new_namespace(name: Ident, base: &Env) -> Env {
+        Env::from_inner(EnvInner {
+            data: IdentMap::default(),
+            parent: Some(base.clone()),
+            namespace: Some(Box::new(Namespace::new(name, &base.runtime()))),
+            runtime: None,
+        })
+    }
+
+    pub fn set(&mut self, key: impl Into<Ident>, value: Form) {
+        let mut guard = self.inner.lock().expect("Poisoned mutex");
+        self.invalidate_for(&guard);
+        guard.data.insert(key.into(), value);
+    }
+
+    /// Forget cached lookups before changing the bindings of this environment, if code may
+    /// have looked past it already: it holds definitions, or closures have captured it. Locals
+    /// are otherwise bound before any code runs in the environment.
+    fn invalidate_for(&self, inner: &EnvInner) {
+        if inner.is_global() || Rc::strong_count(&self.inner) > 1 {
+            invalidate();
         }
     }
 
-    pub fn set(&mut self, key: impl AsRef<str>, value: Form) {
-        self.inner
-            .lock()
-            .expect("Poisoned mutex")
-            .data
-            .insert(key.as_ref().into(), value);
+    /// Get the value assigned to `key`, or return an `UnknownSymbol` error. A dynamic var
+    /// that is bound has the value of its binding.
+    pub fn get(&self, key: impl Into<Ident>) -> Result<Form> {
+        self.locate(key.into()).map(|found| found.current(self))
     }
 
-    /// Get the value assigned to `key`, or return an `UnknownSymbol` error
-    pub fn get(&self, key: &str) -> Result<Form> {
-        let guard = self.inner.lock().expect("Poisoned mutex");
-        // Food
-        if let Some(value) = guard.data.get(key) {
-            Ok(value.clone())
-        } else if let Some(ref parent) = guard.parent {
-            parent.get(key)
-        } else {
-            Err(Error::UnknownSymbol(key.into()))
+    /// The var `key` refers to, unless it is unknown or bound locally
+    pub(crate) fn var(&self, key: Ident) -> Option<VarName> {
+        self.locate(key).ok()?.var
+    }
+
+    /// Like `get`, looking in the environment recorded in `cache` if nothing changed since the
+    /// name was looked up in this environment
+    pub(crate) fn get_cached(&self, key: Ident, cache: &LookupCache) -> Result<Form> {
+        let generation = GENERATION.with(Cell::get);
+        if let Some((cached, ref env, ref holder, var, dynamic)) = *cache.0.borrow() {
+            if cached == generation && env.as_ptr() == Rc::as_ptr(&self.inner) {
+                let value = holder.upgrade().and_then(|holder| {
+                    let guard = holder.lock().expect("Poisoned mutex");
+                    guard.data.get(&key).cloned()
+                });
+                if let Some(value) = value {
+                    return Ok(Found {
+                        value,
+                        holder: None,
+                        var,
+                        dynamic,
+                    }
+                    .current(self));
+                }
+            }
         }
+        let found = self.locate(key)?;
+        *cache.0.borrow_mut() = found.holder.as_ref().map(|holder| {
+            (
+                generation,
+                Rc::downgrade(&self.inner),
+                Rc::downgrade(holder),
+                found.var,
+                found.dynamic,
+            )
+        });
+        Ok(found.current(self))
+    }
+
+    /// Look up `key`, noting where it was found and whether it is a dynamic var
+    fn locate(&self, key: Ident) -> Result<Found> {
+        let mut found = self.find(key)?;
+        found.dynamic = found.var.is_some_and(|var| dynamic::is_dynamic(var, self));
+        Ok(found)
     }
 
-    /// Retrieve the root environment
+    /// Look up `key`, noting where it was found
+    fn find(&self, key: Ident) -> Result<Found> {
+        // The lock is released before moving to the parent, because resolving a name through
+        // another namespace locks that namespace's environment
+        let parent = {
+            let guard = self.inner.lock().expect("Poisoned mutex");
+            if let Some(value) = guard.data.get(&key) {
+                let global = guard.is_global();
+                return Ok(Found {
+                    value: value.clone(),
+                    holder: Some(self.inner.clone()),
+                    var: global.then(|| VarName {
+                        ns: guard.namespace.as_ref().map(|ns| ns.name),
+                        name: key,
+                    }),
+                    dynamic: false,
+                });
+            }
+            if let Some((value, var)) = guard
+                .namespace
+                .as_ref()
+                .and_then(|ns| ns.resolve(&guard.data, key))
+            {
+                return Ok(Found {
+                    value,
+                    holder: None,
+                    var: Some(var),
+                    dynamic: false,
+                });
+            }
+            guard.parent.clone()
+        };
+        match parent {
+            Some(parent) => parent.find(key),
+            None => Err(Error::UnknownSymbol(key.name().into())),
+        }
+    }
+
+    /// Retrieve the root environment of the current namespace
     pub fn root(&self) -> Env {
+        let guard = self.inner.lock().expect("Poisoned mutex");
+        match guard.parent {
+            Some(ref parent) if guard.namespace.is_none() => parent.root(),
+            _ => self.clone(),
+        }
+    }
+
+    /// Retrieve the environment shared by all namespaces
+    pub(crate) fn base(&self) -> Env {
         if let Some(ref parent) = self.inner.lock().expect("Poisoned mutex").parent {
-            return parent.root();
+            parent.base()
         } else {
-            return self.clone();
+            self.clone()
         }
     }
+
+    /// The name of the current namespace, if `ns` or `require` has been used
+    pub fn namespace(&self) -> Option<Ident> {
+        let root = self.root();
+        let guard = root.inner.lock().expect("Poisoned mutex");
+        guard.namespace.as_ref().map(|ns| ns.name)
+    }
+}
+
+impl Default for Env {
+    fn default() -> Env {
+        Env::new()
+    }
 }
 
-impl Extend<(String, Form)> for Env {
-    fn extend<T: IntoIterator<Item = (String, Form)>>(&mut self, iter: T) {
-        let data = &mut self.inner.lock().expect("Poisoned mutex").data;
+impl<K: Into<Ident>> Extend<(K, Form)> for Env {
+    fn extend<T: IntoIterator<Item = (K, Form)>>(&mut self, iter: T) {
+        let mut guard = self.inner.lock().expect("Poisoned mutex");
+        self.invalidate_for(&guard);
         for elem in iter {
-            data.insert(elem.0, elem.1);
+            guard.data.insert(elem.0.into(), elem.1);
         }
     }
 }
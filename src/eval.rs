@@ -1,28 +1,73 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, rc::Rc};
 
-use crate::{form::Ident, Env, Error, Form, FormKind, Result};
+use crate::{
+    convert::Rest,
+    destructure::{self, bind},
+    dynamic, exec,
+    form::{Arity, Ident, UserFn},
+    intern::sym,
+    interrupt, lazy, limits, namespace, pattern,
+    special::{self, Next, Step},
+    trace::{self, Call, Site},
+    Env, Error, Form, FormKind, Result,
+};
 
 fn def(form: Form, env: &mut Env) -> Result<Form> {
-    let (_, symbol, value): ((), Ident, Form) = form.try_into()?;
-    let evaluated = eval(value, env)?;
-    env.set(&symbol.name, evaluated.clone());
+    let (_, symbol, value): ((), Form, Form) = form.try_into()?;
+    let evaluated = interpret(value, env)?;
+    define(symbol, evaluated, env)
+}
+
+/// Define `symbol`, or the names in a destructuring pattern, as `evaluated` in `env`
+pub(crate) fn define(symbol: Form, mut evaluated: Form, env: &mut Env) -> Result<Form> {
+    if !symbol.is_symbol() {
+        bind(&symbol, evaluated.clone(), env)?;
+        return Ok(evaluated);
+    }
+    // Metadata on the symbol (e.g. `(def! ^:private x 1)`) is merged onto the value
+    if let Some(symbol_meta) = symbol.meta.clone() {
+        evaluated = merge_meta(evaluated, *symbol_meta);
+    }
+    let dynamic = dynamic::is_marked(&symbol);
+    let private = namespace::is_private(&symbol);
+    let symbol: Ident = symbol.try_into()?;
+    env.set(symbol, evaluated.clone());
+    // Only definitions in a namespace or the base environment are vars
+    if let Some(var) = env.var(symbol) {
+        if dynamic || dynamic::is_dynamic(var, env) {
+            dynamic::define(var, evaluated.clone(), env);
+        }
+    }
+    namespace::set_private(env, symbol, private);
     Ok(evaluated)
 }
 
+fn merge_meta(target: Form, extra: Form) -> Form {
+    if !crate::core::supports_meta(&target) {
+        return target;
+    }
+    let merged = match (crate::core::form_meta(&target).kind, extra.kind) {
+        (FormKind::HashMap(mut existing), FormKind::HashMap(extra)) => {
+            existing.extend(extra);
+            Form::hash_map(existing)
+        }
+        (_, kind) => Form { kind, meta: None },
+    };
+    crate::core::set_meta(target, merged).expect("checked that target supports metadata")
+}
+
 fn defmacro(form: Form, env: &mut Env) -> Result<Form> {
     let (_, symbol, maybe_macro): ((), Ident, Form) = form.try_into()?;
-    let evaluated = eval(maybe_macro, env)?;
+    let evaluated = interpret(maybe_macro, env)?;
+    define_macro(symbol, evaluated, env)
+}
+
+pub(crate) fn define_macro(symbol: Ident, evaluated: Form, env: &mut Env) -> Result<Form> {
     let as_macro = match evaluated.kind {
-        FormKind::UserFn {
-            binds,
-            bind_rest,
-            body,
-            env,
-            is_macro: _,
-        } => Form::macro_(binds, bind_rest, *body, env),
+        FormKind::UserFn(f) => Form::closure(f.arities.clone(), f.env.clone(), true),
         _ => return Err(Error::InvalidArgument),
     };
-    env.set(&symbol.name, as_macro.clone());
+    env.set(symbol, as_macro.clone());
     Ok(as_macro)
 }
 
@@ -30,14 +75,11 @@ fn let_(form: Form, env: &Env) -> Result<(Form, Env)> {
     let (_, bindings, to_evaluate): ((), Vec<Form>, Form) = form.try_into()?;
     let mut iter = bindings.into_iter().fuse();
     let mut env = Env::new_with(env);
-    let mut evaluated;
     loop {
-        let symbol: Option<Ident> = iter.next().map(TryInto::try_into).transpose()?;
-        let value: Option<Form> = iter.next().map(TryInto::try_into).transpose()?;
-        match (symbol, value) {
-            (Some(symbol), Some(value)) => {
-                evaluated = eval(value, &mut env)?;
-                env.set(symbol.name, evaluated);
+        match (iter.next(), iter.next()) {
+            (Some(pattern), Some(value)) => {
+                let evaluated = interpret(value, &mut env)?;
+                bind(&pattern, evaluated, &mut env)?;
             }
             (None, None) => break,
             _ => return Err(Error::InvalidArgument),
@@ -46,22 +88,136 @@ fn let_(form: Form, env: &Env) -> Result<(Form, Env)> {
     Ok((to_evaluate, env))
 }
 
-fn fn_(form: Form, env: &Env) -> Result<Form> {
-    let (_, bind_symbols, body): ((), Vec<Ident>, Form) = form.try_into()?;
-    let mut iter = bind_symbols.into_iter();
+/// `(match value pattern body ...)` evaluates the body of the first clause whose
+/// [`pattern`](crate::pattern) matches the value, with the names the pattern binds. A clause
+/// can be `pattern :guard test body`, to also require `test` to be truthy.
+fn match_(form: Form, env: &mut Env) -> Result<(Form, Env)> {
+    let (_, value, Rest { values: clauses }): ((), Form, Rest) = form.try_into()?;
+    let clauses = pattern::clauses(&clauses)?;
+    let value = interpret(value, env)?;
+    for clause in clauses {
+        let predicates = clause
+            .pattern
+            .predicates()
+            .iter()
+            .map(|predicate| interpret(predicate.clone(), env))
+            .collect::<Result<Vec<_>>>()?;
+        let Some(bindings) = clause.pattern.matches_with(&value, &predicates)? else {
+            continue;
+        };
+        let mut clause_env = Env::new_with(env);
+        for (name, bound) in bindings {
+            clause_env.set(name, bound);
+        }
+        if let Some(guard) = clause.guard {
+            if !interpret(guard, &mut clause_env)?.is_truthy() {
+                continue;
+            }
+        }
+        return Ok((clause.body, clause_env));
+    }
+    Err(Error::NoMatch(value))
+}
+
+/// Build an arity from a parameter list and its body
+fn arity(params: Vec<Form>, body: Form) -> Arity {
+    let mut iter = params.into_iter();
     let binds = iter
         .by_ref()
-        .take_while(|ident| ident.name != "&")
+        .take_while(|pattern| pattern.as_symbol() != Some(sym::AMPERSAND))
         .collect::<Vec<_>>();
     let bind_rest = iter.next();
+    Arity {
+        binds,
+        bind_rest,
+        body,
+    }
+}
+
+/// `(fn* ([x] ...) ([x y & more] ...))` lists one clause per arity, each starting with a
+/// parameter vector
+fn is_multi_arity(clauses: &[Form]) -> bool {
+    !clauses.is_empty()
+        && clauses.iter().all(|clause| {
+            clause.is_list()
+                && clause
+                    .as_slice()
+                    .and_then(<[Form]>::first)
+                    .is_some_and(Form::is_vector)
+        })
+}
+
+/// Create a closure over `env`. Closures created by the same `fn*` form share their compiled
+/// code through `compiled`, and the form is checked for misplaced `recur` only when the first of
+/// them is created.
+pub(crate) fn fn_(form: Form, env: &Env, compiled: exec::Cache) -> Result<Form> {
+    let (_, Rest { values: clauses }): ((), Rest) = form.try_into()?;
+    let arities = if is_multi_arity(&clauses) {
+        clauses
+            .into_iter()
+            .map(|clause| {
+                let (params, Rest { values: mut body }): (Vec<Form>, Rest) = clause.try_into()?;
+                let body = match body.len() {
+                    0 | 1 => body.pop().unwrap_or_else(Form::nil),
+                    _ => Form::list(std::iter::once(Form::symbol(sym::DO)).chain(body)),
+                };
+                Ok(arity(params, body))
+            })
+            .collect::<Result<Vec<_>>>()?
+    } else {
+        let (params, body): (Vec<Form>, Form) = Form::list(clauses).try_into()?;
+        vec![arity(params, body)]
+    };
+    check_arities(&arities)?;
+    if compiled.tail_checked.get().is_none() {
+        for arity in &arities {
+            let mut params = Vec::new();
+            for pattern in arity.binds.iter().chain(&arity.bind_rest) {
+                destructure::names(pattern, &mut params);
+            }
+            check_tail(&arity.body, true, env, &params)?;
+        }
+        let _ = compiled.tail_checked.set(());
+    }
     let closure_env = Env::new_with(env);
-    Ok(Form::user_fn(binds, bind_rest, body, closure_env))
+    Ok(Form::compiled_closure(arities, closure_env, compiled))
+}
+
+fn check_arities(arities: &[Arity]) -> Result<()> {
+    let mut variadic = arities.iter().filter(|arity| arity.bind_rest.is_some());
+    let variadic = match (variadic.next(), variadic.next()) {
+        (_, Some(_)) => {
+            return Err(Error::InvalidFnDefinition(
+                "only one arity can take rest arguments",
+            ))
+        }
+        (variadic, None) => variadic,
+    };
+    let mut fixed = arities
+        .iter()
+        .filter(|arity| arity.bind_rest.is_none())
+        .map(|arity| arity.binds.len())
+        .collect::<Vec<_>>();
+    fixed.sort_unstable();
+    if fixed.windows(2).any(|pair| pair[0] == pair[1]) {
+        return Err(Error::InvalidFnDefinition(
+            "two arities take the same number of arguments",
+        ));
+    }
+    if let (Some(variadic), Some(&max)) = (variadic, fixed.last()) {
+        if variadic.binds.len() < max {
+            return Err(Error::InvalidFnDefinition(
+                "a fixed arity takes more arguments than the variadic one",
+            ));
+        }
+    }
+    Ok(())
 }
 
 fn if_(form: Form, env: &mut Env) -> Result<Form> {
     let (_, predicate, on_true, on_false): ((), Form, Form, Form) = form.try_into()?;
-    let eval_predicate = eval(predicate, env)?;
-    if TryInto::<bool>::try_into(eval_predicate)? {
+    let eval_predicate = interpret(predicate, env)?;
+    if eval_predicate.is_truthy() {
         Ok(on_true)
     } else {
         Ok(on_false)
@@ -74,7 +230,7 @@ fn do_(form: Form, env: &mut Env) -> Result<Form> {
         .collect::<Vec<Form>>();
     let last = params.pop().unwrap_or_else(Form::nil);
     for form in params {
-        let _ = eval(form, env)?;
+        let _ = interpret(form, env)?;
     }
     Ok(last)
 }
@@ -84,8 +240,8 @@ fn quote(form: Form) -> Result<Form> {
     Ok(quoted)
 }
 
-fn quasiquote_(form: Form) -> Result<Form> {
-    if form.as_fn_name() == Some("unquote") {
+pub(crate) fn quasiquote_(form: Form) -> Result<Form> {
+    if form.as_fn_name() == Some(sym::UNQUOTE) {
         let (_, arg): (Form, Form) = form.try_into()?;
         Ok(arg)
     } else if form.is_empty_list() {
@@ -98,24 +254,24 @@ fn quasiquote_(form: Form) -> Result<Form> {
             .try_into_iter()
             .expect("previously confirmed as list")
             .rfold(Ok(Form::list([])), |accum: Result<Form>, elem| {
-                if elem.as_fn_name() == Some("splice-unquote") {
+                if elem.as_fn_name() == Some(sym::SPLICE_UNQUOTE) {
                     let (_, arg): (Form, Form) = elem.try_into()?;
-                    Ok(Form::list([Form::symbol("concat"), arg, accum?]))
+                    Ok(Form::list([Form::symbol(sym::CONCAT), arg, accum?]))
                 } else {
                     Ok(Form::list([
-                        Form::symbol("cons"),
+                        Form::symbol(sym::CONS),
                         quasiquote_(elem)?,
                         accum?,
                     ]))
                 }
             })?;
         if form.is_vector() {
-            Ok(Form::list([Form::symbol("vec"), result]))
+            Ok(Form::list([Form::symbol(sym::VEC), result]))
         } else {
             Ok(result)
         }
     } else if form.is_symbol() || form.is_hash_map() {
-        Ok(Form::list([Form::symbol("quote"), form]))
+        Ok(Form::list([Form::symbol(sym::QUOTE), form]))
     } else {
         Ok(form)
     }
@@ -144,78 +300,400 @@ fn extract_fn(form: Form) -> Result<(Form, Form)> {
     }
 }
 
-fn as_macro_call(form: &Form, env: &Env) -> Option<Form> {
+pub(crate) fn as_macro_call(form: &Form, env: &Env) -> Option<Form> {
     let name = form.as_fn_name()?;
     env.get(name).ok().filter(Form::is_macro)
 }
 
-fn macro_expand(mut form: Form, env: &Env) -> Result<Form> {
+/// Call `macro_` with the arguments of the macro call `form`
+pub(crate) fn expand_call(macro_: Form, form: Form) -> Result<Form> {
+    let params = Form::list(form.try_into_iter()?.skip(1));
+    macro_.call(params).and_then(lazy::to_code)
+}
+
+pub(crate) fn macro_expand(mut form: Form, env: &Env) -> Result<Form> {
     while let Some(macro_) = as_macro_call(&form, env) {
-        let params = Form::list(form.try_into_iter()?.skip(1));
-        form = macro_.call(params)?;
+        form = expand_call(macro_, form)?;
     }
     Ok(form)
 }
 
+/// Expand `form` one step if it is a macro call
+pub(crate) fn macro_expand_1(form: Form, env: &Env) -> Result<Form> {
+    match as_macro_call(&form, env) {
+        Some(macro_) => expand_call(macro_, form),
+        None => Ok(form),
+    }
+}
+
+/// Expand every macro call in `form`, leaving quoted forms and the parameters of `fn*` alone.
+/// Locals that shadow a macro aren't tracked, so calls of them are expanded too.
+pub fn macro_expand_all(form: Form, env: &Env) -> Result<Form> {
+    let form = macro_expand(form, env)?;
+    let all = |forms: Vec<Form>| {
+        forms
+            .into_iter()
+            .map(|form| macro_expand_all(form, env))
+            .collect::<Result<Vec<_>>>()
+    };
+    let head = form.as_fn_name();
+    let kind = match form.kind {
+        FormKind::List(_) if head == Some(sym::QUOTE) => return Ok(form),
+        FormKind::List(mut items) if head == Some(sym::FN) => {
+            let mut clauses = items.split_off(1);
+            if is_multi_arity(&clauses) {
+                clauses = clauses
+                    .into_iter()
+                    .map(|clause| match clause.kind {
+                        FormKind::List(mut clause_items) => {
+                            let body = clause_items.split_off(1);
+                            clause_items.extend(all(body)?);
+                            Ok(Form {
+                                kind: FormKind::List(clause_items),
+                                meta: clause.meta,
+                            })
+                        }
+                        _ => Ok(clause),
+                    })
+                    .collect::<Result<_>>()?;
+            } else if !clauses.is_empty() {
+                let body = clauses.split_off(1);
+                clauses.extend(all(body)?);
+            }
+            items.extend(clauses);
+            FormKind::List(items)
+        }
+        FormKind::List(items) => FormKind::List(all(items)?),
+        FormKind::Vector(items) => FormKind::Vector(all(items)?),
+        FormKind::HashMap(entries) => FormKind::HashMap(
+            entries
+                .into_iter()
+                .map(|(k, v)| Ok((macro_expand_all(k, env)?, macro_expand_all(v, env)?)))
+                .collect::<Result<_>>()?,
+        ),
+        kind => kind,
+    };
+    Ok(Form {
+        kind,
+        meta: form.meta,
+    })
+}
+
+/// How far the `macroexpand` special forms expand their argument
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Expansion {
+    /// `macroexpand-1`
+    Once,
+    /// `macroexpand`
+    Outer,
+    /// `macroexpand-all`
+    All,
+}
+
+impl Expansion {
+    /// The expansion done by the special form `name`, if it is one of them
+    pub(crate) fn of(name: Ident) -> Option<Expansion> {
+        match name {
+            sym::MACROEXPAND_1 => Some(Expansion::Once),
+            sym::MACROEXPAND => Some(Expansion::Outer),
+            sym::MACROEXPAND_ALL => Some(Expansion::All),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn expand(self, form: Form, env: &Env) -> Result<Form> {
+        match self {
+            Expansion::Once => macro_expand_1(form, env),
+            Expansion::Outer => macro_expand(form, env),
+            Expansion::All => macro_expand_all(form, env),
+        }
+    }
+}
+
+/// The steps `form` goes through as its outermost macro call is expanded, one `macroexpand-1`
+/// at a time, starting with `form` itself. The REPL shows these for `:expand`.
+pub fn expansion_steps(form: Form, env: &Env) -> Result<Vec<Form>> {
+    let mut steps = vec![form.clone()];
+    let mut form = form;
+    while let Some(macro_) = as_macro_call(&form, env) {
+        form = expand_call(macro_, form)?;
+        steps.push(form.clone());
+    }
+    Ok(steps)
+}
+
 fn apply_native_fn(f: Form, params: Form) -> Result<Form> {
     assert!(params.is_list());
     if let FormKind::NativeFn(f) = f.kind {
-        Ok(f(params)?)
+        let result = f.call(params)?;
+        limits::allocate(&result)?;
+        Ok(result)
     } else {
         panic!("apply_native_fn called with wrong Form type: {:?}", f)
     }
 }
 
-fn apply_user_fn(f: Form, params: Form) -> Result<(Form, Env)> {
+/// Describe the argument counts a function accepts, e.g. "1, 2 or at least 3"
+fn expected_arities(user_fn: &UserFn) -> String {
+    let mut counts = user_fn
+        .arities
+        .iter()
+        .map(|arity| match arity.bind_rest {
+            Some(_) => (arity.binds.len(), format!("at least {}", arity.binds.len())),
+            None => (arity.binds.len(), arity.binds.len().to_string()),
+        })
+        .collect::<Vec<_>>();
+    counts.sort();
+    let mut counts = counts.into_iter().map(|(_, text)| text).collect::<Vec<_>>();
+    match counts.pop() {
+        Some(last) if !counts.is_empty() => format!("{} or {last}", counts.join(", ")),
+        Some(last) => last,
+        None => "no arguments".to_string(),
+    }
+}
+
+/// The index of the arity of `user_fn` that a call with `count` arguments uses: the fixed
+/// arity that matches, or else the variadic one
+pub(crate) fn select_arity(user_fn: &UserFn, count: usize) -> Result<usize> {
+    user_fn
+        .arities
+        .iter()
+        .position(|arity| arity.bind_rest.is_none() && arity.accepts(count))
+        .or_else(|| {
+            user_fn
+                .arities
+                .iter()
+                .position(|arity| arity.accepts(count))
+        })
+        .ok_or_else(|| Error::ArityMismatch {
+            found: count,
+            expected: expected_arities(user_fn),
+        })
+}
+
+/// Where `recur` jumps to: the innermost `loop`, or the arity of the function being called
+pub(crate) enum RecurTarget {
+    Fn {
+        user_fn: Rc<UserFn>,
+        arity: usize,
+    },
+    Loop {
+        binds: Vec<Form>,
+        body: Form,
+        env: Env,
+    },
+}
+
+impl RecurTarget {
+    /// Bind the arguments of `recur` and return the body to continue with
+    fn rebind(&self, args: Vec<Form>) -> Result<(Form, Env)> {
+        match *self {
+            RecurTarget::Fn { ref user_fn, arity } => {
+                let arity = &user_fn.arities[arity];
+                // The rest parameter is passed as a single sequence, not spread
+                let expected = arity.binds.len() + usize::from(arity.bind_rest.is_some());
+                if args.len() != expected {
+                    return Err(Error::ArityMismatch {
+                        found: args.len(),
+                        expected: expected.to_string(),
+                    });
+                }
+                let mut env = Env::new_with(&user_fn.env);
+                let patterns = arity.binds.iter().chain(&arity.bind_rest);
+                for (pattern, value) in patterns.zip(args) {
+                    bind(pattern, value, &mut env)?;
+                }
+                Ok((arity.body.clone(), env))
+            }
+            RecurTarget::Loop {
+                ref binds,
+                ref body,
+                ref env,
+            } => {
+                if args.len() != binds.len() {
+                    return Err(Error::ArityMismatch {
+                        found: args.len(),
+                        expected: binds.len().to_string(),
+                    });
+                }
+                let mut env = Env::new_with(env);
+                for (pattern, value) in binds.iter().zip(args) {
+                    bind(pattern, value, &mut env)?;
+                }
+                Ok((body.clone(), env))
+            }
+        }
+    }
+}
+
+fn apply_user_fn(f: Form, params: Form) -> Result<(Form, Env, RecurTarget)> {
     assert!(f.is_user_fn() || f.is_macro());
     assert!(params.is_list());
     match f.kind {
-        FormKind::UserFn {
-            binds,
-            bind_rest,
-            body,
-            env: closure_env,
-            is_macro: _,
-        } => {
-            let mut env = Env::new_with(&closure_env);
-            let mut param_iter = params.try_into_iter()?.fuse();
-            let mut binds_iter = binds.into_iter().fuse();
-            let mut rest = Vec::new();
-            loop {
-                match (binds_iter.next(), param_iter.next()) {
-                    (Some(bind), Some(value)) => env.set(bind.name, value),
-                    (None, Some(value)) if bind_rest.is_some() => rest.push(value),
-                    (None, Some(_)) => {
-                        // Parameter isn't used, no reason to save it
+        FormKind::UserFn(user_fn) => {
+            let mut params = params.try_into_iter()?.collect::<Vec<_>>();
+            let index = select_arity(&user_fn, params.len())?;
+            let arity = &user_fn.arities[index];
+            let mut env = Env::new_with(&user_fn.env);
+            let rest = params.split_off(arity.binds.len());
+            for (pattern, value) in arity.binds.iter().zip(params) {
+                bind(pattern, value, &mut env)?;
+            }
+            if let Some(ref bind_rest) = arity.bind_rest {
+                bind(bind_rest, Form::list(rest), &mut env)?;
+            }
+            let body = arity.body.clone();
+            let target = RecurTarget::Fn {
+                user_fn,
+                arity: index,
+            };
+            Ok((body, env, target))
+        }
+        _ => panic!("apply_user_fn called with wrong Form type: {:?}", f),
+    }
+}
+
+/// Check that `recur` only appears in tail position of `form`, where `locals` are bound around
+/// it. Macros are expanded so that `recur` inside e.g. `cond` is found, and an error expanding
+/// one is returned. A call whose head is bound locally isn't a macro call, even if a macro of
+/// that name is defined.
+pub(crate) fn check_tail(form: &Form, tail: bool, env: &Env, locals: &[Ident]) -> Result<()> {
+    TailCheck {
+        env,
+        locals: locals.to_vec(),
+        lenient: false,
+    }
+    .check(form, tail)
+}
+
+/// The environment macros are looked up in and the names bound locally, while checking a form
+/// for misplaced `recur`
+struct TailCheck<'a> {
+    env: &'a Env,
+    locals: Vec<Ident>,
+    /// Whether a macro call that fails to expand is skipped, to fail when it runs, if it does
+    lenient: bool,
+}
+
+impl TailCheck<'_> {
+    fn check(&mut self, form: &Form, tail: bool) -> Result<()> {
+        let items = match form.kind {
+            FormKind::List(ref items) => items,
+            FormKind::Vector(ref items) => return self.check_all(items),
+            FormKind::HashMap(ref map) => {
+                return map.iter().try_for_each(|(k, v)| {
+                    self.check(k, false).and_then(|()| self.check(v, false))
+                })
+            }
+            _ => return Ok(()),
+        };
+        match form.as_fn_name() {
+            Some(sym::RECUR) if !tail => {
+                Err(Error::InvalidRecur("can only be used in tail position"))
+            }
+            Some(sym::QUOTE | sym::QUASIQUOTE | sym::QUASIQUOTEEXPAND) => Ok(()),
+            // Registered forms decide themselves which of their arguments are in tail position
+            Some(name) if special::is_registered(name) => Ok(()),
+            Some(sym::RECUR) => self.check_all(&items[1..]),
+            Some(sym::IF) => {
+                self.check_all(items.get(1..2).unwrap_or_default())?;
+                items
+                    .iter()
+                    .skip(2)
+                    .try_for_each(|branch| self.check(branch, tail))
+            }
+            Some(sym::DO) => self.check_body(&items[1..], tail),
+            Some(sym::LET | sym::LOOP) => {
+                let bindings = items.get(1).and_then(Form::as_slice).unwrap_or_default();
+                let outer = self.locals.len();
+                let result = bindings.chunks(2).try_for_each(|binding| {
+                    if let [pattern, value] = binding {
+                        self.check(value, false)?;
+                        destructure::names(pattern, &mut self.locals);
                     }
-                    (Some(_), None) => return Err(Error::InvalidArgument), //TODO need a better error
-                    (None, None) => break,
+                    Ok(())
+                });
+                // The body of a loop is a new recur target
+                let tail = tail || form.as_fn_name() == Some(sym::LOOP);
+                let result =
+                    result.and_then(|()| self.check_body(items.get(2..).unwrap_or_default(), tail));
+                self.locals.truncate(outer);
+                result
+            }
+            Some(sym::MATCH) => {
+                self.check_all(items.get(1..2).unwrap_or_default())?;
+                for clause in
+                    pattern::clauses(items.get(2..).unwrap_or_default()).unwrap_or_default()
+                {
+                    self.check_all(clause.pattern.predicates())?;
+                    let outer = self.locals.len();
+                    self.locals.extend(clause.pattern.names());
+                    let result = self
+                        .check_all(clause.guard.as_slice())
+                        .and_then(|()| self.check(&clause.body, tail));
+                    self.locals.truncate(outer);
+                    result?;
                 }
+                Ok(())
             }
-            if let Some(bind_rest_ident) = bind_rest {
-                env.set(bind_rest_ident.name, Form::list(rest));
+            Some(sym::CATCH) => match parse_catch(form.clone()) {
+                Ok(catch) => {
+                    if let CatchFilter::Predicate(ref predicate) = catch.filter {
+                        self.check(predicate, false)?;
+                    }
+                    let outer = self.locals.len();
+                    destructure::names(&catch.pattern, &mut self.locals);
+                    let result = self.check(&catch.body, false);
+                    self.locals.truncate(outer);
+                    result
+                }
+                Err(_) => self.check_all(&items[1..]),
+            },
+            Some(sym::FN) => {
+                // A function body is a new recur target; it is checked when its first closure is
+                // created
+                Ok(())
             }
-            Ok((*body, env))
+            Some(name) if self.locals.contains(&name) => self.check_all(items),
+            _ => match as_macro_call(form, self.env) {
+                Some(macro_) => match expand_call(macro_, form.clone()) {
+                    Ok(expanded) => self.check(&expanded, tail),
+                    Err(_) if self.lenient => Ok(()),
+                    Err(err) => Err(err),
+                },
+                None => self.check_all(items),
+            },
+        }
+    }
+
+    fn check_all(&mut self, forms: &[Form]) -> Result<()> {
+        forms.iter().try_for_each(|form| self.check(form, false))
+    }
+
+    fn check_body(&mut self, forms: &[Form], tail: bool) -> Result<()> {
+        match forms.split_last() {
+            Some((last, init)) => self.check_all(init).and_then(|()| self.check(last, tail)),
+            None => Ok(()),
         }
-        _ => panic!("apply_user_fn called with wrong Form type: {:?}", f),
     }
 }
 
 impl Form {
-    pub fn as_symbol_name(&self) -> Option<&str> {
-        if let FormKind::Symbol(Ident { ref name }) = self.kind {
-            Some(name)
+    pub fn as_symbol_name(&self) -> Option<&'static str> {
+        self.as_symbol().map(|ident| ident.name())
+    }
+
+    pub fn as_symbol(&self) -> Option<Ident> {
+        if let FormKind::Symbol(ident) = self.kind {
+            Some(ident)
         } else {
             None
         }
     }
 
-    pub fn as_fn_name(&self) -> Option<&str> {
+    pub fn as_fn_name(&self) -> Option<Ident> {
         if let FormKind::List(ref inner) = self.kind {
-            inner.first().and_then(|first| match first.kind {
-                FormKind::Symbol(ref ident) => Some(&*ident.name),
-                _ => None,
-            })
+            inner.first().and_then(Form::as_symbol)
         } else {
             None
         }
@@ -233,86 +711,357 @@ impl Form {
         if self.is_native_fn() {
             apply_native_fn(self, params)
         } else if self.is_user_fn() || self.is_macro() {
-            apply_user_fn(self, params).and_then(|(form, mut env)| eval(form, &mut env))
+            exec::current().call(self, params.try_into_iter()?.collect())
+        } else if let FormKind::MultiFn(ref multi) = self.kind {
+            multi.call(params)
         } else {
             Err(Error::NotCallable)
         }
     }
 }
 
+/// Call a user function by walking its body
+pub(crate) fn interpret_call(f: Form, params: Form) -> Result<Form> {
+    apply_user_fn(f, params)
+        .and_then(|(form, mut env, target)| eval_with(form, &mut env, Some(target)))
+}
+
 fn eval_(form: Form, env: &mut Env) -> Result<Form> {
     let (_, param): (Form, Form) = form.try_into()?;
-    let evaluated = eval(param, env)?;
+    let evaluated = interpret(param, env)?;
     eval(evaluated, &mut env.root())
 }
 
-fn try_(form: Form, env: &mut Env) -> Result<Form> {
-    if let Ok((_, to_eval)) = <Form as TryInto<(Form, Form)>>::try_into(form.clone()) {
-        return eval(to_eval, env).or_else(|err| match err {
-            Error::UserError(form) => Ok(form),
-            other => Ok(Form::string(format!("{}", other))),
-        });
-    }
-
-    let (_try, to_eval, (_catch, bind, err_eval)): (Ident, Form, (Ident, Ident, Form)) =
-        form.try_into()?;
-    assert_eq!(_try.name, "try*");
-    assert_eq!(_catch.name, "catch*");
-    eval(to_eval, env).or_else(|err| {
-        let err_arg = match err {
-            Error::UserError(form) => form,
-            other => Form::string(format!("{}", other)),
-        };
-        let mut new_env = Env::new_with(env);
-        new_env.set(bind.name, err_arg);
-        eval(err_eval, &mut new_env)
+/// Which errors a `catch*` clause handles
+pub(crate) enum CatchFilter {
+    All,
+    /// Errors whose `:type`, or the `:type` in their `ex-data`, is this keyword
+    Type(Ident),
+    /// Errors for which this expression evaluates to a function returning a truthy value
+    Predicate(Form),
+}
+
+pub(crate) struct Catch {
+    pub(crate) filter: CatchFilter,
+    pub(crate) pattern: Form,
+    pub(crate) body: Form,
+}
+
+/// Parse `(catch* e body...)`, `(catch* :type e body...)` or `(catch* :when pred e body...)`.
+/// Any keyword but `:when` names a type, so the filter never depends on what the forms after it
+/// look like.
+fn parse_catch(clause: Form) -> Result<Catch> {
+    let mut items: Vec<Form> = clause.try_into()?;
+    items.remove(0);
+    let (filter, consumed) = match items.as_slice() {
+        [Form {
+            kind: FormKind::Keyword(sym::WHEN),
+            ..
+        }, predicate, ..] => (CatchFilter::Predicate(predicate.clone()), 2),
+        [Form {
+            kind: FormKind::Keyword(kind),
+            ..
+        }, ..] => (CatchFilter::Type(*kind), 1),
+        _ => (CatchFilter::All, 0),
+    };
+    items.drain(..consumed);
+    if items.is_empty() {
+        return Err(Error::InvalidTry("catch* needs a binding"));
+    }
+    let pattern = items.remove(0);
+    Ok(Catch {
+        filter,
+        pattern,
+        body: Form::list(std::iter::once(Form::symbol(sym::DO)).chain(items)),
     })
 }
 
+/// Whether the `:type` of `thrown`, or the `:type` in its `ex-data`, is `kind`
+pub(crate) fn has_type(thrown: &Form, kind: Ident) -> bool {
+    let type_of = |form: &Form| match form.kind {
+        FormKind::HashMap(ref map) => map.get(&Form::keyword(sym::TYPE)).cloned(),
+        _ => None,
+    };
+    let kind = Some(Form::keyword(kind));
+    let data = match thrown.kind {
+        FormKind::HashMap(ref map) => map.get(&Form::keyword(sym::DATA)).cloned(),
+        _ => None,
+    };
+    type_of(thrown) == kind || data.and_then(|data| type_of(&data)) == kind
+}
+
+impl Catch {
+    fn matches(&self, thrown: &Form, env: &mut Env) -> Result<bool> {
+        match self.filter {
+            CatchFilter::All => Ok(true),
+            CatchFilter::Type(kind) => Ok(has_type(thrown, kind)),
+            CatchFilter::Predicate(ref predicate) => {
+                let predicate = interpret(predicate.clone(), env)?;
+                Ok(predicate.call(Form::list([thrown.clone()]))?.is_truthy())
+            }
+        }
+    }
+}
+
+/// `(try* body... (catch* ...)... (finally body...))`. The first matching `catch*` clause
+/// handles an error, and the `finally` body always runs afterwards, for its side effects only.
+fn try_(form: Form, env: &mut Env) -> Result<Form> {
+    let (body, catches, finally) = parse_try(form)?;
+    let body = Form::list(std::iter::once(Form::symbol(sym::DO)).chain(body));
+    let result = interpret(body, env).or_else(|err| {
+        if matches!(err, Error::Interrupted) {
+            return Err(err);
+        }
+        let thrown = err.to_form();
+        let frames = trace::save();
+        for catch in catches {
+            if catch.matches(&thrown, env)? {
+                let mut catch_env = Env::new_with(env);
+                bind(&catch.pattern, thrown, &mut catch_env)?;
+                return interpret(catch.body, &mut catch_env);
+            }
+        }
+        trace::restore(frames);
+        Err(err)
+    });
+    if let Some(finally) = finally {
+        let frames = trace::save();
+        interpret(finally, env)?;
+        trace::restore(frames);
+    }
+    result
+}
+
+/// Split a `try*` form into its body forms, `catch*` clauses and `finally` body
+pub(crate) fn parse_try(form: Form) -> Result<(Vec<Form>, Vec<Catch>, Option<Form>)> {
+    let items: Vec<Form> = form.try_into()?;
+    let mut body = Vec::new();
+    let mut catches = Vec::new();
+    let mut finally = None;
+    for item in items.into_iter().skip(1) {
+        match item.as_fn_name() {
+            _ if finally.is_some() => {
+                return Err(Error::InvalidTry("finally must be the last clause"))
+            }
+            Some(sym::CATCH) => catches.push(parse_catch(item)?),
+            Some(sym::FINALLY) => {
+                let mut forms: Vec<Form> = item.try_into()?;
+                forms[0] = Form::symbol(sym::DO);
+                finally = Some(Form::list(forms));
+            }
+            _ if !catches.is_empty() => {
+                return Err(Error::InvalidTry("body forms must come before catch*"))
+            }
+            _ => body.push(item),
+        }
+    }
+    Ok((body, catches, finally))
+}
+
 pub fn eval_ast(form: Form, env: &mut Env) -> Result<Form> {
     match form {
         Form {
-            kind: FormKind::Symbol(Ident { name }),
+            kind: FormKind::Symbol(ident),
             ..
-        } => Ok(env.get(&name)?),
+        } => env.get(ident),
         Form {
             kind: FormKind::List(inner),
-            ..
+            meta,
         } => {
             let evaluated = inner
                 .into_iter()
-                .map(|form| eval(form, env))
+                .map(|form| interpret(form, env))
                 .collect::<Result<Vec<Form>>>()?;
-            Ok(Form::list(evaluated))
+            let evaluated = Form {
+                kind: FormKind::List(evaluated),
+                meta,
+            };
+            limits::allocate(&evaluated)?;
+            Ok(evaluated)
         }
         Form {
             kind: FormKind::Vector(inner),
-            ..
+            meta,
         } => {
             let evaluated = inner
                 .into_iter()
-                .map(|form| eval(form, env))
+                .map(|form| interpret(form, env))
                 .collect::<Result<Vec<Form>>>()?;
-            Ok(Form::vector(evaluated))
+            let evaluated = Form {
+                kind: FormKind::Vector(evaluated),
+                meta,
+            };
+            limits::allocate(&evaluated)?;
+            Ok(evaluated)
         }
         Form {
             kind: FormKind::HashMap(inner),
-            ..
+            meta,
         } => {
+            // Forms are map keys, see the note on `impl Hash for Form`
+            #[allow(clippy::mutable_key_type)]
             let evaluated = inner
                 .into_iter()
-                .map(|(k, v)| Ok((eval(k, env)?, eval(v, env)?)))
+                .map(|(k, v)| Ok((interpret(k, env)?, interpret(v, env)?)))
                 .collect::<Result<HashMap<Form, Form>>>()?;
-            Ok(Form::hash_map(evaluated))
+            let evaluated = Form {
+                kind: FormKind::HashMap(evaluated),
+                meta,
+            };
+            limits::allocate(&evaluated)?;
+            Ok(evaluated)
         }
         other => Ok(other),
     }
 }
 
-pub fn eval(mut form: Form, outer_env: &mut Env) -> Result<Form> {
+fn loop_(form: Form, env: &Env) -> Result<(Form, Env, RecurTarget)> {
+    let (_, bindings, Rest { values: mut body }): ((), Vec<Form>, Rest) = form.try_into()?;
+    let body = match body.len() {
+        0 | 1 => body.pop().unwrap_or_else(Form::nil),
+        _ => Form::list(std::iter::once(Form::symbol(sym::DO)).chain(body)),
+    };
+    if bindings.len() % 2 != 0 {
+        return Err(Error::InvalidArgument);
+    }
+    let mut loop_env = Env::new_with(env);
+    let mut binds = Vec::with_capacity(bindings.len() / 2);
+    let mut iter = bindings.into_iter();
+    while let (Some(pattern), Some(value)) = (iter.next(), iter.next()) {
+        let evaluated = interpret(value, &mut loop_env)?;
+        bind(&pattern, evaluated, &mut loop_env)?;
+        binds.push(pattern);
+    }
+    let target = RecurTarget::Loop {
+        binds,
+        body: body.clone(),
+        env: env.clone(),
+    };
+    Ok((body, loop_env, target))
+}
+
+fn recur(form: Form, env: &mut Env) -> Result<Vec<Form>> {
+    TryInto::<Vec<Form>>::try_into(form)?
+        .into_iter()
+        .skip(1)
+        .map(|arg| interpret(arg, env))
+        .collect()
+}
+
+/// The handler of a built-in special form
+type Builtin = fn(Form, &mut Env) -> Result<Step>;
+
+/// The built-in special forms, which the [`special`] registry starts with
+pub(crate) fn special_forms() -> Vec<(Ident, Builtin)> {
+    fn expansion(expansion: Expansion, form: Form, env: &Env) -> Result<Step> {
+        let (_, arg): (Form, Form) = form.try_into()?;
+        expansion.expand(arg, env).map(Step::value)
+    }
+    vec![
+        (sym::DEF, |form, env| def(form, env).map(Step::value)),
+        (sym::DEFMACRO, |form, env| {
+            defmacro(form, env).map(Step::value)
+        }),
+        (sym::LET, |form, env| {
+            let (body, env) = let_(form, env)?;
+            Ok(Step::tail_in(body, env))
+        }),
+        (sym::DO, |form, env| do_(form, env).map(Step::tail)),
+        (sym::MATCH, |form, env| {
+            let (body, env) = match_(form, env)?;
+            Ok(Step::tail_in(body, env))
+        }),
+        (sym::IF, |form, env| if_(form, env).map(Step::tail)),
+        (sym::FN, |form, env| {
+            fn_(form, env, exec::Cache::default()).map(Step::value)
+        }),
+        (sym::EVAL, |form, env| eval_(form, env).map(Step::value)),
+        (sym::NS, |form, env| {
+            namespace::ns(form, env).map(Step::value)
+        }),
+        (sym::REQUIRE, |form, env| {
+            namespace::require(form, env).map(Step::value)
+        }),
+        (sym::BINDING, |form, env| {
+            dynamic::binding(form, env).map(Step::value)
+        }),
+        (sym::SET, |form, env| {
+            dynamic::set(form, env).map(Step::value)
+        }),
+        (sym::QUOTE, |form, _| quote(form).map(Step::value)),
+        (sym::QUASIQUOTE, |form, _| quasiquote(form).map(Step::tail)),
+        (sym::QUASIQUOTEEXPAND, |form, _| {
+            quasiquoteexpand(form).map(Step::value)
+        }),
+        (sym::MACROEXPAND, |form, env| {
+            expansion(Expansion::Outer, form, env)
+        }),
+        (sym::MACROEXPAND_1, |form, env| {
+            expansion(Expansion::Once, form, env)
+        }),
+        (sym::MACROEXPAND_ALL, |form, env| {
+            expansion(Expansion::All, form, env)
+        }),
+        (sym::TRY, |form, env| try_(form, env).map(Step::value)),
+        (sym::LOOP, |form, env| {
+            let (body, env, target) = loop_(form, env)?;
+            Ok(Step(Next::Loop(body, env, target)))
+        }),
+        (sym::RECUR, |form, env| {
+            recur(form, env).map(|args| Step(Next::Recur(args)))
+        }),
+    ]
+}
+
+/// Evaluate `form` in `outer_env` with the backend selected for this thread, see
+/// [`exec`](crate::exec)
+pub fn eval(form: Form, outer_env: &mut Env) -> Result<Form> {
+    let code = lazy::to_code(form)?;
+    let env = outer_env.clone();
+    env.enter(|| exec::current().eval(code, outer_env))
+}
+
+/// Evaluate `form` by walking it, without analyzing it first
+pub(crate) fn interpret(form: Form, outer_env: &mut Env) -> Result<Form> {
+    eval_with(form, outer_env, None)
+}
+
+/// Interpret a form the compiler declined. Its loops are checked for misplaced `recur` here,
+/// once, rather than each time they run; function bodies are checked when their first closure
+/// is created. A macro call that fails to expand is left to fail when it is evaluated, as it
+/// may be in a branch that isn't taken.
+pub(crate) fn interpret_top_level(form: Form, env: &mut Env) -> Result<Form> {
+    TailCheck {
+        env,
+        locals: Vec::new(),
+        lenient: true,
+    }
+    .check(&form, false)?;
+    interpret(form, env)
+}
+
+fn eval_with(form: Form, outer_env: &mut Env, recur_target: Option<RecurTarget>) -> Result<Form> {
+    let _depth = limits::enter()?;
+    let mut call = None;
+    let result = eval_loop(form, outer_env, recur_target, &mut call);
+    match (&result, call) {
+        (Ok(_), _) => trace::clear(),
+        (Err(_), Some(call)) => trace::unwind(&call),
+        (Err(_), None) => {}
+    }
+    result
+}
+
+/// The body of `eval_with`. `call` holds the user function call being evaluated, if any, for
+/// the stack trace.
+fn eval_loop(
+    mut form: Form,
+    outer_env: &mut Env,
+    mut recur_target: Option<RecurTarget>,
+    call: &mut Option<Call>,
+) -> Result<Form> {
     let mut tco_env: Option<Env> = None;
     loop {
-        // dbg!(&form);
         let env = if let Some(ref mut inner_env) = tco_env {
             inner_env
         } else {
@@ -331,37 +1080,58 @@ pub fn eval(mut form: Form, outer_env: &mut Env) -> Result<Form> {
         if form.is_empty_sequential() {
             return Ok(form);
         }
+        limits::step()?;
+        interrupt::check()?;
 
-        match form.as_fn_name() {
-            Some("def!") => return def(form, env),
-            Some("defmacro!") => return defmacro(form, env),
-            Some("let*") => {
-                let new_env;
-                (form, new_env) = let_(form, env)?;
-                tco_env = Some(new_env);
-            }
-            Some("do") => form = do_(form, env)?,
-            Some("if") => form = if_(form, env)?,
-            Some("fn*") => return fn_(form, env),
-            Some("eval") => return eval_(form, env),
-            Some("quote") => return quote(form),
-            Some("quasiquote") => form = quasiquote(form)?,
-            Some("quasiquoteexpand") => return quasiquoteexpand(form),
-            Some("macroexpand") => {
-                let (_, arg): (Form, Form) = form.try_into()?;
-                return Ok(macro_expand(arg, env)?);
-            }
-            Some("try*") => form = try_(form, env)?,
-            _ => {
-                let (f, params) = extract_fn(eval_ast(form, env)?)?;
-                if f.is_user_fn() {
+        if let Some(special) = form.as_fn_name().and_then(special::get) {
+            match special.eval(form, env)?.0 {
+                Next::Value(value) => return Ok(value),
+                Next::Tail(next, new_env) => {
+                    form = next;
+                    if new_env.is_some() {
+                        tco_env = new_env;
+                    }
+                }
+                Next::Loop(body, new_env, target) => {
+                    form = body;
+                    tco_env = Some(new_env);
+                    recur_target = Some(target);
+                }
+                Next::Recur(args) => {
+                    let target = recur_target
+                        .as_ref()
+                        .ok_or(Error::InvalidRecur("used outside of loop or fn*"))?;
                     let new_env;
-                    (form, new_env) = apply_user_fn(f, params)?;
+                    (form, new_env) = target.rebind(args)?;
                     tco_env = Some(new_env);
-                } else {
-                    return apply_native_fn(f, params);
                 }
             }
+            continue;
+        }
+        let head = form.as_fn_name();
+        let form_meta = form.meta.take();
+        let (mut f, params) = extract_fn(eval_ast(form, env)?)?;
+        if exec::is_compiled(&f) {
+            // The compiled function takes care of its own tail calls and stack frame
+            *call = None;
+            let args = params.try_into_iter()?.collect();
+            return exec::invoke(f, args, Some(Site::new(head, form_meta)));
+        }
+        let this = Call {
+            head,
+            function: f.meta.take(),
+            form: form_meta,
+        };
+        if f.is_user_fn() {
+            *call = Some(this);
+            let (new_env, target);
+            (form, new_env, target) = apply_user_fn(f, params)?;
+            tco_env = Some(new_env);
+            recur_target = Some(target);
+        } else if let FormKind::MultiFn(multi) = f.kind {
+            return multi.call(params).inspect_err(|_| trace::unwind(&this));
+        } else {
+            return apply_native_fn(f, params).inspect_err(|_| trace::unwind(&this));
         }
     }
 }
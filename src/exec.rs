@@ -1,5 +1,147 @@
-// use crate::{Error, Ast};
+//! Execution backends
+//!
+//! Two backends run the same language:
+//!
+//! - the tree-walker, which analyzes forms into Rust closures before running them (see
+//!   [`analyze`](crate::analyze)) and walks whatever analysis declines
+//! - the [`vm`](crate::vm), which compiles forms to bytecode for a stack machine
+//!
+//! The backend is chosen per thread with [`set_backend`], before evaluating anything. [`eval`]
+//! and [`Form::call`] go through the current one, so everything evaluated on the thread, from
+//! `eval` and `load-file` to the functions native code calls back, runs on the same backend.
+//! Functions carry what each backend compiled from them, so a function created on one backend
+//! can still be called on the other.
+//!
+//! [`eval`]: crate::eval
+//! [`Form::call`]: crate::Form::call
 
-// pub trait Exec {
-//     fn exec() -> Result<Ast, Error>;
-// }
+use std::{
+    cell::{Cell, OnceCell},
+    rc::Rc,
+};
+
+use crate::{analyze, bytecode, trace::Site, vm, Env, Form, Result};
+
+/// A way of running code
+pub trait Exec {
+    /// Evaluate `form` in `env`
+    fn eval(&self, form: Form, env: &mut Env) -> Result<Form>;
+
+    /// Call the function or macro `f` with `args`
+    fn call(&self, f: Form, args: Vec<Form>) -> Result<Form>;
+}
+
+/// The tree-walking backend
+pub struct TreeWalker;
+
+impl Exec for TreeWalker {
+    fn eval(&self, form: Form, env: &mut Env) -> Result<Form> {
+        analyze::eval(form, env)
+    }
+
+    fn call(&self, f: Form, args: Vec<Form>) -> Result<Form> {
+        analyze::invoke(f, args, None)
+    }
+}
+
+/// The bytecode backend
+pub struct Vm;
+
+impl Exec for Vm {
+    fn eval(&self, form: Form, env: &mut Env) -> Result<Form> {
+        vm::eval(form, env)
+    }
+
+    fn call(&self, f: Form, args: Vec<Form>) -> Result<Form> {
+        vm::invoke(f, args, None)
+    }
+}
+
+/// The backends to choose from
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    TreeWalker,
+    Vm,
+}
+
+impl Backend {
+    pub fn exec(self) -> &'static dyn Exec {
+        match self {
+            Backend::TreeWalker => &TreeWalker,
+            Backend::Vm => &Vm,
+        }
+    }
+}
+
+impl std::str::FromStr for Backend {
+    type Err = crate::Error;
+
+    /// Parse `tree-walker` or `vm`
+    fn from_str(name: &str) -> Result<Backend> {
+        match name {
+            "tree-walker" => Ok(Backend::TreeWalker),
+            "vm" => Ok(Backend::Vm),
+            _ => Err(crate::Error::UnknownBackend(name.into())),
+        }
+    }
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Backend::TreeWalker => "tree-walker",
+            Backend::Vm => "vm",
+        })
+    }
+}
+
+thread_local! {
+    static BACKEND: Cell<Backend> = const { Cell::new(Backend::TreeWalker) };
+}
+
+/// Run everything evaluated on this thread from now on with `backend`
+pub fn set_backend(backend: Backend) {
+    BACKEND.with(|current| current.set(backend));
+}
+
+/// The backend this thread evaluates with
+pub fn backend() -> Backend {
+    BACKEND.with(Cell::get)
+}
+
+/// The current backend
+pub(crate) fn current() -> &'static dyn Exec {
+    backend().exec()
+}
+
+/// What the backends compiled from one `fn*` form, shared by the closures it creates. Each is
+/// built on the first call on that backend, and is `None` if the function is left to the
+/// tree-walker.
+#[derive(Default)]
+pub(crate) struct Compiled {
+    /// Set once the bodies have been checked for `recur` outside tail position, which expands
+    /// the macros they call
+    pub(crate) tail_checked: OnceCell<()>,
+    pub(crate) analyzed: OnceCell<Option<analyze::Lambda>>,
+    pub(crate) bytecode: OnceCell<Option<bytecode::Function>>,
+}
+
+pub(crate) type Cache = Rc<Compiled>;
+
+/// Whether calls of `f` run compiled code on the current backend
+pub(crate) fn is_compiled(f: &Form) -> bool {
+    match backend() {
+        Backend::TreeWalker => analyze::is_compiled(f),
+        Backend::Vm => vm::is_compiled(f),
+    }
+}
+
+/// Call `f` with `args` on the current backend. `site` is the call form, if the call should
+/// appear in stack traces.
+pub(crate) fn invoke(f: Form, args: Vec<Form>, site: Option<Rc<Site>>) -> Result<Form> {
+    match backend() {
+        Backend::TreeWalker => analyze::invoke(f, args, site),
+        Backend::Vm => vm::invoke(f, args, site),
+    }
+}
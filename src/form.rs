@@ -1,40 +1,70 @@
 use std::{
+    any::Any,
     cell::RefCell,
     collections::HashMap,
     hash::{Hash, Hasher},
     rc::Rc,
+    sync::atomic::{AtomicU64, Ordering},
 };
 
-use crate::{Env, Error, Result};
+pub use crate::intern::Ident;
+use crate::{
+    delay::{Delay, Promise},
+    lazy::LazySeq,
+    multi::MultiFn,
+    Env, Error, Result,
+};
 
-#[derive(Clone, Debug, PartialEq)]
-pub struct Ident {
-    pub name: String,
+/// Atoms are mutable references, so two atoms are equal only if they are the same atom
+///
+/// An atom's metadata belongs to the reference rather than to the value. Copies of a reference
+/// share it, and `alter-meta!` changes it in place for all of them. `with-meta` and `vary-meta`
+/// instead make a new reference to the same value with separate metadata: it is equal to the
+/// original and sees every `reset!` and `swap!`, but `alter-meta!` on one doesn't change the
+/// metadata of the other.
+#[derive(Clone, Debug)]
+pub struct Atom {
+    pub value: Rc<RefCell<Form>>,
+    pub meta: Rc<RefCell<Form>>,
 }
 
-impl Ident {
-    pub fn from_str(name: &str) -> Ident {
-        Ident { name: name.into() }
+impl PartialEq for Atom {
+    fn eq(&self, other: &Atom) -> bool {
+        Rc::ptr_eq(&self.value, &other.value)
     }
 }
 
-impl PartialEq<str> for Ident {
-    fn eq(&self, other: &str) -> bool {
-        self.name == other
-    }
-}
+impl Eq for Atom {}
 
-#[derive(Clone, Debug, PartialEq)]
-pub struct Atom {
-    pub value: Rc<RefCell<Form>>,
+impl Hash for Atom {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(Rc::as_ptr(&self.value) as usize);
+    }
 }
 
 impl Atom {
     pub fn new(form: Form) -> Atom {
+        let value = Rc::new(RefCell::new(form));
+        crate::gc::track_atom(&value);
+        Atom {
+            value,
+            meta: Atom::meta_cell(Form::nil()),
+        }
+    }
+
+    /// Another reference to the same value, with its own metadata cell, see [`Atom`]
+    pub fn with_meta(&self, meta: Form) -> Atom {
         Atom {
-            value: Rc::new(RefCell::new(form)),
+            value: self.value.clone(),
+            meta: Atom::meta_cell(meta),
         }
     }
+
+    fn meta_cell(meta: Form) -> Rc<RefCell<Form>> {
+        let cell = Rc::new(RefCell::new(meta));
+        crate::gc::track_atom_meta(&cell);
+        cell
+    }
 }
 
 impl From<Atom> for Form {
@@ -43,12 +73,279 @@ impl From<Atom> for Form {
     }
 }
 
-#[derive(Clone, Hash, Eq, PartialEq)]
+/// One parameter list and body of a function
+#[derive(Clone)]
+pub struct Arity {
+    /// Binding patterns for the positional parameters and the `&` rest parameter
+    pub binds: Vec<Form>,
+    pub bind_rest: Option<Form>,
+    pub body: Form,
+}
+
+impl Arity {
+    /// Whether a call with `count` arguments can use this arity
+    pub fn accepts(&self, count: usize) -> bool {
+        match self.bind_rest {
+            Some(_) => count >= self.binds.len(),
+            None => count == self.binds.len(),
+        }
+    }
+}
+
+/// A function or macro defined in Lisp code
+///
+/// Closures are immutable once created and compare by identity. Calls dispatch on the number of
+/// arguments to the first fixed arity that matches, falling back to the variadic one.
+pub struct UserFn {
+    pub arities: Vec<Arity>,
+    pub env: Env,
+    pub is_macro: bool,
+    /// The compiled arities, built on the first call
+    pub(crate) compiled: crate::exec::Cache,
+}
+
+impl UserFn {
+    fn new(user_fn: UserFn) -> Rc<UserFn> {
+        let user_fn = Rc::new(user_fn);
+        crate::gc::track_closure(&user_fn);
+        user_fn
+    }
+}
+
+/// A function implemented in Rust
+///
+/// Native functions compare by identity. The address of a `dyn Fn` isn't a usable identity:
+/// most native functions are zero-sized, and vtables may be duplicated across codegen units, so
+/// each one is numbered when it is made with [`Form::native_fn`] or [`Form::native_closure`]
+/// instead.
+#[derive(Clone)]
+pub struct NativeFn {
+    f: Native,
+    id: u64,
+}
+
+#[derive(Clone)]
+enum Native {
+    Static(&'static dyn Fn(Form) -> Result<Form>),
+    Closure(Rc<NativeClosure>),
+}
+
+type CallWith = Box<dyn Fn(&[Form], Form) -> Result<Form>>;
+
+/// A native function made at run time, and the forms it uses. The forms are kept apart from the
+/// function so the collector can trace them.
+pub(crate) struct NativeClosure {
+    pub(crate) captured: Box<[Form]>,
+    f: CallWith,
+}
+
+static NATIVE_FN_IDS: AtomicU64 = AtomicU64::new(0);
+
+impl NativeFn {
+    fn new(f: Native) -> NativeFn {
+        NativeFn {
+            f,
+            id: NATIVE_FN_IDS.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    pub fn call(&self, args: Form) -> Result<Form> {
+        match self.f {
+            Native::Static(f) => f(args),
+            Native::Closure(ref closure) => (closure.f)(&closure.captured, args),
+        }
+    }
+
+    /// The closure this function was made from with [`Form::native_closure`], if it was
+    pub(crate) fn closure(&self) -> Option<&Rc<NativeClosure>> {
+        match self.f {
+            Native::Static(_) => None,
+            Native::Closure(ref closure) => Some(closure),
+        }
+    }
+}
+
+type HostPrintFn = dyn Fn(&dyn Any, &mut std::fmt::Formatter<'_>) -> std::fmt::Result;
+
+/// `Eq` and `Hash` of the Rust type a host value was built from
+#[derive(Clone, Copy)]
+struct ByValue {
+    eq: fn(&dyn Any, &dyn Any) -> bool,
+    hash: fn(&dyn Any, &mut dyn Hasher),
+}
+
+impl ByValue {
+    fn of<T: Any + Eq + Hash>() -> ByValue {
+        ByValue {
+            eq: |a, b| match (a.downcast_ref::<T>(), b.downcast_ref::<T>()) {
+                (Some(a), Some(b)) => a == b,
+                _ => false,
+            },
+            hash: |value, mut state| {
+                value
+                    .downcast_ref::<T>()
+                    .expect("host value of the type it was built from")
+                    .hash(&mut state)
+            },
+        }
+    }
+}
+
+/// An opaque Rust value carried through Lisp code without conversion
+///
+/// A host value prints as `#<type_name>`, and compares and hashes by identity, unless it was
+/// built with [`HostValueBuilder::print`] or [`HostValueBuilder::by_value`].
+#[derive(Clone)]
+pub struct HostValue {
+    pub value: Rc<dyn Any>,
+    pub type_name: &'static str,
+    print: Option<Rc<HostPrintFn>>,
+    by_value: Option<ByValue>,
+}
+
+/// Builds a [`HostValue`] holding a `T`, with hooks that take the `T` itself
+pub struct HostValueBuilder<T> {
+    value: T,
+    type_name: &'static str,
+    print: Option<Rc<HostPrintFn>>,
+    by_value: Option<ByValue>,
+}
+
+impl<T: Any> HostValueBuilder<T> {
+    pub fn type_name(mut self, type_name: &'static str) -> HostValueBuilder<T> {
+        self.type_name = type_name;
+        self
+    }
+
+    /// Customise how the value is printed by `pr-str`, `str` and friends
+    pub fn print<F>(mut self, f: F) -> HostValueBuilder<T>
+    where
+        F: Fn(&T, &mut std::fmt::Formatter<'_>) -> std::fmt::Result + 'static,
+    {
+        self.print = Some(Rc::new(move |value, fmt| {
+            f(
+                value
+                    .downcast_ref()
+                    .expect("host value of the type it was built from"),
+                fmt,
+            )
+        }));
+        self
+    }
+
+    pub fn build(self) -> HostValue {
+        HostValue {
+            value: Rc::new(self.value),
+            type_name: self.type_name,
+            print: self.print,
+            by_value: self.by_value,
+        }
+    }
+}
+
+impl<T: Any + Eq + Hash> HostValueBuilder<T> {
+    /// Compare and hash by the value's own `Eq` and `Hash` instead of by identity. A host value
+    /// built this way is equal to other host values of the same type that were also built this
+    /// way and hold an equal value.
+    pub fn by_value(mut self) -> HostValueBuilder<T> {
+        self.by_value = Some(ByValue::of::<T>());
+        self
+    }
+}
+
+impl HostValue {
+    /// A host value that compares by identity
+    pub fn new<T: Any>(value: T) -> HostValue {
+        HostValue::builder(value).build()
+    }
+
+    pub fn builder<T: Any>(value: T) -> HostValueBuilder<T> {
+        HostValueBuilder {
+            value,
+            type_name: std::any::type_name::<T>(),
+            print: None,
+            by_value: None,
+        }
+    }
+
+    pub fn is<T: Any>(&self) -> bool {
+        self.value.is::<T>()
+    }
+
+    pub fn downcast<T: Any>(&self) -> Option<Rc<T>> {
+        self.value.clone().downcast().ok()
+    }
+
+    fn addr(&self) -> *const () {
+        Rc::as_ptr(&self.value) as *const ()
+    }
+
+    pub(crate) fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.print {
+            Some(ref print) => print(&*self.value, f),
+            None => write!(f, "#<{}>", self.type_name),
+        }
+    }
+}
+
+/// Host values compared by value are only equal to others of the same type that are also
+/// compared by value, so both sides use the same `Eq`
+impl PartialEq for HostValue {
+    fn eq(&self, other: &HostValue) -> bool {
+        if self.addr() == other.addr() {
+            return true;
+        }
+        match (self.by_value, other.by_value) {
+            (Some(by_value), Some(_)) if (*self.value).type_id() == (*other.value).type_id() => {
+                (by_value.eq)(&*self.value, &*other.value)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Hash for HostValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self.by_value {
+            Some(by_value) => (by_value.hash)(&*self.value, state),
+            None => state.write_usize(self.addr() as usize),
+        }
+    }
+}
+
+impl std::fmt::Debug for HostValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HostValue({})", self.type_name)
+    }
+}
+
+#[derive(Clone)]
 pub struct Form {
     pub kind: FormKind,
     pub meta: Option<Box<Form>>,
 }
 
+/// Metadata is not part of a value, so it is ignored by equality and hashing
+impl PartialEq for Form {
+    fn eq(&self, other: &Form) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl Eq for Form {}
+
+/// Forms are used as map keys although some of them hold cells, which clippy's
+/// `mutable_key_type` lint warns about. Changing what a cell holds never changes the hash or
+/// equality of a form: functions, multimethods, atoms, delays and promises hash and compare by
+/// identity, a lazy sequence hashes by its elements, which don't change once realized, and a host
+/// value only hashes its contents if the embedder built it with
+/// [`HostValueBuilder::by_value`].
+impl Hash for Form {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.kind.hash(state)
+    }
+}
+
 macro_rules! form_predicate_fn {
     ($method:ident, $kind:pat) => {
         pub fn $method(&self) -> bool {
@@ -66,9 +363,14 @@ impl Form {
     form_predicate_fn!(is_keyword, FormKind::Keyword(_));
     form_predicate_fn!(is_list, FormKind::List(_));
     form_predicate_fn!(is_vector, FormKind::Vector(_));
+    form_predicate_fn!(is_lazy_seq, FormKind::LazySeq(_));
     form_predicate_fn!(is_hash_map, FormKind::HashMap(_));
     form_predicate_fn!(is_native_fn, FormKind::NativeFn(_));
     form_predicate_fn!(is_atom, FormKind::Atom(_));
+    form_predicate_fn!(is_delay, FormKind::Delay(_));
+    form_predicate_fn!(is_promise, FormKind::Promise(_));
+    form_predicate_fn!(is_multi_fn, FormKind::MultiFn(_));
+    form_predicate_fn!(is_host, FormKind::Host(_));
 
     pub fn nil() -> Form {
         Form {
@@ -84,9 +386,9 @@ impl Form {
         }
     }
 
-    pub fn symbol(name: &str) -> Form {
+    pub fn symbol(name: impl Into<Ident>) -> Form {
         Form {
-            kind: FormKind::Symbol(Ident::from_str(name)),
+            kind: FormKind::Symbol(name.into()),
             meta: None,
         }
     }
@@ -112,7 +414,7 @@ impl Form {
         }
     }
 
-    pub fn keyword(value: impl Into<String>) -> Form {
+    pub fn keyword(value: impl Into<Ident>) -> Form {
         Form {
             kind: FormKind::Keyword(value.into()),
             meta: None,
@@ -126,6 +428,18 @@ impl Form {
         }
     }
 
+    pub fn lazy_seq(seq: LazySeq) -> Form {
+        Form {
+            kind: FormKind::LazySeq(seq),
+            meta: None,
+        }
+    }
+
+    pub fn with_meta(mut self, meta: Option<Form>) -> Form {
+        self.meta = meta.map(Box::new);
+        self
+    }
+
     pub fn is_empty_list(&self) -> bool {
         matches!(self.kind, FormKind::List(ref inner) if inner.is_empty())
     }
@@ -137,6 +451,8 @@ impl Form {
         }
     }
 
+    // Forms are map keys, see the note on `impl Hash for Form`
+    #[allow(clippy::mutable_key_type)]
     pub fn hash_map(value: HashMap<Form, Form>) -> Form {
         Form {
             kind: FormKind::HashMap(value),
@@ -146,20 +462,71 @@ impl Form {
 
     pub fn native_fn(f: &'static dyn Fn(Form) -> Result<Form>) -> Form {
         Form {
-            kind: FormKind::NativeFn(f),
+            kind: FormKind::NativeFn(NativeFn::new(Native::Static(f))),
+            meta: None,
+        }
+    }
+
+    /// A native function that calls `f` with `captured` and its arguments. Forms the function
+    /// uses are passed this way rather than moved into `f`, so the collector can see them.
+    pub fn native_closure<const N: usize>(
+        captured: [Form; N],
+        f: impl Fn(&[Form; N], Form) -> Result<Form> + 'static,
+    ) -> Form {
+        let closure = Rc::new(NativeClosure {
+            captured: Box::new(captured),
+            f: Box::new(move |captured, args| {
+                f(
+                    captured
+                        .try_into()
+                        .expect("called with the forms it captured"),
+                    args,
+                )
+            }),
+        });
+        crate::gc::track_native_closure(&closure);
+        Form {
+            kind: FormKind::NativeFn(NativeFn::new(Native::Closure(closure))),
             meta: None,
         }
     }
 
-    pub fn user_fn(binds: Vec<Ident>, bind_rest: Option<Ident>, body: Form, env: Env) -> Form {
+    pub fn user_fn(binds: Vec<Form>, bind_rest: Option<Form>, body: Form, env: Env) -> Form {
+        let arity = Arity {
+            binds,
+            bind_rest,
+            body,
+        };
+        Form::closure(vec![arity], env, false)
+    }
+
+    /// A function or macro with one or more arities
+    pub fn closure(arities: Vec<Arity>, env: Env, is_macro: bool) -> Form {
         Form {
-            kind: FormKind::UserFn {
-                binds,
-                bind_rest,
-                body: Box::new(body),
+            kind: FormKind::UserFn(UserFn::new(UserFn {
+                arities,
+                env,
+                is_macro,
+                compiled: Default::default(),
+            })),
+            meta: None,
+        }
+    }
+
+    /// A function sharing its compiled code with the other closures created by the same `fn*`
+    /// form
+    pub(crate) fn compiled_closure(
+        arities: Vec<Arity>,
+        env: Env,
+        compiled: crate::exec::Cache,
+    ) -> Form {
+        Form {
+            kind: FormKind::UserFn(UserFn::new(UserFn {
+                arities,
                 env,
                 is_macro: false,
-            },
+                compiled,
+            })),
             meta: None,
         }
     }
@@ -171,19 +538,48 @@ impl Form {
         }
     }
 
-    pub fn macro_(binds: Vec<Ident>, bind_rest: Option<Ident>, body: Form, env: Env) -> Form {
+    pub fn delay(delay: Delay) -> Form {
         Form {
-            kind: FormKind::UserFn {
-                binds,
-                bind_rest,
-                body: Box::new(body),
-                env,
-                is_macro: true,
-            },
+            kind: FormKind::Delay(delay),
+            meta: None,
+        }
+    }
+
+    pub fn promise(promise: Promise) -> Form {
+        Form {
+            kind: FormKind::Promise(promise),
+            meta: None,
+        }
+    }
+
+    pub fn multi_fn(multi: Rc<MultiFn>) -> Form {
+        Form {
+            kind: FormKind::MultiFn(multi),
             meta: None,
         }
     }
 
+    /// Wrap an arbitrary Rust value so it can be passed through Lisp code
+    pub fn host<T: Any>(value: T) -> Form {
+        Form::host_value(HostValue::new(value))
+    }
+
+    pub fn host_value(value: HostValue) -> Form {
+        Form {
+            kind: FormKind::Host(value),
+            meta: None,
+        }
+    }
+
+    pub fn macro_(binds: Vec<Form>, bind_rest: Option<Form>, body: Form, env: Env) -> Form {
+        let arity = Arity {
+            binds,
+            bind_rest,
+            body,
+        };
+        Form::closure(vec![arity], env, true)
+    }
+
     pub fn iter(&self) -> Result<impl Iterator<Item = &Form>> {
         match &self.kind {
             FormKind::List(inner) => Ok(inner.iter()),
@@ -200,20 +596,26 @@ impl Form {
         }
     }
 
-    pub fn try_into_iter(self) -> Result<impl Iterator<Item = Form> + DoubleEndedIterator> {
+    /// Iterate over the elements of a list or vector, or of a lazy sequence, which is realized
+    /// first
+    pub fn try_into_iter(self) -> Result<impl DoubleEndedIterator<Item = Form>> {
         match self.kind {
             FormKind::List(inner) => Ok(inner.into_iter()),
             FormKind::Vector(inner) => Ok(inner.into_iter()),
+            FormKind::LazySeq(seq) => Ok(seq.iter().collect::<Result<Vec<_>>>()?.into_iter()),
             _ => Err(Error::NotIterable),
         }
     }
 
     pub fn is_symbol_named(&self, test: &str) -> bool {
-        matches!(&self.kind, FormKind::Symbol(Ident { name }) if name == test)
+        matches!(&self.kind, FormKind::Symbol(ident) if ident.name() == test)
     }
 
     pub fn is_sequential(&self) -> bool {
-        matches!(&self.kind, FormKind::List(_) | FormKind::Vector(_))
+        matches!(
+            &self.kind,
+            FormKind::List(_) | FormKind::Vector(_) | FormKind::LazySeq(_)
+        )
     }
 
     pub fn is_empty_sequential(&self) -> bool {
@@ -227,12 +629,17 @@ impl Form {
         }
     }
 
+    /// Lisp truthiness: everything except nil and false is true
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self.kind, FormKind::Nil | FormKind::Boolean(false))
+    }
+
     pub fn is_macro(&self) -> bool {
-        matches!(self.kind, FormKind::UserFn { is_macro: true, .. })
+        matches!(self.kind, FormKind::UserFn(ref f) if f.is_macro)
     }
 
     pub fn is_user_fn(&self) -> bool {
-        matches!(self.kind, FormKind::UserFn { is_macro: false, .. })
+        matches!(self.kind, FormKind::UserFn(ref f) if !f.is_macro)
     }
 }
 
@@ -244,42 +651,38 @@ pub enum FormKind {
     Integer(i64),
     Float(f64),
     String(String),
-    Keyword(String),
+    Keyword(Ident),
     List(Vec<Form>),
     Vector(Vec<Form>),
+    LazySeq(LazySeq),
     HashMap(HashMap<Form, Form>),
-    NativeFn(&'static dyn Fn(Form) -> Result<Form>),
-    UserFn {
-        binds: Vec<Ident>,
-        bind_rest: Option<Ident>,
-        body: Box<Form>,
-        env: Env,
-        is_macro: bool,
-    },
+    NativeFn(NativeFn),
+    UserFn(Rc<UserFn>),
     Atom(Atom),
+    Delay(Delay),
+    Promise(Promise),
+    MultiFn(Rc<MultiFn>),
+    Host(HostValue),
 }
 
-// impl FormKind {
-//     fn mal_eq(&self, other: &FormKind) -> bool {
-//         match (self, other) {
-//             (FormKind::Nil, FormKind::Nil) => true,
-//             (FormKind::Boolean(a), FormKind::Boolean(b)) => *a == *b,
-//             (FormKind::Symbol(a), FormKind::Symbol(b)) => *a == *b,
-//             (FormKind::Integer(a), FormKind::Integer(b)) => *a == *b,
-//             (FormKind::Float(a), FormKind::Float(b)) => *a == *b,
-//             (FormKind::String(a), FormKind::String(b)) => *a == *b,
-//             (FormKind::Keyword(a), FormKind::Keyword(b)) => *a == *b,
-//             (FormKind::List(a) | FormKind::Vector(a), FormKind::List(b) | FormKind::Vector(b)) => {
-//                 *a == *b
-//             }
-//             (FormKind::HashMap(a), FormKind::HashMap(b)) => *a == *b,
-//             (FormKind::NativeFn(_), _) => false,
-//             (FormKind::UserFn { .. }, _) => false,
-//             (_, _) => false,
-//         }
-//     }
-// }
+/// Normalise a float for hashing so that `Hash` agrees with `PartialEq`
+fn float_bits(x: f64) -> u64 {
+    if x == 0.0 {
+        0.0f64.to_bits()
+    } else if x.is_nan() {
+        f64::NAN.to_bits()
+    } else {
+        x.to_bits()
+    }
+}
 
+/// Equality between forms
+///
+/// - Integers and floats are never equal to each other, even when they hold the same value.
+/// - Floats compare by value, except that every NaN is equal to every other NaN so that `=` is
+///   reflexive and floats can be used as map keys. `0.0` and `-0.0` are equal.
+/// - Lists, vectors and lazy sequences are equal if they have equal elements in the same order.
+/// - Functions, macros, multimethods, atoms, delays and promises compare by identity.
 impl PartialEq for FormKind {
     fn eq(&self, other: &FormKind) -> bool {
         match (self, other) {
@@ -287,15 +690,22 @@ impl PartialEq for FormKind {
             (FormKind::Boolean(a), FormKind::Boolean(b)) => *a == *b,
             (FormKind::Symbol(a), FormKind::Symbol(b)) => *a == *b,
             (FormKind::Integer(a), FormKind::Integer(b)) => *a == *b,
-            (FormKind::Float(a), FormKind::Float(b)) => *a == *b,
+            (FormKind::Float(a), FormKind::Float(b)) => float_bits(*a) == float_bits(*b),
             (FormKind::String(a), FormKind::String(b)) => *a == *b,
             (FormKind::Keyword(a), FormKind::Keyword(b)) => *a == *b,
             (FormKind::List(a) | FormKind::Vector(a), FormKind::List(b) | FormKind::Vector(b)) => {
                 *a == *b
             }
+            (FormKind::LazySeq(a), b) => crate::lazy::equal(a, b),
+            (a, FormKind::LazySeq(b)) => crate::lazy::equal(b, a),
             (FormKind::HashMap(a), FormKind::HashMap(b)) => *a == *b,
-            (FormKind::NativeFn(_), _) => false,
-            (FormKind::UserFn { .. }, _) => false,
+            (FormKind::NativeFn(a), FormKind::NativeFn(b)) => a.id == b.id,
+            (FormKind::UserFn(a), FormKind::UserFn(b)) => Rc::ptr_eq(a, b),
+            (FormKind::Atom(a), FormKind::Atom(b)) => *a == *b,
+            (FormKind::Delay(a), FormKind::Delay(b)) => *a == *b,
+            (FormKind::Promise(a), FormKind::Promise(b)) => *a == *b,
+            (FormKind::MultiFn(a), FormKind::MultiFn(b)) => Rc::ptr_eq(a, b),
+            (FormKind::Host(a), FormKind::Host(b)) => *a == *b,
             (_, _) => false,
         }
     }
@@ -315,9 +725,9 @@ impl Hash for FormKind {
                 state.write_u8(0x01);
                 Hash::hash(x, state);
             }
-            FormKind::Symbol(Ident { name }) => {
+            FormKind::Symbol(x) => {
                 state.write_u8(0x02);
-                Hash::hash(name, state);
+                Hash::hash(x, state);
             }
             FormKind::Integer(x) => {
                 state.write_u8(0x03);
@@ -325,7 +735,7 @@ impl Hash for FormKind {
             }
             FormKind::Float(x) => {
                 state.write_u8(0x04);
-                Hash::hash(&x.to_bits(), state);
+                Hash::hash(&float_bits(*x), state);
             }
             FormKind::String(x) => {
                 state.write_u8(0x05);
@@ -335,40 +745,66 @@ impl Hash for FormKind {
                 state.write_u8(0x06);
                 Hash::hash(x, state);
             }
-            FormKind::List(x) => {
+            // Lists, vectors and lazy sequences compare equal, so they must hash the same
+            FormKind::List(x) | FormKind::Vector(x) => {
                 state.write_u8(0x07);
                 x.iter().for_each(|v| {
                     state.write_u8(0x00);
                     Hash::hash(v, state);
                 });
             }
-            FormKind::Vector(x) => {
-                state.write_u8(0x08);
-                x.iter().for_each(|v| {
+            FormKind::LazySeq(x) => {
+                state.write_u8(0x07);
+                for v in x.iter() {
+                    let Ok(v) = v else {
+                        // A sequence that fails to realize is equal to nothing, so it doesn't
+                        // hash like the list of the elements before the error either
+                        state.write_u8(0x01);
+                        break;
+                    };
                     state.write_u8(0x00);
-                    Hash::hash(v, state);
-                });
+                    Hash::hash(&v, state);
+                }
             }
+            // Map iteration order is unspecified, so combine the entry hashes in an
+            // order-independent way
             FormKind::HashMap(x) => {
+                use std::collections::hash_map::DefaultHasher;
                 state.write_u8(0x09);
-                x.iter().for_each(|(k, v)| {
-                    state.write_u8(0x00);
-                    Hash::hash(k, state);
-                    state.write_u8(0x01);
-                    Hash::hash(v, state);
+                let combined = x.iter().fold(0u64, |accum, entry| {
+                    let mut hasher = DefaultHasher::new();
+                    Hash::hash(&entry, &mut hasher);
+                    accum.wrapping_add(hasher.finish())
                 });
+                state.write_u64(combined);
             }
-            FormKind::NativeFn(_) => {
+            FormKind::NativeFn(f) => {
                 state.write_u8(0x0A);
-                Hash::hash(&(), state);
+                state.write_u64(f.id);
             }
-            FormKind::UserFn { .. } => {
+            FormKind::UserFn(f) => {
                 state.write_u8(0x0B);
-                Hash::hash(&(), state);
+                state.write_usize(Rc::as_ptr(f) as usize);
             }
-            FormKind::Atom(_) => {
+            FormKind::Atom(x) => {
                 state.write_u8(0x0C);
-                Hash::hash(&(), state);
+                Hash::hash(x, state);
+            }
+            FormKind::Delay(x) => {
+                state.write_u8(0x0E);
+                Hash::hash(x, state);
+            }
+            FormKind::Promise(x) => {
+                state.write_u8(0x0F);
+                Hash::hash(x, state);
+            }
+            FormKind::MultiFn(x) => {
+                state.write_u8(0x10);
+                state.write_usize(Rc::as_ptr(x) as usize);
+            }
+            FormKind::Host(x) => {
+                state.write_u8(0x0D);
+                Hash::hash(x, state);
             }
         }
     }
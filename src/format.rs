@@ -1,33 +1,133 @@
 use std::sync::OnceLock;
 
-use crate::form::{Atom, Form, FormKind};
+use crate::{
+    dynamic,
+    form::{Atom, Form, FormKind},
+    intern::sym,
+    interrupt,
+    lazy::LazySeq,
+    Error,
+};
 
 pub fn pr_str(input: &Form) -> String {
     format!("{:?}", input.kind)
 }
 
+/// Print `form` readably, breaking collections that don't fit in `width` columns over several
+/// lines: the items of a list go under its head, indented by two, and the items of vectors and
+/// maps line up after the opening bracket, a map entry per line.
+pub fn pretty(form: &Form, width: usize) -> String {
+    let mut out = String::new();
+    write_pretty(form, 0, width, &mut out);
+    out
+}
+
+fn write_pretty(form: &Form, column: usize, width: usize, out: &mut String) {
+    let flat = format!("{form:?}");
+    let (start, end, items, per_line, indent) = match &form.kind {
+        _ if column + flat.len() <= width => return out.push_str(&flat),
+        FormKind::List(items) if items.len() > 1 => ("(", ")", items.iter().collect(), 1, 2),
+        FormKind::Vector(items) if items.len() > 1 => ("[", "]", items.iter().collect(), 1, 1),
+        FormKind::HashMap(entries) if entries.len() > 1 => {
+            let flattened: Vec<&Form> = entries.iter().flat_map(|(k, v)| [k, v]).collect();
+            ("{", "}", flattened, 2, 1)
+        }
+        _ => return out.push_str(&flat),
+    };
+    let limit = print_length().map_or(usize::MAX, |length| length.saturating_mul(per_line));
+    out.push_str(start);
+    for (i, line) in items.chunks(per_line).take(limit / per_line).enumerate() {
+        if i > 0 {
+            out.push('\n');
+            out.extend(std::iter::repeat_n(' ', column + indent));
+        }
+        let mut at = column + if i == 0 { 1 } else { indent };
+        for (j, item) in line.iter().enumerate() {
+            if j > 0 {
+                out.push(' ');
+                at += 1;
+            }
+            let before = out.len();
+            write_pretty(item, at, width, out);
+            at = match out[before..].rfind('\n') {
+                Some(newline) => out.len() - (before + newline + 1),
+                None => at + out.len() - before,
+            };
+        }
+    }
+    if items.len() > limit {
+        out.push_str(" ...");
+    }
+    out.push_str(end);
+}
+
+/// The number of items printed per collection, set by `*print-length*`
+fn print_length() -> Option<usize> {
+    dynamic::get(sym::PRINT_LENGTH).and_then(|length| length.try_into().ok())
+}
+
+/// Write `values` between `start` and `end`. `per_item` is the number of values making up one
+/// item, which is two for the flattened entries of a map.
 fn write_list<'a, F>(
     start: &'static str,
     end: &'static str,
-    values: impl IntoIterator<Item = &'a Form>,
+    values: impl ExactSizeIterator<Item = &'a Form>,
+    per_item: usize,
     fmt: F,
     f: &mut std::fmt::Formatter<'_>,
 ) -> std::fmt::Result
 where
     F: Fn(&Form, &mut std::fmt::Formatter) -> std::fmt::Result,
 {
+    let limit = print_length().map_or(usize::MAX, |length| length.saturating_mul(per_item));
+    let elided = values.len() > limit;
     f.write_str(start)?;
     let mut has_fields = false;
-    for form in values {
+    for form in values.take(limit) {
         if has_fields {
             f.write_str(" ")?;
         }
         fmt(form, f)?;
         has_fields = true;
     }
+    if elided {
+        f.write_str(if has_fields { " ..." } else { "..." })?;
+    }
     f.write_str(end)
 }
 
+/// Write a lazy sequence as a list, realizing only the elements `*print-length*` lets through,
+/// so an infinite sequence can be printed when it is set
+fn write_lazy<F>(seq: &LazySeq, fmt: F, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+where
+    F: Fn(&Form, &mut std::fmt::Formatter) -> std::fmt::Result,
+{
+    let limit = print_length().map_or(usize::MAX, |length| length.saturating_add(1));
+    // Realizing each element checks for interrupts, so printing an infinite sequence stops
+    match seq.iter().take(limit).collect::<crate::Result<Vec<Form>>>() {
+        Ok(items) => write_list("(", ")", items.iter(), 1, fmt, f),
+        Err(err) => {
+            // Printing can't fail, so the interrupt is left for evaluation to stop at
+            if matches!(err, Error::Interrupted) {
+                interrupt::restore();
+            }
+            write!(f, "#<error {err}>")
+        }
+    }
+}
+
+/// Write a delay or promise with its value, or as pending if it has none yet
+fn write_pending(
+    f: &mut std::fmt::Formatter<'_>,
+    name: &str,
+    value: Option<Form>,
+) -> std::fmt::Result {
+    match value {
+        Some(value) => write!(f, "#<{name} {value:?}>"),
+        None => write!(f, "#<{name} pending>"),
+    }
+}
+
 fn escape_unprintable(s: &str) -> String {
     use aho_corasick::AhoCorasick;
     static AC: OnceLock<AhoCorasick> = OnceLock::new();
@@ -49,23 +149,33 @@ impl std::fmt::Debug for FormKind {
         match self {
             FormKind::Nil => f.write_str("nil"),
             FormKind::Boolean(b) => write!(f, "{b}"),
-            FormKind::Symbol(ident) => write!(f, "{}", &ident.name),
+            FormKind::Symbol(ident) => write!(f, "{ident}"),
             FormKind::Integer(n) => write!(f, "{n}"),
             FormKind::Float(n) => write!(f, "{n}"),
             FormKind::String(s) => write!(f, "\"{}\"", escape_unprintable(s)),
             FormKind::Keyword(k) => write!(f, ":{k}"),
-            FormKind::List(val) => write_list("(", ")", val, std::fmt::Debug::fmt, f),
-            FormKind::Vector(val) => write_list("[", "]", val, std::fmt::Debug::fmt, f),
+            FormKind::List(val) => write_list("(", ")", val.iter(), 1, std::fmt::Debug::fmt, f),
+            FormKind::Vector(val) => write_list("[", "]", val.iter(), 1, std::fmt::Debug::fmt, f),
+            FormKind::LazySeq(seq) => write_lazy(seq, std::fmt::Debug::fmt, f),
             FormKind::HashMap(val) => {
-                use std::iter::once;
-                let flattened = val.iter().flat_map(|(k, v)| once(k).chain(once(v)));
-                write_list("{", "}", flattened, std::fmt::Debug::fmt, f)
+                let flattened: Vec<&Form> = val.iter().flat_map(|(k, v)| [k, v]).collect();
+                write_list("{", "}", flattened.into_iter(), 2, std::fmt::Debug::fmt, f)
             }
             FormKind::NativeFn(_) => write!(f, "#<native>"),
-            FormKind::UserFn { is_macro, .. } => {
-                write!(f, "{}", if *is_macro { "#<macro>" } else { "#<function>" })
-            }
+            FormKind::UserFn(user_fn) => write!(
+                f,
+                "{}",
+                if user_fn.is_macro {
+                    "#<macro>"
+                } else {
+                    "#<function>"
+                }
+            ),
             FormKind::Atom(atom) => write!(f, "(atom {:?})", *atom.value.borrow()),
+            FormKind::Delay(delay) => write_pending(f, "delay", delay.value()),
+            FormKind::Promise(promise) => write_pending(f, "promise", promise.value()),
+            FormKind::MultiFn(multi) => write!(f, "#<multi-fn {}>", multi.name()),
+            FormKind::Host(host) => host.fmt(f),
         }
     }
 }
@@ -80,14 +190,21 @@ impl std::fmt::Display for FormKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             FormKind::String(s) => write!(f, "{s}"),
-            FormKind::List(val) => write_list("(", ")", val, std::fmt::Display::fmt, f),
-            FormKind::Vector(val) => write_list("[", "]", val, std::fmt::Display::fmt, f),
+            FormKind::List(val) => write_list("(", ")", val.iter(), 1, std::fmt::Display::fmt, f),
+            FormKind::Vector(val) => write_list("[", "]", val.iter(), 1, std::fmt::Display::fmt, f),
+            FormKind::LazySeq(seq) => write_lazy(seq, std::fmt::Display::fmt, f),
             FormKind::HashMap(val) => {
-                use std::iter::once;
-                let flattened = val.iter().flat_map(|(k, v)| once(k).chain(once(v)));
-                write_list("{", "}", flattened, std::fmt::Display::fmt, f)
+                let flattened: Vec<&Form> = val.iter().flat_map(|(k, v)| [k, v]).collect();
+                write_list(
+                    "{",
+                    "}",
+                    flattened.into_iter(),
+                    2,
+                    std::fmt::Display::fmt,
+                    f,
+                )
             }
-            FormKind::Atom(Atom { value }) => write!(f, "{}", *value.borrow()),
+            FormKind::Atom(Atom { value, .. }) => write!(f, "{}", *value.borrow()),
             other => std::fmt::Debug::fmt(other, f),
         }
     }
@@ -0,0 +1,510 @@
+//! Cycle collection for environments, closures and the other values that can refer back to them
+//!
+//! Everything in the interpreter is reference counted, which leaks as soon as values refer back
+//! to themselves. The most common case is a recursive function defined at the top level: the
+//! closure captures its environment, and `def!` stores the closure back into that environment.
+//!
+//! The collector uses trial deletion. Every environment, closure, native closure, atom cell,
+//! multimethod, lazy sequence, delay and promise is registered when it is created. A collection traces the references between registered
+//! objects, and any object with more strong references than the traced ones is known to be
+//! reachable from outside (the Rust stack, an embedder, or an untraced value such as a host
+//! value). Everything reachable from those roots survives; everything else is only kept alive by
+//! cycles, so its contents are cleared, which breaks the cycles and lets reference counting free
+//! it.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    hash::{BuildHasherDefault, Hasher},
+    rc::{Rc, Weak},
+    sync::Mutex,
+};
+
+use crate::{
+    delay::{self, Delay, Promise},
+    env::EnvInner,
+    form::{NativeClosure, UserFn},
+    lazy::{self, LazySeq},
+    multi::MultiFn,
+    Form, FormKind,
+};
+
+/// Counts of objects tracked by the collector
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GcStats {
+    pub live_envs: usize,
+    pub live_closures: usize,
+    pub live_atoms: usize,
+    /// Totals freed by all collections so far
+    pub collected_envs: usize,
+    pub collected_closures: usize,
+    pub collected_atoms: usize,
+    pub collections: usize,
+}
+
+impl GcStats {
+    pub fn to_form(&self) -> Form {
+        let entry = |name: &str, value: usize| {
+            (
+                Form::keyword(name),
+                Form::int(i64::try_from(value).unwrap_or(i64::MAX)),
+            )
+        };
+        Form::hash_map(
+            [
+                entry("live-envs", self.live_envs),
+                entry("live-closures", self.live_closures),
+                entry("live-atoms", self.live_atoms),
+                entry("collected-envs", self.collected_envs),
+                entry("collected-closures", self.collected_closures),
+                entry("collected-atoms", self.collected_atoms),
+                entry("collections", self.collections),
+            ]
+            .into(),
+        )
+    }
+}
+
+/// Number of new objects after which a collection runs automatically
+const DEFAULT_THRESHOLD: usize = 100_000;
+
+struct Registry {
+    envs: Vec<Weak<Mutex<EnvInner>>>,
+    closures: Vec<Weak<UserFn>>,
+    atoms: Vec<Weak<RefCell<Form>>>,
+    meta_cells: Vec<Weak<RefCell<Form>>>,
+    multi_fns: Vec<Weak<MultiFn>>,
+    lazy_seqs: Vec<Weak<RefCell<lazy::State>>>,
+    delays: Vec<Weak<RefCell<delay::State>>>,
+    promises: Vec<Weak<RefCell<Option<Form>>>>,
+    native_closures: Vec<Weak<NativeClosure>>,
+    allocated: usize,
+    threshold: Option<usize>,
+    /// Objects that survived the last collection
+    survivors: usize,
+    stats: GcStats,
+    collecting: bool,
+}
+
+thread_local! {
+    static REGISTRY: RefCell<Registry> = RefCell::new(Registry {
+        envs: Vec::new(),
+        closures: Vec::new(),
+        atoms: Vec::new(),
+        meta_cells: Vec::new(),
+        multi_fns: Vec::new(),
+        lazy_seqs: Vec::new(),
+        delays: Vec::new(),
+        promises: Vec::new(),
+        native_closures: Vec::new(),
+        allocated: 0,
+        threshold: Some(DEFAULT_THRESHOLD),
+        survivors: 0,
+        stats: GcStats::default(),
+        collecting: false,
+    });
+}
+
+fn track(register: impl FnOnce(&mut Registry)) {
+    let due = REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        register(&mut registry);
+        registry.allocated += 1;
+        !registry.collecting
+            && registry
+                .threshold
+                .is_some_and(|threshold| registry.allocated >= threshold.max(registry.survivors))
+    });
+    if due {
+        collect();
+    }
+}
+
+pub(crate) fn track_env(env: &Rc<Mutex<EnvInner>>) {
+    track(|registry| registry.envs.push(Rc::downgrade(env)));
+}
+
+pub(crate) fn track_closure(closure: &Rc<UserFn>) {
+    track(|registry| registry.closures.push(Rc::downgrade(closure)));
+}
+
+pub(crate) fn track_atom(value: &Rc<RefCell<Form>>) {
+    track(|registry| registry.atoms.push(Rc::downgrade(value)));
+}
+
+pub(crate) fn track_atom_meta(meta: &Rc<RefCell<Form>>) {
+    track(|registry| registry.meta_cells.push(Rc::downgrade(meta)));
+}
+
+pub(crate) fn track_multi_fn(multi: &Rc<MultiFn>) {
+    track(|registry| registry.multi_fns.push(Rc::downgrade(multi)));
+}
+
+pub(crate) fn track_lazy_seq(cell: &Rc<RefCell<lazy::State>>) {
+    track(|registry| registry.lazy_seqs.push(Rc::downgrade(cell)));
+}
+
+pub(crate) fn track_delay(cell: &Rc<RefCell<delay::State>>) {
+    track(|registry| registry.delays.push(Rc::downgrade(cell)));
+}
+
+pub(crate) fn track_promise(cell: &Rc<RefCell<Option<Form>>>) {
+    track(|registry| registry.promises.push(Rc::downgrade(cell)));
+}
+
+pub(crate) fn track_native_closure(closure: &Rc<NativeClosure>) {
+    track(|registry| registry.native_closures.push(Rc::downgrade(closure)));
+}
+
+/// Set how many objects may be created before a collection runs automatically. `None` disables
+/// automatic collection.
+///
+/// A collection looks at every live object, so while more than `threshold` objects survive,
+/// the next collection waits until as many new ones have been created, to keep the time spent
+/// collecting proportional to the number of objects created.
+pub fn set_threshold(threshold: Option<usize>) {
+    REGISTRY.with(|registry| registry.borrow_mut().threshold = threshold);
+}
+
+/// Statistics without running a collection
+pub fn stats() -> GcStats {
+    REGISTRY.with(|registry| {
+        let registry = registry.borrow();
+        GcStats {
+            live_envs: registry
+                .envs
+                .iter()
+                .filter(|w| w.strong_count() > 0)
+                .count(),
+            live_closures: registry
+                .closures
+                .iter()
+                .filter(|w| w.strong_count() > 0)
+                .count(),
+            live_atoms: registry
+                .atoms
+                .iter()
+                .filter(|w| w.strong_count() > 0)
+                .count(),
+            ..registry.stats
+        }
+    })
+}
+
+/// Hasher for maps keyed by the address of a node. A multiplication spreads the address into
+/// the high bits, which are rotated down because the low bits of aligned addresses are zero.
+#[derive(Default)]
+struct AddrHasher(u64);
+
+impl Hasher for AddrHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0.rotate_left(8) ^ u64::from(byte)).wrapping_mul(0x517c_c1b7_2722_0a95);
+        }
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.0 = (self.0 ^ n as u64)
+            .wrapping_mul(0x517c_c1b7_2722_0a95)
+            .rotate_left(32);
+    }
+}
+
+enum Node {
+    Env(Rc<Mutex<EnvInner>>),
+    Closure(Rc<UserFn>),
+    Atom(Rc<RefCell<Form>>),
+    AtomMeta(Rc<RefCell<Form>>),
+    MultiFn(Rc<MultiFn>),
+    LazySeq(LazySeq),
+    Delay(Delay),
+    Promise(Promise),
+    NativeClosure(Rc<NativeClosure>),
+}
+
+impl Node {
+    fn addr(&self) -> usize {
+        match self {
+            Node::Env(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Closure(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Atom(rc) | Node::AtomMeta(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::MultiFn(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::LazySeq(seq) => seq.addr(),
+            Node::Delay(delay) => delay.addr(),
+            Node::Promise(promise) => promise.addr(),
+            Node::NativeClosure(rc) => Rc::as_ptr(rc) as *const () as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Env(rc) => Rc::strong_count(rc),
+            Node::Closure(rc) => Rc::strong_count(rc),
+            Node::Atom(rc) | Node::AtomMeta(rc) => Rc::strong_count(rc),
+            Node::MultiFn(rc) => Rc::strong_count(rc),
+            Node::LazySeq(seq) => seq.strong_count(),
+            Node::Delay(delay) => delay.strong_count(),
+            Node::Promise(promise) => promise.strong_count(),
+            Node::NativeClosure(rc) => Rc::strong_count(rc),
+        }
+    }
+
+    /// Call `edge` with the address of every registered object this node holds a strong
+    /// reference to. Returns false if the node is in use and could not be inspected.
+    fn trace(&self, edge: &mut impl FnMut(usize)) -> bool {
+        match self {
+            Node::Env(rc) => match rc.try_lock() {
+                Ok(inner) => {
+                    inner.data.values().for_each(|form| trace_form(form, edge));
+                    if let Some(ref parent) = inner.parent {
+                        edge(Rc::as_ptr(&parent.inner) as *const () as usize);
+                    }
+                    // The base environment holds the environments of its namespaces
+                    if let Some(ref runtime) = inner.runtime {
+                        let Ok(namespaces) = runtime.namespaces.try_borrow() else {
+                            return false;
+                        };
+                        for env in namespaces.values() {
+                            edge(Rc::as_ptr(&env.inner) as *const () as usize);
+                        }
+                        // and the values of its dynamic vars
+                        if !runtime.vars.for_each_value(|form| trace_form(form, edge)) {
+                            return false;
+                        }
+                    }
+                    true
+                }
+                Err(_) => false,
+            },
+            Node::Closure(rc) => {
+                for arity in &rc.arities {
+                    arity
+                        .binds
+                        .iter()
+                        .chain(&arity.bind_rest)
+                        .chain([&arity.body])
+                        .for_each(|form| trace_form(form, edge));
+                }
+                edge(Rc::as_ptr(&rc.env.inner) as *const () as usize);
+                true
+            }
+            Node::Atom(rc) | Node::AtomMeta(rc) => match rc.try_borrow() {
+                Ok(form) => {
+                    trace_form(&form, edge);
+                    true
+                }
+                Err(_) => false,
+            },
+            Node::MultiFn(rc) => rc.for_each_fn(|form| trace_form(form, edge)),
+            Node::LazySeq(seq) => seq.for_each_form(|form| trace_form(form, edge)),
+            Node::Delay(delay) => delay.for_each_form(|form| trace_form(form, edge)),
+            Node::Promise(promise) => promise.for_each_form(|form| trace_form(form, edge)),
+            Node::NativeClosure(rc) => {
+                rc.captured.iter().for_each(|form| trace_form(form, edge));
+                true
+            }
+        }
+    }
+}
+
+fn trace_form(form: &Form, edge: &mut impl FnMut(usize)) {
+    if let Some(ref meta) = form.meta {
+        trace_form(meta, edge);
+    }
+    match form.kind {
+        FormKind::List(ref items) | FormKind::Vector(ref items) => {
+            items.iter().for_each(|item| trace_form(item, edge))
+        }
+        FormKind::HashMap(ref map) => map.iter().for_each(|(k, v)| {
+            trace_form(k, edge);
+            trace_form(v, edge);
+        }),
+        FormKind::UserFn(ref f) => edge(Rc::as_ptr(f) as *const () as usize),
+        FormKind::Atom(ref atom) => {
+            edge(Rc::as_ptr(&atom.value) as *const () as usize);
+            edge(Rc::as_ptr(&atom.meta) as *const () as usize);
+        }
+        FormKind::MultiFn(ref multi) => edge(Rc::as_ptr(multi) as *const () as usize),
+        FormKind::LazySeq(ref seq) => edge(seq.addr()),
+        FormKind::Delay(ref delay) => edge(delay.addr()),
+        FormKind::Promise(ref promise) => edge(promise.addr()),
+        FormKind::NativeFn(ref native) => {
+            if let Some(closure) = native.closure() {
+                edge(Rc::as_ptr(closure) as *const () as usize);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Free every registered object that is only reachable through reference cycles
+pub fn collect() -> GcStats {
+    let nodes = REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        registry.collecting = true;
+        registry.allocated = 0;
+        let mut nodes = Vec::new();
+        registry.envs.retain(|weak| match weak.upgrade() {
+            Some(rc) => {
+                nodes.push(Node::Env(rc));
+                true
+            }
+            None => false,
+        });
+        registry.closures.retain(|weak| match weak.upgrade() {
+            Some(rc) => {
+                nodes.push(Node::Closure(rc));
+                true
+            }
+            None => false,
+        });
+        registry.atoms.retain(|weak| match weak.upgrade() {
+            Some(rc) => {
+                nodes.push(Node::Atom(rc));
+                true
+            }
+            None => false,
+        });
+        registry.meta_cells.retain(|weak| match weak.upgrade() {
+            Some(rc) => {
+                nodes.push(Node::AtomMeta(rc));
+                true
+            }
+            None => false,
+        });
+        registry.multi_fns.retain(|weak| match weak.upgrade() {
+            Some(rc) => {
+                nodes.push(Node::MultiFn(rc));
+                true
+            }
+            None => false,
+        });
+        registry
+            .lazy_seqs
+            .retain(|weak| match LazySeq::upgrade(weak) {
+                Some(seq) => {
+                    nodes.push(Node::LazySeq(seq));
+                    true
+                }
+                None => false,
+            });
+        registry.delays.retain(|weak| match Delay::upgrade(weak) {
+            Some(delay) => {
+                nodes.push(Node::Delay(delay));
+                true
+            }
+            None => false,
+        });
+        registry
+            .promises
+            .retain(|weak| match Promise::upgrade(weak) {
+                Some(promise) => {
+                    nodes.push(Node::Promise(promise));
+                    true
+                }
+                None => false,
+            });
+        registry
+            .native_closures
+            .retain(|weak| match weak.upgrade() {
+                Some(rc) => {
+                    nodes.push(Node::NativeClosure(rc));
+                    true
+                }
+                None => false,
+            });
+        nodes
+    });
+
+    let index: HashMap<usize, usize, BuildHasherDefault<AddrHasher>> = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| (node.addr(), i))
+        .collect();
+    let mut internal = vec![0usize; nodes.len()];
+    // The targets of the edges of each node, one node after another, starting at `starts[i]`
+    let mut targets = Vec::new();
+    let mut starts = Vec::with_capacity(nodes.len() + 1);
+    let mut opaque = vec![false; nodes.len()];
+    for (i, node) in nodes.iter().enumerate() {
+        starts.push(targets.len());
+        let traced = node.trace(&mut |addr| {
+            if let Some(&target) = index.get(&addr) {
+                internal[target] += 1;
+                targets.push(target);
+            }
+        });
+        opaque[i] = !traced;
+    }
+    starts.push(targets.len());
+
+    // One strong reference to each node is held by `nodes` itself
+    let mut reachable = vec![false; nodes.len()];
+    let mut pending: Vec<usize> = (0..nodes.len())
+        .filter(|&i| opaque[i] || nodes[i].strong_count() - 1 > internal[i])
+        .collect();
+    while let Some(i) = pending.pop() {
+        if !reachable[i] {
+            reachable[i] = true;
+            pending.extend_from_slice(&targets[starts[i]..starts[i + 1]]);
+        }
+    }
+
+    let mut freed = GcStats::default();
+    let mut survivors = 0;
+    let mut garbage = Vec::new();
+    let mut parents = Vec::new();
+    let mut runtimes = Vec::new();
+    let mut seqs = Vec::new();
+    let mut delays = Vec::new();
+    for (node, reachable) in nodes.iter().zip(reachable) {
+        if reachable {
+            survivors += 1;
+            continue;
+        }
+        match node {
+            Node::Env(rc) => {
+                let mut inner = rc.lock().expect("Poisoned mutex");
+                garbage.extend(std::mem::take(&mut inner.data).into_values());
+                parents.extend(inner.parent.take());
+                runtimes.extend(inner.runtime.take());
+                freed.collected_envs += 1;
+            }
+            Node::Closure(_) => freed.collected_closures += 1,
+            Node::Atom(rc) => {
+                garbage.push(rc.replace(Form::nil()));
+                freed.collected_atoms += 1;
+            }
+            Node::AtomMeta(rc) => garbage.push(rc.replace(Form::nil())),
+            Node::MultiFn(rc) => garbage.extend(rc.take_methods().into_values()),
+            Node::LazySeq(seq) => seqs.push(seq.clear()),
+            Node::Delay(delay) => delays.push(delay.clear()),
+            Node::Promise(promise) => garbage.extend(promise.clear()),
+            // The captured forms can't be taken out, but the cycles through them pass through
+            // another node that is cleared
+            Node::NativeClosure(_) => {}
+        }
+    }
+    // Drop the contents only once no node is borrowed
+    drop(nodes);
+    drop(garbage);
+    drop(parents);
+    drop(runtimes);
+    drop(seqs);
+    drop(delays);
+
+    REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        registry.collecting = false;
+        registry.survivors = survivors;
+        registry.stats.collected_envs += freed.collected_envs;
+        registry.stats.collected_closures += freed.collected_closures;
+        registry.stats.collected_atoms += freed.collected_atoms;
+        registry.stats.collections += 1;
+    });
+    stats()
+}
@@ -0,0 +1,244 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{BuildHasherDefault, Hasher},
+    sync::{Mutex, OnceLock},
+};
+
+/// An interned symbol or keyword name
+///
+/// Interned names are never freed, so comparing, hashing and copying an `Ident` are integer
+/// operations. The printed name is available through [`Ident::name`].
+///
+/// The interner is a single `Mutex` shared by every thread, and each name is leaked with
+/// `Box::leak` the first time it is interned. Every distinct symbol or keyword ever read,
+/// including ones built at runtime with `symbol`, `keyword` or `read-string`, stays allocated
+/// until the process exits, so programs that make unboundedly many distinct names grow without
+/// limit. [`Ident::new`] takes the lock, while [`Ident::name`] reads an append-only table and
+/// doesn't.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Ident(u32);
+
+struct Interner {
+    ids: HashMap<&'static str, u32>,
+}
+
+fn interner() -> &'static Mutex<Interner> {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(|| {
+        let mut interner = Interner {
+            ids: HashMap::new(),
+        };
+        for name in WELL_KNOWN {
+            interner.intern(name);
+        }
+        Mutex::new(interner)
+    })
+}
+
+impl Interner {
+    fn intern(&mut self, name: &str) -> u32 {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        let name: &'static str = Box::leak(name.into());
+        let id = u32::try_from(self.ids.len()).expect("too many interned names");
+        NAMES.store(id, name);
+        self.ids.insert(name, id);
+        id
+    }
+}
+
+/// Size of the first segment of [`Names`]; each following one is twice as large
+const FIRST_SEGMENT: usize = 64;
+
+/// The name of each id, written once by the interner while it holds its lock and read without
+/// it
+///
+/// The names are kept in segments that are allocated as needed and never moved or freed, so
+/// reading one is a few atomic loads. Segment `k` holds `FIRST_SEGMENT << k` names, and 32 of
+/// them have room for every `u32` id.
+struct Names([OnceLock<&'static [OnceLock<&'static str>]>; 32]);
+
+static NAMES: Names = Names([const { OnceLock::new() }; 32]);
+
+impl Names {
+    /// The segment holding `id` and its index there
+    fn slot(id: u32) -> (usize, usize) {
+        let id = id as usize;
+        let segment = (id / FIRST_SEGMENT + 1).ilog2() as usize;
+        (segment, id - FIRST_SEGMENT * ((1 << segment) - 1))
+    }
+
+    fn store(&self, id: u32, name: &'static str) {
+        let (segment, index) = Names::slot(id);
+        let names = self.0[segment].get_or_init(|| {
+            let names = (0..FIRST_SEGMENT << segment).map(|_| OnceLock::new());
+            Box::leak(names.collect())
+        });
+        names[index]
+            .set(name)
+            .expect("each id is given a name once");
+    }
+
+    fn get(&self, id: u32) -> Option<&'static str> {
+        let (segment, index) = Names::slot(id);
+        self.0[segment].get()?[index].get().copied()
+    }
+}
+
+impl Ident {
+    pub fn new(name: &str) -> Ident {
+        Ident(interner().lock().expect("Poisoned mutex").intern(name))
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(name: &str) -> Ident {
+        Ident::new(name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match NAMES.get(self.0) {
+            Some(name) => name,
+            // The well-known names have ids before anything is interned
+            None => WELL_KNOWN[self.0 as usize],
+        }
+    }
+}
+
+impl PartialEq<str> for Ident {
+    fn eq(&self, other: &str) -> bool {
+        self.name() == other
+    }
+}
+
+impl std::fmt::Debug for Ident {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::fmt::Display for Ident {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl From<&str> for Ident {
+    fn from(name: &str) -> Ident {
+        Ident::new(name)
+    }
+}
+
+impl From<&String> for Ident {
+    fn from(name: &String) -> Ident {
+        Ident::new(name)
+    }
+}
+
+impl From<String> for Ident {
+    fn from(name: String) -> Ident {
+        Ident::new(&name)
+    }
+}
+
+/// Hasher for maps keyed by `Ident`. The ids are small sequential integers, so a single
+/// multiplication spreads them well enough.
+#[derive(Default)]
+pub(crate) struct IdentHasher(u64);
+
+impl Hasher for IdentHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0.rotate_left(8) ^ u64::from(byte)).wrapping_mul(0x517c_c1b7_2722_0a95);
+        }
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.0 = (self.0 ^ u64::from(n)).wrapping_mul(0x517c_c1b7_2722_0a95);
+    }
+}
+
+pub(crate) type IdentMap<V> = HashMap<Ident, V, BuildHasherDefault<IdentHasher>>;
+pub(crate) type IdentSet = HashSet<Ident, BuildHasherDefault<IdentHasher>>;
+
+macro_rules! well_known {
+    ($($konst:ident => $name:literal,)+) => {
+        const WELL_KNOWN: &[&str] = &[$($name),+];
+
+        #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+        #[repr(u32)]
+        enum WellKnown {
+            $($konst),+
+        }
+
+        /// Names used by the evaluator, interned up front so they can be matched on
+        pub mod sym {
+            use super::{Ident, WellKnown};
+
+            $(pub const $konst: Ident = Ident(WellKnown::$konst as u32);)+
+        }
+    };
+}
+
+well_known! {
+    DEF => "def!",
+    DEFMACRO => "defmacro!",
+    LET => "let*",
+    DO => "do",
+    IF => "if",
+    FN => "fn*",
+    EVAL => "eval",
+    QUOTE => "quote",
+    QUASIQUOTE => "quasiquote",
+    QUASIQUOTEEXPAND => "quasiquoteexpand",
+    UNQUOTE => "unquote",
+    SPLICE_UNQUOTE => "splice-unquote",
+    MACROEXPAND => "macroexpand",
+    MACROEXPAND_1 => "macroexpand-1",
+    MACROEXPAND_ALL => "macroexpand-all",
+    TRY => "try*",
+    CATCH => "catch*",
+    WHEN => "when",
+    AMPERSAND => "&",
+    CONCAT => "concat",
+    CONS => "cons",
+    VEC => "vec",
+    WITH_META => "with-meta",
+    DEREF => "deref",
+    LOOP => "loop",
+    RECUR => "recur",
+    KEYS => "keys",
+    STRS => "strs",
+    AS => "as",
+    OR => "or",
+    NS => "ns",
+    REQUIRE => "require",
+    REFER => "refer",
+    ALL => "all",
+    RELOAD => "reload",
+    BINDING => "binding",
+    SET => "set!",
+    DYNAMIC => "dynamic",
+    PRINT_LENGTH => "*print-length*",
+    PRINT_READABLY => "*print-readably*",
+    OUT => "*out*",
+    STDERR => "stderr",
+    FINALLY => "finally",
+    TYPE => "type",
+    MESSAGE => "message",
+    DATA => "data",
+    CAUSE => "cause",
+    EX_INFO => "ex-info",
+    NAME => "name",
+    LINE => "line",
+    COLUMN => "column",
+    TRACE => "trace",
+    LAST_ERROR => "*e",
+    MATCH => "match",
+    GUARD => "guard",
+    DEFAULT => "default",
+}
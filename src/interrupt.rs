@@ -0,0 +1,93 @@
+//! Interrupting evaluation
+//!
+//! Each interpreter has its own interrupt flag, set through the [`Handle`] its environments
+//! give out with [`Env::interrupt_handle`]. The `eval` loop and long-running native functions
+//! check the flag of the interpreter evaluating, so evaluation stops with
+//! [`Error::Interrupted`](crate::Error::Interrupted) at the next step while other interpreters
+//! carry on. The flag is cleared when the error is raised. `try*` never catches the error, so an
+//! interrupted loop can't swallow it, although `finally` bodies still run.
+//!
+//! [`install_sigint_handler`] makes Ctrl-C interrupt one interpreter.
+
+use std::{
+    ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicPtr, Ordering},
+        Arc,
+    },
+};
+
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+
+use crate::{runtime, Env, Error, Result};
+
+/// Interrupts the interpreter it came from. Handles can be sent to other threads, and every
+/// copy sets the same flag.
+#[derive(Clone, Debug, Default)]
+pub struct Handle(Arc<AtomicBool>);
+
+impl Handle {
+    /// Ask evaluation to stop. Safe to call from a signal handler or another thread.
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Forget an interrupt that no evaluation has seen yet
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    /// Whether an interrupt is pending, clearing it
+    fn take(&self) -> bool {
+        self.0.load(Ordering::Relaxed) && self.0.swap(false, Ordering::Relaxed)
+    }
+}
+
+impl Env {
+    /// A handle that interrupts evaluation in the interpreter this environment belongs to
+    pub fn interrupt_handle(&self) -> Handle {
+        self.runtime().interrupt.clone()
+    }
+}
+
+/// Return `Error::Interrupted` if an interrupt is pending for the interpreter evaluating, and
+/// clear it
+pub(crate) fn check() -> Result<()> {
+    if runtime::with_current(|runtime| runtime.interrupt.take()).unwrap_or(false) {
+        Err(Error::Interrupted)
+    } else {
+        Ok(())
+    }
+}
+
+/// Leave an interrupt that was raised but not propagated pending again, so evaluation stops at
+/// its next step
+pub(crate) fn restore() {
+    runtime::with_current(|runtime| runtime.interrupt.interrupt());
+}
+
+/// The flag Ctrl-C sets, leaked by `install_sigint_handler`
+static SIGINT_FLAG: AtomicPtr<AtomicBool> = AtomicPtr::new(ptr::null_mut());
+
+extern "C" fn handle_sigint(_: nix::libc::c_int) {
+    let flag = SIGINT_FLAG.load(Ordering::Acquire);
+    // SAFETY: the flag is leaked, so it is never freed once stored
+    if let Some(flag) = unsafe { flag.as_ref() } {
+        flag.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Make SIGINT interrupt evaluation through `handle` instead of terminating the process. Only
+/// the last handle installed is interrupted, and each one stays allocated until the process
+/// exits.
+pub fn install_sigint_handler(handle: &Handle) -> nix::Result<()> {
+    let flag = Arc::into_raw(handle.0.clone()).cast_mut();
+    SIGINT_FLAG.store(flag, Ordering::Release);
+    let action = SigAction::new(
+        SigHandler::Handler(handle_sigint),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    // SAFETY: the handler only loads and stores atomics, which is async-signal-safe
+    unsafe { signal::sigaction(Signal::SIGINT, &action) }.map(drop)
+}
@@ -0,0 +1,381 @@
+//! Lazy sequences
+//!
+//! A lazy sequence holds a function that produces the sequence the first time it is needed:
+//! nil, a list, a vector, or another lazy sequence, usually one starting with an element
+//! consed onto a further lazy sequence. Each sequence is realized at most once and the result
+//! is cached, so sequences built from other sequences, like `map` and `take`, only realize as
+//! much of their source as is consumed from them, and infinite sequences work as long as only
+//! a finite part of them is used.
+//!
+//! Realizing an element counts as an evaluation step for [`limits`](crate::limits) and checks
+//! for interrupts, and so does iterating over an element that is already realized, so
+//! `(count (range))` can be stopped. Comparing or hashing a lazy sequence
+//! realizes all of it, and so does printing it, unless `*print-length*` is set.
+
+use std::{
+    cell::RefCell,
+    rc::{Rc, Weak},
+};
+
+use crate::{interrupt, limits, Error, Form, FormKind, Result};
+
+type Produce = Box<dyn Fn(&[Form]) -> Result<Form>>;
+
+/// The function producing a sequence or the value of a delay, and the forms it uses. The forms
+/// are kept apart from the function so the collector can trace them.
+pub(crate) struct Thunk {
+    captured: Box<[Form]>,
+    f: Produce,
+}
+
+impl Thunk {
+    pub(crate) fn new(f: impl Fn() -> Result<Form> + 'static) -> Thunk {
+        Thunk {
+            captured: Box::new([]),
+            f: Box::new(move |_| f()),
+        }
+    }
+
+    pub(crate) fn capturing<const N: usize>(
+        captured: [Form; N],
+        f: impl Fn(&[Form; N]) -> Result<Form> + 'static,
+    ) -> Thunk {
+        Thunk {
+            captured: Box::new(captured),
+            f: Box::new(move |captured| {
+                f(captured
+                    .try_into()
+                    .expect("called with the forms it captured"))
+            }),
+        }
+    }
+
+    pub(crate) fn call(&self) -> Result<Form> {
+        (self.f)(&self.captured)
+    }
+
+    pub(crate) fn captured(&self) -> &[Form] {
+        &self.captured
+    }
+}
+
+/// The first element of a sequence and the rest, or `None` if it is empty
+pub type Step = Option<(Form, Form)>;
+
+pub(crate) enum State {
+    Unrealized(Thunk),
+    Realizing,
+    Realized(Step),
+}
+
+/// A sequence computed when it is first needed
+///
+/// Like atoms, clones share the sequence, so realizing one realizes them all.
+#[derive(Clone)]
+pub struct LazySeq(Rc<RefCell<State>>);
+
+impl LazySeq {
+    fn from_state(state: State) -> LazySeq {
+        let seq = LazySeq(Rc::new(RefCell::new(state)));
+        crate::gc::track_lazy_seq(&seq.0);
+        seq
+    }
+
+    /// A sequence produced by calling `f` when it is first needed
+    ///
+    /// The collector can't see the forms `f` holds, so they are never freed while the sequence
+    /// is unrealized if they refer back to it. Use [`LazySeq::capturing`] for those.
+    pub fn new(f: impl Fn() -> Result<Form> + 'static) -> LazySeq {
+        LazySeq::from_state(State::Unrealized(Thunk::new(f)))
+    }
+
+    /// A sequence produced by calling `f` with `captured` when it is first needed
+    pub fn capturing<const N: usize>(
+        captured: [Form; N],
+        f: impl Fn(&[Form; N]) -> Result<Form> + 'static,
+    ) -> LazySeq {
+        LazySeq::from_state(State::Unrealized(Thunk::capturing(captured, f)))
+    }
+
+    /// A sequence starting with `first` and continuing with the sequence `rest`, which isn't
+    /// realized
+    pub fn cons(first: Form, rest: Form) -> LazySeq {
+        LazySeq::from_state(State::Realized(Some((first, rest))))
+    }
+
+    /// Whether the sequence has been produced yet
+    pub fn is_realized(&self) -> bool {
+        matches!(*self.0.borrow(), State::Realized(_))
+    }
+
+    /// Realize the sequence, and return its first element and the rest
+    ///
+    /// If producing the sequence fails, it stays unrealized and the next use tries again.
+    pub fn step(&self) -> Result<Step> {
+        // Producing a sequence often returns another lazy sequence, e.g. when a filter skips
+        // elements. They are realized in a loop, not recursively, so long chains don't
+        // overflow the stack, and all end up with the same result.
+        let mut realizing = Vec::new();
+        let mut current = self.clone();
+        let result = loop {
+            let state = std::mem::replace(&mut *current.0.borrow_mut(), State::Realizing);
+            let thunk = match state {
+                State::Realized(step) => {
+                    *current.0.borrow_mut() = State::Realized(step.clone());
+                    break Ok(step);
+                }
+                State::Realizing => break Err(Error::RecursiveLazySeq),
+                State::Unrealized(thunk) => thunk,
+            };
+            let produced = limits::step()
+                .and_then(|()| interrupt::check())
+                .and_then(|()| thunk.call());
+            realizing.push((current.clone(), thunk));
+            match produced {
+                Ok(Form {
+                    kind: FormKind::LazySeq(next),
+                    ..
+                }) => current = next,
+                Ok(form) => break uncons(form),
+                Err(err) => break Err(err),
+            }
+        };
+        for (seq, thunk) in realizing {
+            *seq.0.borrow_mut() = match result {
+                Ok(ref step) => State::Realized(step.clone()),
+                Err(_) => State::Unrealized(thunk),
+            };
+        }
+        result
+    }
+
+    /// Iterate over the elements, realizing them as they are reached
+    pub fn iter(&self) -> Iter {
+        Iter::Lazy(self.clone())
+    }
+}
+
+impl Drop for LazySeq {
+    fn drop(&mut self) {
+        // Dropping the head of a long realized sequence would otherwise recurse once per
+        // element, so the rest is unlinked first while this is the last reference to it
+        let mut next = self.take_rest();
+        while let Some(seq) = next {
+            next = seq.take_rest();
+        }
+    }
+}
+
+impl LazySeq {
+    /// Take the rest of a realized sequence nothing else refers to, if it is a lazy sequence
+    fn take_rest(&self) -> Option<LazySeq> {
+        if Rc::strong_count(&self.0) > 1 {
+            return None;
+        }
+        let mut state = self.0.try_borrow_mut().ok()?;
+        let State::Realized(Some((_, rest))) = &mut *state else {
+            return None;
+        };
+        match std::mem::replace(rest, Form::nil()).kind {
+            FormKind::LazySeq(seq) => Some(seq),
+            _ => None,
+        }
+    }
+}
+
+/// Access for the collector, which registers the cell of each sequence
+impl LazySeq {
+    pub(crate) fn upgrade(cell: &Weak<RefCell<State>>) -> Option<LazySeq> {
+        cell.upgrade().map(LazySeq)
+    }
+
+    pub(crate) fn addr(&self) -> usize {
+        Rc::as_ptr(&self.0) as *const () as usize
+    }
+
+    pub(crate) fn strong_count(&self) -> usize {
+        Rc::strong_count(&self.0)
+    }
+
+    /// Call `f` with the forms an unrealized sequence captured, or the first element and the
+    /// rest of a realized one. Returns false if the sequence is in use.
+    pub(crate) fn for_each_form(&self, mut f: impl FnMut(&Form)) -> bool {
+        let Ok(state) = self.0.try_borrow() else {
+            return false;
+        };
+        match *state {
+            State::Unrealized(ref thunk) => thunk.captured().iter().for_each(f),
+            State::Realizing | State::Realized(None) => {}
+            State::Realized(Some((ref first, ref rest))) => {
+                f(first);
+                f(rest);
+            }
+        }
+        true
+    }
+
+    /// Empty the sequence, returning what it held
+    pub(crate) fn clear(&self) -> State {
+        self.0.replace(State::Realized(None))
+    }
+}
+
+impl From<LazySeq> for Form {
+    fn from(seq: LazySeq) -> Form {
+        Form::lazy_seq(seq)
+    }
+}
+
+/// Split a sequence into its first element and the rest, or `None` if it is nil or empty. Only
+/// the first element of a lazy sequence is realized.
+pub fn uncons(form: Form) -> Result<Step> {
+    match form.kind {
+        FormKind::Nil => Ok(None),
+        FormKind::List(items) | FormKind::Vector(items) => {
+            let mut items = items.into_iter();
+            Ok(items.next().map(|first| (first, Form::list(items))))
+        }
+        FormKind::LazySeq(seq) => seq.step(),
+        kind => Err(Error::WrongType {
+            expected: "sequence",
+            found: Form {
+                kind,
+                meta: form.meta,
+            },
+        }),
+    }
+}
+
+/// Iterate over a list, vector, lazy sequence or nil
+pub fn iter(form: Form) -> Result<Iter> {
+    match form.kind {
+        FormKind::Nil => Ok(Iter::Done),
+        FormKind::List(items) | FormKind::Vector(items) => Ok(Iter::Items(items.into_iter())),
+        FormKind::LazySeq(seq) => Ok(Iter::Lazy(seq)),
+        kind => Err(Error::WrongType {
+            expected: "sequence",
+            found: Form {
+                kind,
+                meta: form.meta,
+            },
+        }),
+    }
+}
+
+/// A list or vector as a lazy sequence, so that taking its rest doesn't copy the elements.
+/// Lazy sequences and nil are returned as they are.
+pub fn seq(form: Form) -> Result<Form> {
+    match form.kind {
+        FormKind::List(items) | FormKind::Vector(items) => Ok(items
+            .into_iter()
+            .rev()
+            .fold(Form::nil(), |rest, item| LazySeq::cons(item, rest).into())),
+        FormKind::Nil | FormKind::LazySeq(_) => Ok(form),
+        kind => Err(Error::WrongType {
+            expected: "sequence",
+            found: Form {
+                kind,
+                meta: form.meta,
+            },
+        }),
+    }
+}
+
+/// All the elements of a list, vector, lazy sequence or nil
+pub fn realize(form: Form) -> Result<Vec<Form>> {
+    iter(form)?.collect()
+}
+
+/// The elements of a sequence, realized one at a time. Iteration stops after an error,
+/// including an interrupt.
+pub enum Iter {
+    Items(std::vec::IntoIter<Form>),
+    Lazy(LazySeq),
+    Failed(Error),
+    Done,
+}
+
+impl Iterator for Iter {
+    type Item = Result<Form>;
+
+    fn next(&mut self) -> Option<Result<Form>> {
+        match std::mem::replace(self, Iter::Done) {
+            Iter::Items(mut items) => {
+                let item = items.next()?;
+                *self = Iter::Items(items);
+                Some(Ok(item))
+            }
+            Iter::Lazy(seq) => match interrupt::check().and_then(|()| seq.step()) {
+                Ok(Some((first, rest))) => {
+                    *self = iter(rest).unwrap_or_else(Iter::Failed);
+                    Some(Ok(first))
+                }
+                Ok(None) => None,
+                Err(err) => Some(Err(err)),
+            },
+            Iter::Failed(err) => Some(Err(err)),
+            Iter::Done => None,
+        }
+    }
+}
+
+/// Compare a lazy sequence with another sequence element by element. A sequence that fails to
+/// realize is equal to nothing.
+pub(crate) fn equal(seq: &LazySeq, other: &FormKind) -> bool {
+    let mut others: Box<dyn Iterator<Item = Result<Form>>> = match other {
+        FormKind::List(items) | FormKind::Vector(items) => Box::new(items.iter().cloned().map(Ok)),
+        FormKind::LazySeq(other) => Box::new(other.iter()),
+        _ => return false,
+    };
+    let mut items = seq.iter();
+    loop {
+        match (items.next(), others.next()) {
+            (None, None) => return true,
+            (Some(Ok(a)), Some(Ok(b))) if a == b => {}
+            _ => return false,
+        }
+    }
+}
+
+/// Realize the lazy sequences in `form` into lists, since code is made of lists. Macros and
+/// `eval` get code built with `concat` and `map`, which are lazy.
+pub(crate) fn to_code(form: Form) -> Result<Form> {
+    if !contains_lazy(&form) {
+        return Ok(form);
+    }
+    let kind = match form.kind {
+        FormKind::LazySeq(seq) => FormKind::List(
+            seq.iter()
+                .map(|item| item.and_then(to_code))
+                .collect::<Result<_>>()?,
+        ),
+        FormKind::List(items) => {
+            FormKind::List(items.into_iter().map(to_code).collect::<Result<_>>()?)
+        }
+        FormKind::Vector(items) => {
+            FormKind::Vector(items.into_iter().map(to_code).collect::<Result<_>>()?)
+        }
+        FormKind::HashMap(entries) => FormKind::HashMap(
+            entries
+                .into_iter()
+                .map(|(key, value)| Ok((to_code(key)?, to_code(value)?)))
+                .collect::<Result<_>>()?,
+        ),
+        kind => kind,
+    };
+    Ok(Form {
+        kind,
+        meta: form.meta,
+    })
+}
+
+fn contains_lazy(form: &Form) -> bool {
+    match form.kind {
+        FormKind::LazySeq(_) => true,
+        FormKind::List(ref items) | FormKind::Vector(ref items) => items.iter().any(contains_lazy),
+        FormKind::HashMap(ref entries) => entries
+            .iter()
+            .any(|(key, value)| contains_lazy(key) || contains_lazy(value)),
+        _ => false,
+    }
+}
@@ -1,20 +1,43 @@
-mod convert;
+mod analyze;
+mod bytecode;
+pub mod convert;
 pub mod core;
+pub mod delay;
+mod destructure;
+mod dynamic;
 mod env;
 pub mod eval;
 pub mod exec;
 pub mod form;
 pub mod format;
-// mod ptr;
+pub mod gc;
+mod intern;
+pub mod interrupt;
+pub mod lazy;
+pub mod limits;
+pub mod multi;
+mod namespace;
+pub mod pattern;
 mod reader;
+mod runtime;
+#[cfg(feature = "serde")]
+mod serde_form;
+pub mod special;
+pub mod trace;
+pub mod vm;
 
 use std::{convert::Infallible, num::TryFromIntError};
 
+pub use convert::Host;
 pub use env::Env;
 pub use eval::eval;
-pub use form::{Form, FormKind};
+pub use form::{Form, FormKind, HostValue, HostValueBuilder, Ident};
 pub use format::pr_str;
 pub use reader::read_str;
+#[cfg(feature = "derive")]
+pub use risp_derive::{FromForm, IntoForm};
+#[cfg(feature = "serde")]
+pub use serde_form::{from_form, to_form};
 use thiserror::Error;
 
 pub type Result<T> = std::result::Result<T, crate::Error>;
@@ -47,6 +70,173 @@ pub enum Error {
     IndexOutOfRange(usize),
     #[error("{0}")]
     UserError(Form),
+    #[error("missing field '{0}'")]
+    MissingField(String),
+    #[error("invalid field '{field}': {source}")]
+    InvalidField { field: String, source: Box<Error> },
+    #[error("unknown variant '{0}'")]
+    UnknownVariant(String),
+    #[error("expected {expected}, found {found:?}")]
+    WrongType { expected: &'static str, found: Form },
+    #[error("at position {position}: {source}")]
+    AtPosition { position: usize, source: Box<Error> },
+    #[error("expected at most {expected} elements, found {found}")]
+    TooManyElements { expected: usize, found: usize },
+    #[error("invalid binding pattern {0:?}")]
+    InvalidPattern(Form),
+    #[error("cannot bind {found:?} to pattern {pattern:?}")]
+    BindingMismatch { pattern: Box<Form>, found: Form },
+    #[error("wrong number of arguments ({found}), expected {expected}")]
+    ArityMismatch { found: usize, expected: String },
+    #[error("invalid function definition: {0}")]
+    InvalidFnDefinition(&'static str),
+    #[error("recur {0}")]
+    InvalidRecur(&'static str),
+    #[error("could not find module '{0}' on the load path")]
+    ModuleNotFound(String),
+    #[error("circular require of '{0}'")]
+    CircularRequire(String),
+    #[error("invalid require spec {0:?}")]
+    InvalidRequire(Form),
+    #[error("'{0}' is not dynamic")]
+    NotDynamic(String),
+    #[error("'{0}' has no binding to set")]
+    Unbound(String),
+    #[error("invalid try*: {0}")]
+    InvalidTry(&'static str),
+    #[error("{0} limit exceeded")]
+    LimitExceeded(limits::Limit),
+    #[error("evaluation interrupted")]
+    Interrupted,
+    #[error("unknown backend '{0}'")]
+    UnknownBackend(String),
+    #[error("'{0}' is a built-in special form")]
+    BuiltinSpecialForm(String),
+    #[error("lazy sequence realized while realizing itself")]
+    RecursiveLazySeq,
+    #[error("delay forced while forcing itself")]
+    RecursiveDelay,
+    #[error("promise has not been delivered")]
+    Undelivered,
+    #[error("invalid match: {0}")]
+    InvalidMatch(&'static str),
+    #[error("no clause matched {0:?}")]
+    NoMatch(Form),
+    #[error("no method in multimethod '{name}' for dispatch value {value:?}")]
+    NoMethod { name: Ident, value: Form },
+    #[error("several methods in multimethod '{name}' match dispatch value {value:?}")]
+    AmbiguousMethod { name: Ident, value: Form },
+    #[error("cannot derive {child:?} from its descendant {parent:?}")]
+    CyclicDerivation { child: Box<Form>, parent: Form },
+}
+
+impl Error {
+    /// The error an `AtPosition` or `InvalidField` error says where it happened, or this error
+    pub fn innermost(&self) -> &Error {
+        match self {
+            Error::AtPosition { source, .. } | Error::InvalidField { source, .. } => {
+                source.innermost()
+            }
+            other => other,
+        }
+    }
+
+    /// The `:type` keyword an error is surfaced with when caught by `try*`: that of the
+    /// innermost error, so e.g. a native's argument of the wrong type is a `:wrong-type` error
+    /// wherever it was found
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Error::AtPosition { source, .. } | Error::InvalidField { source, .. } => {
+                source.type_name()
+            }
+            Error::Eof => "eof",
+            Error::UnbalancedList => "unbalanced-list",
+            Error::UnknownSymbol(_) => "unknown-symbol",
+            Error::InvalidNumber(_) => "invalid-number",
+            Error::InvalidArgument => "invalid-argument",
+            Error::SerdeError(_) => "serde-error",
+            Error::InvalidApply => "invalid-apply",
+            Error::NumberConversion => "number-conversion",
+            Error::NotIterable => "not-iterable",
+            Error::FileReadError(_) => "file-read-error",
+            Error::NotCallable => "not-callable",
+            Error::IndexOutOfRange(_) => "index-out-of-range",
+            Error::UserError(_) => "user-error",
+            Error::MissingField(_) => "missing-field",
+            Error::UnknownVariant(_) => "unknown-variant",
+            Error::WrongType { .. } => "wrong-type",
+            Error::TooManyElements { .. } => "too-many-elements",
+            Error::InvalidPattern(_) => "invalid-pattern",
+            Error::BindingMismatch { .. } => "binding-mismatch",
+            Error::ArityMismatch { .. } => "arity-mismatch",
+            Error::InvalidFnDefinition(_) => "invalid-fn-definition",
+            Error::InvalidRecur(_) => "invalid-recur",
+            Error::ModuleNotFound(_) => "module-not-found",
+            Error::CircularRequire(_) => "circular-require",
+            Error::InvalidRequire(_) => "invalid-require",
+            Error::NotDynamic(_) => "not-dynamic",
+            Error::Unbound(_) => "unbound",
+            Error::InvalidTry(_) => "invalid-try",
+            Error::LimitExceeded(_) => "limit-exceeded",
+            Error::Interrupted => "interrupted",
+            Error::UnknownBackend(_) => "unknown-backend",
+            Error::BuiltinSpecialForm(_) => "builtin-special-form",
+            Error::RecursiveLazySeq => "recursive-lazy-seq",
+            Error::RecursiveDelay => "recursive-delay",
+            Error::Undelivered => "undelivered",
+            Error::InvalidMatch(_) => "invalid-match",
+            Error::NoMatch(_) => "no-match",
+            Error::NoMethod { .. } => "no-method",
+            Error::AmbiguousMethod { .. } => "ambiguous-method",
+            Error::CyclicDerivation { .. } => "cyclic-derivation",
+        }
+    }
+
+    /// The value a `catch*` clause binds: the thrown value for `throw`, and a map with `:type`
+    /// and `:message` for errors raised by the interpreter. Exceeded limits also name the
+    /// `:limit`, and `match` errors the `:value` that matched no clause. Errors in an argument or
+    /// field have the `:position` or `:field` it was in.
+    // Forms are map keys, see the note on `impl Hash for Form`
+    #[allow(clippy::mutable_key_type)]
+    pub fn to_form(&self) -> Form {
+        let mut map = match self {
+            Error::UserError(thrown) => return thrown.clone(),
+            Error::AtPosition { position, source } => {
+                return with_entry(source.to_form(), "position", Form::from(*position as i64))
+            }
+            Error::InvalidField { field, source } => {
+                return with_entry(source.to_form(), "field", Form::string(field))
+            }
+            other => std::collections::HashMap::from([
+                (
+                    Form::keyword(intern::sym::TYPE),
+                    Form::keyword(other.type_name()),
+                ),
+                (
+                    Form::keyword(intern::sym::MESSAGE),
+                    Form::string(other.to_string()),
+                ),
+            ]),
+        };
+        if let Error::LimitExceeded(limit) = self {
+            map.insert(Form::keyword("limit"), Form::keyword(limit.to_string()));
+        }
+        if let Error::NoMatch(value) = self {
+            map.insert(Form::keyword("value"), value.clone());
+        }
+        Form::hash_map(map)
+    }
+}
+
+/// `map` with `key` added, keeping any `key` it has from a more deeply nested error
+fn with_entry(map: Form, key: &str, value: Form) -> Form {
+    match map.kind {
+        FormKind::HashMap(mut map) => {
+            map.entry(Form::keyword(key)).or_insert(value);
+            Form::hash_map(map)
+        }
+        _ => map,
+    }
 }
 
 impl From<Infallible> for Error {
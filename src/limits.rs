@@ -0,0 +1,178 @@
+//! Limits on evaluation, for running untrusted code
+//!
+//! Limits are set per thread with [`set`] and apply to everything evaluated afterwards, until
+//! they are replaced or removed with [`clear`]. Exceeding one returns
+//! [`Error::LimitExceeded`](crate::Error::LimitExceeded), which `try*` can catch as
+//! `:limit-exceeded`. Fuel, allocation and time stay exhausted, so a handler that evaluates
+//! anything but literals fails again; only the depth limit recovers as the stack unwinds.
+//!
+//! - `fuel` is the number of evaluation steps: each function call, tail call and `recur` counts
+//!   as one. Forms that fall back to the tree-walker also count each special form, and every
+//!   element of a lazy sequence counts when it is realized.
+//! - `max_depth` is the nesting depth of function calls and `eval`. Every non-tail call adds to
+//!   it, so pick a depth that fits the stack of the thread evaluating.
+//! - `max_allocation` is the total number of collection elements, map entries and string bytes
+//!   built by evaluation and native functions. It counts everything allocated, not what is live.
+//! - `timeout` is the wall-clock time since the limits were set. It is checked every
+//!   [`TIME_CHECK_INTERVAL`] steps.
+
+use std::{
+    cell::RefCell,
+    time::{Duration, Instant},
+};
+
+use crate::{Error, Form, FormKind, Result};
+
+/// Number of steps between checks of the clock
+pub const TIME_CHECK_INTERVAL: u64 = 1024;
+
+/// Limits on evaluation. `None` leaves that resource unlimited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub fuel: Option<u64>,
+    pub max_depth: Option<usize>,
+    pub max_allocation: Option<usize>,
+    pub timeout: Option<Duration>,
+}
+
+/// The limit that was exceeded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    Fuel,
+    Depth,
+    Allocation,
+    Time,
+}
+
+impl std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Limit::Fuel => "fuel",
+            Limit::Depth => "depth",
+            Limit::Allocation => "allocation",
+            Limit::Time => "time",
+        })
+    }
+}
+
+/// Resources used since the limits were set
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub steps: u64,
+    pub depth: usize,
+    pub allocated: usize,
+}
+
+struct State {
+    limits: Limits,
+    usage: Usage,
+    deadline: Option<Instant>,
+}
+
+thread_local! {
+    static STATE: RefCell<Option<State>> = const { RefCell::new(None) };
+}
+
+/// Apply `limits` to evaluation on this thread, starting the clock and resetting usage
+pub fn set(limits: Limits) {
+    let deadline = limits.timeout.map(|timeout| Instant::now() + timeout);
+    STATE.with(|state| {
+        *state.borrow_mut() = Some(State {
+            limits,
+            usage: Usage::default(),
+            deadline,
+        })
+    });
+}
+
+/// Remove all limits on this thread
+pub fn clear() {
+    STATE.with(|state| *state.borrow_mut() = None);
+}
+
+/// Resources used since [`set`], or `None` without limits
+pub fn usage() -> Option<Usage> {
+    STATE.with(|state| state.borrow().as_ref().map(|state| state.usage))
+}
+
+fn with_state(f: impl FnOnce(&mut State) -> Result<()>) -> Result<()> {
+    STATE.with(|state| match *state.borrow_mut() {
+        Some(ref mut state) => f(state),
+        None => Ok(()),
+    })
+}
+
+/// Count one evaluation step
+pub(crate) fn step() -> Result<()> {
+    with_state(|state| {
+        state.usage.steps += 1;
+        if state
+            .limits
+            .fuel
+            .is_some_and(|fuel| state.usage.steps > fuel)
+        {
+            return Err(Error::LimitExceeded(Limit::Fuel));
+        }
+        match state.deadline {
+            Some(deadline)
+                if state.usage.steps % TIME_CHECK_INTERVAL == 0 && Instant::now() >= deadline =>
+            {
+                Err(Error::LimitExceeded(Limit::Time))
+            }
+            _ => Ok(()),
+        }
+    })
+}
+
+/// Decrements the depth when an `eval` returns
+pub(crate) struct Depth(bool);
+
+impl Drop for Depth {
+    fn drop(&mut self) {
+        if self.0 {
+            let _ = with_state(|state| {
+                state.usage.depth = state.usage.depth.saturating_sub(1);
+                Ok(())
+            });
+        }
+    }
+}
+
+/// Enter a nested `eval`
+pub(crate) fn enter() -> Result<Depth> {
+    let mut entered = false;
+    with_state(|state| {
+        if state
+            .limits
+            .max_depth
+            .is_some_and(|max| state.usage.depth >= max)
+        {
+            return Err(Error::LimitExceeded(Limit::Depth));
+        }
+        state.usage.depth += 1;
+        entered = true;
+        Ok(())
+    })?;
+    Ok(Depth(entered))
+}
+
+/// Count the elements of a newly built `form`
+pub(crate) fn allocate(form: &Form) -> Result<()> {
+    let size = match form.kind {
+        FormKind::List(ref items) | FormKind::Vector(ref items) => items.len(),
+        FormKind::HashMap(ref map) => map.len(),
+        FormKind::String(ref s) => s.len(),
+        _ => return Ok(()),
+    };
+    with_state(|state| {
+        state.usage.allocated = state.usage.allocated.saturating_add(size);
+        if state
+            .limits
+            .max_allocation
+            .is_some_and(|max| state.usage.allocated > max)
+        {
+            return Err(Error::LimitExceeded(Limit::Allocation));
+        }
+        Ok(())
+    })
+}
@@ -1,12 +1,33 @@
 use risp::{form::Form, Env, Error};
 use rustyline::{error::ReadlineError, DefaultEditor};
-use tracing_subscriber;
 
 const HISTORY_FILE: &str = ".risp-history";
+/// The width expansions are printed in
+const WIDTH: usize = 80;
 
 fn read_eval(input: &str, env: &mut Env) -> Result<Form, Error> {
     let form = risp::read_str(input)?;
-    Ok(risp::eval(form, env)?)
+    risp::eval(form, env)
+}
+
+/// Show each step of the expansion of the macro call in `input`, then the form with every
+/// macro expanded if that is different
+fn expand(input: &str, env: &Env) -> Result<(), Error> {
+    let form = risp::read_str(input)?;
+    let steps = risp::eval::expansion_steps(form.clone(), env)?;
+    for (i, step) in steps.iter().enumerate() {
+        println!("{i}: {}", indent(&risp::format::pretty(step, WIDTH - 3), 3));
+    }
+    let all = risp::eval::macro_expand_all(form, env)?;
+    if Some(&all) != steps.last() {
+        println!("all: {}", indent(&risp::format::pretty(&all, WIDTH - 5), 5));
+    }
+    Ok(())
+}
+
+/// Indent every line of `text` after the first by `by` spaces
+fn indent(text: &str, by: usize) -> String {
+    text.replace('\n', &format!("\n{}", " ".repeat(by)))
 }
 
 fn main() {
@@ -17,21 +38,57 @@ fn main() {
         eprintln!("No previous history.");
     }
 
+    // The backend comes from `--backend=NAME`, or the RISP_BACKEND environment variable
+    let (backends, args): (Vec<_>, Vec<_>) = std::env::args()
+        .skip(1)
+        .partition(|arg| arg.starts_with("--backend="));
+    let backend = backends
+        .last()
+        .and_then(|arg| arg.strip_prefix("--backend="))
+        .map(str::to_owned)
+        .or_else(|| std::env::var("RISP_BACKEND").ok());
+    if let Some(name) = backend {
+        match name.parse() {
+            Ok(backend) => risp::exec::set_backend(backend),
+            Err(err) => eprintln!("{err}"),
+        }
+    }
+
     let mut env = Env::new();
-    let args = Form::list(std::env::args().skip(1).map(|s| Form::string(s)));
+    let args = Form::list(args.into_iter().map(Form::string));
     env.set("*ARGV*", args.clone());
     risp::core::populate(&mut env);
+    let interrupt = env.interrupt_handle();
+    if let Err(err) = risp::interrupt::install_sigint_handler(&interrupt) {
+        eprintln!("Could not install the Ctrl-C handler: {err}");
+    }
     let _ = read_eval(r#"(println (str "Mal [" *host-language* "]"))"#, &mut env);
+    read_eval("(ns user)", &mut env).expect("creating the user namespace");
     loop {
-        match rl.readline("user> ") {
+        let prompt = match env.namespace() {
+            Some(ns) => format!("{ns}> "),
+            None => "user> ".into(),
+        };
+        match rl.readline(&prompt) {
             Ok(line) if line.is_empty() => continue,
             Ok(line) => {
                 if !line.is_empty() {
                     let _ = rl.add_history_entry(&line);
                     rl.save_history(HISTORY_FILE).expect("saving history");
+                    interrupt.reset();
+                    if let Some(input) = line.strip_prefix(":expand ") {
+                        if let Err(e) = expand(input, &env) {
+                            eprintln!("{:?}", e)
+                        }
+                        continue;
+                    }
                     match read_eval(&line, &mut env) {
-                        Ok(result) => println!("{:?}", result),
-                        Err(e) => eprintln!("{:?}", e),
+                        // Printing uses the interpreter's `*print-length*`
+                        Ok(result) => env.enter(|| println!("{:?}", result)),
+                        Err(e) => {
+                            risp::trace::record_error(&e, &mut env);
+                            eprintln!("{:?}", e)
+                        }
                     }
                 }
             }
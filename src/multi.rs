@@ -0,0 +1,207 @@
+//! Multimethods and the keyword hierarchy they dispatch through
+//!
+//! `(defmulti name dispatch-fn)` defines a function that calls `dispatch-fn` with its
+//! arguments, and passes them on to the method for the value that returns, added with
+//! `(defmethod name value [params] body)`. A method also handles the values that are
+//! [`isa`](Hierarchy::isa) its dispatch value, such as keywords that `(derive child parent)`
+//! put below it, and the method for `:default` handles values no other method does. Methods can
+//! be added and removed wherever the multimethod can be referred to, e.g. from a file loaded
+//! after the one that defines it. `defmulti` leaves a name that already is a multimethod as it
+//! is, so reloading the file that defines it keeps the methods added elsewhere.
+//!
+//! Like namespaces, the hierarchy belongs to the interpreter: `derive` in one doesn't change
+//! what `isa?` says in another, and a multimethod dispatches through the hierarchy of the
+//! interpreter it was made in.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use crate::{form::Ident, intern::sym, Env, Error, Form, FormKind, Result};
+
+/// The parents of each keyword that has been derived
+#[derive(Debug, Default)]
+pub struct Hierarchy(RefCell<HashMap<Form, Vec<Form>>>);
+
+/// The hierarchy of the interpreter `env` belongs to
+pub fn hierarchy(env: &Env) -> Rc<Hierarchy> {
+    Rc::clone(&env.runtime().hierarchy)
+}
+
+/// A function that picks a method by the value of its dispatch function
+pub struct MultiFn {
+    name: Ident,
+    dispatch: Form,
+    methods: RefCell<HashMap<Form, Form>>,
+    hierarchy: Rc<Hierarchy>,
+}
+
+impl MultiFn {
+    /// A multimethod without methods, dispatching through the hierarchy of the interpreter
+    /// `env` belongs to
+    pub fn new(name: impl Into<Ident>, dispatch: Form, env: &Env) -> MultiFn {
+        MultiFn::with_hierarchy(name.into(), dispatch, hierarchy(env))
+    }
+
+    pub(crate) fn with_hierarchy(name: Ident, dispatch: Form, hierarchy: Rc<Hierarchy>) -> MultiFn {
+        MultiFn {
+            name,
+            dispatch,
+            methods: RefCell::default(),
+            hierarchy,
+        }
+    }
+
+    pub fn name(&self) -> Ident {
+        self.name
+    }
+
+    /// Handle `value` with `method`, replacing the method it had
+    pub fn add_method(&self, value: Form, method: Form) {
+        self.methods.borrow_mut().insert(value, method);
+    }
+
+    /// Remove the method for `value`, returning whether there was one
+    pub fn remove_method(&self, value: &Form) -> bool {
+        self.methods.borrow_mut().remove(value).is_some()
+    }
+
+    /// The method that handles `value`: the one for `value` itself, else the most specific
+    /// one for a value it [`isa`](Hierarchy::isa), else the `:default` method
+    pub fn method(&self, value: &Form) -> Result<Option<Form>> {
+        let methods = self.methods.borrow();
+        if let Some(method) = methods.get(value) {
+            return Ok(Some(method.clone()));
+        }
+        let isa = |child: &Form, parent: &Form| self.hierarchy.isa(child, parent);
+        let candidates = methods
+            .iter()
+            .filter(|(key, _)| isa(value, key))
+            .collect::<Vec<_>>();
+        let best = candidates
+            .iter()
+            .find(|(key, _)| candidates.iter().all(|(other, _)| isa(key, other)));
+        match (best, candidates.is_empty()) {
+            (Some((_, method)), _) => Ok(Some((*method).clone())),
+            (None, false) => Err(Error::AmbiguousMethod {
+                name: self.name,
+                value: value.clone(),
+            }),
+            (None, true) => Ok(methods.get(&Form::keyword(sym::DEFAULT)).cloned()),
+        }
+    }
+
+    /// Dispatch on `args` and call the method that handles them
+    pub fn call(&self, args: Form) -> Result<Form> {
+        let value = self.dispatch.clone().call(args.clone())?;
+        match self.method(&value)? {
+            Some(method) => method.call(args),
+            None => Err(Error::NoMethod {
+                name: self.name,
+                value,
+            }),
+        }
+    }
+
+    /// The multimethod as a function
+    pub fn into_form(self) -> Form {
+        let multi = Rc::new(self);
+        crate::gc::track_multi_fn(&multi);
+        Form::multi_fn(multi)
+    }
+
+    /// Call `f` with the dispatch function and every method, for the collector. Returns false
+    /// if the methods are being changed.
+    pub(crate) fn for_each_fn(&self, mut f: impl FnMut(&Form)) -> bool {
+        let Ok(methods) = self.methods.try_borrow() else {
+            return false;
+        };
+        f(&self.dispatch);
+        methods.values().for_each(f);
+        true
+    }
+
+    /// Remove every method, for the collector
+    // Forms are map keys, see the note on `impl Hash for Form`
+    #[allow(clippy::mutable_key_type)]
+    pub(crate) fn take_methods(&self) -> HashMap<Form, Form> {
+        std::mem::take(&mut *self.methods.borrow_mut())
+    }
+
+    /// The multimethod `f` is, if it is one
+    pub fn of(f: &Form) -> Option<Rc<MultiFn>> {
+        match f.kind {
+            FormKind::MultiFn(ref multi) => Some(Rc::clone(multi)),
+            _ => None,
+        }
+    }
+}
+
+fn keyword(form: &Form) -> Result<()> {
+    match form.kind {
+        FormKind::Keyword(_) => Ok(()),
+        _ => Err(Error::WrongType {
+            expected: "keyword",
+            found: form.clone(),
+        }),
+    }
+}
+
+impl Hierarchy {
+    /// Make the keyword `parent` a parent of the keyword `child`
+    pub fn derive(&self, child: Form, parent: Form) -> Result<()> {
+        keyword(&child)?;
+        keyword(&parent)?;
+        if self.isa(&parent, &child) {
+            return Err(Error::CyclicDerivation {
+                child: Box::new(child),
+                parent,
+            });
+        }
+        let mut parents = self.0.borrow_mut();
+        let listed = parents.entry(child).or_default();
+        if !listed.contains(&parent) {
+            listed.push(parent);
+        }
+        Ok(())
+    }
+
+    /// The keywords `child` was derived from directly
+    pub fn parents(&self, child: &Form) -> Vec<Form> {
+        self.0.borrow().get(child).cloned().unwrap_or_default()
+    }
+
+    /// The keywords `child` was derived from, directly or through other keywords
+    // Forms are map keys, see the note on `impl Hash for Form`
+    #[allow(clippy::mutable_key_type)]
+    pub fn ancestors(&self, child: &Form) -> Vec<Form> {
+        let mut found = Vec::new();
+        let mut seen = HashSet::new();
+        let mut pending = self.parents(child);
+        while let Some(parent) = pending.pop() {
+            if seen.insert(parent.clone()) {
+                pending.extend(self.parents(&parent));
+                found.push(parent);
+            }
+        }
+        found
+    }
+
+    /// Whether `child` is `parent`, derives from it, or is a vector of values that each are
+    /// what is at the same position of the vector `parent`
+    pub fn isa(&self, child: &Form, parent: &Form) -> bool {
+        if child == parent {
+            return true;
+        }
+        match (&child.kind, &parent.kind) {
+            (FormKind::Vector(children), FormKind::Vector(parents)) => {
+                children.len() == parents.len()
+                    && children.iter().zip(parents).all(|(c, p)| self.isa(c, p))
+            }
+            (FormKind::Keyword(_), FormKind::Keyword(_)) => self.ancestors(child).contains(parent),
+            _ => false,
+        }
+    }
+}
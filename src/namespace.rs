@@ -0,0 +1,287 @@
+//! Namespaces and the module loader
+//!
+//! A namespace is a root environment with a name. Its parent is the base environment that
+//! `core::populate` filled in, so core functions stay visible while definitions made in one
+//! namespace don't leak into another.
+//!
+//! - `(ns foo.bar (:require ...))` creates or switches to a namespace
+//! - `(require 'foo.bar '[foo.baz :as baz :refer [f g]])` loads modules once and records aliases
+//!   and referred names in the current namespace. `:refer :all` refers every public name, and a
+//!   `:reload` argument loads the modules again even if they are cached.
+//! - `alias/name` and `foo.baz/name` resolve through the current namespace's aliases, or by the
+//!   full namespace name
+//!
+//! Module `foo.bar-baz` is loaded from `foo/bar_baz.risp` in the first directory of
+//! `*load-path*` that contains it.
+//!
+//! Names defined with `^:private` are only visible inside their own namespace.
+//! Outside a namespace, `require` first switches to `user`, so that later definitions stay out
+//! of the base environment the modules share.
+
+use std::{
+    path::PathBuf,
+    rc::{Rc, Weak},
+};
+
+use crate::{
+    dynamic::VarName,
+    eval,
+    intern::{sym, IdentMap, IdentSet},
+    read_str,
+    runtime::Runtime,
+    Env, Error, Form, FormKind, Ident, Result,
+};
+
+const EXTENSION: &str = "risp";
+
+#[derive(Clone, Debug)]
+pub(crate) struct Namespace {
+    pub(crate) name: Ident,
+    aliases: IdentMap<Ident>,
+    /// Referred name to the namespace it was referred from
+    refers: IdentMap<Ident>,
+    refer_all: Vec<Ident>,
+    /// Names defined with `:private` metadata
+    private: IdentSet,
+    /// Where the other namespaces are found. The runtime is owned by the base environment,
+    /// which outlives the namespace.
+    runtime: Weak<Runtime>,
+}
+
+impl Namespace {
+    pub(crate) fn new(name: Ident, runtime: &Rc<Runtime>) -> Namespace {
+        Namespace {
+            name,
+            aliases: IdentMap::default(),
+            refers: IdentMap::default(),
+            refer_all: Vec::new(),
+            private: IdentSet::default(),
+            runtime: Rc::downgrade(runtime),
+        }
+    }
+
+    /// Resolve a qualified or referred name to its value and the var it is. `own` holds the
+    /// definitions of this namespace, whose environment is already locked by the caller.
+    pub(crate) fn resolve(&self, own: &IdentMap<Form>, key: Ident) -> Option<(Form, VarName)> {
+        let runtime = self.runtime.upgrade()?;
+        let lookup = |ns: Ident, name: Ident| {
+            let value = if ns == self.name {
+                own.get(&name).cloned()
+            } else {
+                public(&runtime, ns, name)
+            };
+            Some((value?, VarName { ns: Some(ns), name }))
+        };
+        if let Some((prefix, name)) = key.name().split_once('/') {
+            if prefix.is_empty() || name.is_empty() {
+                return None;
+            }
+            let prefix = Ident::new(prefix);
+            let ns = self.aliases.get(&prefix).copied().unwrap_or(prefix);
+            return lookup(ns, Ident::new(name));
+        }
+        if let Some(&ns) = self.refers.get(&key) {
+            return lookup(ns, key);
+        }
+        self.refer_all.iter().find_map(|&ns| lookup(ns, key))
+    }
+}
+
+/// Whether the metadata of `symbol`, as in `(def! ^:private x 1)`, makes its definition private
+pub(crate) fn is_private(symbol: &Form) -> bool {
+    match symbol.meta.as_deref() {
+        Some(Form {
+            kind: FormKind::HashMap(meta),
+            ..
+        }) => meta
+            .get(&Form::keyword("private"))
+            .is_some_and(Form::is_truthy),
+        _ => false,
+    }
+}
+
+/// Record whether the definition of `name` in the namespace of `env` is private
+pub(crate) fn set_private(env: &Env, name: Ident, private: bool) {
+    let root = env.root();
+    let mut guard = root.inner.lock().expect("Poisoned mutex");
+    if let Some(ns) = guard.namespace.as_mut() {
+        if private {
+            ns.private.insert(name);
+        } else {
+            ns.private.remove(&name);
+        }
+    }
+}
+
+/// A definition made directly in namespace `ns` that isn't private
+fn public(runtime: &Runtime, ns: Ident, name: Ident) -> Option<Form> {
+    let env = runtime.namespaces.borrow().get(&ns).cloned()?;
+    let guard = env.inner.lock().expect("Poisoned mutex");
+    let private = guard
+        .namespace
+        .as_ref()
+        .is_some_and(|ns| ns.private.contains(&name));
+    guard.data.get(&name).filter(|_| !private).cloned()
+}
+
+/// The environment of namespace `name`, created as a child of `base` if it doesn't exist yet
+fn find_or_create(name: Ident, base: &Env) -> Env {
+    base.runtime()
+        .namespaces
+        .borrow_mut()
+        .entry(name)
+        .or_insert_with(|| Env::new_namespace(name, base))
+        .clone()
+}
+
+/// The root of `env`, after switching a top-level `env` outside any namespace to `user`. Returns
+/// `None` if `env` has locals, which switching would lose.
+fn current(env: &mut Env) -> Option<Env> {
+    let root = env.root();
+    if root.namespace().is_some() {
+        return Some(root);
+    }
+    if !Rc::ptr_eq(&root.inner, &env.inner) {
+        return None;
+    }
+    *env = find_or_create(Ident::new("user"), &root);
+    Some(env.clone())
+}
+
+fn module_path(name: Ident, env: &Env) -> Result<PathBuf> {
+    let relative: PathBuf = name
+        .name()
+        .replace('-', "_")
+        .split('.')
+        .collect::<PathBuf>()
+        .with_extension(EXTENSION);
+    let load_path: Vec<String> = match env.get("*load-path*") {
+        Ok(paths) => paths.try_into()?,
+        Err(Error::UnknownSymbol(_)) => vec![".".into()],
+        Err(err) => return Err(err),
+    };
+    load_path
+        .iter()
+        .map(|dir| PathBuf::from(dir).join(&relative))
+        .find(|path| path.is_file())
+        .ok_or_else(|| Error::ModuleNotFound(name.name().into()))
+}
+
+/// Load module `name` from the load path, unless it is already loaded
+fn load(name: Ident, env: &Env, reload: bool) -> Result<()> {
+    let runtime = env.runtime();
+    // A module being loaded is already registered, so check for cycles first
+    if runtime.loading.borrow().contains(&name) {
+        return Err(Error::CircularRequire(name.name().into()));
+    }
+    let existed = runtime.namespaces.borrow().contains_key(&name);
+    if existed && !reload {
+        return Ok(());
+    }
+    let source = std::fs::read_to_string(module_path(name, env)?)?;
+    let mut module_env = find_or_create(name, &env.base());
+    runtime.loading.borrow_mut().push(name);
+    let result =
+        read_str(&format!("(do {source}\nnil)")).and_then(|form| eval(form, &mut module_env));
+    runtime.loading.borrow_mut().pop();
+    if result.is_err() && !existed {
+        runtime.namespaces.borrow_mut().remove(&name);
+    }
+    result.map(drop)
+}
+
+/// Specs may be quoted, as in `(require 'foo)`, or written bare inside `ns`
+fn unquote(spec: Form) -> Form {
+    match spec.kind {
+        FormKind::List(ref items)
+            if items.len() == 2 && items[0].as_symbol() == Some(sym::QUOTE) =>
+        {
+            items[1].clone()
+        }
+        _ => spec,
+    }
+}
+
+fn require_spec(spec: Form, env: &mut Env, reload: bool) -> Result<()> {
+    let invalid = || Error::InvalidRequire(spec.clone());
+    let (name, options) = match spec.kind {
+        FormKind::Symbol(name) => (name, &[][..]),
+        FormKind::Vector(ref items) => match items.split_first() {
+            Some((name, options)) if options.len() % 2 == 0 => {
+                (name.as_symbol().ok_or_else(invalid)?, options)
+            }
+            _ => return Err(invalid()),
+        },
+        _ => return Err(invalid()),
+    };
+    load(name, env, reload)?;
+
+    let mut alias = None;
+    let mut refers = Vec::new();
+    let mut refer_all = false;
+    for option in options.chunks(2) {
+        match (&option[0].kind, &option[1].kind) {
+            (FormKind::Keyword(sym::AS), FormKind::Symbol(name)) => alias = Some(*name),
+            (FormKind::Keyword(sym::REFER), FormKind::Keyword(sym::ALL)) => refer_all = true,
+            (FormKind::Keyword(sym::REFER), FormKind::Vector(names)) => {
+                for referred in names {
+                    let referred = referred.as_symbol().ok_or_else(invalid)?;
+                    if public(&env.runtime(), name, referred).is_none() {
+                        return Err(Error::UnknownSymbol(format!("{name}/{referred}")));
+                    }
+                    refers.push(referred);
+                }
+            }
+            _ => return Err(invalid()),
+        }
+    }
+
+    let current = current(env).ok_or_else(invalid)?;
+    let mut guard = current.inner.lock().expect("Poisoned mutex");
+    let ns = guard
+        .namespace
+        .as_mut()
+        .expect("current() returns a namespace");
+    if let Some(alias) = alias {
+        ns.aliases.insert(alias, name);
+    }
+    ns.refers
+        .extend(refers.into_iter().map(|referred| (referred, name)));
+    if refer_all && !ns.refer_all.contains(&name) {
+        ns.refer_all.push(name);
+    }
+    // Referred names shadow definitions further up
+    crate::env::invalidate();
+    Ok(())
+}
+
+fn require_all(specs: impl IntoIterator<Item = Form>, env: &mut Env) -> Result<()> {
+    let specs: Vec<Form> = specs.into_iter().map(unquote).collect();
+    let reload = specs.contains(&Form::keyword(sym::RELOAD));
+    specs
+        .into_iter()
+        .filter(|spec| *spec != Form::keyword(sym::RELOAD))
+        .try_for_each(|spec| require_spec(spec, env, reload))
+}
+
+/// `(require spec...)`, which switches `env` to the `user` namespace if it isn't in one
+pub(crate) fn require(form: Form, env: &mut Env) -> Result<Form> {
+    let specs: Vec<Form> = form.try_into()?;
+    require_all(specs.into_iter().skip(1), env)?;
+    Ok(Form::nil())
+}
+
+/// `(ns name (:require spec...))`, which switches `env` to the namespace
+pub(crate) fn ns(form: Form, env: &mut Env) -> Result<Form> {
+    let (_, name, clauses): ((), Ident, crate::convert::Rest) = form.try_into()?;
+    *env = find_or_create(name, &env.base());
+    for clause in clauses.values {
+        match clause.as_slice() {
+            Some([head, specs @ ..]) if *head == Form::keyword(sym::REQUIRE) => {
+                require_all(specs.iter().cloned(), env)?
+            }
+            _ => return Err(Error::InvalidRequire(clause)),
+        }
+    }
+    Ok(Form::nil())
+}
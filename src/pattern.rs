@@ -0,0 +1,319 @@
+//! Pattern matching
+//!
+//! A [`Pattern`] tests the shape of a value and binds names to its parts. `match` tries the
+//! patterns of its clauses in order, and native functions can use them to take their
+//! arguments apart:
+//!
+//! - `_` matches anything
+//! - a symbol matches anything and binds the value. A name used twice only matches equal
+//!   values.
+//! - `'x`, and anything that isn't a symbol, vector, map or list, matches equal values, so
+//!   numbers, strings, keywords, nil and booleans match themselves
+//! - a vector matches a list, vector or lazy sequence element by element, with `& rest`
+//!   matching the remaining elements as a list and `:as name` binding the whole value. Without
+//!   `& rest` the lengths must be equal.
+//! - a map matches a map that has each of its keys, with the value matching the key's pattern
+//! - `(pred p)` matches a value for which the function `pred` returns true and which matches
+//!   `p`, e.g. `(string? s)`
+//!
+//! The predicates are expressions, evaluated outside the pattern, so a predicate can't refer
+//! to the names the pattern binds.
+
+use std::collections::HashMap;
+
+use crate::{
+    destructure::split_vector, form::Ident, intern::sym, lazy, Env, Error, Form, FormKind, Result,
+};
+
+/// A pattern parsed from a form
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    node: Node,
+    names: Vec<Ident>,
+    predicates: Vec<Form>,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Any,
+    /// Bind the name at an index into `names`
+    Bind(usize),
+    Literal(Form),
+    Seq {
+        items: Vec<Node>,
+        rest: Option<Box<Node>>,
+        as_: Option<usize>,
+    },
+    Map(Vec<(Form, Node)>),
+    /// Test with the predicate at an index into `predicates`
+    Test(usize, Box<Node>),
+}
+
+/// The values a successful match bound, in the order of [`Pattern::names`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bindings(Vec<(Ident, Form)>);
+
+impl Bindings {
+    /// The value bound to `name`
+    pub fn get(&self, name: &str) -> Option<&Form> {
+        self.0
+            .iter()
+            .find(|(bound, _)| *bound == *name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn into_values(self) -> impl Iterator<Item = Form> {
+        self.0.into_iter().map(|(_, value)| value)
+    }
+}
+
+impl IntoIterator for Bindings {
+    type Item = (Ident, Form);
+    type IntoIter = std::vec::IntoIter<(Ident, Form)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl Pattern {
+    pub fn parse(form: &Form) -> Result<Pattern> {
+        let mut pattern = Pattern {
+            node: Node::Any,
+            names: Vec::new(),
+            predicates: Vec::new(),
+        };
+        pattern.node = pattern.parse_node(form)?;
+        Ok(pattern)
+    }
+
+    fn parse_node(&mut self, form: &Form) -> Result<Node> {
+        match form.kind {
+            FormKind::Symbol(name) if name == *"_" => Ok(Node::Any),
+            FormKind::Symbol(sym::AMPERSAND) => Err(Error::InvalidPattern(form.clone())),
+            FormKind::Symbol(name) => Ok(Node::Bind(self.name(name))),
+            FormKind::List(ref items) => match items.as_slice() {
+                [head, quoted] if head.as_symbol() == Some(sym::QUOTE) => {
+                    Ok(Node::Literal(quoted.clone()))
+                }
+                [predicate, inner] => {
+                    self.predicates.push(predicate.clone());
+                    let index = self.predicates.len() - 1;
+                    Ok(Node::Test(index, Box::new(self.parse_node(inner)?)))
+                }
+                _ => Err(Error::InvalidPattern(form.clone())),
+            },
+            FormKind::Vector(ref items) => {
+                let (items, rest, as_) = split_vector(form, items)?;
+                Ok(Node::Seq {
+                    items: items
+                        .iter()
+                        .map(|item| self.parse_node(item))
+                        .collect::<Result<_>>()?,
+                    rest: match rest {
+                        Some(rest) => Some(Box::new(self.parse_node(rest)?)),
+                        None => None,
+                    },
+                    as_: as_.map(|name| self.name(name)),
+                })
+            }
+            FormKind::HashMap(ref entries) => Ok(Node::Map(
+                entries
+                    .iter()
+                    .map(|(key, value)| Ok((unquote(key), self.parse_node(value)?)))
+                    .collect::<Result<_>>()?,
+            )),
+            _ => Ok(Node::Literal(form.clone())),
+        }
+    }
+
+    fn name(&mut self, name: Ident) -> usize {
+        match self.names.iter().position(|&bound| bound == name) {
+            Some(index) => index,
+            None => {
+                self.names.push(name);
+                self.names.len() - 1
+            }
+        }
+    }
+
+    /// The names the pattern binds, each once
+    pub fn names(&self) -> &[Ident] {
+        &self.names
+    }
+
+    /// The predicate expressions of the `(pred p)` patterns, in the order
+    /// [`matches_with`](Pattern::matches_with) takes their values
+    pub fn predicates(&self) -> &[Form] {
+        &self.predicates
+    }
+
+    /// Match `value`, testing it with the functions in `predicates`, the evaluated
+    /// [`predicates`](Pattern::predicates). Returns the bindings, or `None` if it doesn't
+    /// match.
+    pub fn matches_with(&self, value: &Form, predicates: &[Form]) -> Result<Option<Bindings>> {
+        let mut matcher = Matcher {
+            predicates,
+            bound: vec![None; self.names.len()],
+        };
+        if !matcher.matches(&self.node, value)? {
+            return Ok(None);
+        }
+        Ok(Some(Bindings(
+            self.names
+                .iter()
+                .zip(matcher.bound)
+                .map(|(&name, value)| (name, value.expect("a match binds every name")))
+                .collect(),
+        )))
+    }
+
+    /// Match `value`, evaluating the predicates in `env`
+    pub fn matches(&self, value: &Form, env: &Env) -> Result<Option<Bindings>> {
+        let predicates = self
+            .predicates
+            .iter()
+            .map(|predicate| crate::eval(predicate.clone(), &mut env.clone()))
+            .collect::<Result<Vec<_>>>()?;
+        self.matches_with(value, &predicates)
+    }
+}
+
+fn unquote(form: &Form) -> Form {
+    match form.as_slice() {
+        Some([head, quoted]) if form.is_list() && head.as_symbol() == Some(sym::QUOTE) => {
+            quoted.clone()
+        }
+        _ => form.clone(),
+    }
+}
+
+struct Matcher<'a> {
+    predicates: &'a [Form],
+    bound: Vec<Option<Form>>,
+}
+
+impl Matcher<'_> {
+    fn matches(&mut self, node: &Node, value: &Form) -> Result<bool> {
+        match *node {
+            Node::Any => Ok(true),
+            Node::Bind(index) => Ok(self.bind(index, value)),
+            Node::Literal(ref literal) => Ok(literal == value),
+            Node::Seq {
+                ref items,
+                ref rest,
+                as_,
+            } => {
+                if !value.is_sequential() || !self.seq(items, rest.as_deref(), value.clone())? {
+                    return Ok(false);
+                }
+                Ok(as_.is_none_or(|index| self.bind(index, value)))
+            }
+            Node::Map(ref entries) => {
+                let FormKind::HashMap(ref map) = value.kind else {
+                    return Ok(false);
+                };
+                self.map(entries, map)
+            }
+            Node::Test(index, ref inner) => {
+                let accepted = self.predicates[index]
+                    .clone()
+                    .call(Form::list([value.clone()]))?;
+                Ok(accepted.is_truthy() && self.matches(inner, value)?)
+            }
+        }
+    }
+
+    fn bind(&mut self, index: usize, value: &Form) -> bool {
+        match self.bound[index] {
+            Some(ref bound) => bound == value,
+            None => {
+                self.bound[index] = Some(value.clone());
+                true
+            }
+        }
+    }
+
+    /// Match the elements of a list, vector or lazy sequence. Only as many elements of a lazy
+    /// sequence are realized as the pattern looks at.
+    fn seq(&mut self, items: &[Node], rest: Option<&Node>, value: Form) -> Result<bool> {
+        if let Some(elements) = value.as_slice() {
+            let fits = match rest {
+                Some(_) => elements.len() >= items.len(),
+                None => elements.len() == items.len(),
+            };
+            if !fits {
+                return Ok(false);
+            }
+            for (item, element) in items.iter().zip(elements) {
+                if !self.matches(item, element)? {
+                    return Ok(false);
+                }
+            }
+            return match rest {
+                Some(rest) => self.matches(rest, &Form::list(elements[items.len()..].to_vec())),
+                None => Ok(true),
+            };
+        }
+        let mut remaining = value;
+        for item in items {
+            let Some((first, next)) = lazy::uncons(remaining)? else {
+                return Ok(false);
+            };
+            if !self.matches(item, &first)? {
+                return Ok(false);
+            }
+            remaining = next;
+        }
+        match rest {
+            Some(rest) => self.matches(rest, &remaining),
+            None => Ok(lazy::uncons(remaining)?.is_none()),
+        }
+    }
+
+    // Forms are map keys, see the note on `impl Hash for Form`
+    #[allow(clippy::mutable_key_type)]
+    fn map(&mut self, entries: &[(Form, Node)], map: &HashMap<Form, Form>) -> Result<bool> {
+        for (key, node) in entries {
+            match map.get(key) {
+                Some(value) if self.matches(node, value)? => {}
+                _ => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// One clause of a `match` form: a pattern, an optional `:guard` expression and a body
+pub(crate) struct Clause {
+    pub(crate) pattern: Pattern,
+    pub(crate) guard: Option<Form>,
+    pub(crate) body: Form,
+}
+
+/// Parse the clauses of a `match` form, `pattern body` or `pattern :guard test body`
+pub(crate) fn clauses(forms: &[Form]) -> Result<Vec<Clause>> {
+    let mut clauses = Vec::new();
+    let mut forms = forms.iter();
+    while let Some(pattern) = forms.next() {
+        let pattern = Pattern::parse(pattern)?;
+        let mut body = forms.next();
+        let mut guard = None;
+        if body.is_some_and(|form| *form == Form::keyword(sym::GUARD)) {
+            guard = Some(
+                forms
+                    .next()
+                    .ok_or(Error::InvalidMatch(":guard needs a test"))?
+                    .clone(),
+            );
+            body = forms.next();
+        }
+        let body = body.ok_or(Error::InvalidMatch("each pattern needs a body"))?;
+        clauses.push(Clause {
+            pattern,
+            guard,
+            body: body.clone(),
+        });
+    }
+    Ok(clauses)
+}
@@ -1,5 +1,6 @@
 use itertools::Itertools;
 
+use super::Source;
 use crate::{
     form::{Form, FormKind},
     Error,
@@ -19,22 +20,27 @@ impl ListInner {
         }
     }
 
+    /// Read the items up to the end symbol. `f` receives the items and the location of the start
+    /// symbol.
     pub fn read<'a, F>(
         &self,
         token_iter: &mut Peekable<impl Iterator<Item = &'a str>>,
+        source: &Source,
         f: F,
     ) -> Option<Result<Form, Error>>
     where
-        F: FnOnce(Vec<Form>) -> Result<Form, Error>,
+        F: FnOnce(Vec<Form>, Option<Form>) -> Result<Form, Error>,
     {
-        assert_eq!(token_iter.next(), Some(self.start_symbol));
+        let start = token_iter.next();
+        assert_eq!(start, Some(self.start_symbol));
+        let location = start.and_then(|token| source.location(token));
         let mut values = Vec::new();
         loop {
             if token_iter.peek() == Some(&self.end_symbol) {
                 token_iter.next();
-                break Some(f(values));
+                break Some(f(values, location));
             }
-            match super::read_form(token_iter) {
+            match super::read_form(token_iter, source) {
                 Some(Ok(ast)) => values.push(ast),
                 e @ Some(Err(_)) => break e,
                 None => break Some(Err(Error::UnbalancedList)),
@@ -43,22 +49,29 @@ impl ListInner {
     }
 }
 
+/// Read a list, with its location as metadata. Vectors and maps are values, so they are read
+/// without one and `meta` on a literal is nil.
 pub fn read_list<'a>(
     token_iter: &mut Peekable<impl Iterator<Item = &'a str>>,
+    source: &Source,
 ) -> Option<Result<Form, Error>> {
-    ListInner::new("(", ")").read(token_iter, |values| Ok(Form::list(values)))
+    ListInner::new("(", ")").read(token_iter, source, |values, location| {
+        Ok(Form::list(values).with_meta(location))
+    })
 }
 
 pub fn read_vector<'a>(
     token_iter: &mut Peekable<impl Iterator<Item = &'a str>>,
+    source: &Source,
 ) -> Option<Result<Form, Error>> {
-    ListInner::new("[", "]").read(token_iter, |values| Ok(Form::vector(values)))
+    ListInner::new("[", "]").read(token_iter, source, |values, _| Ok(Form::vector(values)))
 }
 
 pub fn read_hash_map<'a>(
     token_iter: &mut Peekable<impl Iterator<Item = &'a str>>,
+    source: &Source,
 ) -> Option<Result<Form, Error>> {
-    ListInner::new("{", "}").read(token_iter, |values| {
+    ListInner::new("{", "}").read(token_iter, source, |values, _| {
         if values.len() % 2 == 1 {
             Err(Error::InvalidArgument)
         } else {
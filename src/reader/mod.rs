@@ -12,11 +12,48 @@ use nom::{
 
 use crate::{
     form::{Form, FormKind, Ident},
+    intern::sym,
     Error,
 };
 
 mod list;
 
+/// The text being read, used to work out where each token came from
+struct Source<'a> {
+    input: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> Source<'a> {
+    fn new(input: &'a str) -> Source<'a> {
+        let line_starts = std::iter::once(0)
+            .chain(input.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+        Source { input, line_starts }
+    }
+
+    /// Metadata map holding the 1-based `:line` and `:column` of `token`, which must be a slice
+    /// of the input
+    fn location(&self, token: &str) -> Option<Form> {
+        let offset = (token.as_ptr() as usize).checked_sub(self.input.as_ptr() as usize)?;
+        if offset > self.input.len() {
+            return None;
+        }
+        let line = self.line_starts.partition_point(|&start| start <= offset);
+        let column = self.input[self.line_starts[line - 1]..offset]
+            .chars()
+            .count()
+            + 1;
+        Some(Form::hash_map(
+            [
+                (Form::keyword("line"), Form::int(line as i64)),
+                (Form::keyword("column"), Form::int(column as i64)),
+            ]
+            .into(),
+        ))
+    }
+}
+
 fn is_whitespace(c: char) -> bool {
     c.is_whitespace() || c == ','
 }
@@ -122,25 +159,20 @@ fn read_string<'a>(
 fn read_keyword<'a>(
     token_iter: &mut Peekable<impl Iterator<Item = &'a str>>,
 ) -> Option<Result<Form, Error>> {
-    token_iter.next().map(|name| {
-        let no_colon = name.chars().skip(1).collect::<String>();
-        Ok(Form {
-            kind: FormKind::Keyword(no_colon),
-            meta: None,
-        })
-    })
+    token_iter.next().map(|name| Ok(Form::keyword(&name[1..])))
 }
 
 fn reader_macro<'a>(
-    fnname: &str,
+    fnname: Ident,
     token_iter: &mut Peekable<impl Iterator<Item = &'a str>>,
+    source: &Source,
 ) -> Result<Form, Error> {
     token_iter.next();
     let mut values = vec![Form {
-        kind: FormKind::Symbol(Ident::from_str(fnname)),
+        kind: FormKind::Symbol(fnname),
         meta: None,
     }];
-    match read_form(token_iter) {
+    match read_form(token_iter, source) {
         Some(Ok(form_result)) => values.push(form_result),
         Some(err @ Err(_)) => return err,
         None => return Err(Error::Eof),
@@ -151,31 +183,60 @@ fn reader_macro<'a>(
     })
 }
 
+/// `^:flag` is shorthand for `^{:flag true}`, and `^Tag` or `^"Tag"` for `^{:tag Tag}`
+fn expand_meta_shorthand(meta: Form) -> Form {
+    let entry = match meta.kind {
+        FormKind::Keyword(_) => (meta, Form::boolean(true)),
+        FormKind::Symbol(_) | FormKind::String(_) => (Form::keyword("tag"), meta),
+        _ => return meta,
+    };
+    Form::hash_map([entry].into())
+}
+
 fn meta_reader_macro<'a>(
     token_iter: &mut Peekable<impl Iterator<Item = &'a str>>,
+    source: &Source,
 ) -> Result<Form, Error> {
     assert_eq!(token_iter.next(), Some("^"));
-    let meta = read_form(token_iter).transpose()?.ok_or(Error::Eof)?;
-    let form = read_form(token_iter).transpose()?.ok_or(Error::Eof)?;
-    let symbol = Form::symbol("with-meta");
+    let meta = read_form(token_iter, source)
+        .transpose()?
+        .ok_or(Error::Eof)?;
+    let meta = expand_meta_shorthand(meta);
+    let mut form = read_form(token_iter, source)
+        .transpose()?
+        .ok_or(Error::Eof)?;
+    // Symbols evaluate to something else, so their metadata is attached at read time, where
+    // special forms like `def!` can see it
+    if form.is_symbol() {
+        form.meta = match (form.meta.take().map(|existing| existing.kind), meta.kind) {
+            (Some(FormKind::HashMap(mut existing)), FormKind::HashMap(extra)) => {
+                existing.extend(extra);
+                Some(Box::new(Form::hash_map(existing)))
+            }
+            (_, kind) => Some(Box::new(Form { kind, meta: None })),
+        };
+        return Ok(form);
+    }
+    let symbol = Form::symbol(sym::WITH_META);
     Ok(Form::list([symbol, form, meta]))
 }
 
 fn read_form<'a>(
     token_iter: &mut Peekable<impl Iterator<Item = &'a str>>,
+    source: &Source,
 ) -> Option<Result<Form, Error>> {
     match token_iter.peek() {
         Some(&"nil") => read_nil(token_iter),
         Some(&"true") | Some(&"false") => read_bool(token_iter),
-        Some(&"(") => self::list::read_list(token_iter),
-        Some(&"[") => self::list::read_vector(token_iter),
-        Some(&"{") => self::list::read_hash_map(token_iter),
-        Some(&"'") => Some(reader_macro("quote", token_iter)),
-        Some(&"`") => Some(reader_macro("quasiquote", token_iter)),
-        Some(&"~") => Some(reader_macro("unquote", token_iter)),
-        Some(&"~@") => Some(reader_macro("splice-unquote", token_iter)),
-        Some(&"@") => Some(reader_macro("deref", token_iter)),
-        Some(&"^") => Some(meta_reader_macro(token_iter)),
+        Some(&"(") => self::list::read_list(token_iter, source),
+        Some(&"[") => self::list::read_vector(token_iter, source),
+        Some(&"{") => self::list::read_hash_map(token_iter, source),
+        Some(&"'") => Some(reader_macro(sym::QUOTE, token_iter, source)),
+        Some(&"`") => Some(reader_macro(sym::QUASIQUOTE, token_iter, source)),
+        Some(&"~") => Some(reader_macro(sym::UNQUOTE, token_iter, source)),
+        Some(&"~@") => Some(reader_macro(sym::SPLICE_UNQUOTE, token_iter, source)),
+        Some(&"@") => Some(reader_macro(sym::DEREF, token_iter, source)),
+        Some(&"^") => Some(meta_reader_macro(token_iter, source)),
         Some(s) if str::parse::<i64>(s).is_ok() => read_number(token_iter),
         Some(s) if s.starts_with('"') => read_string(token_iter),
         Some(s) if s.starts_with(':') => read_keyword(token_iter),
@@ -185,12 +246,13 @@ fn read_form<'a>(
 }
 
 pub fn read_str(input: &str) -> Result<Form, Error> {
+    let source = Source::new(input);
     let mut parser = iterator(input, tokenize);
     let ast = {
         let mut fused = (&mut parser).fuse();
         let ast = {
             let mut iter = Iterator::peekable(&mut fused);
-            read_form(&mut iter).ok_or(Error::Eof)
+            read_form(&mut iter, &source).ok_or(Error::Eof)
         }?;
         match fused.next() {
             Some(_) => Err(Error::Eof),
@@ -0,0 +1,80 @@
+//! State shared by everything evaluated in one interpreter
+//!
+//! An interpreter is a base environment and the environments below it. The base environment
+//! owns a [`Runtime`], so two interpreters on the same thread don't see each other's namespaces
+//! or keyword hierarchies.
+//!
+//! Native functions don't get an environment, so the runtime of the interpreter that is
+//! evaluating is also kept per thread while it evaluates.
+
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use crate::{dynamic::Vars, intern::IdentMap, interrupt, multi::Hierarchy, Env, Ident};
+
+#[derive(Default)]
+pub(crate) struct Runtime {
+    /// The environment of each namespace, by name
+    pub(crate) namespaces: RefCell<IdentMap<Env>>,
+    /// Modules currently being loaded, to report circular requires
+    pub(crate) loading: RefCell<Vec<Ident>>,
+    /// What `derive` made keywords children of
+    pub(crate) hierarchy: Rc<Hierarchy>,
+    /// The dynamic vars and their bindings
+    pub(crate) vars: Vars,
+    /// Set to stop evaluation
+    pub(crate) interrupt: interrupt::Handle,
+}
+
+thread_local! {
+    /// The runtimes of the interpreters evaluating on this thread, innermost last
+    static CURRENT: RefCell<Vec<Rc<Runtime>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The runtime of the interpreter currently evaluating, if any
+pub(crate) fn current() -> Option<Rc<Runtime>> {
+    CURRENT.with(|current| current.borrow().last().cloned())
+}
+
+/// Call `f` with the runtime of the interpreter currently evaluating, if any, without cloning it
+pub(crate) fn with_current<T>(f: impl FnOnce(&Runtime) -> T) -> Option<T> {
+    CURRENT.with(|current| current.borrow().last().map(|runtime| f(runtime)))
+}
+
+/// Leaves the runtime entered by `Env::enter` when dropped
+struct Leave;
+
+impl Drop for Leave {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.borrow_mut().pop());
+    }
+}
+
+// The namespaces' environments lead back to the base environment that owns the runtime, so only
+// their names are printed
+impl fmt::Debug for Runtime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let namespaces = self.namespaces.try_borrow();
+        let names = namespaces.iter().flat_map(|namespaces| namespaces.keys());
+        f.debug_struct("Runtime")
+            .field("namespaces", &names.collect::<Vec<_>>())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Env {
+    /// The runtime of the interpreter this environment belongs to
+    pub(crate) fn runtime(&self) -> Rc<Runtime> {
+        let base = self.base();
+        let guard = base.inner.lock().expect("Poisoned mutex");
+        guard.runtime.clone().unwrap_or_default()
+    }
+
+    /// Run `f` in the interpreter this environment belongs to, so native functions it calls,
+    /// and printing, see that interpreter's dynamic vars
+    pub fn enter<T>(&self, f: impl FnOnce() -> T) -> T {
+        let runtime = self.runtime();
+        CURRENT.with(|current| current.borrow_mut().push(runtime));
+        let _leave = Leave;
+        f()
+    }
+}
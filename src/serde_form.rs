@@ -0,0 +1,611 @@
+//! Serde support for `Form`
+//!
+//! Nil, booleans, numbers, strings, keywords, lists, vectors and maps map onto the serde data
+//! model. Keywords serialize as strings, and string map keys deserialize as keywords, so a map
+//! read from JSON can be used with `(get m :key)`. String keys of Rust maps serialize as
+//! keywords too, so a `HashMap<String, _>` round-trips through `to_form` and `from_form`.
+//! Functions, atoms and host values cannot be serialized, and lazy sequences only if they end
+//! within 65536 elements.
+//!
+//! `to_form` and `from_form` convert between Rust values and `Form` directly, for example to read
+//! a configuration struct out of an evaluated risp value.
+
+use std::collections::HashMap;
+
+use serde::{
+    de::{self, IntoDeserializer},
+    ser::{self, SerializeMap as _, SerializeSeq as _},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{lazy::LazySeq, Error, Form, FormKind, Result};
+
+/// The most elements of a lazy sequence that are realized to serialize it, so that serializing
+/// an infinite sequence fails instead of running out of memory
+const MAX_LAZY_LENGTH: usize = 1 << 16;
+
+impl ser::Error for Error {
+    fn custom<T: std::fmt::Display>(msg: T) -> Error {
+        Error::SerdeError(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: std::fmt::Display>(msg: T) -> Error {
+        Error::SerdeError(msg.to_string())
+    }
+}
+
+/// Convert any serializable Rust value into a `Form`
+pub fn to_form<T: Serialize + ?Sized>(value: &T) -> Result<Form> {
+    value.serialize(FormSerializer)
+}
+
+/// Build a Rust value out of a `Form`
+pub fn from_form<'de, T: Deserialize<'de>>(form: Form) -> Result<T> {
+    T::deserialize(form)
+}
+
+impl Serialize for Form {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match self.kind {
+            FormKind::Nil => serializer.serialize_unit(),
+            FormKind::Boolean(b) => serializer.serialize_bool(b),
+            FormKind::Integer(n) => serializer.serialize_i64(n),
+            FormKind::Float(n) => serializer.serialize_f64(n),
+            FormKind::String(ref s) => serializer.serialize_str(s),
+            FormKind::Keyword(k) | FormKind::Symbol(k) => serializer.serialize_str(k.name()),
+            FormKind::List(ref items) | FormKind::Vector(ref items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            FormKind::LazySeq(ref lazy) => {
+                let items = realize(lazy).map_err(ser::Error::custom)?;
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in &items {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            FormKind::HashMap(ref entries) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (k, v) in entries {
+                    map.serialize_entry(k, v)?;
+                }
+                map.end()
+            }
+            _ => Err(ser::Error::custom(format!("cannot serialize {self:?}"))),
+        }
+    }
+}
+
+/// The elements of `lazy`, or an error if it has more than [`MAX_LAZY_LENGTH`]
+fn realize(lazy: &LazySeq) -> Result<Vec<Form>> {
+    let items = lazy
+        .iter()
+        .take(MAX_LAZY_LENGTH + 1)
+        .collect::<Result<Vec<_>>>()?;
+    if items.len() > MAX_LAZY_LENGTH {
+        return Err(Error::SerdeError(format!(
+            "lazy sequence longer than {MAX_LAZY_LENGTH} elements"
+        )));
+    }
+    Ok(items)
+}
+
+struct FormVisitor;
+
+impl<'de> de::Visitor<'de> for FormVisitor {
+    type Value = Form;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a risp value")
+    }
+
+    fn visit_unit<E>(self) -> std::result::Result<Form, E> {
+        Ok(Form::nil())
+    }
+
+    fn visit_none<E>(self) -> std::result::Result<Form, E> {
+        Ok(Form::nil())
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, d: D) -> std::result::Result<Form, D::Error> {
+        Form::deserialize(d)
+    }
+
+    fn visit_bool<E>(self, v: bool) -> std::result::Result<Form, E> {
+        Ok(Form::boolean(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> std::result::Result<Form, E> {
+        Ok(Form::int(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> std::result::Result<Form, E> {
+        i64::try_from(v)
+            .map(Form::int)
+            .map_err(|_| E::custom(format!("integer {v} out of range")))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> std::result::Result<Form, E> {
+        Ok(Form::float(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> std::result::Result<Form, E> {
+        Ok(Form::string(v))
+    }
+
+    fn visit_string<E>(self, v: String) -> std::result::Result<Form, E> {
+        Ok(Form::string(v))
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<Form, A::Error> {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Form::vector(items))
+    }
+
+    // Forms are map keys, see the note on `impl Hash for Form`
+    #[allow(clippy::mutable_key_type)]
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> std::result::Result<Form, A::Error> {
+        let mut entries = HashMap::new();
+        while let Some((key, value)) = map.next_entry::<Form, Form>()? {
+            let key = match key.kind {
+                FormKind::String(s) => Form::keyword(s),
+                _ => key,
+            };
+            entries.insert(key, value);
+        }
+        Ok(Form::hash_map(entries))
+    }
+}
+
+impl<'de> Deserialize<'de> for Form {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Form, D::Error> {
+        deserializer.deserialize_any(FormVisitor)
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Form {
+    type Deserializer = Form;
+
+    fn into_deserializer(self) -> Form {
+        self
+    }
+}
+
+impl<'de> Deserializer<'de> for Form {
+    type Error = Error;
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.kind {
+            FormKind::Nil => visitor.visit_unit(),
+            FormKind::Boolean(b) => visitor.visit_bool(b),
+            FormKind::Integer(n) => visitor.visit_i64(n),
+            FormKind::Float(n) => visitor.visit_f64(n),
+            FormKind::String(s) => visitor.visit_string(s),
+            FormKind::Keyword(k) | FormKind::Symbol(k) => visitor.visit_str(k.name()),
+            FormKind::List(items) | FormKind::Vector(items) => {
+                visitor.visit_seq(de::value::SeqDeserializer::new(items.into_iter()))
+            }
+            FormKind::LazySeq(lazy) => {
+                let items = realize(&lazy)?;
+                visitor.visit_seq(de::value::SeqDeserializer::new(items.into_iter()))
+            }
+            FormKind::HashMap(entries) => {
+                visitor.visit_map(de::value::MapDeserializer::new(entries.into_iter()))
+            }
+            other => Err(de::Error::custom(format!("cannot deserialize {other:?}"))),
+        }
+    }
+
+    fn deserialize_option<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.is_nil() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Unit variants are written as keywords or strings, other variants as a map with a single
+    /// entry from the variant name to its contents
+    fn deserialize_enum<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        match self.kind {
+            FormKind::Keyword(k) => visitor.visit_enum(k.name().into_deserializer()),
+            FormKind::String(s) => visitor.visit_enum(s.into_deserializer()),
+            FormKind::HashMap(entries) if entries.len() == 1 => {
+                let (variant, value) = entries.into_iter().next().expect("checked length");
+                visitor.visit_enum(EnumAccess { variant, value })
+            }
+            _ => Err(de::Error::custom("expected an enum variant")),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct EnumAccess {
+    variant: Form,
+    value: Form,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess {
+    type Error = Error;
+    type Variant = Form;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Form)> {
+        Ok((seed.deserialize(self.variant)?, self.value))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Form {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        de::Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: de::Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        self.deserialize_any(visitor)
+    }
+
+    fn struct_variant<V: de::Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_any(visitor)
+    }
+}
+
+/// Serializer producing `Form`s. Structs become maps with keyword keys, sequences and tuples
+/// become vectors, and enums use the same tagging as `deserialize_enum`.
+struct FormSerializer;
+
+fn tagged(variant: &'static str, value: Form) -> Form {
+    Form::hash_map([(Form::keyword(variant), value)].into())
+}
+
+impl Serializer for FormSerializer {
+    type Ok = Form;
+    type Error = Error;
+    type SerializeSeq = SerializeVec;
+    type SerializeTuple = SerializeVec;
+    type SerializeTupleStruct = SerializeVec;
+    type SerializeTupleVariant = SerializeVec;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeMap;
+    type SerializeStructVariant = SerializeMap;
+
+    fn serialize_bool(self, v: bool) -> Result<Form> {
+        Ok(Form::boolean(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Form> {
+        Ok(Form::int(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Form> {
+        Ok(Form::int(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Form> {
+        Ok(Form::int(v.into()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Form> {
+        Ok(Form::int(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Form> {
+        Ok(Form::int(v.into()))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Form> {
+        Ok(Form::int(v.into()))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Form> {
+        Ok(Form::int(v.into()))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Form> {
+        Ok(Form::int(v.try_into()?))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Form> {
+        Ok(Form::float(v.into()))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Form> {
+        Ok(Form::float(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Form> {
+        Ok(Form::string(v))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Form> {
+        Ok(Form::string(v))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Form> {
+        Ok(Form::vector(v.iter().map(|&b| Form::int(b.into()))))
+    }
+
+    fn serialize_none(self) -> Result<Form> {
+        Ok(Form::nil())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Form> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Form> {
+        Ok(Form::nil())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Form> {
+        Ok(Form::nil())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Form> {
+        Ok(Form::keyword(variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Form> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Form> {
+        Ok(tagged(variant, to_form(value)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeVec> {
+        Ok(SerializeVec {
+            items: Vec::with_capacity(len.unwrap_or(0)),
+            variant: None,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeVec> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SerializeVec> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVec> {
+        Ok(SerializeVec {
+            items: Vec::with_capacity(len),
+            variant: Some(variant),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeMap> {
+        Ok(SerializeMap {
+            entries: HashMap::new(),
+            key: None,
+            variant: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeMap> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SerializeMap> {
+        Ok(SerializeMap {
+            entries: HashMap::new(),
+            key: None,
+            variant: Some(variant),
+        })
+    }
+}
+
+struct SerializeVec {
+    items: Vec<Form>,
+    variant: Option<&'static str>,
+}
+
+impl SerializeVec {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.items.push(to_form(value)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Form> {
+        let vector = Form::vector(self.items);
+        Ok(match self.variant {
+            Some(variant) => tagged(variant, vector),
+            None => vector,
+        })
+    }
+}
+
+impl ser::SerializeSeq for SerializeVec {
+    type Ok = Form;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Form> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SerializeVec {
+    type Ok = Form;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Form> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeVec {
+    type Ok = Form;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Form> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeVec {
+    type Ok = Form;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Form> {
+        self.finish()
+    }
+}
+
+struct SerializeMap {
+    entries: HashMap<Form, Form>,
+    key: Option<Form>,
+    variant: Option<&'static str>,
+}
+
+impl SerializeMap {
+    fn finish(self) -> Result<Form> {
+        let map = Form::hash_map(self.entries);
+        Ok(match self.variant {
+            Some(variant) => tagged(variant, map),
+            None => map,
+        })
+    }
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = Form;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        // String keys become keywords, as they do when deserializing a `Form`
+        let key = to_form(key)?;
+        self.key = Some(match key.kind {
+            FormKind::String(s) => Form::keyword(s),
+            _ => key,
+        });
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| <Error as ser::Error>::custom("map value without a key"))?;
+        self.entries.insert(key, to_form(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Form> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for SerializeMap {
+    type Ok = Form;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.entries.insert(Form::keyword(key), to_form(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Form> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeMap {
+    type Ok = Form;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.entries.insert(Form::keyword(key), to_form(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Form> {
+        self.finish()
+    }
+}
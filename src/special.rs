@@ -0,0 +1,147 @@
+//! Special forms
+//!
+//! A special form gets its arguments unevaluated, with the environment it appears in, and
+//! decides what to evaluate itself. Every special form, built in or not, is a [`SpecialForm`]
+//! in a per-thread registry; embedders add their own with [`register`]:
+//!
+//! ```
+//! use risp::{special::{self, Step}, Env, Form};
+//!
+//! // (unless test body) evaluates body, in tail position, when test is falsy
+//! special::register("unless", |form: Form, env: &mut Env| {
+//!     let (_, test, body): (Form, Form, Form) = form.try_into()?;
+//!     if risp::eval(test, env)?.is_truthy() {
+//!         Ok(Step::value(Form::nil()))
+//!     } else {
+//!         Ok(Step::tail(body))
+//!     }
+//! })
+//! .unwrap();
+//! ```
+//!
+//! A form continues with [`Step::tail`] to have the evaluator run another form in its place, as
+//! a tail call, so loops through it don't grow the stack. Register forms before evaluating code
+//! that uses them: functions are compiled on their first call. Code using a registered form
+//! runs on the tree-walker, whatever the backend.
+
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{eval, Env, Error, Form, Ident, Result};
+
+/// A form that gets its arguments unevaluated
+pub trait SpecialForm {
+    /// Evaluate `form`, the whole list including the name of the special form, in `env`
+    fn eval(&self, form: Form, env: &mut Env) -> Result<Step>;
+}
+
+impl<F: Fn(Form, &mut Env) -> Result<Step>> SpecialForm for F {
+    fn eval(&self, form: Form, env: &mut Env) -> Result<Step> {
+        self(form, env)
+    }
+}
+
+/// What a special form evaluated to
+pub struct Step(pub(crate) Next);
+
+pub(crate) enum Next {
+    Value(Form),
+    /// Evaluate the form in tail position, in the environment if given, or else in the one of
+    /// the special form
+    Tail(Form, Option<Env>),
+    /// Evaluate the body of a `loop` in its environment, with its `recur` target
+    Loop(Form, Env, eval::RecurTarget),
+    /// Jump to the innermost `recur` target with the arguments
+    Recur(Vec<Form>),
+}
+
+impl Step {
+    /// The form evaluated to `value`
+    pub fn value(value: Form) -> Step {
+        Step(Next::Value(value))
+    }
+
+    /// Evaluate `form` in place of the special form, in the same environment
+    pub fn tail(form: Form) -> Step {
+        Step(Next::Tail(form, None))
+    }
+
+    /// Evaluate `form` in place of the special form, in `env`
+    pub fn tail_in(form: Form, env: Env) -> Step {
+        Step(Next::Tail(form, Some(env)))
+    }
+}
+
+struct Entry {
+    form: Rc<dyn SpecialForm>,
+    builtin: bool,
+}
+
+thread_local! {
+    static REGISTRY: RefCell<HashMap<Ident, Entry>> = RefCell::new(
+        eval::special_forms()
+            .into_iter()
+            .map(|(name, form)| {
+                let form = Rc::new(form) as Rc<dyn SpecialForm>;
+                (name, Entry { form, builtin: true })
+            })
+            .collect(),
+    );
+}
+
+/// Make `name` a special form on this thread, replacing any form registered under it before.
+/// The built-in special forms can't be replaced.
+pub fn register(name: &str, form: impl SpecialForm + 'static) -> Result<()> {
+    let name = Ident::from(name);
+    REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        if registry.get(&name).is_some_and(|entry| entry.builtin) {
+            return Err(Error::BuiltinSpecialForm(name.to_string()));
+        }
+        let form = Rc::new(form);
+        registry.insert(
+            name,
+            Entry {
+                form,
+                builtin: false,
+            },
+        );
+        Ok(())
+    })
+}
+
+/// Remove the special form registered under `name`, returning whether there was one. The
+/// built-in special forms can't be removed.
+pub fn unregister(name: &str) -> bool {
+    let name = Ident::from(name);
+    REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        match registry.get(&name) {
+            Some(entry) if !entry.builtin => registry.remove(&name).is_some(),
+            _ => false,
+        }
+    })
+}
+
+/// Whether `name` is a special form on this thread
+pub fn is_special(name: &str) -> bool {
+    REGISTRY.with(|registry| registry.borrow().contains_key(&Ident::from(name)))
+}
+
+/// The special form registered under `name`
+pub(crate) fn get(name: Ident) -> Option<Rc<dyn SpecialForm>> {
+    REGISTRY.with(|registry| {
+        let registry = registry.borrow();
+        registry.get(&name).map(|entry| Rc::clone(&entry.form))
+    })
+}
+
+/// Whether `name` is a special form registered from outside the crate, which the compilers
+/// leave to the tree-walker
+pub(crate) fn is_registered(name: Ident) -> bool {
+    REGISTRY.with(|registry| {
+        registry
+            .borrow()
+            .get(&name)
+            .is_some_and(|entry| !entry.builtin)
+    })
+}
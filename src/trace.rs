@@ -0,0 +1,149 @@
+//! Stack traces for errors
+//!
+//! Every function call evaluated by `eval` keeps the metadata of the call form, which holds the
+//! `:line` and `:column` the reader recorded, and the name the function was called by: the
+//! `:name` in its metadata, or else the symbol at the head of the call, which is usually the name
+//! it was defined with by `def!`. When an error propagates out of a call, a [`Frame`] is appended
+//! to the trace of the error in flight, innermost call first. Calls replaced by a tail call no
+//! longer have a frame.
+//!
+//! The trace is kept per thread until the error is caught by `try*`, or until evaluation
+//! succeeds again. Embedders retrieve it with [`take`], and the REPL stores it in `*e`.
+
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{dynamic, intern::sym, Env, Error, Form, FormKind, Ident};
+
+/// A function call that was active when an error occurred
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    /// The name of the function, if it has `:name` metadata or was called through a symbol
+    pub name: Option<String>,
+    pub line: Option<i64>,
+    pub column: Option<i64>,
+}
+
+thread_local! {
+    static TRACE: RefCell<Vec<Frame>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The metadata of a call, kept until the call returns
+pub(crate) struct Call {
+    pub(crate) head: Option<Ident>,
+    pub(crate) function: Option<Box<Form>>,
+    pub(crate) form: Option<Box<Form>>,
+}
+
+fn meta_entry(meta: &Option<Box<Form>>, key: Ident) -> Option<Form> {
+    match meta.as_deref()?.kind {
+        FormKind::HashMap(ref map) => map.get(&Form::keyword(key)).cloned(),
+        _ => None,
+    }
+}
+
+fn frame(head: Option<Ident>, function: &Option<Box<Form>>, form: &Option<Box<Form>>) -> Frame {
+    let int = |key| meta_entry(form, key).and_then(|form| form.try_into().ok());
+    Frame {
+        name: meta_entry(function, sym::NAME)
+            .map(|name| name.to_string())
+            .or_else(|| head.map(|head| head.name().into())),
+        line: int(sym::LINE),
+        column: int(sym::COLUMN),
+    }
+}
+
+/// Record that an error propagated out of `call`
+pub(crate) fn unwind(call: &Call) {
+    let frame = frame(call.head, &call.function, &call.form);
+    TRACE.with(|trace| trace.borrow_mut().push(frame));
+}
+
+/// Where a function was called from, kept by compiled code for every call it makes
+pub(crate) struct Site {
+    head: Option<Ident>,
+    meta: Option<Box<Form>>,
+}
+
+impl Site {
+    pub(crate) fn new(head: Option<Ident>, meta: Option<Box<Form>>) -> Rc<Site> {
+        Rc::new(Site { head, meta })
+    }
+
+    /// Record that an error propagated out of a call of `function` from `site`, if the call
+    /// should appear in the trace
+    pub(crate) fn unwind(site: &Option<Rc<Site>>, function: &Form) {
+        if let Some(site) = site {
+            let frame = frame(site.head, &function.meta, &site.meta);
+            TRACE.with(|trace| trace.borrow_mut().push(frame));
+        }
+    }
+}
+
+/// Forget the trace after evaluation succeeded or an error was caught
+pub(crate) fn clear() {
+    TRACE.with(|trace| trace.borrow_mut().clear());
+}
+
+/// Set the trace aside while `try*` evaluates handlers for an error that may propagate further
+pub(crate) fn save() -> Vec<Frame> {
+    TRACE.with(|trace| std::mem::take(&mut *trace.borrow_mut()))
+}
+
+pub(crate) fn restore(frames: Vec<Frame>) {
+    TRACE.with(|trace| *trace.borrow_mut() = frames);
+}
+
+/// Take the trace of the most recent error that escaped `eval`, innermost call first
+pub fn take() -> Vec<Frame> {
+    save()
+}
+
+impl Frame {
+    // Forms are map keys, see the note on `impl Hash for Form`
+    #[allow(clippy::mutable_key_type)]
+    pub fn to_form(&self) -> Form {
+        let mut map = std::collections::HashMap::new();
+        if let Some(ref name) = self.name {
+            map.insert(Form::keyword(sym::NAME), Form::string(name));
+        }
+        if let Some(line) = self.line {
+            map.insert(Form::keyword(sym::LINE), Form::int(line));
+        }
+        if let Some(column) = self.column {
+            map.insert(Form::keyword(sym::COLUMN), Form::int(column));
+        }
+        Form::hash_map(map)
+    }
+}
+
+impl std::fmt::Display for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name.as_deref().unwrap_or("fn*"))?;
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, " ({line}:{column})"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Store `err` and the trace of the calls it propagated through in `*e`, as the map a `catch*`
+/// clause would receive with an added `:trace` vector. Thrown values that aren't maps are
+/// wrapped in a `:user-error` map, with the value as its `:data`.
+// Forms are map keys, see the note on `impl Hash for Form`
+#[allow(clippy::mutable_key_type)]
+pub fn record_error(err: &Error, env: &mut Env) -> Form {
+    let trace = Form::vector(take().iter().map(Frame::to_form));
+    let mut map = match err.to_form().kind {
+        FormKind::HashMap(map) => map,
+        _ => [
+            (Form::keyword(sym::TYPE), Form::keyword(err.type_name())),
+            (Form::keyword(sym::MESSAGE), Form::string(err.to_string())),
+            (Form::keyword(sym::DATA), err.to_form()),
+        ]
+        .into(),
+    };
+    map.insert(Form::keyword(sym::TRACE), trace);
+    let form = Form::hash_map(map);
+    dynamic::define(dynamic::VarName::core(sym::LAST_ERROR), form.clone(), env);
+    form
+}
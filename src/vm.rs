@@ -0,0 +1,586 @@
+//! The bytecode virtual machine
+//!
+//! The machine runs the chunks built by [`bytecode`](crate::bytecode) on a single stack of
+//! values. A call of a compiled function pushes a frame whose slots start where the arguments
+//! were, so calls between compiled functions don't grow the Rust stack, and a call in tail
+//! position replaces the frame of the caller. Native functions, and functions the compiler
+//! declines, run as they do on the tree-walker; when they call back into compiled code, a new
+//! machine runs it.
+//!
+//! `try*` installs a handler in the frame of the function. An error moves control to the
+//! innermost handler, popping the frames without one and recording them in the stack trace.
+//! `catch*` handlers let interrupts through, but `finally` handlers run for them too.
+
+use std::{collections::HashMap, rc::Rc};
+
+use itertools::Itertools;
+
+use crate::{
+    bytecode::{self, Chunk, Function, Op, Pattern},
+    destructure, dynamic,
+    eval::{self},
+    form::UserFn,
+    intern::sym,
+    interrupt, limits, namespace,
+    trace::{self, Site},
+    Env, Error, Form, FormKind, Ident, Result,
+};
+
+/// An error handler installed by `try*`
+struct Handler {
+    /// Where the handling code starts
+    target: usize,
+    /// Whether the handler runs `catch*` clauses, which don't handle interrupts
+    catches: bool,
+    /// The height of the stack, the number of dynamic bindings and the number of errors being
+    /// handled when the handler was installed, to go back to
+    height: usize,
+    restores: usize,
+    pending: usize,
+}
+
+/// A running chunk
+struct Frame {
+    chunk: Rc<Chunk>,
+    pc: usize,
+    /// Where the slots of the frame start on the stack
+    base: usize,
+    /// The height of the stack to go back to when the frame returns
+    height: usize,
+    env: Env,
+    /// The function running and where it was called from, for the stack trace
+    function: Form,
+    site: Option<Rc<Site>>,
+    handlers: Vec<Handler>,
+    /// The dynamic bindings made by `binding` forms that are still running
+    restores: Vec<dynamic::Restore>,
+    _depth: Option<limits::Depth>,
+}
+
+/// The chunk, environment and argument layout of a function about to be called
+struct Entry {
+    chunk: Rc<Chunk>,
+    env: Env,
+    params: usize,
+    rest: bool,
+}
+
+#[derive(Default)]
+struct Machine {
+    stack: Vec<Form>,
+    frames: Vec<Frame>,
+    /// The errors being handled by `catch*` or `finally` code, with the trace each one had
+    pending: Vec<(Error, Vec<trace::Frame>)>,
+    /// The environment `ns` or `require` switched to, for the caller of `eval`
+    namespace: Option<Env>,
+}
+
+/// Compile and evaluate `form` in `env`
+pub(crate) fn eval(form: Form, env: &mut Env) -> Result<Form> {
+    let _depth = limits::enter()?;
+    let result = match form.kind {
+        FormKind::List(ref items) if form.as_fn_name() == Some(sym::DO) => items[1..]
+            .iter()
+            .try_fold(Form::nil(), |_, form| eval(form.clone(), env)),
+        _ => match bytecode::compile(&form, env) {
+            Some(chunk) => {
+                let mut machine = Machine::default();
+                machine.stack.resize(chunk.slots(), Form::nil());
+                machine
+                    .frames
+                    .push(Frame::new(Rc::new(chunk), 0, env.clone()));
+                let result = machine.run(0);
+                if let Some(switched) = machine.namespace {
+                    *env = switched;
+                }
+                result
+            }
+            None => eval::interpret_top_level(form, env),
+        },
+    };
+    if result.is_ok() {
+        trace::clear();
+    }
+    result
+}
+
+/// Call `f` with `args`. `site` is the call form, if the call should appear in stack traces.
+pub(crate) fn invoke(f: Form, args: Vec<Form>, site: Option<Rc<Site>>) -> Result<Form> {
+    let mut machine = Machine::default();
+    let argc = args.len();
+    machine.stack.push(f);
+    machine.stack.extend(args);
+    match machine.call(argc, site, false)? {
+        Some(value) => Ok(value),
+        None => machine.run(0),
+    }
+}
+
+/// The bytecode of `user_fn`, compiling it on the first call
+fn function(user_fn: &UserFn) -> Option<&Function> {
+    let bytecode = &user_fn.compiled.bytecode;
+    if bytecode.get().is_none() {
+        // Compilation can call macros, which may call this function, so the cell is only
+        // filled once compilation is done
+        let _ = bytecode.set(Function::compile(user_fn));
+    }
+    bytecode.get()?.as_ref()
+}
+
+/// Whether calls of `f` run bytecode
+pub(crate) fn is_compiled(f: &Form) -> bool {
+    matches!(f.kind, FormKind::UserFn(ref user_fn) if function(user_fn).is_some())
+}
+
+/// The bytecode `form` compiles to in `env`, or `None` if it would run on the tree-walker.
+/// Functions are compiled on their first call, so the bodies of `fn*` forms aren't included;
+/// see [`disassemble_fn`].
+pub fn disassemble(form: &Form, env: &Env) -> Option<String> {
+    bytecode::compile(form, env).map(|chunk| chunk.to_string())
+}
+
+/// The bytecode of each arity of the function `f`, or `None` if it runs on the tree-walker
+pub fn disassemble_fn(f: &Form) -> Option<String> {
+    let FormKind::UserFn(ref user_fn) = f.kind else {
+        return None;
+    };
+    let function = function(user_fn)?;
+    Some(
+        function
+            .arities
+            .iter()
+            .map(|arity| arity.chunk.to_string())
+            .join("\n"),
+    )
+}
+
+impl Frame {
+    fn new(chunk: Rc<Chunk>, base: usize, env: Env) -> Frame {
+        Frame {
+            chunk,
+            pc: 0,
+            base,
+            height: base,
+            env,
+            function: Form::nil(),
+            site: None,
+            handlers: Vec::new(),
+            restores: Vec::new(),
+            _depth: None,
+        }
+    }
+}
+
+impl Machine {
+    /// Run until the frame above `floor` returns, and return its value
+    fn run(&mut self, floor: usize) -> Result<Form> {
+        let pending = self.pending.len();
+        loop {
+            match self.execute(floor) {
+                Ok(value) => return Ok(value),
+                Err(err) => {
+                    if let Err(err) = self.throw(err, floor) {
+                        self.pending.truncate(pending);
+                        return Err(err);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Move control to the innermost handler of `err` above `floor`, unwinding the frames
+    /// without one. Returns the error if no handler takes it.
+    fn throw(&mut self, err: Error, floor: usize) -> Result<()> {
+        while self.frames.len() > floor {
+            let frame = self.frames.last_mut().expect("a frame above the floor");
+            while let Some(handler) = frame.handlers.pop() {
+                if handler.catches && matches!(err, Error::Interrupted) {
+                    continue;
+                }
+                self.stack.truncate(handler.height);
+                frame.restores.truncate(handler.restores);
+                self.pending.truncate(handler.pending);
+                self.stack.push(err.to_form());
+                self.pending.push((err, trace::save()));
+                frame.pc = handler.target;
+                return Ok(());
+            }
+            let frame = self.frames.pop().expect("a frame above the floor");
+            Site::unwind(&frame.site, &frame.function);
+            self.stack.truncate(frame.height);
+        }
+        Err(err)
+    }
+
+    /// Pop the current frame, handing `value` to its caller. Returns the value instead if the
+    /// frame was the one above `floor`.
+    fn return_(&mut self, value: Form, floor: usize) -> Option<Form> {
+        let frame = self.frames.pop().expect("a frame to return from");
+        self.stack.truncate(frame.height);
+        if self.frames.len() == floor {
+            return Some(value);
+        }
+        self.stack.push(value);
+        None
+    }
+
+    /// Call the function below the top `argc` values of the stack. Compiled functions get a
+    /// frame, which replaces the current one for a `tail` call; anything else runs to
+    /// completion, and its value is returned.
+    fn call(&mut self, argc: usize, site: Option<Rc<Site>>, tail: bool) -> Result<Option<Form>> {
+        let f = self.stack.remove(self.stack.len() - argc - 1);
+        let result = match f.kind {
+            FormKind::NativeFn(ref native) => {
+                let args = self.stack.split_off(self.stack.len() - argc);
+                native
+                    .call(Form::list(args))
+                    .and_then(|result| limits::allocate(&result).map(|()| result))
+            }
+            FormKind::MultiFn(ref multi) => {
+                let args = self.stack.split_off(self.stack.len() - argc);
+                multi.call(Form::list(args))
+            }
+            FormKind::UserFn(ref user_fn) => {
+                let entry = function(user_fn).map(|function| {
+                    let arity = &function.arities[eval::select_arity(user_fn, argc)?];
+                    Ok(Entry {
+                        chunk: Rc::clone(&arity.chunk),
+                        env: user_fn.env.clone(),
+                        params: arity.params,
+                        rest: arity.rest,
+                    })
+                });
+                match entry {
+                    Some(Ok(entry)) => {
+                        self.enter(f, entry, argc, site, tail)?;
+                        return Ok(None);
+                    }
+                    Some(Err(err)) => Err(err),
+                    None => {
+                        let args = self.stack.split_off(self.stack.len() - argc);
+                        eval::interpret_call(f.clone(), Form::list(args))
+                    }
+                }
+            }
+            _ => return Err(Error::NotCallable),
+        };
+        result.map(Some).inspect_err(|_| Site::unwind(&site, &f))
+    }
+
+    /// Push a frame for a call of `function` with the top `argc` values of the stack
+    fn enter(
+        &mut self,
+        function: Form,
+        entry: Entry,
+        argc: usize,
+        site: Option<Rc<Site>>,
+        tail: bool,
+    ) -> Result<()> {
+        let mut base = self.stack.len() - argc;
+        let depth = match self.frames.last_mut() {
+            // A tail call keeps the depth of the call it replaces
+            Some(frame) if tail && frame._depth.is_some() => frame._depth.take(),
+            _ => Some(limits::enter()?),
+        };
+        if tail {
+            let frame = self.frames.pop().expect("a frame making the tail call");
+            self.stack.drain(frame.height..base);
+            base = frame.height;
+        }
+        if entry.rest {
+            let rest = self.stack.split_off(base + entry.params);
+            self.stack.push(Form::list(rest));
+        }
+        self.stack.resize(base + entry.chunk.slots(), Form::nil());
+        self.frames.push(Frame {
+            function,
+            site,
+            _depth: depth,
+            ..Frame::new(entry.chunk, base, entry.env)
+        });
+        Ok(())
+    }
+
+    /// Run the `:or` default of a pattern being bound in the current frame
+    fn run_default(&mut self, chunk: Rc<Chunk>) -> Result<Form> {
+        let frame = self.frames.last().expect("a frame binding the pattern");
+        let floor = self.frames.len();
+        let default = Frame {
+            height: self.stack.len(),
+            ..Frame::new(chunk, frame.base, frame.env.clone())
+        };
+        self.frames.push(default);
+        self.run(floor)
+    }
+
+    fn pop(&mut self) -> Form {
+        self.stack.pop().expect("a value on the stack")
+    }
+
+    /// Run instructions until the frame above `floor` returns
+    fn execute(&mut self, floor: usize) -> Result<Form> {
+        loop {
+            let frame = self.frames.last_mut().expect("a running frame");
+            let op = frame.chunk.code[frame.pc];
+            frame.pc += 1;
+            match op {
+                Op::Const(i) => {
+                    let value = frame.chunk.constants[i as usize].clone();
+                    self.stack.push(value);
+                }
+                Op::Nil => self.stack.push(Form::nil()),
+                Op::Local(slot) | Op::Load(slot) => {
+                    let value = self.stack[frame.base + slot as usize].clone();
+                    self.stack.push(value);
+                }
+                Op::Store(slot) => {
+                    let value = self.stack.pop().expect("a value to store");
+                    self.stack[frame.base + slot as usize] = value;
+                }
+                Op::Global(i) => {
+                    let (name, ref cache) = frame.chunk.globals[i as usize];
+                    let value = frame.env.get_cached(name, cache)?;
+                    self.stack.push(value);
+                }
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::Jump(target) => frame.pc = target as usize,
+                Op::JumpIfFalse(target) => {
+                    if !self.stack.pop().expect("a condition").is_truthy() {
+                        frame.pc = target as usize;
+                    }
+                }
+                Op::Call(i) | Op::TailCall(i) => {
+                    let tail = matches!(op, Op::TailCall(_));
+                    let call_site = &frame.chunk.sites[i as usize];
+                    let (argc, site) = (call_site.argc, Rc::clone(&call_site.site));
+                    limits::step()?;
+                    interrupt::check()?;
+                    match self.call(argc, Some(site), tail)? {
+                        Some(value) if tail => {
+                            if let Some(value) = self.return_(value, floor) {
+                                return Ok(value);
+                            }
+                        }
+                        Some(value) => self.stack.push(value),
+                        None => {}
+                    }
+                }
+                Op::Return => {
+                    let value = self.pop();
+                    if let Some(value) = self.return_(value, floor) {
+                        return Ok(value);
+                    }
+                }
+                Op::Recur(i) => {
+                    limits::step()?;
+                    interrupt::check()?;
+                    let recur = &frame.chunk.recurs[i as usize];
+                    for &slot in recur.slots.iter().rev() {
+                        let value = self.stack.pop().expect("a recur argument");
+                        self.stack[frame.base + slot] = value;
+                    }
+                    frame.pc = recur.target;
+                }
+                _ => self.execute_rare(op)?,
+            }
+        }
+    }
+
+    /// Run an instruction other than the loads, jumps, calls and returns, which `execute` runs
+    /// itself. Keeping these out of `execute` keeps its frame small, as natives that call back
+    /// into compiled code add one for each call.
+    fn execute_rare(&mut self, op: Op) -> Result<()> {
+        let frame = self.frames.last_mut().expect("a running frame");
+        match op {
+            Op::Bind(i) => {
+                let (chunk, base) = (Rc::clone(&frame.chunk), frame.base);
+                let value = self.pop();
+                let pattern = &chunk.patterns[i as usize];
+                let mut binder = SlotBinder {
+                    machine: self,
+                    pattern,
+                    base,
+                };
+                destructure::bind(&pattern.form, value, &mut binder)?;
+            }
+            Op::Vector(i) => {
+                let literal = &frame.chunk.literals[i as usize];
+                let items = self.stack.split_off(self.stack.len() - literal.len);
+                let evaluated = Form {
+                    kind: FormKind::Vector(items),
+                    meta: literal.meta.clone(),
+                };
+                limits::allocate(&evaluated)?;
+                self.stack.push(evaluated);
+            }
+            Op::HashMap(i) => {
+                let literal = &frame.chunk.literals[i as usize];
+                let items = self.stack.split_off(self.stack.len() - 2 * literal.len);
+                let evaluated = Form {
+                    kind: FormKind::HashMap(items.into_iter().tuples().collect::<HashMap<_, _>>()),
+                    meta: literal.meta.clone(),
+                };
+                limits::allocate(&evaluated)?;
+                self.stack.push(evaluated);
+            }
+            Op::Closure(i) => {
+                let closure = &frame.chunk.closures[i as usize];
+                let env = if closure.captured.is_empty() {
+                    frame.env.clone()
+                } else {
+                    let mut env = Env::new_with(&frame.env);
+                    env.extend(
+                        closure
+                            .captured
+                            .iter()
+                            .map(|&(name, slot)| (name, self.stack[frame.base + slot].clone())),
+                    );
+                    env
+                };
+                let created = eval::fn_(closure.form.clone(), &env, Rc::clone(&closure.cache))?;
+                self.stack.push(created);
+            }
+            Op::Def(i) => {
+                let symbol = frame.chunk.constants[i as usize].clone();
+                let value = self.stack.pop().expect("a value to define");
+                let defined = eval::define(symbol, value, &mut frame.env)?;
+                self.stack.push(defined);
+            }
+            Op::DefMacro(name) => {
+                let value = self.stack.pop().expect("a macro to define");
+                let defined = eval::define_macro(name, value, &mut frame.env)?;
+                self.stack.push(defined);
+            }
+            Op::Eval => {
+                let value = self.stack.pop().expect("a form to evaluate");
+                let evaluated = crate::eval(value, &mut frame.env.root())?;
+                self.stack.push(evaluated);
+            }
+            Op::Ns(i) => {
+                let form = frame.chunk.constants[i as usize].clone();
+                let value = namespace::ns(form, &mut frame.env)?;
+                self.namespace = Some(frame.env.clone());
+                self.stack.push(value);
+            }
+            Op::Require(i) => {
+                let form = frame.chunk.constants[i as usize].clone();
+                let value = namespace::require(form, &mut frame.env)?;
+                self.namespace = Some(frame.env.clone());
+                self.stack.push(value);
+            }
+            Op::MacroExpand(i, expansion) => {
+                let form = frame.chunk.constants[i as usize].clone();
+                let expanded = expansion.expand(form, &frame.env)?;
+                self.stack.push(expanded);
+            }
+            Op::CheckDynamic(name) => drop(dynamic::resolve(name, &frame.env)?),
+            Op::SetDynamic(name) => {
+                let value = self.stack.pop().expect("a value to set");
+                let var = dynamic::resolve(name, &frame.env)?;
+                self.stack.push(dynamic::replace(var, value, &frame.env)?);
+            }
+            Op::PushBindings(i) => {
+                let names = &frame.chunk.bindings[i as usize];
+                let values = self.stack.split_off(self.stack.len() - names.len());
+                let pushed = names
+                    .iter()
+                    .map(|&name| dynamic::resolve(name, &frame.env))
+                    .zip(values)
+                    .map(|(var, value)| Ok((var?, value)))
+                    .collect::<Result<Vec<_>>>()?;
+                frame.restores.push(dynamic::push(pushed, &frame.env));
+            }
+            Op::PopBindings => {
+                frame.restores.pop();
+            }
+            Op::Try(target) | Op::TryFinally(target) => frame.handlers.push(Handler {
+                target: target as usize,
+                catches: matches!(op, Op::Try(_)),
+                height: self.stack.len(),
+                restores: frame.restores.len(),
+                pending: self.pending.len(),
+            }),
+            Op::EndTry => {
+                frame.handlers.pop();
+            }
+            Op::CatchType(kind) => {
+                let thrown = self.stack.last().expect("a thrown value");
+                let matches = eval::has_type(thrown, kind);
+                self.stack.push(Form::boolean(matches));
+            }
+            Op::CatchTest => {
+                let predicate = self.pop();
+                let thrown = self.stack.last().expect("a thrown value").clone();
+                let matches = predicate.call(Form::list([thrown]))?.is_truthy();
+                self.stack.push(Form::boolean(matches));
+            }
+            Op::Caught => {
+                self.pending.pop();
+                trace::clear();
+            }
+            Op::Rethrow => {
+                let (err, frames) = self.pending.pop().expect("an error being handled");
+                trace::restore(frames);
+                return Err(err);
+            }
+            Op::Fail(i) => return Err(frame.chunk.failures[i as usize].to_error()),
+            Op::Match(i) => {
+                let (chunk, base) = (Rc::clone(&frame.chunk), frame.base);
+                let site = &chunk.matches[i as usize];
+                let predicates = self
+                    .stack
+                    .split_off(self.stack.len() - site.pattern.predicates().len());
+                let value = self.pop();
+                let matched = match site.pattern.matches_with(&value, &predicates)? {
+                    Some(bindings) => {
+                        for (bound, &slot) in bindings.into_values().zip(&site.slots) {
+                            self.stack[base + slot] = bound;
+                        }
+                        true
+                    }
+                    None => false,
+                };
+                self.stack.push(Form::boolean(matched));
+            }
+            Op::NoMatch => return Err(Error::NoMatch(self.pop())),
+            Op::Const(_)
+            | Op::Nil
+            | Op::Local(_)
+            | Op::Load(_)
+            | Op::Store(_)
+            | Op::Global(_)
+            | Op::Pop
+            | Op::Jump(_)
+            | Op::JumpIfFalse(_)
+            | Op::Call(_)
+            | Op::TailCall(_)
+            | Op::Return
+            | Op::Recur(_) => unreachable!("run by execute"),
+        }
+        Ok(())
+    }
+}
+
+/// Binds the names of a pattern to the slots of the current frame
+struct SlotBinder<'a> {
+    machine: &'a mut Machine,
+    pattern: &'a Pattern,
+    base: usize,
+}
+
+impl destructure::Binder for SlotBinder<'_> {
+    fn set(&mut self, name: Ident, value: Form) {
+        if let Some(&(_, slot)) = self.pattern.slots.iter().find(|(n, _)| *n == name) {
+            self.machine.stack[self.base + slot] = value;
+        }
+    }
+
+    fn default(&mut self, name: Ident, _: &Form) -> Result<Form> {
+        match self.pattern.defaults.iter().find(|(n, _)| *n == name) {
+            Some((_, chunk)) => self.machine.run_default(Rc::clone(chunk)),
+            None => Ok(Form::nil()),
+        }
+    }
+}
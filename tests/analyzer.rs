@@ -0,0 +1,127 @@
+mod common;
+
+use common::{assert_all_eval_to, each_backend, eval_all};
+
+#[test]
+fn closures_capture_locals() {
+    each_backend(|| {
+        assert_all_eval_to(
+            &[
+                "(def! adder (fn* (n) (fn* (x) (+ x n))))",
+                "(map (adder 10) [1 2 3])",
+            ],
+            "(11 12 13)",
+        );
+        assert_all_eval_to(&["(let* [x 1 f (fn* [] x) x 2] (f))"], "2");
+        // A closure can refer to bindings that come after it
+        assert_all_eval_to(
+            &["(let* [even? (fn* (n) (if (= n 0) true (odd? (- n 1)))) odd? (fn* (n) (if (= n 0) false (even? (- n 1))))] (even? 10))"],
+            "true",
+        );
+        // Even after it was called with the name still resolving to the global
+        assert_all_eval_to(
+            &[
+                "(def! y 1)",
+                "(let* [f (fn* [] y) before (f) y 2] [before (f)])",
+            ],
+            "[1 2]",
+        );
+    });
+}
+
+#[test]
+fn definitions_in_local_scope() {
+    each_backend(|| {
+        // def! defines in the innermost environment, so the definition is only visible inside
+        assert_all_eval_to(&["(let* [x 5] (do (def! y (* x 2)) (+ y 1)))"], "11");
+        assert_all_eval_to(&["((fn* [] (do (defmacro! m (fn* [] 7)) (m))))"], "7");
+        assert!(eval_all(&["(let* [x 5] (def! y x))", "y"]).is_err());
+    });
+}
+
+#[test]
+fn macros_defined_in_a_top_level_do() {
+    each_backend(|| {
+        assert_all_eval_to(
+            &["(do (defmacro! twice (fn* (x) `(do ~x ~x))) (def! n (atom 0)) (twice (swap! n (fn* (x) (+ x 1)))))"],
+            "2",
+        );
+    });
+}
+
+#[test]
+fn locals_shadow_macros() {
+    each_backend(|| {
+        assert_all_eval_to(&["(let* [cond list] (cond 1 2))"], "(1 2)");
+        assert_all_eval_to(&["((fn* (when) (when 3)) (fn* (x) (+ x 1)))"], "4");
+    });
+}
+
+#[test]
+fn redefinitions_are_seen_by_compiled_functions() {
+    each_backend(|| {
+        assert_all_eval_to(
+            &[
+                "(def! f (fn* [] 1))",
+                "(def! g (fn* [] (f)))",
+                "(g)",
+                "(def! f (fn* [] 2))",
+                "(g)",
+            ],
+            "2",
+        );
+    });
+}
+
+#[test]
+fn tail_calls_do_not_grow_the_stack() {
+    each_backend(|| {
+        assert_all_eval_to(
+            &[
+                "(def! even? (fn* (n) (if (= n 0) true (odd? (- n 1)))))",
+                "(def! odd? (fn* (n) (if (= n 0) false (even? (- n 1)))))",
+                "(even? 100000)",
+            ],
+            "true",
+        );
+    });
+}
+
+#[test]
+fn arities_and_destructuring() {
+    each_backend(|| {
+        assert_all_eval_to(
+            &[
+                "(def! f (fn* ([] 0) ([x] x) ([x y & more] (+ x y (count more)))))",
+                "[(f) (f 1) (f 1 2) (f 1 2 3 4)]",
+            ],
+            "[0 1 3 5]",
+        );
+        assert_all_eval_to(
+            &["((fn* [[a b] {:keys [c] :or {c (+ a b)}}] [a b c]) [1 2] {})"],
+            "[1 2 3]",
+        );
+    });
+}
+
+#[test]
+fn untaken_branches_are_not_expanded_early() {
+    each_backend(|| {
+        assert_all_eval_to(
+            &[
+                "(defmacro! bad (fn* [] (throw :expanded)))",
+                "(if true 1 (bad))",
+            ],
+            "1",
+        );
+    });
+}
+
+#[test]
+fn misplaced_recur_is_an_error() {
+    each_backend(|| {
+        assert!(eval_all(&["(recur 1)"]).is_err());
+        assert!(eval_all(&["(loop [i 0] (+ 1 (recur i)))"]).is_err());
+        assert!(eval_all(&["(loop [i 0] (recur 1 2))"]).is_err());
+    });
+}
@@ -0,0 +1,164 @@
+use risp::{
+    exec::{self, Backend},
+    read_str, trace, vm, Env, Form, FormKind,
+};
+
+/// Programs run on both backends. Each line is evaluated separately, and what it returns or
+/// raises, with the stack trace of errors, must be the same. The other integration tests run on
+/// both backends too, so features get their tests there rather than a copy here.
+const PROGRAMS: &[&str] = &[
+    // Functions, closures and arities
+    r#"(def! fib (fn* (n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2))))))
+       (fib 15)
+       (def! adder (fn* (n) (fn* (x) (+ x n))))
+       (map (adder 10) [1 2 3])
+       (let* [x 1 f (fn* [] x) x 2] (f))
+       (def! f (fn* ([] 0) ([x] x) ([x y & more] (+ x y (count more)))))
+       [(f) (f 1) (f 1 2) (f 1 2 3 4)]
+       ((fn* [& xs] xs))
+       ((fn* [a] a))
+       ((fn* [a] a) 1 2)
+       (1 2)
+       (undefined 1)"#,
+    // Tail calls, loop and recur
+    r#"(def! sum (fn* (n acc) (if (= n 0) acc (sum (- n 1) (+ acc n)))))
+       (sum 10000 0)
+       (loop [i 0 acc []] (if (< i 5) (recur (+ i 1) (conj acc i)) acc))
+       (loop [[a & more] [1 2 3] total 0] (if a (recur more (+ total a)) total))
+       ((fn* [n] (if (> n 0) (recur (- n 1)) :done)) 100)
+       (recur 1)
+       (loop [i 0] (+ 1 (recur i)))
+       (loop [i 0] (recur 1 2))
+       (let* [even? (fn* (n) (if (= n 0) true (odd? (- n 1)))) odd? (fn* (n) (if (= n 0) false (even? (- n 1))))] (even? 1001))"#,
+    // Destructuring
+    r#"((fn* [[a b] {:keys [c] :or {c (+ a b)}}] [a b c]) [1 2] {})
+       (let* [{:keys [x y] :or {y (* x 10)} :as all} {:x 1}] [x y all])
+       (let* [[a [b c] & d] [1 [2 3] 4 5]] [a b c d])
+       (let* [[a b] 5] a)"#,
+    // Definitions, macros and quoting
+    r#"(defmacro! unless2 (fn* (c a b) `(if ~c ~b ~a)))
+       (unless2 false 1 2)
+       (macroexpand (unless2 a b c))
+       (let* [cond list] (cond 1 2))
+       (do (defmacro! twice (fn* (x) `(do ~x ~x))) (def! n (atom 0)) (twice (swap! n (fn* (x) (+ x 1)))))
+       (let* [x 5] (do (def! y (* x 2)) (+ y 1)))
+       y
+       (eval '(+ 1 2))
+       (eval (list 'def! 'z 3))
+       z
+       `(1 ~(+ 1 1) ~@[3 4])
+       {:a [1 (+ 1 2)]}
+       (meta ^{:line 1} [1 2])"#,
+    // Exceptions
+    r#"(try* (throw {:a 1}) (catch* e (get e :a)))
+       (try* (nth [] 3) (catch* e e))
+       (try* (nth [] 3) (catch* :index-out-of-range e :caught) (catch* :default e :default))
+       (try* (throw 1) (catch* :when string? e :string) (catch* :when number? e :number))
+       (try* (throw 1) (catch* :when string? e :string))
+       (def! a (atom []))
+       (try* (try* (throw :inner) (finally (swap! a conj :finally))) (catch* e [e @a]))
+       (try* 1 (finally (swap! a conj :again)))
+       @a
+       (try* (throw :x) (catch* e (throw :y)))
+       (try* (try* (throw :x) (catch* e (throw e))) (catch* e [:outer e]))"#,
+    // Dynamic vars and namespaces
+    r#"(def! ^:dynamic *depth* 0)
+       (def! depth (fn* [] *depth*))
+       (binding [*depth* 1] (depth))
+       (binding [*depth* 1] (set! *depth* 2) *depth*)
+       (depth)
+       (set! *depth* 3)
+       (ns other)
+       (def! x 10)
+       (ns user)
+       (other/x)
+       other/x"#,
+    // Stack traces
+    r#"(do (def! inner (fn* (x) (nth x 5))) (def! middle (fn* (x) (let* [y (inner x)] y))) (def! outer (fn* () (let* [r (middle [1 2])] r))))
+       (outer)
+       (doall (map inner [[1]]))
+       (try* (outer) (catch* e :caught))
+       (try* (outer) (finally 1))"#,
+];
+
+fn run(program: &str, backend: Backend) -> Vec<Result<Form, String>> {
+    exec::set_backend(backend);
+    let mut env = Env::new();
+    risp::core::populate(&mut env);
+    let transcript = program
+        .lines()
+        .map(|line| match risp::eval(read_str(line).unwrap(), &mut env) {
+            // Functions and references are only equal to themselves, so they are compared by how
+            // they print
+            Ok(value)
+                if matches!(
+                    value.kind,
+                    FormKind::UserFn(_)
+                        | FormKind::Atom(_)
+                        | FormKind::Delay(_)
+                        | FormKind::Promise(_)
+                ) =>
+            {
+                Ok(Form::string(format!("{value:?}")))
+            }
+            Ok(value) => Ok(value),
+            Err(err) => Err(format!("{} {err} {:?}", err.type_name(), trace::take())),
+        })
+        .collect();
+    exec::set_backend(Backend::default());
+    transcript
+}
+
+#[test]
+fn backends_agree() {
+    for program in PROGRAMS {
+        let expected = run(program, Backend::TreeWalker);
+        let found = run(program, Backend::Vm);
+        for ((line, expected), found) in program.lines().zip(expected).zip(found) {
+            assert_eq!(expected, found, "{}", line.trim());
+        }
+    }
+}
+
+#[test]
+fn vm_calls_do_not_use_the_rust_stack() {
+    exec::set_backend(Backend::Vm);
+    let mut env = Env::new();
+    risp::core::populate(&mut env);
+    risp::eval(
+        read_str("(def! deep (fn* (n) (if (= n 0) 0 (+ 1 (deep (- n 1))))))").unwrap(),
+        &mut env,
+    )
+    .unwrap();
+    let result = risp::eval(read_str("(deep 100000)").unwrap(), &mut env);
+    exec::set_backend(Backend::default());
+    assert_eq!(result.unwrap(), Form::int(100000));
+}
+
+#[test]
+fn functions_compile_to_bytecode() {
+    let mut env = Env::new();
+    risp::core::populate(&mut env);
+    let fib = risp::eval(
+        read_str("(fn* (n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))").unwrap(),
+        &mut env,
+    )
+    .unwrap();
+    let code = vm::disassemble_fn(&fib).unwrap();
+    assert!(code.contains("Call"), "{code}");
+    assert!(vm::disassemble(&read_str("(let* [x 1] [x x])").unwrap(), &env).is_some());
+    let code = vm::disassemble(&read_str("(match [1] [(number? x)] x _ 0)").unwrap(), &env);
+    assert!(code.is_some_and(|code| code.contains("Match")));
+}
+
+#[test]
+fn backends_are_chosen_by_name() {
+    assert_eq!("vm".parse::<Backend>().unwrap(), Backend::Vm);
+    assert_eq!(
+        "tree-walker".parse::<Backend>().unwrap(),
+        Backend::TreeWalker
+    );
+    assert!("jit".parse::<Backend>().is_err());
+    assert_eq!(Backend::Vm.to_string(), "vm");
+    assert_eq!(exec::backend(), Backend::TreeWalker);
+}
@@ -0,0 +1,92 @@
+//! Helpers shared by the integration tests
+
+// Each test crate uses only some of the helpers
+#![allow(dead_code)]
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    thread,
+};
+
+use risp::{
+    exec::{self, Backend},
+    read_str, Env, Form,
+};
+
+/// The backends every test runs on
+pub const BACKENDS: [Backend; 2] = [Backend::TreeWalker, Backend::Vm];
+
+/// Run `test` on each backend in turn
+pub fn each_backend(test: impl Fn()) {
+    for backend in BACKENDS {
+        exec::set_backend(backend);
+        let report = ReportBackend(backend);
+        test();
+        drop(report);
+    }
+    exec::set_backend(Backend::default());
+}
+
+/// Names the backend a test failed on
+struct ReportBackend(Backend);
+
+impl Drop for ReportBackend {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            eprintln!("failed on the {} backend", self.0);
+        }
+    }
+}
+
+/// An environment with the core library
+pub fn env() -> Env {
+    let mut env = Env::new();
+    risp::core::populate(&mut env);
+    env
+}
+
+pub fn eval(input: &str, env: &mut Env) -> risp::Result<Form> {
+    risp::eval(read_str(input)?, env)
+}
+
+/// The hash of `form` with the standard hasher
+pub fn hash_of(form: &Form) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    form.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Evaluate `inputs` in order in a new environment, returning what the last one returns
+pub fn eval_all(inputs: &[&str]) -> risp::Result<Form> {
+    let mut env = env();
+    let mut result = Form::nil();
+    for input in inputs {
+        result = eval(input, &mut env)?;
+    }
+    Ok(result)
+}
+
+/// Assert that `input` evaluates to what `expected` reads as, in a new environment
+pub fn assert_evals_to(input: &str, expected: &str) {
+    assert_evals_in(input, expected, &mut env());
+}
+
+/// Assert that `input` evaluates to what `expected` reads as, in `env`
+pub fn assert_evals_in(input: &str, expected: &str, env: &mut Env) {
+    assert_eq!(
+        eval(input, env).unwrap(),
+        read_str(expected).unwrap(),
+        "{input}"
+    );
+}
+
+/// Assert that `inputs` evaluated in order in a new environment end with what `expected` reads
+/// as
+pub fn assert_all_eval_to(inputs: &[&str], expected: &str) {
+    assert_eq!(
+        eval_all(inputs).unwrap(),
+        read_str(expected).unwrap(),
+        "{inputs:?}"
+    );
+}
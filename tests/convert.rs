@@ -0,0 +1,94 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use risp::{read_str, Error, Form, Result};
+
+fn read(input: &str) -> Form {
+    read_str(input).unwrap()
+}
+
+fn convert<T>(input: &str) -> Result<T>
+where
+    Form: TryInto<T, Error = Error>,
+{
+    read(input).try_into()
+}
+
+#[test]
+fn scalars() {
+    assert_eq!(convert::<f64>("3").unwrap(), 3.0);
+    assert_eq!(convert::<usize>("7").unwrap(), 7);
+    assert_eq!(convert::<u32>("7").unwrap(), 7);
+    assert_eq!(convert::<char>(r#""x""#).unwrap(), 'x');
+    assert!(convert::<bool>("true").unwrap());
+}
+
+#[test]
+fn bool_is_strict() {
+    let err = convert::<bool>("nil").unwrap_err();
+    assert_eq!(err.to_string(), "expected boolean, found nil");
+    assert!(read("0").is_truthy());
+    assert!(!read("nil").is_truthy());
+}
+
+#[test]
+fn out_of_range_integers() {
+    let err = convert::<usize>("-1").unwrap_err();
+    assert_eq!(err.to_string(), "expected non-negative integer, found -1");
+    assert!(convert::<u32>("4294967296").is_err());
+    assert!(convert::<char>(r#""ab""#).is_err());
+}
+
+#[test]
+fn options() {
+    assert_eq!(convert::<Option<i64>>("nil").unwrap(), None);
+    assert_eq!(convert::<Option<i64>>("5").unwrap(), Some(5));
+    assert_eq!(
+        convert::<Option<String>>("5").unwrap_err().to_string(),
+        "expected string, found 5"
+    );
+}
+
+#[test]
+fn collections() {
+    let set = convert::<HashSet<i64>>("[1 2 2 3]").unwrap();
+    assert_eq!(set, HashSet::from([1, 2, 3]));
+    let map = convert::<BTreeMap<String, i64>>(r#"{"b" 2 "a" 1}"#).unwrap();
+    assert_eq!(
+        map.into_iter().collect::<Vec<_>>(),
+        [("a".into(), 1), ("b".into(), 2)]
+    );
+    // Forms are map keys, see the note on `impl Hash for Form`
+    #[allow(clippy::mutable_key_type)]
+    let map = convert::<HashMap<Form, i64>>("nil").unwrap();
+    assert!(map.is_empty());
+}
+
+#[test]
+fn long_tuples() {
+    let tuple =
+        convert::<(i64, i64, i64, i64, i64, i64, i64, String)>(r#"(1 2 3 4 5 6 7 "eight")"#)
+            .unwrap();
+    assert_eq!(tuple.7, "eight");
+}
+
+#[test]
+fn errors_report_position() {
+    let err = convert::<(i64, String)>("(1 2)").unwrap_err();
+    assert_eq!(err.to_string(), "at position 1: expected string, found 2");
+    assert!(matches!(err, Error::AtPosition { position: 1, .. }));
+
+    let err = convert::<Vec<Vec<i64>>>("[[1] [2 :x]]").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "at position 1: at position 1: expected integer, found :x"
+    );
+
+    let err = convert::<HashMap<Form, i64>>(r#"{:a "s"}"#).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        r#"invalid field ':a': expected integer, found "s""#
+    );
+
+    let err = convert::<(i64,)>("(1 2 3)").unwrap_err();
+    assert_eq!(err.to_string(), "expected at most 1 elements, found 3");
+}
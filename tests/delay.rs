@@ -0,0 +1,111 @@
+mod common;
+
+use common::{assert_evals_in, each_backend, env, eval};
+use risp::{delay::Delay, Error, Form};
+
+#[test]
+fn delays_compute_their_value_once() {
+    each_backend(|| {
+        let mut env = env();
+        eval("(def! calls (atom 0))", &mut env).unwrap();
+        eval("(def! d (delay (swap! calls + 1) :value))", &mut env).unwrap();
+        assert_evals_in("[(realized? d) @calls]", "[false 0]", &mut env);
+        assert_evals_in(
+            "[(force d) @d (deref d)]",
+            "[:value :value :value]",
+            &mut env,
+        );
+        assert_evals_in("[(realized? d) @calls]", "[true 1]", &mut env);
+        assert_evals_in(
+            "[(force 1) (delay? d) (delay? 1)]",
+            "[1 true false]",
+            &mut env,
+        );
+        assert_eq!(
+            eval("(pr-str d (delay 1))", &mut env).unwrap(),
+            Form::string("#<delay :value> #<delay pending>")
+        );
+    });
+}
+
+#[test]
+fn failed_delays_are_retried() {
+    each_backend(|| {
+        let mut env = env();
+        eval("(def! calls (atom 0))", &mut env).unwrap();
+        eval(
+            "(def! d (delay (if (< (swap! calls + 1) 2) (throw :again) :done)))",
+            &mut env,
+        )
+        .unwrap();
+        assert_evals_in("(try* @d (catch* e e))", ":again", &mut env);
+        assert_evals_in("[(realized? d) @d @calls]", "[false :done 2]", &mut env);
+
+        eval("(def! loops (delay @loops))", &mut env).unwrap();
+        assert!(matches!(
+            eval("@loops", &mut env),
+            Err(Error::RecursiveDelay)
+        ));
+    });
+}
+
+#[test]
+fn promises_are_delivered_once() {
+    each_backend(|| {
+        let mut env = env();
+        eval("(def! p (promise))", &mut env).unwrap();
+        assert_evals_in(
+            "[(realized? p) (deref p 100 :timeout)]",
+            "[false :timeout]",
+            &mut env,
+        );
+        assert!(matches!(eval("@p", &mut env), Err(Error::Undelivered)));
+        assert_evals_in("(= (deliver p 1) p)", "true", &mut env);
+        assert_evals_in("[(deliver p 2) @p (realized? p)]", "[nil 1 true]", &mut env);
+        assert_eq!(
+            eval("(pr-str p)", &mut env).unwrap(),
+            Form::string("#<promise 1>")
+        );
+    });
+}
+
+#[test]
+fn memoize_caches_on_the_arguments() {
+    each_backend(|| {
+        let mut env = env();
+        eval("(def! calls (atom 0))", &mut env).unwrap();
+        eval(
+            "(def! slow+ (memoize (fn* [& xs] (do (swap! calls + 1) (apply + xs)))))",
+            &mut env,
+        )
+        .unwrap();
+        assert_evals_in(
+            "[(slow+ 1 2) (slow+ 1 2) (slow+ 3) (slow+) (slow+ 1 2)]",
+            "[3 3 3 0 3]",
+            &mut env,
+        );
+        assert_evals_in("@calls", "3", &mut env);
+        assert_evals_in("((memoize list) [1] nil)", "([1] nil)", &mut env);
+
+        // A memoized recursive function only computes each value once
+        eval(
+            "(def! fib (memoize (fn* [n] (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))))",
+            &mut env,
+        )
+        .unwrap();
+        assert_evals_in("(fib 80)", "23416728348467685", &mut env);
+        assert!(eval("(memoize 1)", &mut env).is_err());
+    });
+}
+
+#[test]
+fn delays_can_be_made_from_rust() {
+    each_backend(|| {
+        let mut env = env();
+        let delay = Delay::new(|| Ok(Form::int(42)));
+        env.set("answer", delay.clone().into());
+        assert!(!delay.is_realized());
+        assert_evals_in("@answer", "42", &mut env);
+        assert_eq!(delay.value(), Some(Form::int(42)));
+    });
+}
@@ -0,0 +1,146 @@
+#![cfg(feature = "derive")]
+
+use risp::{read_str, Env, Error, Form, FromForm, IntoForm};
+
+fn eval(input: &str) -> Form {
+    let mut env = Env::new();
+    risp::core::populate(&mut env);
+    risp::eval(read_str(input).unwrap(), &mut env).unwrap()
+}
+
+fn default_port() -> i64 {
+    8080
+}
+
+#[derive(Debug, PartialEq, IntoForm, FromForm)]
+enum Mode {
+    Fast,
+    ReadOnly,
+    Retry { attempts: i64 },
+    Named(String),
+    Range(i64, i64),
+}
+
+#[derive(Debug, PartialEq, IntoForm, FromForm)]
+struct Server {
+    host: String,
+    #[form(default = "default_port")]
+    port: i64,
+    #[form(optional)]
+    user: Option<String>,
+    #[form(rename = "on")]
+    enabled: bool,
+    #[form(default)]
+    tags: Vec<String>,
+    mode: Mode,
+}
+
+#[derive(Debug, PartialEq, IntoForm, FromForm)]
+struct Point(i64, i64);
+
+#[derive(Debug, PartialEq, IntoForm, FromForm)]
+#[form(vector)]
+struct Pair {
+    key: String,
+    value: i64,
+}
+
+#[test]
+fn struct_from_map() {
+    let server: Server = eval(r#"{:host "localhost" :on true :mode {:retry {:attempts (* 2 3)}}}"#)
+        .try_into()
+        .unwrap();
+    assert_eq!(
+        server,
+        Server {
+            host: "localhost".into(),
+            port: 8080,
+            user: None,
+            enabled: true,
+            tags: vec![],
+            mode: Mode::Retry { attempts: 6 },
+        }
+    );
+}
+
+#[test]
+fn struct_round_trip() {
+    let server = Server {
+        host: "example.org".into(),
+        port: 22,
+        user: Some("root".into()),
+        enabled: false,
+        tags: vec!["a".into()],
+        mode: Mode::ReadOnly,
+    };
+    let form = Form::from(server);
+    assert_eq!(
+        form,
+        eval(
+            r#"{:host "example.org" :port 22 :user "root" :on false :tags ["a"] :mode :read-only}"#
+        )
+    );
+    let back: Server = form.try_into().unwrap();
+    assert_eq!(back.mode, Mode::ReadOnly);
+    assert_eq!(back.user.as_deref(), Some("root"));
+}
+
+#[test]
+fn optional_fields_are_left_out() {
+    let point = Server {
+        host: "h".into(),
+        port: 1,
+        user: None,
+        enabled: true,
+        tags: vec![],
+        mode: Mode::Fast,
+    };
+    assert_eq!(
+        Form::from(point),
+        eval(r#"{:host "h" :port 1 :on true :tags [] :mode :fast}"#)
+    );
+}
+
+#[test]
+fn vectors_and_tagged_variants() {
+    assert_eq!(Form::from(Point(1, 2)), eval("[1 2]"));
+    assert_eq!(
+        TryInto::<Point>::try_into(eval("[3 4]")).unwrap(),
+        Point(3, 4)
+    );
+
+    let pair = Pair {
+        key: "k".into(),
+        value: 1,
+    };
+    assert_eq!(Form::from(pair), eval(r#"["k" 1]"#));
+
+    assert_eq!(Form::from(Mode::Named("x".into())), eval(r#"{:named "x"}"#));
+    assert_eq!(Form::from(Mode::Range(1, 2)), eval("{:range [1 2]}"));
+    let mode: Mode = eval("{:range [1 2]}").try_into().unwrap();
+    assert_eq!(mode, Mode::Range(1, 2));
+}
+
+#[test]
+fn errors_name_the_failing_field() {
+    let err = TryInto::<Server>::try_into(eval(r#"{:on true :mode :fast}"#)).unwrap_err();
+    assert!(matches!(err, Error::MissingField(ref field) if field == "host"));
+
+    let err = TryInto::<Server>::try_into(eval(r#"{:host 1 :on true :mode :fast}"#)).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid field 'host': expected string, found 1"
+    );
+
+    let err = TryInto::<Server>::try_into(eval(
+        r#"{:host "h" :on true :mode {:retry {:attempts "x"}}}"#,
+    ))
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid field 'mode': invalid field 'retry': invalid field 'attempts': expected integer, found \"x\""
+    );
+
+    let err = TryInto::<Mode>::try_into(eval(":slow")).unwrap_err();
+    assert!(matches!(err, Error::UnknownVariant(ref tag) if tag == "slow"));
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use common::{assert_evals_to, each_backend, env, eval};
+use risp::Error;
+
+#[test]
+fn vectors() {
+    each_backend(|| {
+        assert_evals_to(
+            "(let* [[a [b c] & r :as all] [1 [2 3] 4 5]] (list a b c r all))",
+            "(1 2 3 (4 5) [1 [2 3] 4 5])",
+        );
+        assert_evals_to("(let* [[a b] [1]] (list a b))", "(1 nil)");
+    });
+}
+
+#[test]
+fn maps() {
+    each_backend(|| {
+        assert_evals_to(
+            r#"(let* [{:keys [a b] :strs [c] :or {b (+ 1 1)} :as m} {:a 1 "c" 3}] (list a b c m))"#,
+            r#"(1 2 3 {:a 1 "c" 3})"#,
+        );
+        assert_evals_to("(let* [{x :x [y] :ys} {:x 1 :ys [2]}] (list x y))", "(1 2)");
+    });
+}
+
+#[test]
+fn defaults_follow_the_order_of_keys() {
+    each_backend(|| {
+        for _ in 0..20 {
+            assert_evals_to(
+                "(let* [{:keys [a b c d] :or {d (+ c 1) c (+ b 1) b (+ a 1)}} {:a 1}] [a b c d])",
+                "[1 2 3 4]",
+            );
+        }
+        assert_evals_to(
+            "(let* [{:keys [x y] :or {x (* y 10)}} {:y 2}] [x y])",
+            "[20 2]",
+        );
+        assert_evals_to(
+            r#"(let* [{:keys [a] :strs [b] :or {b (+ a 1)}} {:a 1}] [a b])"#,
+            "[1 2]",
+        );
+    });
+}
+
+#[test]
+fn function_parameters() {
+    each_backend(|| {
+        assert_evals_to(
+            "((fn* ([a b] & {:keys [c]}) (list a b c)) [1 2] :c 3)",
+            "(1 2 3)",
+        );
+    });
+}
+
+#[test]
+fn def() {
+    each_backend(|| {
+        assert_evals_to("(do (def! [a {:keys [b]}] [1 {:b 2}]) (list a b))", "(1 2)");
+    });
+}
+
+#[test]
+fn shape_errors() {
+    each_backend(|| {
+        let err = eval("(let* [[a] 1] a)", &mut env()).unwrap_err();
+        assert!(matches!(err, Error::BindingMismatch { .. }), "{err}");
+        assert_eq!(err.to_string(), "cannot bind 1 to pattern [a]");
+
+        let err = eval("(let* [[a & b c] [1]] a)", &mut env()).unwrap_err();
+        assert!(matches!(err, Error::InvalidPattern(_)), "{err}");
+    });
+}
@@ -0,0 +1,139 @@
+mod common;
+
+use common::{assert_evals_in, assert_evals_to, each_backend, env, eval, eval_all};
+use risp::{read_str, Error, Form};
+
+const DEPTH: &str = "(def! ^:dynamic *depth* 0)";
+const SHOW: &str = "(def! show (fn* () *depth*))";
+
+#[test]
+fn binding_is_visible_to_called_functions() {
+    each_backend(|| {
+        let result = eval_all(&[
+            DEPTH,
+            SHOW,
+            "[(show) (binding [*depth* 1] [(show) (binding [*depth* 2] (show)) (show)]) (show)]",
+        ]);
+        assert_eq!(result.unwrap(), read_str("[0 [1 2 1] 0]").unwrap());
+    });
+}
+
+#[test]
+fn binding_is_restored_after_an_error() {
+    each_backend(|| {
+        let result = eval_all(&[
+            DEPTH,
+            SHOW,
+            r#"(try* (binding [*depth* 1] (throw "boom")) (catch* e (show)))"#,
+        ]);
+        assert_eq!(result.unwrap(), Form::int(0));
+    });
+}
+
+#[test]
+fn set_replaces_the_innermost_binding() {
+    each_backend(|| {
+        let result = eval_all(&[DEPTH, SHOW, "(binding [*depth* 1] (set! *depth* 5) (show))"]);
+        assert_eq!(result.unwrap(), Form::int(5));
+        let err = eval_all(&[DEPTH, "(set! *depth* 5)"]).unwrap_err();
+        assert!(
+            matches!(err, Error::Unbound(ref name) if name == "*depth*"),
+            "{err}"
+        );
+    });
+}
+
+#[test]
+fn locals_shadow_bound_vars() {
+    each_backend(|| {
+        for (input, expected) in [
+            (
+                "(binding [*print-length* 2] (let* [*print-length* 5] *print-length*))",
+                "5",
+            ),
+            ("(binding [*out* :stderr] ((fn* (*out*) *out*) 7))", "7"),
+            (
+                "(binding [*print-length* 2] (let* [*print-length* 5] (pr-str [1 2 3])))",
+                "\"[1 2 ...]\"",
+            ),
+        ] {
+            assert_evals_to(input, expected);
+        }
+        let result = eval_all(&[
+            DEPTH,
+            SHOW,
+            "(let* [*depth* :local] (binding [*depth* 1] [*depth* (show)]))",
+        ]);
+        assert_eq!(result.unwrap(), read_str("[:local 1]").unwrap());
+    });
+}
+
+#[test]
+fn vars_of_different_namespaces_are_bound_separately() {
+    each_backend(|| {
+        let result = eval_all(&[
+            "(ns a)",
+            "(def! ^:dynamic *level* :a)",
+            "(def! level (fn* () *level*))",
+            "(ns b)",
+            "(def! ^:dynamic *level* :b)",
+            "(def! level (fn* () *level*))",
+            "(binding [*level* :bound] [(a/level) (level) a/*level*])",
+        ]);
+        assert_eq!(result.unwrap(), read_str("[:a :bound :a]").unwrap());
+    });
+}
+
+#[test]
+fn only_dynamic_vars_can_be_bound() {
+    each_backend(|| {
+        let err = eval_all(&["(def! x 1)", "(binding [x 2] x)"]).unwrap_err();
+        assert!(
+            matches!(err, Error::NotDynamic(ref name) if name == "x"),
+            "{err}"
+        );
+    });
+}
+
+#[test]
+fn printing_reads_dynamic_vars() {
+    each_backend(|| {
+        for (input, expected) in [
+            (
+                r#"(with-out-str (println "a" [1 2]) (prn "b"))"#,
+                "\"a [1 2]\\n\\\"b\\\"\\n\"",
+            ),
+            (
+                "(binding [*print-length* 2] (pr-str [1 2 3] '(1 2)))",
+                "\"[1 2 ...] (1 2)\"",
+            ),
+            ("(binding [*print-length* 0] (pr-str {:a 1}))", "\"{...}\""),
+            (
+                r#"(binding [*print-readably* false] (pr-str "a\nb"))"#,
+                "\"a\\nb\"",
+            ),
+        ] {
+            let result = eval_all(&[input]).unwrap();
+            assert_eq!(result, read_str(expected).unwrap(), "{input}");
+        }
+    });
+}
+
+#[test]
+fn interpreters_have_their_own_vars() {
+    each_backend(|| {
+        let (mut a, mut b) = (env(), env());
+        eval("(def! ^:dynamic *x* 1)", &mut a).unwrap();
+        eval("(def! *x* 1)", &mut b).unwrap();
+        let err = eval("(binding [*x* 2] *x*)", &mut b).unwrap_err();
+        assert!(
+            matches!(err, Error::NotDynamic(ref name) if name == "*x*"),
+            "{err}"
+        );
+        assert_evals_in("(binding [*x* 2] *x*)", "2", &mut a);
+
+        eval("(def! *print-length* 2)", &mut a).unwrap();
+        assert_evals_in("(pr-str [1 2 3])", "\"[1 2 ...]\"", &mut a);
+        assert_evals_in("(pr-str [1 2 3])", "\"[1 2 3]\"", &mut b);
+    });
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use std::collections::HashMap;
+
+use common::{each_backend, env, eval, hash_of};
+use risp::{form::Atom, read_str, Form};
+
+fn assert_same(a: &Form, b: &Form) {
+    assert_eq!(a, b);
+    assert_eq!(
+        hash_of(a),
+        hash_of(b),
+        "{a:?} and {b:?} are equal but hash differently"
+    );
+}
+
+#[test]
+fn signed_zeros_are_equal() {
+    assert_same(&Form::float(0.0), &Form::float(-0.0));
+}
+
+#[test]
+fn nan_is_equal_to_itself() {
+    assert_same(&Form::float(f64::NAN), &Form::float(f64::NAN));
+    assert_same(&Form::float(f64::NAN), &Form::float(-f64::NAN));
+}
+
+#[test]
+fn integers_and_floats_are_distinct() {
+    assert_ne!(Form::int(1), Form::float(1.0));
+}
+
+#[test]
+fn lists_and_vectors_with_same_elements() {
+    let list = Form::list([Form::int(1), Form::int(2)]);
+    let vector = Form::vector([Form::int(1), Form::int(2)]);
+    assert_same(&list, &vector);
+}
+
+#[test]
+fn maps_hash_independently_of_insertion_order() {
+    let forward = (0..32).map(|n| (Form::int(n), Form::int(n * 2)));
+    let backward = (0..32).rev().map(|n| (Form::int(n), Form::int(n * 2)));
+    let a = Form::hash_map(forward.collect());
+    let b = Form::hash_map(backward.collect());
+    assert_same(&a, &b);
+}
+
+#[test]
+fn functions_compare_by_identity() {
+    each_backend(|| {
+        let mut env = env();
+        let f = eval("(def! f (fn* (x) x))", &mut env).unwrap();
+        let g = eval("(fn* (x) x)", &mut env).unwrap();
+        assert_same(&f, &f.clone());
+        assert_same(&f, &eval("f", &mut env).unwrap());
+        assert_ne!(f, g);
+    });
+}
+
+#[test]
+fn native_functions_compare_by_identity() {
+    each_backend(|| {
+        let mut env = env();
+        let plus = eval("+", &mut env).unwrap();
+        assert_same(&plus, &eval("+", &mut env).unwrap());
+        assert_ne!(plus, eval("-", &mut env).unwrap());
+    });
+}
+
+#[test]
+fn zero_sized_native_functions_are_distinct() {
+    fn one(_: Form) -> risp::Result<Form> {
+        Ok(Form::int(1))
+    }
+    fn two(_: Form) -> risp::Result<Form> {
+        Ok(Form::int(2))
+    }
+    let (one, two) = (Form::native_fn(&one), Form::native_fn(&two));
+    assert_same(&one, &one.clone());
+    assert_ne!(one, two);
+    assert_ne!(hash_of(&one), hash_of(&two));
+}
+
+#[test]
+fn atoms_compare_by_identity() {
+    let a = Form::atom(Atom::new(Form::int(1)));
+    let b = Form::atom(Atom::new(Form::int(1)));
+    assert_same(&a, &a.clone());
+    assert_ne!(a, b);
+}
+
+#[test]
+fn values_work_as_map_keys() {
+    each_backend(|| {
+        let f = eval("(fn* () 1)", &mut env()).unwrap();
+        let atom = Form::atom(Atom::new(Form::nil()));
+        // Functions and atoms hash by identity, so they are stable keys although they hold cells
+        #[allow(clippy::mutable_key_type)]
+        let mut map = HashMap::new();
+        map.insert(f.clone(), 1);
+        map.insert(atom.clone(), 2);
+        map.insert(Form::float(f64::NAN), 3);
+        map.insert(Form::float(0.0), 4);
+        assert_eq!(map.get(&f), Some(&1));
+        assert_eq!(map.get(&atom), Some(&2));
+        assert_eq!(map.get(&Form::float(f64::NAN)), Some(&3));
+        assert_eq!(map.get(&Form::float(-0.0)), Some(&4));
+    });
+}
+
+#[test]
+fn lazy_sequences_hash_like_their_elements() {
+    each_backend(|| {
+        let mut env = env();
+        assert_same(
+            &eval("(map list [1 2])", &mut env).unwrap(),
+            &read_str("((1) (2))").unwrap(),
+        );
+        let failing = eval(
+            r#"(map (fn* [x] (if (= x 3) (throw "three") x)) [1 2 3])"#,
+            &mut env,
+        )
+        .unwrap();
+        let prefix = read_str("(1 2)").unwrap();
+        assert_ne!(failing, prefix);
+        assert_ne!(hash_of(&failing), hash_of(&prefix));
+    });
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use common::{assert_evals_to, each_backend, eval_all};
+use risp::{read_str, Error, Form};
+
+#[test]
+fn ex_info_carries_message_data_and_cause() {
+    each_backend(|| {
+        assert_evals_to(
+            r#"(try* (throw (ex-info "bad" {:n 1} "root")) (catch* e [(ex-message e) (ex-data e) (ex-cause e)]))"#,
+            r#"["bad" {:n 1} "root"]"#,
+        );
+        assert_evals_to(r#"(ex-message "plain")"#, r#""plain""#);
+        assert_evals_to("(ex-data 1)", "nil");
+    });
+}
+
+#[test]
+fn native_errors_are_maps_with_a_type() {
+    each_backend(|| {
+        assert_evals_to(
+            "(try* (nope) (catch* e e))",
+            r#"{:type :unknown-symbol :message "'nope' not found"}"#,
+        );
+        assert_evals_to(
+            "(try* (nth [1] 5) (catch* e (get e :type)))",
+            ":index-out-of-range",
+        );
+    });
+}
+
+#[test]
+fn first_matching_catch_handles_the_error() {
+    each_backend(|| {
+        let clauses = r#"(catch* :unknown-symbol e :unknown)
+                         (catch* :validation e :invalid)
+                         (catch* :when string? e :string)
+                         (catch* e :other)"#;
+        for (thrown, expected) in [
+            "(nope)",
+            r#"(throw (ex-info "bad" {:type :validation}))"#,
+            r#"(throw "text")"#,
+            "(throw 1)",
+        ]
+        .into_iter()
+        .zip([":unknown", ":invalid", ":string", ":other"])
+        {
+            assert_evals_to(&format!("(try* {thrown} {clauses})"), expected);
+        }
+        let err = eval_all(&["(try* (throw 1) (catch* :validation e e))"]).unwrap_err();
+        assert!(
+            matches!(err, Error::UserError(ref form) if *form == Form::int(1)),
+            "{err}"
+        );
+    });
+}
+
+#[test]
+fn argument_errors_have_the_type_of_the_underlying_error() {
+    each_backend(|| {
+        assert_evals_to(
+            r#"(try* (+ 1 "a") (catch* :wrong-type e :caught))"#,
+            ":caught",
+        );
+        assert_evals_to(
+            r#"(try* (+ 1 "a") (catch* e [(get e :type) (get e :position)]))"#,
+            "[:wrong-type 1]",
+        );
+    });
+}
+
+#[test]
+fn only_keywords_name_a_type_to_catch() {
+    each_backend(|| {
+        assert_evals_to("(try* (throw 1) (catch* e :ignored e))", "1");
+        assert_evals_to("(try* (throw 1) (catch* :when number? e (+ e 1)))", "2");
+    });
+}
+
+#[test]
+fn finally_always_runs() {
+    each_backend(|| {
+        let result = eval_all(&[
+            "(def! log (atom []))",
+            "(try* :ok (finally (swap! log conj 1)))",
+            "(try* (throw 2) (catch* e e) (finally (swap! log conj 2)))",
+            "(try* (try* (throw 3) (finally (swap! log conj 3))) (catch* e e))",
+            "@log",
+        ]);
+        assert_eq!(result.unwrap(), read_str("[1 2 3]").unwrap());
+        assert_evals_to("(try* :ok (finally :ignored))", ":ok");
+    });
+}
+
+#[test]
+fn try_result_is_not_evaluated_again() {
+    each_backend(|| {
+        assert_evals_to("(try* '(+ 1 2) (catch* e e))", "(+ 1 2)");
+        assert_evals_to("(try* (throw '(+ 1 2)) (catch* e e))", "(+ 1 2)");
+    });
+}
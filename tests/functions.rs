@@ -0,0 +1,61 @@
+mod common;
+
+use common::{each_backend, eval_all};
+use risp::{read_str, Error};
+
+const MULTI: &str = "(def! f (fn* ([] :none) ([x] x) ([x y] (+ x y)) ([x y & more] more)))";
+
+#[test]
+fn dispatches_on_argument_count() {
+    each_backend(|| {
+        for (call, expected) in [
+            ("(f)", ":none"),
+            ("(f 1)", "1"),
+            ("(f 1 2)", "3"),
+            ("(f 1 2 3 4)", "(3 4)"),
+        ] {
+            assert_eq!(
+                eval_all(&[MULTI, call]).unwrap(),
+                read_str(expected).unwrap(),
+                "{call}"
+            );
+        }
+    });
+}
+
+#[test]
+fn extra_arguments_are_an_error() {
+    each_backend(|| {
+        let err = eval_all(&["((fn* (a) a) 1 2)"]).unwrap_err();
+        assert!(
+            matches!(err, Error::ArityMismatch { found: 2, .. }),
+            "{err}"
+        );
+        assert_eq!(err.to_string(), "wrong number of arguments (2), expected 1");
+    });
+}
+
+#[test]
+fn mismatch_lists_accepted_counts() {
+    each_backend(|| {
+        let err = eval_all(&["((fn* ([a] a) ([a b c & d] a)) 1 2)"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "wrong number of arguments (2), expected 1 or at least 3"
+        );
+    });
+}
+
+#[test]
+fn conflicting_arities_are_rejected() {
+    each_backend(|| {
+        for definition in [
+            "(fn* ([a] 1) ([b] 2))",
+            "(fn* ([& a] 1) ([b & c] 2))",
+            "(fn* ([a b] 1) ([c & d] 2))",
+        ] {
+            let err = eval_all(&[definition]).unwrap_err();
+            assert!(matches!(err, Error::InvalidFnDefinition(_)), "{definition}");
+        }
+    });
+}
@@ -0,0 +1,174 @@
+mod common;
+
+use common::{each_backend, env, eval};
+use risp::{gc, Env, Form, FormKind};
+
+/// The count at `key` of the map `(gc)` or `(gc-stats)` returns
+fn count(stats: &Form, key: &str) -> i64 {
+    let FormKind::HashMap(ref stats) = stats.kind else {
+        panic!("{stats}");
+    };
+    stats[&Form::keyword(key)].clone().try_into().unwrap()
+}
+
+/// How many closures, atoms and environments `(gc)` reports freed after `inputs` are evaluated
+/// in an interpreter that is then dropped, beyond what dropping an unused interpreter frees
+fn freed(inputs: &[&str]) -> [i64; 3] {
+    let collected = |inputs: &[&str], scratch: &mut Env| {
+        let before = eval("(gc)", scratch).unwrap();
+        {
+            let mut env = env();
+            for input in inputs {
+                eval(input, &mut env).unwrap();
+            }
+        }
+        let after = eval("(gc)", scratch).unwrap();
+        ["collected-closures", "collected-atoms", "collected-envs"]
+            .map(|key| count(&after, key) - count(&before, key))
+    };
+    gc::set_threshold(None);
+    let mut scratch = env();
+    let baseline = collected(&[], &mut scratch);
+    let found = collected(inputs, &mut scratch);
+    [0, 1, 2].map(|i| found[i] - baseline[i])
+}
+
+#[test]
+fn called_closures_are_collected() {
+    each_backend(|| {
+        gc::set_threshold(None);
+        let mut env = env();
+        let before = gc::collect();
+        eval(
+            "(let* [f (fn* (n) (if (= n 0) 0 (f (- n 1))))] (f 5))",
+            &mut env,
+        )
+        .unwrap();
+        let after = gc::collect();
+        assert!(after.collected_closures > before.collected_closures);
+        assert!(after.collected_envs > before.collected_envs);
+    });
+}
+
+#[test]
+fn top_level_recursive_functions_are_collected() {
+    const COUNTDOWN: &str = "(def! countdown (fn* (n) (if (= n 0) 0 (countdown (- n 1)))))";
+    each_backend(|| {
+        let [closures, atoms, _] = freed(&[COUNTDOWN]);
+        assert_eq!((closures, atoms), (1, 0));
+        let [closures, atoms, _] = freed(&[COUNTDOWN, "(countdown 10)"]);
+        assert_eq!((closures, atoms), (1, 0));
+    });
+}
+
+#[test]
+fn mutually_recursive_let_closures_are_collected() {
+    const EVEN_ODD: &str = "(let* [even? (fn* (n) (if (= n 0) true (odd? (- n 1)))) odd? (fn* (n) (if (= n 0) false (even? (- n 1))))] ";
+    each_backend(|| {
+        let [closures, atoms, envs] = freed(&[&format!("{EVEN_ODD} nil)")]);
+        assert_eq!((closures, atoms), (2, 0));
+        assert!(envs > 0);
+        let [closures, atoms, envs] = freed(&[&format!("{EVEN_ODD} (even? 11))")]);
+        assert_eq!((closures, atoms), (2, 0));
+        assert!(envs > 0);
+    });
+}
+
+#[test]
+fn self_referencing_atoms_are_collected() {
+    each_backend(|| {
+        let [closures, atoms, _] = freed(&["(let* [a (atom nil)] (do (reset! a a) nil))"]);
+        assert_eq!((closures, atoms), (0, 1));
+        let [closures, atoms, _] =
+            freed(&["(let* [a (atom nil)] (do (reset! a (fn* () @a)) nil))"]);
+        assert_eq!((closures, atoms), (1, 1));
+        let [closures, atoms, _] =
+            freed(&["(let* [a (atom nil)] (do (reset! a (fn* () @a)) ((deref a))))"]);
+        assert_eq!((closures, atoms), (1, 1));
+    });
+}
+
+#[test]
+fn garbage_is_freed_while_the_interpreter_runs() {
+    each_backend(|| {
+        gc::set_threshold(None);
+        let mut env = env();
+        eval("(gc)", &mut env).unwrap();
+        let live = count(&eval("(gc-stats)", &mut env).unwrap(), "live-closures");
+        eval(
+            "(let* [f (fn* (n) (if (= n 0) 0 (f (- n 1))))] (f 5))",
+            &mut env,
+        )
+        .unwrap();
+        let stats = eval("(gc-stats)", &mut env).unwrap();
+        assert_eq!(count(&stats, "live-closures"), live + 1);
+        eval("(gc)", &mut env).unwrap();
+        let stats = eval("(gc-stats)", &mut env).unwrap();
+        assert_eq!(count(&stats, "live-closures"), live);
+    });
+}
+
+#[test]
+fn namespaces_are_collected_with_their_interpreter() {
+    each_backend(|| {
+        let [closures, _, envs] = freed(&["(ns other)", "(def! f (fn* () f))"]);
+        assert_eq!(closures, 1);
+        assert!(envs > 0);
+    });
+}
+
+#[test]
+fn dynamic_vars_are_collected_with_their_interpreter() {
+    each_backend(|| {
+        let [closures, _, envs] = freed(&["(def! ^:dynamic *g* (fn* [] 1))"]);
+        assert_eq!(closures, 1);
+        assert!(envs > 0);
+    });
+}
+
+#[test]
+fn recursive_multimethods_are_collected() {
+    each_backend(|| {
+        let [closures, _, _] = freed(&["(defmulti mm (fn* [x] x))", "(defmethod mm 1 [x] mm)"]);
+        assert_eq!(closures, 2);
+    });
+}
+
+#[test]
+fn lazy_sequences_referring_to_themselves_are_collected() {
+    each_backend(|| {
+        // The function `lazy-seq` wraps its body in
+        let [closures, _, _] = freed(&["((fn* [] (do (def! s (lazy-seq (cons 1 s))) nil)))"]);
+        assert_eq!(closures, 1);
+        // Once realized, the sequence is its own rest
+        let [_, atoms, _] =
+            freed(&["((fn* [] (do (def! s (lazy-seq (cons (atom 1) s))) (doall (take 3 s)))))"]);
+        assert_eq!(atoms, 1);
+        let [closures, _, _] = freed(&["(def! xs (map (fn* [x] xs) [1 2]))", "(first xs)"]);
+        assert_eq!(closures, 1);
+    });
+}
+
+#[test]
+fn delays_and_promises_referring_to_themselves_are_collected() {
+    each_backend(|| {
+        let [closures, _, _] = freed(&["((fn* [] (do (def! d (delay d)) nil)))"]);
+        assert_eq!(closures, 1);
+        // Once forced, the delay only refers to itself through its value
+        let [_, atoms, _] = freed(&["((fn* [] (do (def! d (delay [d (atom 1)])) @d)))"]);
+        assert_eq!(atoms, 1);
+        let [closures, _, _] = freed(&["(def! p (promise))", "(deliver p (fn* [] p))"]);
+        assert_eq!(closures, 1);
+    });
+}
+
+#[test]
+fn recursive_memoized_functions_are_collected() {
+    each_backend(|| {
+        let [closures, atoms, _] = freed(&[
+            "(def! fib (memoize (fn* [n] (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))))",
+            "(fib 10)",
+        ]);
+        assert_eq!((closures, atoms), (1, 1));
+    });
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use std::collections::HashMap;
+
+use common::{each_backend, env, eval, hash_of};
+use risp::{Form, Host, HostValue};
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct Point {
+    x: i64,
+    y: i64,
+}
+
+fn point(x: i64, y: i64) -> Form {
+    Form::host_value(HostValue::builder(Point { x, y }).by_value().build())
+}
+
+#[test]
+fn host_values_compare_by_identity_by_default() {
+    let a = Form::host(Point { x: 1, y: 2 });
+    let b = Form::host(Point { x: 1, y: 2 });
+    assert_eq!(a, a.clone());
+    assert_eq!(hash_of(&a), hash_of(&a.clone()));
+    assert_ne!(a, b);
+}
+
+#[test]
+fn host_values_can_compare_by_value() {
+    let a = point(1, 2);
+    let b = point(1, 2);
+    assert_eq!(a, b);
+    assert_eq!(hash_of(&a), hash_of(&b));
+    assert_ne!(a, point(2, 1));
+
+    // A host value compared by value hashes the `Point` it holds, which can't change
+    #[allow(clippy::mutable_key_type)]
+    let mut map = HashMap::new();
+    map.insert(a, 1);
+    assert_eq!(map.get(&b), Some(&1));
+}
+
+#[test]
+fn comparison_by_value_is_symmetric() {
+    let by_value = point(1, 2);
+    let by_identity = Form::host(Point { x: 1, y: 2 });
+    assert_ne!(by_value, by_identity);
+    assert_ne!(by_identity, by_value);
+
+    let other_type = Form::host_value(HostValue::builder((1i64, 2i64)).by_value().build());
+    assert_ne!(by_value, other_type);
+    assert_ne!(other_type, by_value);
+}
+
+#[test]
+fn host_values_print_with_their_hook() {
+    let plain = Form::host_value(
+        HostValue::builder(Point { x: 1, y: 2 })
+            .type_name("point")
+            .build(),
+    );
+    assert_eq!(risp::pr_str(&plain), "#<point>");
+
+    let printed = HostValue::builder(Point { x: 1, y: 2 })
+        .print(|p, f| write!(f, "#point[{} {}]", p.x, p.y))
+        .build();
+    assert_eq!(risp::pr_str(&Form::host_value(printed)), "#point[1 2]");
+}
+
+#[test]
+fn native_functions_take_host_values() {
+    fn norm(params: Form) -> risp::Result<Form> {
+        let (p,): (Host<Point>,) = params.try_into()?;
+        Ok(Form::int(p.x.abs() + p.y.abs()))
+    }
+    each_backend(|| {
+        let mut env = env();
+        env.set("p", point(3, -4));
+        env.set("norm", Form::native_fn(&norm));
+        assert_eq!(eval("(norm p)", &mut env).unwrap(), Form::int(7));
+        assert!(eval("(norm 1)", &mut env).is_err());
+    });
+}
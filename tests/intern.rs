@@ -0,0 +1,75 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    hash::{Hash, Hasher},
+};
+
+use risp::{read_str, Env, Form, Ident};
+
+fn hash_of(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[test]
+fn interned_names_keep_their_text() {
+    let ident = Ident::new("some-name");
+    assert_eq!(ident.name(), "some-name");
+    assert_eq!(ident.to_string(), "some-name");
+    assert!(ident == *"some-name");
+    assert_eq!(Ident::from(String::from("some-name")), ident);
+}
+
+#[test]
+fn names_interned_on_other_threads_keep_their_text() {
+    let threads = (0..4)
+        .map(|thread| {
+            std::thread::spawn(move || {
+                (0..1000)
+                    .map(|n| Ident::new(&format!("name-{thread}-{n}")))
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect::<Vec<_>>();
+    for (thread, handle) in threads.into_iter().enumerate() {
+        for (n, ident) in handle.join().unwrap().into_iter().enumerate() {
+            assert_eq!(ident.name(), format!("name-{thread}-{n}"));
+        }
+    }
+}
+
+#[test]
+fn identifiers_compare_and_hash_by_name() {
+    assert_eq!(Ident::new("a"), Ident::new("a"));
+    assert_eq!(hash_of(Ident::new("a")), hash_of(Ident::new("a")));
+    assert_ne!(Ident::new("a"), Ident::new("b"));
+    let names: HashSet<Ident> = ["x", "y", "x"].into_iter().map(Ident::new).collect();
+    assert_eq!(names.len(), 2);
+}
+
+#[test]
+fn symbols_and_keywords_with_the_same_name_differ() {
+    let symbol = Form::symbol("thing");
+    let keyword = Form::keyword("thing");
+    assert_eq!(read_str("thing").unwrap(), symbol);
+    assert_eq!(read_str(":thing").unwrap(), keyword);
+    assert_ne!(symbol, keyword);
+    assert_eq!(hash_of(read_str(":thing").unwrap()), hash_of(&keyword));
+    assert_eq!(risp::pr_str(&keyword), ":thing");
+}
+
+#[test]
+fn interned_names_resolve_in_environments() {
+    let mut env = Env::new();
+    risp::core::populate(&mut env);
+    env.set("from-rust", Form::int(1));
+    env.set(Ident::new("from-ident"), Form::int(2));
+    let result = risp::eval(
+        read_str(r#"[from-rust from-ident (symbol "from-rust") (keyword "k")]"#).unwrap(),
+        &mut env,
+    )
+    .unwrap();
+    assert_eq!(result, read_str("[1 2 from-rust :k]").unwrap());
+    assert_eq!(env.get("from-ident").unwrap(), Form::int(2));
+    assert!(env.get("never-defined").is_err());
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use std::{thread, time::Duration};
+
+use common::{each_backend, env, eval};
+use risp::{read_str, Env, Error, Form};
+
+/// Interrupt whatever `env`'s interpreter is evaluating after a short delay
+fn interrupt_soon(env: &Env) -> thread::JoinHandle<()> {
+    let interrupt = env.interrupt_handle();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        interrupt.interrupt();
+    })
+}
+
+#[test]
+fn interrupts_stop_evaluation() {
+    each_backend(|| {
+        let mut env = env();
+        eval("(def! log (atom []))", &mut env).unwrap();
+
+        let handle = interrupt_soon(&env);
+        let result = eval("(loop [] (recur))", &mut env);
+        handle.join().unwrap();
+        assert!(matches!(result, Err(Error::Interrupted)), "{result:?}");
+
+        // try* doesn't catch the interrupt, but finally still runs
+        let handle = interrupt_soon(&env);
+        let result = eval(
+            "(try* (loop [] (do (try* (loop [] (recur)) (catch* e nil)) (recur))) (catch* e :caught) (finally (swap! log conj :finally)))",
+            &mut env,
+        );
+        handle.join().unwrap();
+        assert!(matches!(result, Err(Error::Interrupted)), "{result:?}");
+        assert_eq!(
+            eval("@log", &mut env).unwrap(),
+            read_str("[:finally]").unwrap()
+        );
+
+        // Long-running natives check the flag too
+        eval("(def! slow (fn* (x) (loop [] (recur))))", &mut env).unwrap();
+        let handle = interrupt_soon(&env);
+        let result = eval("(doall (map slow [1 2 3]))", &mut env);
+        handle.join().unwrap();
+        assert!(matches!(result, Err(Error::Interrupted)), "{result:?}");
+
+        // So do natives walking an infinite sequence
+        for input in [
+            "(count (range))",
+            "(nth (range) 1000000000000)",
+            "(dorun (range))",
+            "(apply + (range))",
+        ] {
+            let handle = interrupt_soon(&env);
+            let result = eval(input, &mut env);
+            handle.join().unwrap();
+            assert!(
+                matches!(result, Err(Error::Interrupted)),
+                "{input}: {result:?}"
+            );
+        }
+
+        // The interrupt is consumed, so evaluation continues normally afterwards
+        assert_eq!(eval("(+ 1 2)", &mut env).unwrap(), Form::int(3));
+
+        let interrupt = env.interrupt_handle();
+        interrupt.interrupt();
+        interrupt.reset();
+        assert_eq!(eval("(+ 1 2)", &mut env).unwrap(), Form::int(3));
+    });
+}
+
+#[test]
+fn printing_an_infinite_sequence_can_be_interrupted() {
+    each_backend(|| {
+        let mut env = env();
+        let handle = interrupt_soon(&env);
+        let result = eval("(do (pr-str (range)) :printed)", &mut env);
+        handle.join().unwrap();
+        assert!(matches!(result, Err(Error::Interrupted)), "{result:?}");
+    });
+}
+
+#[test]
+fn interrupts_stop_only_their_interpreter() {
+    each_backend(|| {
+        let (mut first, mut second) = (env(), env());
+        second.interrupt_handle().interrupt();
+        assert_eq!(
+            eval("(count (range 1000))", &mut first).unwrap(),
+            Form::int(1000)
+        );
+        let result = eval("(loop [] (recur))", &mut second);
+        assert!(matches!(result, Err(Error::Interrupted)), "{result:?}");
+    });
+}
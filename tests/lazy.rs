@@ -0,0 +1,208 @@
+mod common;
+
+use common::{assert_evals_to, each_backend, env, eval};
+use risp::{
+    lazy::{self, LazySeq},
+    limits::{self, Limits},
+    read_str, Error, Form,
+};
+
+#[test]
+fn infinite_sequences_can_be_taken_from() {
+    each_backend(|| {
+        assert_evals_to("(take 5 (range))", "(0 1 2 3 4)");
+        assert_evals_to("(range 2 11 3)", "(2 5 8)");
+        assert_evals_to("(range 3 0 -1)", "(3 2 1)");
+        assert_evals_to("(take 4 (iterate (fn* [x] (* x 2)) 1))", "(1 2 4 8)");
+        assert_evals_to("(take 3 (repeat :x))", "(:x :x :x)");
+        assert_evals_to("(repeat 2 :x)", "(:x :x)");
+        assert_evals_to("(take 5 (cycle [1 2]))", "(1 2 1 2 1)");
+        assert_evals_to("(cycle [])", "()");
+        assert_evals_to("(take 3 (drop 10 (range)))", "(10 11 12)");
+        assert_evals_to("(take-while (fn* [x] (< x 4)) (range))", "(0 1 2 3)");
+        assert_evals_to("(nth (map (fn* [x] (* x x)) (range)) 12)", "144");
+        assert_evals_to("(first (drop 100000 (range)))", "100000");
+    });
+}
+
+#[test]
+fn map_takes_several_collections() {
+    each_backend(|| {
+        assert_evals_to("(map + [1 2 3] (range 10 100))", "(11 13 15)");
+        assert_evals_to("(map list [1 2] [:a] '(x y z))", "((1 :a x))");
+    });
+}
+
+#[test]
+fn lazy_seq_builds_self_referential_sequences() {
+    each_backend(|| {
+        let mut env = env();
+        eval(
+            "(def! fibs (cons 0 (cons 1 (lazy-seq (map + fibs (rest fibs))))))",
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(
+            eval("(take 10 fibs)", &mut env).unwrap(),
+            read_str("(0 1 1 2 3 5 8 13 21 34)").unwrap()
+        );
+        eval(
+            "(def! from (fn* [n] (lazy-seq (cons n (from (+ n 1))))))",
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(
+            eval("(take 3 (from 5))", &mut env).unwrap(),
+            read_str("(5 6 7)").unwrap()
+        );
+    });
+}
+
+#[test]
+fn elements_are_realized_on_demand_and_once() {
+    each_backend(|| {
+        let mut env = env();
+        eval("(def! seen (atom 0))", &mut env).unwrap();
+        eval(
+            "(def! xs (map (fn* [x] (do (swap! seen + 1) x)) (range 10)))",
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(eval("@seen", &mut env).unwrap(), Form::int(0));
+        assert_eq!(eval("(first xs)", &mut env).unwrap(), Form::int(0));
+        assert_eq!(eval("(nth xs 2)", &mut env).unwrap(), Form::int(2));
+        assert_eq!(eval("@seen", &mut env).unwrap(), Form::int(3));
+        assert_eq!(eval("(count xs)", &mut env).unwrap(), Form::int(10));
+        assert_eq!(eval("(count xs)", &mut env).unwrap(), Form::int(10));
+        assert_eq!(eval("@seen", &mut env).unwrap(), Form::int(10));
+
+        eval("(reset! seen 0)", &mut env).unwrap();
+        let xs = "(map (fn* [x] (swap! seen + 1)) [1 2 3])";
+        assert_eq!(
+            eval(&format!("(dorun {xs})"), &mut env).unwrap(),
+            Form::nil()
+        );
+        assert_eq!(
+            eval(&format!("(doall {xs})"), &mut env).unwrap(),
+            read_str("(4 5 6)").unwrap()
+        );
+    });
+}
+
+#[test]
+fn lazy_sequences_behave_like_lists() {
+    each_backend(|| {
+        assert_evals_to("(= (map + [1 2]) '(1 2))", "true");
+        assert_evals_to("(= [0 1 2] (range 3))", "true");
+        assert_evals_to("(= (range 3) (range 4))", "false");
+        assert_evals_to("(get {'(0 1) :found} (range 2))", ":found");
+        assert_evals_to(
+            "[(seq (range 0)) (seq? (range 1)) (empty? (range))]",
+            "[nil true false]",
+        );
+        assert_evals_to(
+            "[(first (range 0)) (rest (range 1)) (rest (range 3))]",
+            "[nil () (1 2)]",
+        );
+        assert_evals_to("(cons 0 (range 1 3))", "(0 1 2)");
+        assert_evals_to("(conj (range 1 3) 0 -1)", "(-1 0 1 2)");
+        assert_evals_to("(concat [1] (range 2 4) nil '(4))", "(1 2 3 4)");
+        assert_evals_to("(apply + (range 5))", "10");
+        assert_evals_to(
+            "(let* [[a b & more] (range)] [a b (take 2 more)])",
+            "[0 1 (2 3)]",
+        );
+        assert_evals_to("(pr-str (map + [1 2]))", r#""(1 2)""#);
+        assert_evals_to(
+            "(binding [*print-length* 3] (pr-str (range)))",
+            r#""(0 1 2 ...)""#,
+        );
+    });
+}
+
+#[test]
+fn code_built_lazily_is_evaluated_as_lists() {
+    each_backend(|| {
+        let mut env = env();
+        eval(
+            "(defmacro! my-do (fn* (& body) (concat (list 'do) (map (fn* [x] x) body))))",
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(eval("(my-do 1 2 3)", &mut env).unwrap(), Form::int(3));
+        assert_eq!(
+            eval("(eval (concat '(+ 1) (range 2 4)))", &mut env).unwrap(),
+            Form::int(6)
+        );
+        assert_eq!(
+            eval("(macroexpand (my-do 1))", &mut env).unwrap(),
+            read_str("(do 1)").unwrap()
+        );
+        assert!(eval("(macroexpand (my-do 1))", &mut env).unwrap().is_list());
+    });
+}
+
+#[test]
+fn failed_elements_are_retried() {
+    each_backend(|| {
+        let mut env = env();
+        eval("(def! calls (atom 0))", &mut env).unwrap();
+        eval(
+            "(def! xs (lazy-seq (do (swap! calls + 1) (if (< @calls 2) (throw :again) (list 1)))))",
+            &mut env,
+        )
+        .unwrap();
+        assert!(eval("(first xs)", &mut env).is_err());
+        assert_eq!(eval("(first xs)", &mut env).unwrap(), Form::int(1));
+        assert_eq!(eval("@calls", &mut env).unwrap(), Form::int(2));
+
+        eval("(def! loops (lazy-seq (first loops)))", &mut env).unwrap();
+        assert!(matches!(
+            eval("(first loops)", &mut env),
+            Err(Error::RecursiveLazySeq)
+        ));
+    });
+}
+
+#[test]
+fn realizing_counts_against_limits() {
+    each_backend(|| {
+        let mut env = env();
+        limits::set(Limits {
+            fuel: Some(1000),
+            ..Limits::default()
+        });
+        let result = eval("(count (range))", &mut env);
+        limits::clear();
+        assert!(matches!(result, Err(Error::LimitExceeded(_))), "{result:?}");
+    });
+}
+
+#[test]
+fn lazy_sequences_can_be_made_from_rust() {
+    each_backend(|| {
+        let naturals = (0..).map(Form::int);
+        fn from(items: impl Iterator<Item = Form> + Clone + 'static) -> Form {
+            LazySeq::new(move || {
+                let mut items = items.clone();
+                Ok(match items.next() {
+                    Some(first) => LazySeq::cons(first, from(items)).into(),
+                    None => Form::nil(),
+                })
+            })
+            .into()
+        }
+        let seq = from(naturals);
+        let taken: Vec<Form> = lazy::iter(seq)
+            .unwrap()
+            .take(3)
+            .collect::<risp::Result<_>>()
+            .unwrap();
+        assert_eq!(taken, [Form::int(0), Form::int(1), Form::int(2)]);
+
+        // Dropping a long realized sequence doesn't recurse through it
+        let long = eval("(doall (range 200000))", &mut env()).unwrap();
+        assert_eq!(lazy::realize(long.clone()).unwrap().len(), 200000);
+        drop(long);
+    });
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use std::time::Duration;
+
+use common::each_backend;
+use risp::{
+    limits::{self, Limit, Limits},
+    read_str, Env, Error, Form,
+};
+
+fn eval_limited(limits: Limits, inputs: &[&str]) -> risp::Result<Form> {
+    limits::clear();
+    let mut env = Env::new();
+    risp::core::populate(&mut env);
+    limits::set(limits);
+    let mut result = Form::nil();
+    for input in inputs {
+        result = risp::eval(read_str(input)?, &mut env)?;
+    }
+    Ok(result)
+}
+
+fn assert_exceeded(result: risp::Result<Form>, expected: Limit) {
+    match result {
+        Err(Error::LimitExceeded(limit)) => assert_eq!(limit, expected),
+        other => panic!("expected {expected} limit to be exceeded, got {other:?}"),
+    }
+}
+
+const SPIN: &str = "(def! spin (fn* (n) (spin (+ n 1))))";
+
+#[test]
+fn fuel_stops_infinite_tail_calls() {
+    each_backend(|| {
+        let fuel = Limits {
+            fuel: Some(10_000),
+            ..Limits::default()
+        };
+        assert_exceeded(eval_limited(fuel, &[SPIN, "(spin 0)"]), Limit::Fuel);
+        assert!(limits::usage().unwrap().steps > 10_000);
+        assert_eq!(
+            eval_limited(fuel, &["(loop [i 0] (if (< i 100) (recur (+ i 1)) i))"]).unwrap(),
+            Form::int(100)
+        );
+    });
+}
+
+#[test]
+fn depth_stops_runaway_recursion() {
+    each_backend(|| {
+        let depth = Limits {
+            max_depth: Some(60),
+            ..Limits::default()
+        };
+        let deep = "(def! deep (fn* (n) (+ 1 (deep n))))";
+        assert_exceeded(eval_limited(depth, &[deep, "(deep 0)"]), Limit::Depth);
+        // The depth is regained as the error unwinds, so the limit can be caught
+        let caught = eval_limited(
+            depth,
+            &[
+                deep,
+                "(try* (deep 0) (catch* :limit-exceeded e (get e :limit)))",
+            ],
+        );
+        assert_eq!(caught.unwrap(), Form::keyword("depth"));
+        assert_eq!(limits::usage().unwrap().depth, 0);
+    });
+}
+
+#[test]
+fn allocation_counts_built_collections() {
+    each_backend(|| {
+        let allocation = Limits {
+            max_allocation: Some(1000),
+            ..Limits::default()
+        };
+        let grow = "(def! grow (fn* (v) (grow (conj v 1))))";
+        assert_exceeded(
+            eval_limited(allocation, &[grow, "(grow [])"]),
+            Limit::Allocation,
+        );
+    });
+}
+
+#[test]
+fn timeout_stops_long_evaluation() {
+    each_backend(|| {
+        let timeout = Limits {
+            timeout: Some(Duration::from_millis(50)),
+            ..Limits::default()
+        };
+        assert_exceeded(eval_limited(timeout, &[SPIN, "(spin 0)"]), Limit::Time);
+    });
+}
+
+#[test]
+fn cleared_limits_do_not_apply() {
+    each_backend(|| {
+        let fuel = Limits {
+            fuel: Some(1),
+            ..Limits::default()
+        };
+        assert_exceeded(eval_limited(fuel, &["(+ 1 (+ 2 3))"]), Limit::Fuel);
+        limits::clear();
+        let mut env = Env::new();
+        risp::core::populate(&mut env);
+        let result = risp::eval(read_str("(+ 1 (+ 2 3))").unwrap(), &mut env);
+        assert_eq!(result.unwrap(), Form::int(6));
+        assert_eq!(limits::usage(), None);
+    });
+}
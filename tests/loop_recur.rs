@@ -0,0 +1,136 @@
+mod common;
+
+use common::{assert_evals_in, assert_evals_to, each_backend, env, eval};
+use risp::{
+    special::{self, Step},
+    Error, Form,
+};
+
+#[test]
+fn loop_does_not_grow_the_stack() {
+    each_backend(|| {
+        assert_evals_to("(loop [i 0] (if (< i 50000) (recur (+ i 1)) i))", "50000");
+    });
+}
+
+#[test]
+fn loop_bindings_destructure() {
+    each_backend(|| {
+        assert_evals_to(
+            "(loop [[a & more] [1 2 3] acc 0] (if a (recur more (+ acc a)) acc))",
+            "6",
+        );
+    });
+}
+
+#[test]
+fn recur_in_function_body() {
+    each_backend(|| {
+        assert_evals_to(
+            "((fn* (n acc) (if (= n 0) acc (recur (- n 1) (+ acc n)))) 50000 0)",
+            "1250025000",
+        );
+        // The rest parameter is passed as one sequence
+        assert_evals_to(
+            "((fn* (x & xs) (if (empty? xs) x (recur (+ x (first xs)) (rest xs)))) 1 2 3)",
+            "6",
+        );
+    });
+}
+
+#[test]
+fn recur_through_macros() {
+    each_backend(|| {
+        assert_evals_to("(loop [i 0] (cond (> i 3) i :else (recur (+ i 1))))", "4");
+    });
+}
+
+#[test]
+fn recur_outside_tail_position_is_rejected() {
+    each_backend(|| {
+        for input in [
+            "(fn* (n) (+ 1 (recur n)))",
+            "(loop [i 0] (do (recur 1) 2))",
+            "(loop [i 0] (if (recur 1) 1 2))",
+            "(loop [i 0] (try* (recur 1) (catch* e e)))",
+        ] {
+            let err = eval(input, &mut env()).unwrap_err();
+            assert!(matches!(err, Error::InvalidRecur(_)), "{input}: {err}");
+        }
+    });
+}
+
+#[test]
+fn recur_argument_count_must_match() {
+    each_backend(|| {
+        let err = eval("(loop [i 0] (recur 1 2))", &mut env()).unwrap_err();
+        assert!(
+            matches!(err, Error::ArityMismatch { found: 2, .. }),
+            "{err}"
+        );
+        let err = eval("(recur 1)", &mut env()).unwrap_err();
+        assert!(matches!(err, Error::InvalidRecur(_)), "{err}");
+    });
+}
+
+#[test]
+fn function_bodies_are_checked_once_per_form() {
+    each_backend(|| {
+        let mut env = env();
+        for input in [
+            "(def! expansions (atom 0))",
+            "(defmacro! counted (fn* (x) (do (swap! expansions + 1) x)))",
+            "(def! make (fn* (n) (fn* () (counted n))))",
+        ] {
+            eval(input, &mut env).unwrap();
+        }
+        assert_evals_in("(do (make 1) (make 2) (make 3) @expansions)", "1", &mut env);
+    });
+}
+
+#[test]
+fn expansion_errors_in_function_bodies_are_reported() {
+    each_backend(|| {
+        let mut env = env();
+        eval(r#"(defmacro! broken (fn* () (throw "broken")))"#, &mut env).unwrap();
+        let err = eval("(fn* () (broken))", &mut env).unwrap_err();
+        assert!(matches!(err, Error::UserError(_)), "{err}");
+    });
+}
+
+#[test]
+fn locals_shadow_macros_when_checking_recur() {
+    each_backend(|| {
+        assert_evals_to("((fn* [cond] (cond 1)) list)", "(1)");
+        assert_evals_to("(loop [cond list] (cond 1))", "(1)");
+        assert_evals_to("(let* [cond list] (loop [] (cond 1)))", "(1)");
+        assert_evals_to("(match list f (f 1))", "(1)");
+
+        let mut env = env();
+        eval(r#"(defmacro! boom (fn* () (throw "boom")))"#, &mut env).unwrap();
+        eval("(def! f (fn* [boom] (boom)))", &mut env).unwrap();
+        assert_evals_in("(f (fn* [] :called))", ":called", &mut env);
+    });
+}
+
+#[test]
+fn interpreted_loops_are_not_checked_each_time_they_run() {
+    // Code using a registered form runs on the tree-walker
+    special::register("identity*", |form: Form, _: &mut risp::Env| {
+        let (_, value): (Form, Form) = form.try_into()?;
+        Ok(Step::tail(value))
+    })
+    .unwrap();
+    each_backend(|| {
+        let mut env = env();
+        for input in [
+            "(def! expansions (atom 0))",
+            "(defmacro! counted (fn* (x) (do (swap! expansions + 1) x)))",
+            "(def! run (fn* [] (identity* (loop [i 0] (if (< i 5) (recur (+ i 1)) (if false (counted i) i))))))",
+        ] {
+            eval(input, &mut env).unwrap();
+        }
+        eval("(do (run) (run) (reset! expansions 0))", &mut env).unwrap();
+        assert_evals_in("(do (run) (run) @expansions)", "0", &mut env);
+    });
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use common::{assert_evals_in, each_backend, eval};
+use risp::{read_str, Env};
+
+const MACROS: &[&str] = &[
+    "(defmacro! unless2 (fn* (c a b) `(if ~c ~b ~a)))",
+    "(defmacro! my-when (fn* (c & body) `(unless2 ~c nil (do ~@body))))",
+];
+
+fn env() -> Env {
+    let mut env = common::env();
+    for input in MACROS {
+        eval(input, &mut env).unwrap();
+    }
+    env
+}
+
+fn assert_evals_to(input: &str, expected: &str) {
+    assert_evals_in(input, expected, &mut env());
+}
+
+#[test]
+fn macroexpand_1_expands_one_step() {
+    each_backend(|| {
+        assert_evals_to(
+            "(macroexpand-1 (my-when x 1 2))",
+            "(unless2 x nil (do 1 2))",
+        );
+        assert_evals_to("(macroexpand (my-when x 1 2))", "(if x (do 1 2) nil)");
+        assert_evals_to("(macroexpand-1 (+ 1 2))", "(+ 1 2)");
+    });
+}
+
+#[test]
+fn macroexpand_all_walks_the_whole_form() {
+    each_backend(|| {
+        assert_evals_to(
+            "(macroexpand-all [(my-when x (my-when y 1)) {:k (unless2 a b c)}])",
+            "[(if x (do (if y (do 1) nil)) nil) {:k (if a c b)}]",
+        );
+        // Quoted forms and parameter lists are left alone
+        assert_evals_to(
+            "(macroexpand-all (my-when x '(my-when y 1)))",
+            "(if x (do (quote (my-when y 1))) nil)",
+        );
+        assert_evals_to(
+            "(macroexpand-all (fn* ([my-when] (my-when 1)) ([a b] (unless2 a b 3))))",
+            "(fn* ([my-when] (if 1 (do) nil)) ([a b] (if a 3 b)))",
+        );
+    });
+}
+
+#[test]
+fn expansion_steps_start_with_the_form() {
+    each_backend(|| {
+        let env = env();
+        let steps = risp::eval::expansion_steps(read_str("(my-when x 1)").unwrap(), &env).unwrap();
+        let expected = [
+            "(my-when x 1)",
+            "(unless2 x nil (do 1))",
+            "(if x (do 1) nil)",
+        ];
+        assert_eq!(steps, expected.map(|step| read_str(step).unwrap()).to_vec());
+    });
+}
+
+#[test]
+fn pretty_printing_breaks_long_forms() {
+    let form = read_str("(if x (do (println \"one\") (println \"two\")) [1 2])").unwrap();
+    assert_eq!(risp::format::pretty(&form, 80), format!("{form:?}"));
+    assert_eq!(
+        risp::format::pretty(&form, 20),
+        "(if\n  x\n  (do\n    (println \"one\")\n    (println \"two\"))\n  [1 2])"
+    );
+    let nested = read_str("[[1 2] 33]").unwrap();
+    assert_eq!(risp::format::pretty(&nested, 4), "[[1\n  2]\n 33]");
+}
@@ -0,0 +1,143 @@
+mod common;
+
+use common::{assert_evals_to, each_backend, env, eval};
+use risp::{pattern::Pattern, read_str, Error, Form, Ident};
+
+#[test]
+fn literals_and_symbols() {
+    each_backend(|| {
+        assert_evals_to("(match 2 1 :one 2 :two _ :other)", ":two");
+        assert_evals_to("(match 3 1 :one _ :other)", ":other");
+        assert_evals_to(r#"(match "hi" "hi" :greeting s s)"#, ":greeting");
+        assert_evals_to("(match nil nil :nil _ :other)", ":nil");
+        assert_evals_to("(match 'x 'y :y 'x :x)", ":x");
+        assert_evals_to("(match 5 n (* n 2))", "10");
+        assert_evals_to("(let* [n 1] (match 5 n n))", "5");
+    });
+}
+
+#[test]
+fn sequences_with_rest() {
+    each_backend(|| {
+        assert_evals_to("(match [1 2] [a] :one [a b] [:two a b])", "[:two 1 2]");
+        assert_evals_to("(match '(1 2 3) [a & more] [a more])", "[1 (2 3)]");
+        assert_evals_to("(match [] [a & more] :some [] :empty)", ":empty");
+        assert_evals_to(
+            "(match [1 [2 3]] [_ [b c] :as all] [b c all])",
+            "[2 3 [1 [2 3]]]",
+        );
+        assert_evals_to("(match (range) [a b & _] [a b])", "[0 1]");
+        assert_evals_to("(match (range 3) [a b] :two [a b c] :three)", ":three");
+        assert_evals_to("(match {:a 1} [& _] :seq _ :other)", ":other");
+        // A name used twice matches equal values only
+        assert_evals_to("(match [1 2] [x x] :same _ :different)", ":different");
+        assert_evals_to("(match [2 2] [x x] :same _ :different)", ":same");
+    });
+}
+
+#[test]
+fn maps_by_key() {
+    each_backend(|| {
+        assert_evals_to(
+            "(match {:type :circle :r 2} {:type :square :side s} (* s s) {:type :circle :r r} [:circle r])",
+            "[:circle 2]",
+        );
+        assert_evals_to(
+            "(match {:a 1} {:b b} :b {:a nil} :nil-a _ :other)",
+            ":other",
+        );
+        assert_evals_to("(match {\"k\" [1 2]} {\"k\" [_ x]} x)", "2");
+    });
+}
+
+#[test]
+fn predicates_and_guards() {
+    each_backend(|| {
+        assert_evals_to(
+            r#"(map (fn* [x] (match x (number? n) [:number n] (string? s) [:string s] _ :other)) [1 "a" :k])"#,
+            r#"([:number 1] [:string "a"] :other)"#,
+        );
+        assert_evals_to(
+            "(let* [small? (fn* [x] (< x 10))] (match 12 (small? n) :small n :big))",
+            ":big",
+        );
+        assert_evals_to(
+            "(match [3 4] [a b] :guard (> a b) :descending [a b] :guard (< a b) :ascending _ :equal)",
+            ":ascending",
+        );
+        assert_evals_to(
+            "(map (fn* [x] (match x (number? n) :guard (> n 1) :big _ :small)) [1 2])",
+            "(:small :big)",
+        );
+    });
+}
+
+#[test]
+fn clause_bodies_are_in_tail_position() {
+    each_backend(|| {
+        assert_evals_to(
+            "(loop [xs [1 2 3] acc 0] (match xs [] acc [x & more] (recur more (+ acc x))))",
+            "6",
+        );
+        assert_evals_to(
+            "(do (def! count-down (fn* [n] (match n 0 :done _ (count-down (- n 1))))) (count-down 10000))",
+            ":done",
+        );
+    });
+}
+
+#[test]
+fn failures() {
+    each_backend(|| {
+        let mut env = env();
+        assert!(matches!(
+            eval("(match 3 1 :one 2 :two)", &mut env),
+            Err(Error::NoMatch(value)) if value == Form::int(3)
+        ));
+        assert_eq!(
+            eval(
+                "(try* (match [1] [] :empty) (catch* e [(get e :type) (get e :value)]))",
+                &mut env
+            )
+            .unwrap(),
+            read_str("[:no-match [1]]").unwrap()
+        );
+        assert!(matches!(
+            eval("(match 1 x)", &mut env),
+            Err(Error::InvalidMatch(_))
+        ));
+        assert!(matches!(
+            eval("(match 1 (a b c) 1)", &mut env),
+            Err(Error::InvalidPattern(_))
+        ));
+    });
+}
+
+#[test]
+fn patterns_can_be_matched_from_rust() {
+    each_backend(|| {
+        let env = env();
+        let pattern = Pattern::parse(&read_str("[(keyword? op) & (seq? args)]").unwrap()).unwrap();
+        assert_eq!(pattern.names(), [Ident::from("op"), Ident::from("args")]);
+
+        let bindings = pattern
+            .matches(&read_str("(:add 1 2)").unwrap(), &env)
+            .unwrap()
+            .unwrap();
+        assert_eq!(bindings.get("op"), Some(&Form::keyword("add")));
+        assert_eq!(bindings.get("args"), Some(&read_str("(1 2)").unwrap()));
+
+        assert!(pattern
+            .matches(&read_str("[1 2]").unwrap(), &env)
+            .unwrap()
+            .is_none());
+
+        // Without an environment, the predicates are passed in already evaluated
+        let pattern = Pattern::parse(&read_str("{:x x :y 0}").unwrap()).unwrap();
+        let bindings = pattern
+            .matches_with(&read_str("{:x 1 :y 0}").unwrap(), &[])
+            .unwrap()
+            .unwrap();
+        assert_eq!(bindings.into_values().collect::<Vec<_>>(), [Form::int(1)]);
+    });
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use common::{assert_evals_in, each_backend, env, eval};
+use risp::read_str;
+
+#[test]
+fn reader_records_locations_of_lists() {
+    each_backend(|| {
+        let form = read_str("(f\n  [1 2]\n  {:a 1})").unwrap();
+        let location =
+            |line, column| read_str(&format!("{{:line {line} :column {column}}}")).unwrap();
+        assert_eq!(form.meta.as_deref(), Some(&location(1, 1)));
+        let items = form.as_slice().unwrap();
+        assert_eq!(items[1].meta, None);
+        assert_eq!(items[2].meta, None);
+
+        let mut env = env();
+        assert_evals_in("(meta '(1 2))", "{:line 1 :column 8}", &mut env);
+        assert_evals_in("(meta [1 2])", "nil", &mut env);
+        assert_evals_in("(meta {:a 1})", "nil", &mut env);
+        assert_evals_in("(meta (with-meta [1] {:a 1}))", "{:a 1}", &mut env);
+        assert_evals_in("(meta (vary-meta [1] assoc :a 1))", "{:a 1}", &mut env);
+        assert_evals_in("(meta ^{:doc \"x\"} [1 2])", "{:doc \"x\"}", &mut env);
+    });
+}
+
+#[test]
+fn vary_meta_applies_a_function_to_the_metadata() {
+    each_backend(|| {
+        let mut env = env();
+        assert_evals_in(
+            "(meta (vary-meta (with-meta [1] {:a 1}) assoc :b 2))",
+            "{:a 1 :b 2}",
+            &mut env,
+        );
+        assert_evals_in(
+            "(vary-meta (with-meta [1] {:a 1}) assoc :b 2)",
+            "[1]",
+            &mut env,
+        );
+        assert_evals_in(
+            "(meta (vary-meta 'sym assoc :tag :t))",
+            "{:tag :t}",
+            &mut env,
+        );
+    });
+}
+
+#[test]
+fn alter_meta_changes_atom_metadata_in_place() {
+    each_backend(|| {
+        let mut env = env();
+        eval("(def! a (atom 1))", &mut env).unwrap();
+        eval("(def! b a)", &mut env).unwrap();
+        assert_evals_in(
+            "(alter-meta! a assoc :watched true)",
+            "{:watched true}",
+            &mut env,
+        );
+        assert_evals_in("(meta b)", "{:watched true}", &mut env);
+
+        // `with-meta` makes a new reference with separate metadata, to the same value
+        eval("(def! c (with-meta a {:other true}))", &mut env).unwrap();
+        eval("(alter-meta! c assoc :more 1)", &mut env).unwrap();
+        assert_evals_in("(meta a)", "{:watched true}", &mut env);
+        assert_evals_in("(meta c)", "{:other true :more 1}", &mut env);
+        eval("(reset! c 2)", &mut env).unwrap();
+        assert_evals_in("[@a (= a c)]", "[2 true]", &mut env);
+    });
+}
+
+#[test]
+fn def_merges_symbol_metadata_onto_the_value() {
+    each_backend(|| {
+        let mut env = env();
+        eval("(def! ^:private secret (fn* [] 1))", &mut env).unwrap();
+        assert_evals_in("(get (meta secret) :private)", "true", &mut env);
+        eval(
+            "(def! ^{:doc \"numbers\"} xs (with-meta [1] {:a 1}))",
+            &mut env,
+        )
+        .unwrap();
+        assert_evals_in("(meta xs)", "{:a 1 :doc \"numbers\"}", &mut env);
+    });
+}
@@ -0,0 +1,180 @@
+mod common;
+
+use std::fs;
+
+use common::{assert_evals_in, each_backend, env, eval};
+use risp::{multi::MultiFn, Error, Form};
+
+#[test]
+fn methods_are_picked_by_the_dispatch_value() {
+    each_backend(|| {
+        let mut env = env();
+        eval("(defmulti area (fn* [shape] (get shape :kind)))", &mut env).unwrap();
+        eval(
+            "(defmethod area :square [{:keys [side]}] (* side side))",
+            &mut env,
+        )
+        .unwrap();
+        eval(
+            "(defmethod area :rect [s] (* (get s :w) (get s :h)))",
+            &mut env,
+        )
+        .unwrap();
+        assert_evals_in(
+            "[(area {:kind :square :side 3}) (area {:kind :rect :w 2 :h 5})]",
+            "[9 10]",
+            &mut env,
+        );
+        assert!(matches!(
+            eval("(area {:kind :circle})", &mut env),
+            Err(Error::NoMethod { value, .. }) if value == Form::keyword("circle")
+        ));
+
+        eval("(defmethod area :default [_] :unknown)", &mut env).unwrap();
+        assert_evals_in("(area {:kind :circle})", ":unknown", &mut env);
+
+        // Redefining a method replaces it, and removing it falls back to the default
+        eval("(defmethod area :square [s] :replaced)", &mut env).unwrap();
+        assert_evals_in("(area {:kind :square :side 3})", ":replaced", &mut env);
+        eval("(remove-method area :square)", &mut env).unwrap();
+        assert_evals_in("(area {:kind :square :side 3})", ":unknown", &mut env);
+        assert_evals_in("[(fn? area) (multi-fn? area)]", "[true true]", &mut env);
+        assert_evals_in("(pr-str area)", r##""#<multi-fn area>""##, &mut env);
+    });
+}
+
+#[test]
+fn dispatch_follows_the_hierarchy() {
+    each_backend(|| {
+        let mut env = env();
+        eval("(derive :shape/square :shape/rect)", &mut env).unwrap();
+        eval("(derive :shape/rect :shape/polygon)", &mut env).unwrap();
+        assert_evals_in(
+            "[(isa? :shape/square :shape/polygon) (isa? :shape/polygon :shape/square) (isa? 1 1)]",
+            "[true false true]",
+            &mut env,
+        );
+        assert_evals_in(
+            "(isa? [:shape/square :x] [:shape/rect :x])",
+            "true",
+            &mut env,
+        );
+
+        eval("(defmulti sides (fn* [x] x))", &mut env).unwrap();
+        eval("(defmethod sides :shape/polygon [_] :many)", &mut env).unwrap();
+        assert_evals_in("(sides :shape/square)", ":many", &mut env);
+        // The most specific method wins
+        eval("(defmethod sides :shape/rect [_] 4)", &mut env).unwrap();
+        assert_evals_in(
+            "[(sides :shape/square) (sides :shape/polygon)]",
+            "[4 :many]",
+            &mut env,
+        );
+        assert_evals_in("((get-method sides :shape/square) nil)", "4", &mut env);
+
+        // A value below two unrelated methods is ambiguous
+        eval("(derive :shape/square :shape/regular)", &mut env).unwrap();
+        eval("(defmethod sides :shape/regular [_] :equal)", &mut env).unwrap();
+        assert!(matches!(
+            eval("(sides :shape/square)", &mut env),
+            Err(Error::AmbiguousMethod { .. })
+        ));
+
+        assert!(matches!(
+            eval("(derive :shape/polygon :shape/square)", &mut env),
+            Err(Error::CyclicDerivation { .. })
+        ));
+        assert!(eval("(derive \"square\" :shape/rect)", &mut env).is_err());
+    });
+}
+
+#[test]
+fn defmulti_keeps_an_existing_multimethod() {
+    each_backend(|| {
+        let mut env = env();
+        eval("(defmulti kind (fn* [x] x))", &mut env).unwrap();
+        eval("(defmethod kind :a [_] :first)", &mut env).unwrap();
+        eval("(defmulti kind (fn* [x] :b))", &mut env).unwrap();
+        assert_evals_in("(kind :a)", ":first", &mut env);
+
+        // A name bound to anything else is redefined
+        eval("(def! other 1)", &mut env).unwrap();
+        eval("(defmulti other (fn* [x] x))", &mut env).unwrap();
+        assert_evals_in("(multi-fn? other)", "true", &mut env);
+    });
+}
+
+#[test]
+fn interpreters_have_their_own_hierarchy() {
+    each_backend(|| {
+        let mut first = env();
+        let mut second = env();
+        eval("(derive :square :rect)", &mut first).unwrap();
+        assert_evals_in("(isa? :square :rect)", "true", &mut first);
+        assert_evals_in("(isa? :square :rect)", "false", &mut second);
+
+        eval("(defmulti sides (fn* [x] x))", &mut second).unwrap();
+        eval("(defmethod sides :rect [_] 4)", &mut second).unwrap();
+        assert!(matches!(
+            eval("(sides :square)", &mut second),
+            Err(Error::NoMethod { .. })
+        ));
+    });
+}
+
+#[test]
+fn methods_can_be_added_from_other_files() {
+    each_backend(|| {
+        let dir = std::env::temp_dir().join(format!("risp-multi-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let base = dir.join("base.risp");
+        let extra = dir.join("extra.risp");
+        fs::write(
+            &base,
+            "(defmulti greet (fn* [x] (get x :lang)))\n(defmethod greet :en [_] \"hello\")",
+        )
+        .unwrap();
+        fs::write(
+            &extra,
+            "(derive :fr-ca :fr)\n(defmethod greet :fr [_] \"bonjour\")",
+        )
+        .unwrap();
+
+        let mut env = env();
+        for file in [&base, &extra] {
+            eval(
+                &format!("(load-file {:?})", file.to_str().unwrap()),
+                &mut env,
+            )
+            .unwrap();
+        }
+        assert_evals_in(
+            "[(greet {:lang :en}) (greet {:lang :fr-ca})]",
+            r#"["hello" "bonjour"]"#,
+            &mut env,
+        );
+        fs::remove_dir_all(dir).unwrap();
+    });
+}
+
+#[test]
+fn multimethods_can_be_made_from_rust() {
+    each_backend(|| {
+        let mut env = env();
+        let first = eval("(fn* [x & _] x)", &mut env).unwrap();
+        let multi = MultiFn::new("describe", first, &env);
+        multi.add_method(
+            Form::int(1),
+            eval("(fn* [_ y] [:one y])", &mut env).unwrap(),
+        );
+        env.set("describe", multi.into_form());
+        assert_evals_in("(describe 1 2)", "[:one 2]", &mut env);
+
+        eval("(defmethod describe 2 [_ y] [:two y])", &mut env).unwrap();
+        let describe = eval("describe", &mut env).unwrap();
+        let multi = MultiFn::of(&describe).unwrap();
+        assert!(multi.method(&Form::int(2)).unwrap().is_some());
+        assert!(multi.remove_method(&Form::int(1)));
+        assert!(MultiFn::of(&eval("(fn* [] 1)", &mut env).unwrap()).is_none());
+    });
+}
@@ -0,0 +1,215 @@
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use common::{each_backend, env, eval};
+use risp::{read_str, Env, Error, Form};
+
+/// Write `modules` (relative path, source) under a fresh directory and return it
+fn load_path(test: &str, modules: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("risp-ns-{}-{test}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    for (path, source) in modules {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, source).unwrap();
+    }
+    dir
+}
+
+fn env_with_load_path(dir: &Path) -> Env {
+    let mut env = env();
+    env.set(
+        "*load-path*",
+        Form::vector([Form::string(dir.to_str().unwrap())]),
+    );
+    env
+}
+
+fn eval_all(dir: &Path, inputs: &[&str]) -> risp::Result<Form> {
+    let mut env = env_with_load_path(dir);
+    let mut result = Form::nil();
+    for input in inputs {
+        result = eval(input, &mut env)?;
+    }
+    Ok(result)
+}
+
+const MATH: (&str, &str) = (
+    "util/math.risp",
+    "(ns util.math) (def! square (fn* (x) (* x x))) (def! answer 42)",
+);
+
+#[test]
+fn require_with_alias_and_refer() {
+    each_backend(|| {
+        let dir = load_path("alias", &[MATH]);
+        for (input, expected) in [
+            ("(do (require '[util.math :as m]) (m/square 3))", "9"),
+            ("(do (require '[util.math :refer [answer]]) answer)", "42"),
+            ("(do (require '[util.math :refer :all]) (square 4))", "16"),
+            ("(do (require 'util.math) util.math/answer)", "42"),
+        ] {
+            assert_eq!(
+                eval_all(&dir, &[input]).unwrap(),
+                read_str(expected).unwrap(),
+                "{input}"
+            );
+        }
+    });
+}
+
+#[test]
+fn modules_are_loaded_once() {
+    each_backend(|| {
+        let dir = load_path(
+            "once",
+            &[(
+                "counter.risp",
+                "(ns counter) (swap! loads (fn* (n) (+ n 1)))",
+            )],
+        );
+        let result = eval_all(
+            &dir,
+            &[
+                "(def! loads (atom 0))",
+                "(require 'counter)",
+                "(require 'counter)",
+                "(def! cached @loads)",
+                "(require 'counter :reload)",
+                "[cached @loads]",
+            ],
+        )
+        .unwrap();
+        assert_eq!(result, read_str("[1 2]").unwrap());
+    });
+}
+
+#[test]
+fn namespaces_do_not_clobber_each_other() {
+    each_backend(|| {
+        let dir = load_path(
+            "clobber",
+            &[
+                (
+                    "a.risp",
+                    "(ns a) (def! name :a) (def! get-name (fn* () name))",
+                ),
+                ("b.risp", "(ns b) (def! name :b)"),
+            ],
+        );
+        let result = eval_all(
+            &dir,
+            &[
+                "(def! name :user)",
+                "(require '[a :as a] '[b :as b])",
+                "[name a/name b/name (a/get-name)]",
+            ],
+        )
+        .unwrap();
+        assert_eq!(result, read_str("[:user :a :b :a]").unwrap());
+    });
+}
+
+#[test]
+fn ns_switches_the_current_namespace() {
+    each_backend(|| {
+        let dir = load_path("switch", &[MATH]);
+        let mut env = Env::new();
+        risp::core::populate(&mut env);
+        env.set(
+            "*load-path*",
+            Form::vector([Form::string(dir.to_str().unwrap())]),
+        );
+        for input in [
+            "(ns app (:require [util.math :as m]))",
+            "(def! x (m/square 5))",
+            "(ns other)",
+        ] {
+            risp::eval(read_str(input).unwrap(), &mut env).unwrap();
+        }
+        assert_eq!(env.namespace().unwrap(), *"other");
+        let x = risp::eval(read_str("app/x").unwrap(), &mut env).unwrap();
+        assert_eq!(x, Form::int(25));
+        assert!(risp::eval(read_str("x").unwrap(), &mut env).is_err());
+    });
+}
+
+#[test]
+fn missing_and_circular_modules_are_errors() {
+    each_backend(|| {
+        let dir = load_path(
+            "errors",
+            &[
+                ("ping.risp", "(ns ping (:require pong))"),
+                ("pong.risp", "(ns pong (:require ping))"),
+            ],
+        );
+        let err = eval_all(&dir, &["(require 'nowhere)"]).unwrap_err();
+        assert!(
+            matches!(err, Error::ModuleNotFound(ref name) if name == "nowhere"),
+            "{err}"
+        );
+        let err = eval_all(&dir, &["(require 'ping)"]).unwrap_err();
+        assert!(
+            matches!(err, Error::CircularRequire(ref name) if name == "ping"),
+            "{err}"
+        );
+    });
+}
+
+#[test]
+fn interpreters_have_their_own_namespaces() {
+    each_backend(|| {
+        let dir = load_path("separate", &[MATH]);
+        let mut first = env_with_load_path(&dir);
+        let mut second = env_with_load_path(&dir);
+        for input in ["(ns shared)", "(def! x 1)", "(require '[util.math :as m])"] {
+            eval(input, &mut first).unwrap();
+        }
+        assert!(eval("shared/x", &mut second).is_err());
+        assert!(eval("(m/square 2)", &mut second).is_err());
+        assert!(eval("util.math/answer", &mut second).is_err());
+        assert_eq!(eval("shared/x", &mut first).unwrap(), Form::int(1));
+    });
+}
+
+#[test]
+fn require_outside_a_namespace_switches_to_user() {
+    each_backend(|| {
+        let dir = load_path(
+            "user",
+            &[("peek.risp", "(ns peek) (def! seen (fn* () defined-later))")],
+        );
+        let mut env = env_with_load_path(&dir);
+        eval("(require '[peek :as p])", &mut env).unwrap();
+        assert_eq!(env.namespace().unwrap(), *"user");
+        eval("(def! defined-later 1)", &mut env).unwrap();
+        let err = eval("(p/seen)", &mut env).unwrap_err();
+        assert!(matches!(err, Error::UnknownSymbol(_)), "{err}");
+        assert!(eval("(let* [x 1] (require 'peek))", &mut env).is_ok());
+    });
+}
+
+#[test]
+fn private_definitions_are_not_referred() {
+    each_backend(|| {
+        let dir = load_path(
+            "private",
+            &[(
+                "secrets.risp",
+                "(ns secrets) (def! ^:private hidden 1) (def! shown (fn* () hidden))",
+            )],
+        );
+        let mut env = env_with_load_path(&dir);
+        eval("(require '[secrets :refer :all])", &mut env).unwrap();
+        assert_eq!(eval("(shown)", &mut env).unwrap(), Form::int(1));
+        assert!(eval("hidden", &mut env).is_err());
+        assert!(eval("secrets/hidden", &mut env).is_err());
+        let err = eval("(require '[secrets :refer [hidden]])", &mut env).unwrap_err();
+        assert!(matches!(err, Error::UnknownSymbol(_)), "{err}");
+    });
+}
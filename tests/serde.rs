@@ -0,0 +1,128 @@
+#![cfg(feature = "serde")]
+
+mod common;
+
+use std::collections::HashMap;
+
+use common::{each_backend, env, eval};
+use risp::{from_form, read_str, to_form, Form};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Mode {
+    Fast,
+    Careful { retries: u32 },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Config {
+    name: String,
+    max_depth: u32,
+    tags: Vec<String>,
+    parent: Option<String>,
+    mode: Mode,
+}
+
+#[test]
+fn config_from_evaluated_form() {
+    each_backend(|| {
+        let form = eval(
+            r#"{:name "demo" :max-depth (+ 1 2) :tags ["a" "b"] :parent nil
+                :mode {:careful {:retries 3}}}"#,
+            &mut env(),
+        )
+        .unwrap();
+        let config: Config = from_form(form).unwrap();
+        assert_eq!(
+            config,
+            Config {
+                name: "demo".into(),
+                max_depth: 3,
+                tags: vec!["a".into(), "b".into()],
+                parent: None,
+                mode: Mode::Careful { retries: 3 },
+            }
+        );
+    });
+}
+
+#[test]
+fn struct_round_trip() {
+    let config = Config {
+        name: "x".into(),
+        max_depth: 1,
+        tags: vec![],
+        parent: Some("p".into()),
+        mode: Mode::Fast,
+    };
+    let form = to_form(&config).unwrap();
+    assert_eq!(
+        form,
+        read_str(r#"{:name "x" :max-depth 1 :tags [] :parent "p" :mode :fast}"#).unwrap()
+    );
+    assert_eq!(from_form::<Config>(form).unwrap(), config);
+}
+
+#[test]
+fn json_round_trip() {
+    let form = read_str(r#"{:a [1 "s" true nil] :b {:c :kw}}"#).unwrap();
+    let json = serde_json::to_string(&form).unwrap();
+    let back: Form = serde_json::from_str(&json).unwrap();
+    // Keywords come back as strings, everything else is unchanged
+    assert_eq!(
+        back,
+        read_str(r#"{:a [1 "s" true nil] :b {:c "kw"}}"#).unwrap()
+    );
+}
+
+#[test]
+fn json_numbers() {
+    let form: Form = serde_json::from_str("[1, 2.5, -0.0]").unwrap();
+    assert_eq!(
+        form,
+        Form::vector([Form::int(1), Form::float(2.5), Form::float(0.0)])
+    );
+    assert_eq!(serde_json::to_string(&form).unwrap(), "[1,2.5,-0.0]");
+}
+
+#[test]
+fn functions_cannot_be_serialized() {
+    each_backend(|| {
+        let mut env = env();
+        let f = eval("(fn* (x) x)", &mut env).unwrap();
+        assert!(serde_json::to_string(&f).is_err());
+        let map = eval("{:a +}", &mut env).unwrap();
+        assert!(from_form::<HashMap<String, i64>>(map).is_err());
+    });
+}
+
+#[test]
+fn string_keys_round_trip_as_keywords() {
+    let map = HashMap::from([("a".to_string(), 1), ("b".to_string(), 2)]);
+    let form = to_form(&map).unwrap();
+    assert_eq!(form, read_str("{:a 1 :b 2}").unwrap());
+    assert_eq!(from_form::<HashMap<String, i64>>(form).unwrap(), map);
+
+    let form: Form = serde_json::from_str(r#"{"a": 1}"#).unwrap();
+    assert_eq!(serde_json::to_string(&form).unwrap(), r#"{"a":1}"#);
+    assert_eq!(
+        from_form::<HashMap<String, i64>>(form).unwrap(),
+        HashMap::from([("a".to_string(), 1)])
+    );
+}
+
+#[test]
+fn lazy_sequences_serialize_only_when_bounded() {
+    each_backend(|| {
+        let mut env = env();
+        let squares = eval("(map (fn* [x] (* x x)) [1 2 3])", &mut env).unwrap();
+        assert_eq!(serde_json::to_string(&squares).unwrap(), "[1,4,9]");
+        let taken = eval("(take 3 (range))", &mut env).unwrap();
+        assert_eq!(from_form::<Vec<i64>>(taken).unwrap(), [0, 1, 2]);
+        let unbounded = eval("(range)", &mut env).unwrap();
+        assert!(serde_json::to_string(&unbounded).is_err());
+        assert!(from_form::<Vec<i64>>(unbounded).is_err());
+    });
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use common::{each_backend, env, eval};
+use risp::{
+    read_str,
+    special::{self, Step},
+    Env, Error, Form,
+};
+
+/// `(my-if test then else)`, continuing with the branch in tail position
+fn my_if(form: Form, env: &mut Env) -> risp::Result<Step> {
+    let (_, test, then, otherwise): (Form, Form, Form, Form) = form.try_into()?;
+    Ok(Step::tail(if risp::eval(test, env)?.is_truthy() {
+        then
+    } else {
+        otherwise
+    }))
+}
+
+/// `(with-one name body)` evaluates body with name bound to 1
+fn with_one(form: Form, env: &mut Env) -> risp::Result<Step> {
+    let (_, name, body): (Form, Form, Form) = form.try_into()?;
+    let mut inner = Env::new_with(env);
+    inner.set(
+        name.as_symbol().ok_or(Error::InvalidArgument)?,
+        Form::int(1),
+    );
+    Ok(Step::tail_in(body, inner))
+}
+
+#[test]
+fn registered_forms_get_unevaluated_arguments() {
+    each_backend(|| {
+        special::register("args", |form: Form, _: &mut Env| {
+            Ok(Step::value(Form::list(form.try_into_iter()?.skip(1))))
+        })
+        .unwrap();
+        let mut env = env();
+        assert_eq!(
+            eval("(args a (undefined) 3)", &mut env).unwrap(),
+            read_str("(a (undefined) 3)").unwrap()
+        );
+        assert!(special::is_special("args"));
+        assert!(special::is_special("let*"));
+        assert!(!special::is_special("map"));
+    });
+}
+
+#[test]
+fn registered_forms_take_part_in_tail_calls() {
+    special::register("my-if", my_if).unwrap();
+    each_backend(|| {
+        let mut env = env();
+        eval(
+            "(def! count-down (fn* (n) (my-if (= n 0) :done (count-down (- n 1)))))",
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(
+            eval("(count-down 20000)", &mut env).unwrap(),
+            Form::keyword("done")
+        );
+        assert_eq!(
+            eval(
+                "(loop [i 0] (my-if (< i 20000) (recur (+ i 1)) i))",
+                &mut env
+            )
+            .unwrap(),
+            Form::int(20000)
+        );
+    });
+}
+
+#[test]
+fn registered_forms_can_continue_in_a_new_environment() {
+    each_backend(|| {
+        special::register("with-one", with_one).unwrap();
+        let mut env = env();
+        assert_eq!(
+            eval("(let* [x 2] (with-one y (+ x y)))", &mut env).unwrap(),
+            Form::int(3)
+        );
+        assert!(eval("y", &mut env).is_err());
+    });
+}
+
+#[test]
+fn built_in_forms_cannot_be_replaced() {
+    each_backend(|| {
+        let err = special::register("if", my_if).unwrap_err();
+        assert!(matches!(err, Error::BuiltinSpecialForm(ref name) if name == "if"));
+        assert_eq!(err.type_name(), "builtin-special-form");
+        assert!(!special::unregister("if"));
+
+        special::register("my-if", my_if).unwrap();
+        assert!(special::unregister("my-if"));
+        assert!(!special::is_special("my-if"));
+        assert!(eval("(my-if true 1 2)", &mut env()).is_err());
+    });
+}
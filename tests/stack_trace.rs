@@ -0,0 +1,73 @@
+mod common;
+
+use common::{each_backend, env, eval};
+use risp::{trace::Frame, Form};
+
+fn frame(name: &str, line: i64, column: i64) -> Frame {
+    Frame {
+        name: Some(name.into()),
+        line: Some(line),
+        column: Some(column),
+    }
+}
+
+const PROGRAM: &str = r#"(do
+  (def! inner (fn* (x) (nth x 5)))
+  (def! middle (fn* (x) (let* [y (inner x)] y)))
+  (def! outer (fn* () (let* [r (middle [1 2])] r))))"#;
+
+#[test]
+fn errors_carry_the_active_calls() {
+    each_backend(|| {
+        let mut env = env();
+        eval(PROGRAM, &mut env).unwrap();
+        assert!(eval("(outer)", &mut env).is_err());
+        assert_eq!(
+            risp::trace::take(),
+            [
+                frame("nth", 2, 24),
+                frame("inner", 3, 34),
+                frame("middle", 4, 32),
+                frame("outer", 1, 1),
+            ]
+        );
+    });
+}
+
+#[test]
+fn caught_errors_leave_no_trace() {
+    each_backend(|| {
+        let mut env = env();
+        eval(PROGRAM, &mut env).unwrap();
+        eval("(try* (outer) (catch* e nil))", &mut env).unwrap();
+        assert_eq!(risp::trace::take(), []);
+    });
+}
+
+#[test]
+fn finally_keeps_the_trace_of_the_error() {
+    each_backend(|| {
+        let mut env = env();
+        eval(PROGRAM, &mut env).unwrap();
+        assert!(eval("(try* (outer) (finally (+ 1 2)))", &mut env).is_err());
+        assert_eq!(risp::trace::take().len(), 4);
+    });
+}
+
+#[test]
+fn recorded_errors_can_be_printed() {
+    each_backend(|| {
+        let mut env = env();
+        eval(PROGRAM, &mut env).unwrap();
+        let err = eval("(outer)", &mut env).unwrap_err();
+        let recorded = risp::trace::record_error(&err, &mut env);
+        assert_eq!(eval("*e", &mut env).unwrap(), recorded);
+        let printed = eval("(with-out-str (print-stack-trace))", &mut env).unwrap();
+        assert_eq!(
+            printed,
+            Form::string(
+                "index-out-of-range: index 5 out of range\n  at nth (2:24)\n  at inner (3:34)\n  at middle (4:32)\n  at outer (1:1)\n"
+            )
+        );
+    });
+}